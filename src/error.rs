@@ -5,10 +5,13 @@ pub type Result<T> = core::result::Result<T, Error>;
 #[derive(Debug, From)]
 pub enum Error {
     InvalidBranchOrTag,
+    NotAGitRepository(std::path::PathBuf),
+    BareRepository(std::path::PathBuf),
+    /// .git file that does not contain a valid gitdir: line
+    InvalidGitFile(std::path::PathBuf),
     InvalidHash(String),
     InvalidGitObject,
     NotATreeGitObject,
-    TreeChildNotLoaded,
     ObjectBytesNotLoaded,
     Unreachable,
     InvalidSmartHttpRes,
@@ -35,6 +38,102 @@ pub enum Error {
     ObjectNotFound(String),
     CantBuildFromRefDelta,
     InvalidPackFile,
+    /// index-pack needs a .pack file name to name the index after it
+    InvalidPackFileName(std::path::PathBuf),
+    InvalidPackIndex,
+    UnsupportedPackVersion(u32),
+    PackChecksumMismatch {
+        expected: String,
+        got: String,
+    },
+    /// there are bytes left after reading the number of objects in the pack header
+    IncorrectPackObjectCount {
+        expected: u32,
+        remaining_bytes: usize,
+    },
+    InvalidIndex,
+    UnsupportedIndexVersion(u32),
+    UnsupportedIndexExtension(String),
+    IndexChecksumMismatch {
+        expected: String,
+        got: String,
+    },
+    UnmergedIndex,
+    UnmergedPath(String),
+    NothingToCommit,
+    InvalidConfig,
+    InvalidConfigFile(std::path::PathBuf),
+    InvalidConfigValue(String),
+    EmptyIdentName,
+    InvalidDate(String),
+    InvalidDateFormat(String),
+    InvalidPrettyFormat(String),
+    InvalidDiffAlgorithm(String),
+    PathOutsideRepository(std::path::PathBuf),
+    PathspecNotFound(String),
+    /// path of a tree that cannot be written in the work tree
+    InvalidPath(String),
+    /// paths given to add that are ignored, without --force
+    PathsIgnored(Vec<String>),
+    CannotPeel {
+        hash: String,
+        target_type: String,
+    },
+    InvalidRefName(String),
+    TagAlreadyExists(String),
+    TagNotFound(String),
+    RefNotFound(String),
+    RefLocked(std::path::PathBuf),
+    RefNameConflict(String),
+    SymbolicRefTooDeep(String),
+    NotASymbolicRef(String),
+    InvalidPackedRefs,
+    InvalidReflog(String),
+    InvalidRevision(String),
+    AmbiguousRevision(String),
+    PathNotInRevision {
+        path: String,
+        revision: String,
+    },
+    NoUpstream(String),
+    NoMergeBase(String),
+    NotABranch(String),
+    BranchAlreadyExists(String),
+    BranchNotFound(String),
+    /// branch deleted without force that is not merged in its upstream or HEAD
+    BranchNotMerged(String),
+    /// branch that can not be deleted or replaced because HEAD points to it
+    BranchCheckedOut(String),
+    UpstreamNotFound(String),
+    /// files with staged or unstaged changes that a checkout would replace
+    LocalChangesWouldBeOverwritten(Vec<String>),
+    /// files that are not tracked and that a checkout would replace
+    UntrackedFilesWouldBeOverwritten(Vec<String>),
+    ReflogEntryNotFound(String),
+    /// a merge stopped on conflicts and was not concluded by a commit
+    MergeInProgress,
+    /// merge of a commit that has no common ancestor with HEAD
+    UnrelatedHistories(String),
+    /// commit without -m and without a prepared merge message
+    EmptyCommitMessage,
+    /// remote name that is neither configured nor a url
+    RemoteNotFound(String),
+    InvalidRefspec(String),
+    /// ref of a refspec that the remote does not have
+    RemoteRefNotFound(String),
+    /// push destination that is not a full ref name and can not be guessed
+    /// from the source
+    UnqualifiedDestination(String),
+    /// push of the current branch to an upstream branch of another name
+    UpstreamNameMismatch {
+        branch: String,
+        upstream: String,
+    },
+    UnexpectedRefValue {
+        name: String,
+        expected: String,
+        got: String,
+    },
     // -- Externals
     #[from]
     Io(std::io::Error),
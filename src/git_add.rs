@@ -0,0 +1,126 @@
+use std::fs::read_dir;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::git_ignore::IgnoreRules;
+use crate::git_index::{GitIndex, IndexEntry};
+use crate::git_object::GitObject;
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// Adds the files to the staging area.
+/// Directories are added recursively, and the files of the index that were
+/// deleted from the working tree are removed from the index. Untracked files
+/// that are ignored are skipped, and are an error when given explicitly,
+/// unless forced
+pub fn git_add<P: AsRef<Path>>(repository: &Repository, paths: &[P], force: bool) -> Result<()> {
+    let mut index = GitIndex::read(repository)?;
+    let mut ignore_rules = match force {
+        true => None,
+        false => Some(IgnoreRules::load(repository)?),
+    };
+
+    let work_tree = repository.work_tree()?;
+    let mut ignored_paths = Vec::new();
+    for path in paths {
+        let path = repository.path_in_work_tree(path)?;
+
+        // entries of the index under the path that no longer exist are removed
+        let dir_prefix = format!("{}/", path);
+        let deleted_paths = index
+            .entries
+            .iter()
+            .filter(|entry| {
+                path.is_empty() || entry.path == path || entry.path.starts_with(&dir_prefix)
+            })
+            .filter(|entry| std::fs::symlink_metadata(work_tree.join(&entry.path)).is_err())
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<_>>();
+        for deleted_path in &deleted_paths {
+            index.remove_entry(deleted_path);
+        }
+
+        if let Ok(metadata) = std::fs::symlink_metadata(work_tree.join(&path)) {
+            if is_ignored(&mut ignore_rules, &index, &path, metadata.is_dir())? {
+                ignored_paths.push(path);
+                continue;
+            }
+            add_path(repository, &mut index, &mut ignore_rules, &path)?;
+        } else if deleted_paths.is_empty() {
+            return Err(Error::PathspecNotFound(path));
+        }
+    }
+
+    index.write(repository)?;
+    // the other paths are added all the same
+    match ignored_paths.is_empty() {
+        true => Ok(()),
+        false => Err(Error::PathsIgnored(ignored_paths)),
+    }
+}
+
+/// Whether the path is ignored and nothing under it is tracked
+fn is_ignored(
+    ignore_rules: &mut Option<IgnoreRules>,
+    index: &GitIndex,
+    path: &str,
+    is_dir: bool,
+) -> Result<bool> {
+    let Some(ignore_rules) = ignore_rules else {
+        return Ok(false);
+    };
+    if path.is_empty() || !ignore_rules.is_ignored(path, is_dir)? {
+        return Ok(false);
+    }
+    let dir_prefix = format!("{}/", path);
+    Ok(!index
+        .entries
+        .iter()
+        .any(|entry| entry.path == path || entry.path.starts_with(&dir_prefix)))
+}
+
+/// Adds a file or a directory, given relative to the root of the work tree
+fn add_path(
+    repository: &Repository,
+    index: &mut GitIndex,
+    ignore_rules: &mut Option<IgnoreRules>,
+    path: &str,
+) -> Result<()> {
+    let full_path = repository.work_tree()?.join(path);
+    let metadata = std::fs::symlink_metadata(&full_path)?;
+
+    if metadata.is_dir() {
+        let mut children = read_dir(&full_path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        children.sort();
+        for child in children {
+            if child == ".git" {
+                continue;
+            }
+            let is_dir = std::fs::symlink_metadata(full_path.join(&child))?.is_dir();
+            let child = String::from_utf8_lossy(child.as_bytes()).to_string();
+            let child_path = match path.is_empty() {
+                true => child,
+                false => format!("{}/{}", path, child),
+            };
+            if !is_ignored(ignore_rules, index, &child_path, is_dir)? {
+                add_path(repository, index, ignore_rules, &child_path)?;
+            }
+        }
+        return Ok(());
+    }
+
+    // no need to hash the file again if its stat data did not change
+    if let Some(entry) = index.get(path) {
+        if entry.matches_metadata(&metadata) {
+            return Ok(());
+        }
+    }
+
+    let git_object = GitObject::from_work_tree_file(&full_path)?;
+    git_object.write(repository.object_database())?;
+
+    index.add_entry(IndexEntry::from_metadata(path, &git_object.hash, &metadata));
+    Ok(())
+}
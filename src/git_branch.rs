@@ -0,0 +1,432 @@
+use crate::git_config::ConfigFile;
+use crate::git_ident::committer_signature;
+use crate::git_refs::{GitRefDatabase, RefValue, NULL_HASH};
+use crate::git_remote::Refspec;
+use crate::git_repository::Repository;
+use crate::git_rev_walk::{merge_bases, rev_walk, RevWalkOptions};
+use crate::git_revision::{resolve_ref_name, resolve_revision, upstream_ref};
+use crate::{Error, Result};
+
+/// Which branches are listed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BranchKind {
+    /// refs/heads/*
+    #[default]
+    Local,
+    /// refs/remotes/* (-r)
+    Remote,
+    /// local then remote-tracking branches (-a)
+    All,
+}
+
+/// A listed branch
+#[derive(Debug)]
+pub struct Branch {
+    /// full name, for ex refs/heads/main or refs/remotes/origin/main. None for
+    /// a detached HEAD
+    pub ref_name: Option<String>,
+    /// commit the branch points to
+    pub hash: String,
+    /// branch a symbolic ref points to, for ex refs/remotes/origin/main for
+    /// refs/remotes/origin/HEAD
+    pub target: Option<String>,
+    /// whether HEAD points to the branch
+    pub is_head: bool,
+}
+
+/// How a branch compares to the branch it follows
+#[derive(Debug)]
+pub struct Tracking {
+    /// full name of the upstream branch, for ex refs/remotes/origin/main
+    pub upstream: String,
+    /// number of commits of the branch that are not in the upstream, and of
+    /// the upstream that are not in the branch. None if the upstream branch
+    /// does not exist anymore
+    pub ahead_behind: Option<(usize, usize)>,
+}
+
+/// Options of branch creation
+#[derive(Debug, Default, Clone)]
+pub struct BranchCreateOptions {
+    /// resets the branch to the start point if it already exists
+    pub force: bool,
+    /// sets the start point as upstream (Some(true)) or not (Some(false)).
+    /// By default, it depends on branch.autoSetupMerge: only remote-tracking
+    /// start points are followed, or local branches too if it is `always`
+    pub track: Option<bool>,
+}
+
+/// Lists the branches, sorted by name. A detached HEAD is listed first among
+/// the local branches. Symbolic refs whose target does not exist are skipped
+pub fn git_branch_list(repository: &Repository, kind: BranchKind) -> Result<Vec<Branch>> {
+    let refs = repository.refs();
+    let head_ref = refs.head_ref()?;
+    let mut branches = Vec::new();
+
+    if kind != BranchKind::Remote && head_ref.is_none() {
+        if let Some(hash) = refs.resolve("HEAD")? {
+            branches.push(Branch {
+                ref_name: None,
+                hash,
+                target: None,
+                is_head: true,
+            });
+        }
+    }
+    let prefixes = match kind {
+        BranchKind::Local => vec!["refs/heads/"],
+        BranchKind::Remote => vec!["refs/remotes/"],
+        BranchKind::All => vec!["refs/heads/", "refs/remotes/"],
+    };
+    for prefix in prefixes {
+        for (ref_name, hash) in refs.list(prefix)? {
+            branches.push(Branch {
+                target: refs.read_symbolic(&ref_name)?,
+                is_head: head_ref.as_ref() == Some(&ref_name),
+                ref_name: Some(ref_name),
+                hash,
+            });
+        }
+    }
+    Ok(branches)
+}
+
+/// Describes a detached HEAD from the last checkout in the reflog of HEAD:
+/// `HEAD detached at <name>` if HEAD did not move since, `HEAD detached from
+/// <name>` otherwise. The name is the ref that was checked out if it still
+/// points to the same commit, the abbreviated hash of the commit otherwise
+pub fn detached_head_description(repository: &Repository) -> Result<String> {
+    let refs = repository.refs();
+    let object_database = repository.object_database();
+    let last_checkout = refs
+        .read_reflog("HEAD")?
+        .into_iter()
+        .rev()
+        .find_map(|entry| {
+            let moves = entry.message.strip_prefix("checkout: moving from ")?;
+            let (_, to) = moves.rsplit_once(" to ")?;
+            Some((to.to_string(), entry.new_hash))
+        });
+    let Some((to, checked_out_hash)) = last_checkout else {
+        return Ok("no branch".to_string());
+    };
+
+    let ref_hash = match refs.expand_name(&to)? {
+        Some(ref_name) => refs
+            .resolve(&ref_name)?
+            .map(|hash| object_database.peel(&hash, "commit"))
+            .transpose()
+            .ok()
+            .flatten()
+            .map(|hash| (ref_name, hash)),
+        None => None,
+    };
+    let name = match ref_hash {
+        Some((ref_name, hash)) if hash == checked_out_hash => {
+            let name = ref_name.as_str();
+            name.strip_prefix("refs/tags/")
+                .or_else(|| name.strip_prefix("refs/remotes/"))
+                .unwrap_or(name)
+                .to_string()
+        }
+        _ => object_database.abbreviate(&checked_out_hash, 7)?,
+    };
+    let is_at = refs.resolve("HEAD")?.as_ref() == Some(&checked_out_hash);
+    let preposition = if is_at { "at" } else { "from" };
+    Ok(format!("HEAD detached {} {}", preposition, name))
+}
+
+/// Upstream of the branch (a full name, for ex refs/heads/main) and how far
+/// apart they are. None if the branch has no upstream configured
+pub fn branch_tracking(repository: &Repository, ref_name: &str) -> Result<Option<Tracking>> {
+    let Some(branch) = ref_name.strip_prefix("refs/heads/") else {
+        return Ok(None);
+    };
+    let upstream = match upstream_ref(repository, branch) {
+        Ok(upstream) => upstream,
+        Err(Error::NoUpstream(_)) => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    let refs = repository.refs();
+    let (Some(hash), Some(upstream_hash)) = (refs.resolve(ref_name)?, refs.resolve(&upstream)?)
+    else {
+        return Ok(Some(Tracking {
+            upstream,
+            ahead_behind: None,
+        }));
+    };
+    let count = |range: String| -> Result<usize> {
+        Ok(rev_walk(repository, &[range], &RevWalkOptions::default())?.len())
+    };
+    let ahead = count(format!("{}..{}", upstream_hash, hash))?;
+    let behind = count(format!("{}..{}", hash, upstream_hash))?;
+    Ok(Some(Tracking {
+        upstream,
+        ahead_behind: Some((ahead, behind)),
+    }))
+}
+
+/// Creates the branch refs/heads/<name> at the commit of the start point, or
+/// resets it there with force (except the branch checked out).
+/// Depending on the options, the start point becomes the upstream of the
+/// branch. Returns the upstream that was set, if any
+pub fn git_branch_create(
+    repository: &Repository,
+    name: &str,
+    start: &str,
+    options: &BranchCreateOptions,
+) -> Result<Option<String>> {
+    let refs = repository.refs();
+    let ref_name = branch_ref_name(name)?;
+    let old_hash = refs.resolve(&ref_name)?;
+    if old_hash.is_some() {
+        if !options.force {
+            return Err(Error::BranchAlreadyExists(name.to_string()));
+        }
+        if refs.head_ref()?.as_ref() == Some(&ref_name) {
+            return Err(Error::BranchCheckedOut(name.to_string()));
+        }
+    }
+    let hash = repository
+        .object_database()
+        .peel(&resolve_revision(repository, start)?, "commit")
+        .map_err(|_| Error::InvalidRevision(start.to_string()))?;
+
+    let upstream = match options.track {
+        Some(false) => None,
+        Some(true) => Some(
+            resolve_ref_name(repository, start)?
+                .filter(|start| start.starts_with("refs/"))
+                .ok_or_else(|| Error::NotABranch(start.to_string()))?,
+        ),
+        None => {
+            let config = repository.config()?;
+            let tracked_prefixes = match config.get("branch.autoSetupMerge") {
+                Some("false") => vec![],
+                Some("always") => vec!["refs/remotes/", "refs/heads/"],
+                _ => vec!["refs/remotes/"],
+            };
+            resolve_ref_name(repository, start)?.filter(|start| {
+                tracked_prefixes
+                    .iter()
+                    .any(|prefix| start.starts_with(prefix))
+            })
+        }
+    };
+
+    let expected_old_hash = old_hash.as_deref().unwrap_or(NULL_HASH);
+    refs.update(&ref_name, &hash, Some(expected_old_hash))?;
+    let identity = committer_signature(&repository.config()?)?.to_string();
+    let message = match old_hash {
+        Some(_) => format!("branch: Reset to {}", start),
+        None => format!("branch: Created from {}", start),
+    };
+    refs.append_reflog(&ref_name, old_hash.as_deref(), &hash, &identity, &message)?;
+
+    if let Some(upstream) = &upstream {
+        set_upstream(repository, name, upstream)?;
+    }
+    Ok(upstream)
+}
+
+/// Deletes the branch (a remote-tracking branch, for ex origin/main, when
+/// remote is set) and its configuration. Without force, a local branch must be
+/// merged in its upstream, or in HEAD if it has none. The branch checked out
+/// can not be deleted. Returns what the branch pointed to
+pub fn git_branch_delete(
+    repository: &Repository,
+    name: &str,
+    remote: bool,
+    force: bool,
+) -> Result<RefValue> {
+    let refs = repository.refs();
+    let not_found = || Error::BranchNotFound(name.to_string());
+    // as with git, a branch with an invalid name is not found
+    let ref_name = match remote {
+        true => format!("refs/remotes/{}", name),
+        false => branch_ref_name(name).map_err(|_| not_found())?,
+    };
+    if !GitRefDatabase::is_valid_name(&ref_name) {
+        return Err(not_found());
+    }
+    let value = refs.read(&ref_name)?.ok_or_else(not_found)?;
+    if !remote && refs.head_ref()?.as_ref() == Some(&ref_name) {
+        return Err(Error::BranchCheckedOut(name.to_string()));
+    }
+
+    // remote-tracking branches are deleted without checking them
+    if let (RefValue::Hash(hash), false, false) = (&value, force, remote) {
+        let upstream = match upstream_ref(repository, name) {
+            Ok(upstream) => refs.resolve(&upstream)?,
+            Err(Error::NoUpstream(_)) => None,
+            Err(error) => return Err(error),
+        };
+        let reference = match upstream {
+            Some(upstream) => Some(upstream),
+            None => refs.resolve("HEAD")?,
+        };
+        let is_merged = match reference {
+            Some(reference) => {
+                let reference = repository.object_database().peel(&reference, "commit")?;
+                merge_bases(repository, hash, &[reference])?.contains(hash)
+            }
+            None => false,
+        };
+        if !is_merged {
+            return Err(Error::BranchNotMerged(name.to_string()));
+        }
+    }
+
+    refs.delete(&ref_name, None)?;
+    if !remote {
+        let mut config = ConfigFile::open(repository.git_dir().join("config"))?;
+        config.rename_section(&format!("branch.{}", name), None)?;
+        config.write()?;
+    }
+    Ok(value)
+}
+
+/// Renames the branch (the current one if no name is given) with its reflog
+/// and its configuration. HEAD follows if it pointed to the branch. An
+/// existing branch is only replaced with force, and never if it is checked out
+pub fn git_branch_rename(
+    repository: &Repository,
+    name: Option<&str>,
+    new_name: &str,
+    force: bool,
+) -> Result<()> {
+    let refs = repository.refs();
+    let head_ref = refs.head_ref()?;
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => current_branch(repository)?,
+    };
+    let ref_name = branch_ref_name(&name)?;
+    let new_ref_name = branch_ref_name(new_name)?;
+    let is_head = head_ref.as_ref() == Some(&ref_name);
+    if refs.resolve(&new_ref_name)?.is_some() && new_ref_name != ref_name {
+        if !force {
+            return Err(Error::BranchAlreadyExists(new_name.to_string()));
+        }
+        if head_ref.as_ref() == Some(&new_ref_name) {
+            return Err(Error::BranchCheckedOut(new_name.to_string()));
+        }
+    }
+
+    match refs.resolve(&ref_name)? {
+        Some(_) => {
+            let identity = committer_signature(&repository.config()?)?.to_string();
+            let message = format!("Branch: renamed {} to {}", ref_name, new_ref_name);
+            let hash = refs.rename(&ref_name, &new_ref_name, &identity, &message)?;
+            if is_head {
+                refs.write_symbolic("HEAD", &new_ref_name)?;
+                // as with git, HEAD logs the deletion of the old branch and
+                // the creation of the new one
+                refs.append_reflog("HEAD", Some(&hash), NULL_HASH, &identity, &message)?;
+                refs.append_reflog("HEAD", None, &hash, &identity, &message)?;
+            }
+        }
+        // a branch without commits only exists in HEAD
+        None if is_head => refs.write_symbolic("HEAD", &new_ref_name)?,
+        None => return Err(Error::BranchNotFound(name)),
+    }
+
+    let mut config = ConfigFile::open(repository.git_dir().join("config"))?;
+    config.rename_section(
+        &format!("branch.{}", name),
+        Some(&format!("branch.{}", new_name)),
+    )?;
+    config.write()
+}
+
+/// Makes the branch (the current one if no name is given) follow the upstream,
+/// a local or a remote-tracking branch. Returns the full name of the upstream
+pub fn git_branch_set_upstream(
+    repository: &Repository,
+    name: Option<&str>,
+    upstream: &str,
+) -> Result<String> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => current_branch(repository)?,
+    };
+    if repository
+        .refs()
+        .resolve(&branch_ref_name(&name)?)?
+        .is_none()
+    {
+        return Err(Error::BranchNotFound(name));
+    }
+    let upstream_ref = resolve_ref_name(repository, upstream)?
+        .ok_or_else(|| Error::UpstreamNotFound(upstream.to_string()))?;
+    set_upstream(repository, &name, &upstream_ref)?;
+    Ok(upstream_ref)
+}
+
+/// Removes the upstream of the branch (the current one if no name is given)
+pub fn git_branch_unset_upstream(repository: &Repository, name: Option<&str>) -> Result<()> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => current_branch(repository)?,
+    };
+    let config = repository.config()?;
+    let (remote, merge) = (
+        format!("branch.{}.remote", name),
+        format!("branch.{}.merge", name),
+    );
+    if config.get(&remote).is_none() || config.get(&merge).is_none() {
+        return Err(Error::NoUpstream(name));
+    }
+    let mut config = ConfigFile::open(repository.git_dir().join("config"))?;
+    config.unset(&merge)?;
+    config.unset(&remote)?;
+    config.write()
+}
+
+/// Writes branch.<name>.remote and branch.<name>.merge for the upstream.
+/// A local branch is followed with the remote `.`, a remote-tracking branch
+/// with the remote whose fetch refspec maps a branch of the remote to it
+fn set_upstream(repository: &Repository, name: &str, upstream: &str) -> Result<()> {
+    let (remote, merge) = match upstream.strip_prefix("refs/remotes/") {
+        None if upstream.starts_with("refs/heads/") => (".".to_string(), upstream.to_string()),
+        None => return Err(Error::NotABranch(upstream.to_string())),
+        Some(_) => {
+            let config = repository.config()?;
+            config
+                .entries
+                .iter()
+                .filter(|entry| entry.section == "remote" && entry.key == "fetch")
+                .find_map(|entry| {
+                    let remote = entry.subsection.as_deref()?;
+                    let refspec = Refspec::parse(entry.value.as_deref()?).ok()?;
+                    let merge = refspec.map_destination(upstream)?;
+                    Some((remote.to_string(), merge))
+                })
+                .ok_or_else(|| Error::NotABranch(upstream.to_string()))?
+        }
+    };
+    let mut config = ConfigFile::open(repository.git_dir().join("config"))?;
+    config.set(&format!("branch.{}.remote", name), &remote)?;
+    config.set(&format!("branch.{}.merge", name), &merge)?;
+    config.write()
+}
+
+/// Name of the branch checked out, for ex main. Fails if HEAD is detached
+fn current_branch(repository: &Repository) -> Result<String> {
+    repository
+        .refs()
+        .head_ref()?
+        .as_deref()
+        .and_then(|head_ref| head_ref.strip_prefix("refs/heads/"))
+        .map(str::to_string)
+        .ok_or_else(|| Error::NotABranch("HEAD".to_string()))
+}
+
+/// refs/heads/<name>, if name is a valid branch name
+fn branch_ref_name(name: &str) -> Result<String> {
+    let ref_name = format!("refs/heads/{}", name);
+    if name == "HEAD" || name.starts_with('-') || !GitRefDatabase::is_valid_name(&ref_name) {
+        return Err(Error::InvalidRefName(name.to_string()));
+    }
+    Ok(ref_name)
+}
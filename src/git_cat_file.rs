@@ -1,69 +1,69 @@
 use crate::git_object::{GitObject, GitObjectContent};
-use crate::{Error, Result};
+use crate::git_repository::Repository;
+use crate::Result;
 
-/// Output the contents or other properties such as size or type
-/// for the git objects in the current directory
+/// What cat-file should output about the object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatFileMode {
+    /// Only checks that the object exists and is valid
+    Exists,
+    /// Content of the object, formatted based on its type
+    PrettyPrint,
+    /// Type of the object
+    Type,
+    /// Size of the object
+    Size,
+}
+
+/// Result of cat-file, depending on the requested mode
+#[derive(Debug)]
+pub enum CatFileOutput {
+    Exists,
+    /// raw bytes as blobs may not be valid utf8
+    Content(Vec<u8>),
+    Type(String),
+    Size(usize),
+}
+
+/// Retrieves the contents or other properties such as size or type
+/// for the git objects of the repository
 pub fn git_cat_file(
-    pretty_print: bool,
-    exit_with_zero_status_if_exists: bool,
-    type_obj: bool,
-    size: bool,
+    repository: &Repository,
+    mode: CatFileMode,
     hash: &str,
-) -> Result<()> {
-    let git_obj = GitObject::from_hash(hash, ".")?;
+) -> Result<CatFileOutput> {
+    let git_obj = repository.find_object(hash)?;
 
-    // TODO: would be better with an enum
-    if exit_with_zero_status_if_exists {
-        println!("Valid object");
-    } else if pretty_print {
-        match git_obj.content {
-            GitObjectContent::Commit { content } => {
-                println!("tree {}", content.tree_sha);
-                for parent_sha in content.parents_sha {
-                    println!("parent {}", parent_sha);
-                }
-                println!(
-                    "author {} <{}> {} {}",
-                    content.author_name,
-                    content.author_email,
-                    content.timestamp,
-                    content.author_timezone
-                );
-                println!(
-                    "committer {} <{}> {} {}",
-                    content.author_name,
-                    content.author_email,
-                    content.timestamp,
-                    content.author_timezone
-                );
-                println!();
-                print!("{}", content.commit_msg);
-            }
+    Ok(match mode {
+        CatFileMode::Exists => CatFileOutput::Exists,
+        CatFileMode::PrettyPrint => CatFileOutput::Content(pretty_content(git_obj)),
+        CatFileMode::Type => CatFileOutput::Type(git_obj.content_type()),
+        CatFileMode::Size => CatFileOutput::Size(git_obj.size),
+    })
+}
 
-            GitObjectContent::Tree { content } => {
-                for tree_child in content {
-                    match tree_child.git_object {
-                        Some(git_obj) => {
-                            println!(
-                                "{:0>6} {} {}\t{}",
-                                tree_child.mode,
-                                git_obj.content_type(),
-                                git_obj.hash,
-                                tree_child.name
-                            );
-                        }
-                        None => Err(Error::TreeChildNotLoaded)?,
-                    }
-                }
+/// Content of the object in the format of cat-file -p
+pub fn pretty_content(git_obj: GitObject) -> Vec<u8> {
+    match git_obj.content {
+        // commits are printed as they are stored
+        GitObjectContent::Commit { content } => content.to_bytes(),
+
+        GitObjectContent::Tree { content } => {
+            // the type is deduced from the mode so children are not loaded
+            let mut output = String::new();
+            for tree_child in content {
+                output.push_str(&format!(
+                    "{:0>6} {} {}\t{}\n",
+                    tree_child.mode,
+                    tree_child.content_type(),
+                    tree_child.hash,
+                    tree_child.name
+                ));
             }
-            GitObjectContent::Blob { content } => print!("{}", content),
-            GitObjectContent::Tag { content } => print!("{}", content),
+            output.into_bytes()
         }
-    } else if size {
-        println!("{}", git_obj.size)
-    } else if type_obj {
-        println!("{}", git_obj.content_type())
+        // blobs may contain non utf8 bytes so we keep them as is
+        GitObjectContent::Blob { content } => content,
+        GitObjectContent::Tag { content } => content.to_bytes(),
     }
-
-    Ok(())
 }
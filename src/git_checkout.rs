@@ -0,0 +1,652 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+
+use crate::git_diff::matches_paths;
+use crate::git_ident::committer_signature;
+use crate::git_index::{GitIndex, IndexEntry};
+use crate::git_object::{GitObject, GitObjectContent};
+use crate::git_object_database::GitObjectDatabase;
+use crate::git_refs::{GitRefDatabase, NULL_HASH};
+use crate::git_repository::Repository;
+use crate::git_revision::{resolve_ref_name, resolve_revision};
+use crate::{Error, Result};
+
+/// Options of switch and checkout
+#[derive(Debug, Default, Clone)]
+pub struct SwitchOptions {
+    /// creates this branch at the target and switches to it (-c, -b)
+    pub new_branch: Option<String>,
+    /// points HEAD directly to the commit, even if the target is a branch
+    pub detach: bool,
+    /// discards the local changes instead of refusing to overwrite them
+    pub force: bool,
+}
+
+/// What a switch did, to describe it
+#[derive(Debug)]
+pub struct SwitchOutput {
+    /// commit HEAD pointed to before, None on a branch without commits
+    pub previous_hash: Option<String>,
+    /// branch checked out before (for ex refs/heads/main), None if HEAD was
+    /// detached
+    pub previous_branch: Option<String>,
+    /// commit HEAD now points to, None when a new branch is created while on a
+    /// branch without commits
+    pub hash: Option<String>,
+    /// branch now checked out, None if HEAD is detached
+    pub branch: Option<String>,
+    /// whether the branch was created
+    pub created: bool,
+}
+
+/// Full name of the branch a target of switch designates (`main`, `@{-1}` or
+/// `-` for the previous branch), None if it is not a local branch
+pub fn branch_ref(repository: &Repository, target: &str) -> Result<Option<String>> {
+    let target = match target {
+        "-" => "@{-1}",
+        target => target,
+    };
+    let name = resolve_ref_name(repository, target)?;
+    Ok(name.filter(|name| name.starts_with("refs/heads/")))
+}
+
+/// Checks out the target commit: the index and the work tree are updated from
+/// the tree of HEAD to the tree of the target, then HEAD points to the branch
+/// (or to the commit if detached) and the move is recorded in the reflog.
+/// Files that do not change between both trees keep their local changes. The
+/// switch is refused before anything is written if a file that changes has
+/// local changes, or if an untracked file is in the way, unless forced
+pub fn git_switch(
+    repository: &Repository,
+    target: &str,
+    options: &SwitchOptions,
+) -> Result<SwitchOutput> {
+    let refs = repository.refs();
+    let object_database = repository.object_database();
+    let target = match target {
+        "-" => "@{-1}",
+        target => target,
+    };
+    let previous_hash = refs.resolve("HEAD")?;
+    let previous_branch = refs.head_ref()?;
+
+    // without commits, the new branch is only where the first one will go
+    if let (Some(name), None, "HEAD") = (&options.new_branch, &previous_hash, target) {
+        let branch = format!("refs/heads/{}", name);
+        if !GitRefDatabase::is_valid_name(&branch) {
+            return Err(Error::InvalidRefName(name.clone()));
+        }
+        refs.write_symbolic("HEAD", &branch)?;
+        return Ok(SwitchOutput {
+            previous_hash,
+            previous_branch,
+            hash: None,
+            branch: Some(branch),
+            created: true,
+        });
+    }
+
+    let hash = object_database
+        .peel(&resolve_revision(repository, target)?, "commit")
+        .map_err(|_| Error::InvalidRevision(target.to_string()))?;
+
+    let branch = match &options.new_branch {
+        Some(name) => {
+            let branch = format!("refs/heads/{}", name);
+            if !GitRefDatabase::is_valid_name(&branch) {
+                return Err(Error::InvalidRefName(name.clone()));
+            }
+            if refs.resolve(&branch)?.is_some() {
+                return Err(Error::BranchAlreadyExists(name.clone()));
+            }
+            Some(branch)
+        }
+        None if options.detach => None,
+        None => Some(
+            branch_ref(repository, target)?.ok_or_else(|| Error::NotABranch(target.to_string()))?,
+        ),
+    };
+
+    let old_tree = previous_hash
+        .as_ref()
+        .map(|hash| object_database.peel(hash, "tree"))
+        .transpose()?;
+    let new_tree = object_database.peel(&hash, "tree")?;
+
+    let mut index = GitIndex::read(repository)?;
+    switch_trees(
+        repository,
+        &mut index,
+        old_tree.as_deref(),
+        &new_tree,
+        options.force,
+    )?;
+    index.write(repository)?;
+
+    let identity = committer_signature(&repository.config()?)?.to_string();
+    if options.new_branch.is_some() {
+        let branch = branch.as_deref().ok_or(Error::Unreachable)?;
+        refs.update(branch, &hash, Some(NULL_HASH))?;
+        let message = format!("branch: Created from {}", target);
+        refs.append_reflog(branch, None, &hash, &identity, &message)?;
+    }
+    match &branch {
+        Some(branch) => refs.write_symbolic("HEAD", branch)?,
+        None => {
+            refs.update("HEAD", &hash, None)?;
+        }
+    }
+
+    let from = match (&previous_branch, &previous_hash) {
+        (Some(previous_branch), _) => GitRefDatabase::shorten_name(previous_branch),
+        (None, Some(previous_hash)) => previous_hash.as_str(),
+        (None, None) => "HEAD",
+    };
+    let to = match &branch {
+        Some(branch) => GitRefDatabase::shorten_name(branch),
+        None => target,
+    };
+    // a detached HEAD that does not move is not logged
+    let is_unchanged =
+        branch.is_none() && previous_branch.is_none() && previous_hash.as_ref() == Some(&hash);
+    if !is_unchanged {
+        let message = format!("checkout: moving from {} to {}", from, to);
+        refs.append_reflog("HEAD", previous_hash.as_deref(), &hash, &identity, &message)?;
+    }
+
+    Ok(SwitchOutput {
+        previous_hash,
+        previous_branch,
+        hash: Some(hash),
+        branch,
+        created: options.new_branch.is_some(),
+    })
+}
+
+/// Restores the files under the paths (relative to the root of the work tree)
+/// from the index, or from the tree of the source revision, in which case the
+/// index is updated as well. Local changes of these files are lost.
+/// Files of the index that are not in the source tree are kept
+pub fn git_checkout_paths(
+    repository: &Repository,
+    source: Option<&str>,
+    paths: &[String],
+) -> Result<()> {
+    let object_database = repository.object_database();
+    let work_tree = repository.work_tree()?;
+    let mut index = GitIndex::read(repository)?;
+
+    let files = match source {
+        Some(source) => {
+            let tree = object_database
+                .peel(&resolve_revision(repository, source)?, "tree")
+                .map_err(|_| Error::InvalidRevision(source.to_string()))?;
+            object_database
+                .read_tree_recursively(&tree)?
+                .into_iter()
+                .map(|(path, child)| (path, (child.mode, child.hash)))
+                .collect::<BTreeMap<_, _>>()
+        }
+        None => {
+            if let Some(entry) = index
+                .entries
+                .iter()
+                .find(|entry| entry.stage() != 0 && matches_paths(&entry.path, paths))
+            {
+                return Err(Error::UnmergedPath(entry.path.clone()));
+            }
+            index
+                .entries
+                .iter()
+                .map(|entry| (entry.path.clone(), (entry.mode, entry.hash.clone())))
+                .collect()
+        }
+    };
+
+    for path in paths {
+        if !files
+            .keys()
+            .any(|file| matches_paths(file, std::slice::from_ref(path)))
+        {
+            return Err(Error::PathspecNotFound(path.clone()));
+        }
+    }
+
+    let files = files
+        .into_iter()
+        .filter(|(path, _)| matches_paths(path, paths))
+        .collect::<Vec<_>>();
+    for (path, _) in &files {
+        verify_path(path)?;
+    }
+
+    let index_mtime = index_mtime(repository);
+    for (path, (mode, hash)) in files {
+        let is_up_to_date = index.get(&path).is_some_and(|entry| {
+            entry.mode == mode
+                && entry.hash == hash
+                && work_tree_file(work_tree, entry, index_mtime)
+                    .is_ok_and(|file| file == Some((mode, hash.clone())))
+        });
+        if is_up_to_date {
+            continue;
+        }
+        let content = blob_content(object_database, mode, &hash)?;
+        write_work_tree_file(work_tree, &path, mode, &content)?;
+        index.add_entry(new_index_entry(work_tree, &path, mode, &hash)?);
+    }
+    index.write(repository)
+}
+
+/// What happens to a file of the work tree during a switch
+enum FileUpdate {
+    Remove,
+    Write { mode: u32, hash: String },
+}
+
+/// Updates the index and the work tree, going from the old tree to the new
+/// one (a two-way merge):
+/// - files that are the same in both trees are left as they are, with their
+///   local changes
+/// - other files are replaced by the version of the new tree, or removed, as
+///   long as they have no staged or unstaged change
+///
+/// Nothing is written if a file would lose its changes, unless forced: then
+/// the index and the work tree match the new tree
+pub fn switch_trees(
+    repository: &Repository,
+    index: &mut GitIndex,
+    old_tree: Option<&str>,
+    new_tree: &str,
+    force: bool,
+) -> Result<()> {
+    let object_database = repository.object_database();
+    let work_tree = repository.work_tree()?;
+    if !force && index.entries.iter().any(|entry| entry.stage() != 0) {
+        return Err(Error::UnmergedIndex);
+    }
+
+    let read_tree = |tree: Option<&str>| -> Result<BTreeMap<String, (u32, String)>> {
+        let Some(tree) = tree else {
+            return Ok(BTreeMap::new());
+        };
+        Ok(object_database
+            .read_tree_recursively(tree)?
+            .into_iter()
+            .map(|(path, child)| (path, (child.mode, child.hash)))
+            .collect())
+    };
+    let old_files = read_tree(old_tree)?;
+    let new_files = read_tree(Some(new_tree))?;
+    for path in new_files.keys() {
+        verify_path(path)?;
+    }
+
+    let index_files = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() == 0)
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect::<BTreeMap<_, _>>();
+    let unmerged_paths = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() != 0)
+        .map(|entry| entry.path.as_str())
+        .collect::<BTreeSet<_>>();
+    let mut paths = old_files.keys().cloned().collect::<BTreeSet<_>>();
+    paths.extend(new_files.keys().cloned());
+    paths.extend(index.entries.iter().map(|entry| entry.path.clone()));
+
+    let index_mtime = index_mtime(repository);
+    let mut updates = Vec::new();
+    let mut changed_files = Vec::new();
+    let mut untracked_files = Vec::new();
+    for path in paths {
+        let old = old_files.get(&path);
+        let new = new_files.get(&path);
+        let index_entry = index_files.get(path.as_str());
+        let indexed = index_entry.map(|entry| (entry.mode, entry.hash.clone()));
+        // only hashed when needed
+        let work_tree_file = || match index_entry {
+            Some(entry) => work_tree_file(work_tree, entry, index_mtime),
+            None => Ok(None),
+        };
+        let update = match new {
+            Some((mode, hash)) => FileUpdate::Write {
+                mode: *mode,
+                hash: hash.clone(),
+            },
+            None => FileUpdate::Remove,
+        };
+
+        if force {
+            let is_up_to_date = match new {
+                Some(new) => {
+                    indexed.as_ref() == Some(new) && work_tree_file()?.as_ref() == Some(new)
+                }
+                None => {
+                    index_entry.is_none()
+                        && old.is_none()
+                        && !unmerged_paths.contains(path.as_str())
+                }
+            };
+            if !is_up_to_date {
+                updates.push((path, update));
+            }
+            continue;
+        }
+
+        if old == new {
+            continue;
+        }
+        // the change is already staged: the index and the work tree are kept
+        if indexed.as_ref() == new {
+            continue;
+        }
+        if indexed.as_ref() != old {
+            changed_files.push(path);
+            continue;
+        }
+        if index_entry.is_some() {
+            // a deleted file is not a local change that would be lost
+            let file = work_tree_file()?;
+            if file.is_some() && file != indexed {
+                changed_files.push(path);
+                continue;
+            }
+        } else if new.is_some() {
+            if let Some(untracked_file) = file_in_the_way(work_tree, &path, &index_files)? {
+                untracked_files.push(untracked_file);
+                continue;
+            }
+        }
+        updates.push((path, update));
+    }
+    if !changed_files.is_empty() {
+        return Err(Error::LocalChangesWouldBeOverwritten(changed_files));
+    }
+    if !untracked_files.is_empty() {
+        return Err(Error::UntrackedFilesWouldBeOverwritten(untracked_files));
+    }
+
+    // files are removed first, as a file may replace a directory
+    for (path, update) in &updates {
+        if let FileUpdate::Remove = update {
+            remove_work_tree_file(work_tree, path)?;
+            index.remove_entry(path);
+        }
+    }
+    for (path, update) in &updates {
+        if let FileUpdate::Write { mode, hash } = update {
+            let content = blob_content(object_database, *mode, hash)?;
+            write_work_tree_file(work_tree, path, *mode, &content)?;
+            index.add_entry(new_index_entry(work_tree, path, *mode, hash)?);
+        }
+    }
+    Ok(())
+}
+
+/// Paths among the given ones whose file in the work tree differs from the
+/// index. Deleted files are not counted, as no change would be lost
+pub fn unstaged_changes(
+    repository: &Repository,
+    index: &GitIndex,
+    paths: &[String],
+) -> Result<Vec<String>> {
+    let work_tree = repository.work_tree()?;
+    let index_mtime = index_mtime(repository);
+    let mut changed_files = Vec::new();
+    for path in paths {
+        let Some(entry) = index.get(path) else {
+            continue;
+        };
+        let file = work_tree_file(work_tree, entry, index_mtime)?;
+        if file.is_some_and(|file| file != (entry.mode, entry.hash.clone())) {
+            changed_files.push(path.clone());
+        }
+    }
+    Ok(changed_files)
+}
+
+/// Finds the untracked file that is where a file is about to be written: the
+/// file itself, a file where one of its parent directories should be, or a
+/// file in a directory at its place
+fn file_in_the_way(
+    work_tree: &Path,
+    path: &str,
+    index_files: &BTreeMap<&str, &IndexEntry>,
+) -> Result<Option<String>> {
+    // tracked files in the way are removed by the switch
+    let is_untracked = |path: &str| !index_files.contains_key(path);
+    for (pos, _) in path.match_indices('/') {
+        let parent = &path[..pos];
+        match std::fs::symlink_metadata(work_tree.join(parent)) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) if is_untracked(parent) => return Ok(Some(parent.to_string())),
+            _ => return Ok(None),
+        }
+    }
+
+    let Ok(metadata) = std::fs::symlink_metadata(work_tree.join(path)) else {
+        return Ok(None);
+    };
+    if !metadata.is_dir() {
+        return Ok(Some(path.to_string()));
+    }
+    let mut dirs = vec![path.to_string()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(work_tree.join(&dir))? {
+            let entry = entry?;
+            let child = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                dirs.push(child);
+            } else if is_untracked(&child) {
+                return Ok(Some(child));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// mtime of the index file. Files modified in the same second as the index was
+/// written may change again without their stat data changing
+fn index_mtime(repository: &Repository) -> Option<(u32, u32)> {
+    std::fs::metadata(repository.git_dir().join("index"))
+        .map(|metadata| (metadata.mtime() as u32, metadata.mtime_nsec() as u32))
+        .ok()
+}
+
+/// Mode and hash of the file of the work tree of an index entry, None if it
+/// does not exist. The file is only hashed if its stat data changed
+fn work_tree_file(
+    work_tree: &Path,
+    entry: &IndexEntry,
+    index_mtime: Option<(u32, u32)>,
+) -> Result<Option<(u32, String)>> {
+    let file_path = work_tree.join(&entry.path);
+    let Ok(metadata) = std::fs::symlink_metadata(&file_path) else {
+        return Ok(None);
+    };
+    // submodules are not inspected
+    if entry.mode == 160000 {
+        return Ok(Some((entry.mode, entry.hash.clone())));
+    }
+    if metadata.is_dir() {
+        return Ok(Some((40000, String::new())));
+    }
+    let is_racy =
+        index_mtime.is_some_and(|mtime| (entry.mtime_seconds, entry.mtime_nanoseconds) >= mtime);
+    if entry.matches_metadata(&metadata) && !is_racy {
+        return Ok(Some((entry.mode, entry.hash.clone())));
+    }
+    let git_object = GitObject::from_work_tree_file(&file_path)?;
+    Ok(Some((
+        IndexEntry::mode_from_metadata(&metadata),
+        git_object.hash,
+    )))
+}
+
+/// Content of a blob, empty for submodules
+fn blob_content(object_database: &GitObjectDatabase, mode: u32, hash: &str) -> Result<Vec<u8>> {
+    if mode == 160000 {
+        return Ok(Vec::new());
+    }
+    match object_database.read_object(hash)?.content {
+        GitObjectContent::Blob { content } => Ok(content),
+        _ => Err(Error::InvalidGitObject),
+    }
+}
+
+/// Index entry of a file that was just written in the work tree
+fn new_index_entry(work_tree: &Path, path: &str, mode: u32, hash: &str) -> Result<IndexEntry> {
+    let metadata = std::fs::symlink_metadata(work_tree.join(path))?;
+    let mut entry = IndexEntry::from_metadata(path, hash, &metadata);
+    entry.mode = mode;
+    Ok(entry)
+}
+
+/// Checks that a path of a tree can be written in the work tree: none of its
+/// components may be empty, `.`, `..` or `.git` (in any case), as the file
+/// would land outside the work tree or in the repository
+pub fn verify_path(path: &str) -> Result<()> {
+    let is_valid = path.split('/').all(|component| {
+        !matches!(component, "" | "." | "..")
+            && !component.eq_ignore_ascii_case(".git")
+            && !component.contains('\0')
+    });
+    if !is_valid {
+        return Err(Error::InvalidPath(path.to_string()));
+    }
+    Ok(())
+}
+
+/// Writes a file of the work tree (path relative to its root) with the content
+/// of a blob: a regular file, an executable, or a symbolic link whose target is
+/// the content. Submodules get an empty directory.
+/// Missing directories are created and whatever was at the path is replaced
+pub fn write_work_tree_file(work_tree: &Path, path: &str, mode: u32, content: &[u8]) -> Result<()> {
+    let file_path = work_tree.join(path);
+    for (pos, _) in path.match_indices('/') {
+        let parent = work_tree.join(&path[..pos]);
+        match std::fs::symlink_metadata(&parent) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => std::fs::remove_file(&parent)?,
+            Err(_) => break,
+        }
+    }
+    if let Some(parent) = file_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::symlink_metadata(&file_path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(&file_path)?,
+        Ok(_) => std::fs::remove_file(&file_path)?,
+        Err(_) => {}
+    }
+    match mode {
+        120000 => {
+            let target = std::ffi::OsStr::from_bytes(content);
+            std::os::unix::fs::symlink(target, &file_path)?;
+        }
+        160000 => std::fs::create_dir(&file_path)?,
+        100755 => {
+            std::fs::write(&file_path, content)?;
+            let permissions = std::fs::Permissions::from_mode(0o755);
+            std::fs::set_permissions(&file_path, permissions)?;
+        }
+        _ => std::fs::write(&file_path, content)?,
+    }
+    Ok(())
+}
+
+/// Removes a file of the work tree (path relative to its root), and the
+/// directories that become empty
+pub fn remove_work_tree_file(work_tree: &Path, path: &str) -> Result<()> {
+    let file_path = work_tree.join(path);
+    match std::fs::symlink_metadata(&file_path) {
+        Ok(metadata) if metadata.is_dir() => {
+            // an empty directory left by a submodule
+            if std::fs::remove_dir(&file_path).is_err() {
+                return Ok(());
+            }
+        }
+        Ok(_) => std::fs::remove_file(&file_path)?,
+        Err(_) => return Ok(()),
+    }
+    let mut dir = path;
+    while let Some((parent, _)) = dir.rsplit_once('/') {
+        if std::fs::remove_dir(work_tree.join(parent)).is_err() {
+            break;
+        }
+        dir = parent;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_init::git_init;
+
+    fn write(repository: &Repository, git_object: GitObject) -> String {
+        git_object.write(repository.object_database()).unwrap();
+        git_object.hash
+    }
+
+    fn tree_with(repository: &Repository, mode: &str, name: &str, hash: &str) -> String {
+        let mut content = format!("{} {}\0", mode, name).into_bytes();
+        content.extend(hex::decode(hash).unwrap());
+        write(
+            repository,
+            GitObject::from_tree_content_bytes(content).unwrap(),
+        )
+    }
+
+    #[test]
+    fn invalid_paths() {
+        assert!(verify_path("dir/file").is_ok());
+        assert!(verify_path(".gitignore").is_ok());
+        assert!(verify_path("dir/..file").is_ok());
+        for path in [
+            "",
+            "/file",
+            "dir//file",
+            "./file",
+            "dir/../file",
+            ".git/config",
+        ] {
+            assert!(matches!(verify_path(path), Err(Error::InvalidPath(_))));
+        }
+        assert!(verify_path("dir/.GIT/hooks/pre-commit").is_err());
+    }
+
+    #[test]
+    fn trees_cannot_write_outside_the_work_tree() {
+        let directory = std::env::temp_dir().join(format!("git-checkout-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let work_tree = directory.join("repository");
+        git_init(&work_tree).unwrap();
+        let repository = Repository::open(&work_tree).unwrap();
+
+        let blob = write(
+            &repository,
+            GitObject::from_blob_content_bytes(b"content\n".to_vec()).unwrap(),
+        );
+        let evil_tree = tree_with(&repository, "100644", "evil", &blob);
+        let trees = [
+            tree_with(&repository, "40000", "..", &evil_tree),
+            tree_with(&repository, "40000", ".Git", &evil_tree),
+            tree_with(&repository, "100644", "../evil", &blob),
+        ];
+        for tree in trees {
+            let mut index = GitIndex::read(&repository).unwrap();
+            let result = switch_trees(&repository, &mut index, None, &tree, true);
+            assert!(matches!(result, Err(Error::InvalidPath(_))));
+        }
+        assert!(!directory.join("evil").exists());
+        assert!(!work_tree.join(".Git").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,45 +1,106 @@
 use std::path::Path;
 
 use crate::{
+    git_config::ConfigFile,
+    git_ident::committer_signature,
+    git_index::GitIndex,
     git_init::git_init,
-    git_object::{GitObject, GitObjectContent},
+    git_object::GitObjectContent,
     git_pack::{GitPack, UploadPackDiscovery},
+    git_repository::Repository,
 };
 use crate::{Error, Result};
 
+/// Clones the repository into the target directory and returns the new repository
 /// see https://www.git-scm.com/docs/http-protocol
-pub fn git_clone<P: AsRef<Path> + ?Sized>(repository_url: &str, directory: &P) -> Result<()> {
+pub fn git_clone<P: AsRef<Path> + ?Sized>(
+    repository_url: &str,
+    directory: &P,
+) -> Result<Repository> {
     // creates new repo
     git_init(directory)?;
+    let repository = Repository::open(directory)?;
+
+    // the remote is configured so that it can be fetched from later
+    let remote_name = "origin";
+    let fetch_refspec = format!("+refs/heads/*:refs/remotes/{}/*", remote_name);
+    let mut config = ConfigFile::open(repository.git_dir().join("config"))?;
+    config.set(&format!("remote.{}.url", remote_name), repository_url)?;
+    config.set(&format!("remote.{}.fetch", remote_name), &fetch_refspec)?;
 
     // retrieve upload_pack_discovery information and writes the refs and HEAD
     let upload_pack_discovery = UploadPackDiscovery::from_repository_url(repository_url)?;
-    upload_pack_discovery.write_head_and_refs(directory)?;
+    let branch = upload_pack_discovery.write_head_and_refs(&repository, remote_name)?;
+
+    // the checked out branch follows its remote-tracking branch
+    if let Some(branch) = &branch {
+        let name = branch.trim_start_matches("refs/heads/");
+        config.set(&format!("branch.{}.remote", name), remote_name)?;
+        config.set(&format!("branch.{}.merge", name), branch)?;
+    }
+    config.write()?;
 
     // retrieve the packfile content
-    let pack_content =
-        GitPack::create_minimal_pack_content_from_head_hash(&upload_pack_discovery.head_hash);
-    let git_pack = GitPack::from_repository_url_and_pack_content(repository_url, &pack_content)?;
+    // only ask for capabilities the server advertised
+    let capabilities = ["ofs-delta"]
+        .into_iter()
+        .filter(|capability| upload_pack_discovery.supports(capability))
+        .collect::<Vec<_>>();
+    // the objects of every branch and tag that was stored are needed
+    let mut wants = vec![upload_pack_discovery.head_hash.as_str()];
+    for (hash, name) in &upload_pack_discovery.refs {
+        let is_stored = name.starts_with("refs/heads/")
+            || (name.starts_with("refs/tags/") && !name.ends_with("^{}"));
+        if is_stored && !wants.contains(&hash.as_str()) {
+            wants.push(hash);
+        }
+    }
+    let pack_content = GitPack::create_minimal_pack_content(&wants, &capabilities);
+    let git_pack = GitPack::from_repository_url_and_pack_content(
+        &upload_pack_discovery.repository_url,
+        &pack_content,
+    )?;
 
-    // transform the packfile into the corresponding git objects
-    let git_objects = git_pack.into_git_objects()?;
+    // stores the packfile and its index in the .git/objects/pack folder
+    let object_database = repository.object_database();
+    git_pack.write(object_database)?;
 
-    // writes all objects to .git folder
-    for git_object in git_objects {
-        git_object.write(directory, false)?;
+    // the refs created by the clone start their reflog
+    let identity = committer_signature(&repository.config()?)?.to_string();
+    let message = format!("clone: from {}", repository_url);
+    let refs = repository.refs();
+    let remote_head = format!("refs/remotes/{}/HEAD", remote_name);
+    for name in ["HEAD", remote_head.as_str()]
+        .into_iter()
+        .chain(branch.as_deref())
+    {
+        if refs.resolve(name)?.is_some() {
+            refs.append_reflog(
+                name,
+                None,
+                &upload_pack_discovery.head_hash,
+                &identity,
+                &message,
+            )?;
+        }
     }
 
     // retrieves the tree object corresponding to the commit where HEAD points to
-    let current_commit_object =
-        GitObject::from_hash(&upload_pack_discovery.head_hash, directory)?.content;
+    let current_commit_object = object_database
+        .read_object(&upload_pack_discovery.head_hash)?
+        .content;
     let main_tree_sha = match current_commit_object {
         GitObjectContent::Commit { content } => content.tree_sha,
         _ => Err(Error::NotATreeGitObject)?,
     };
-    let tree = GitObject::from_hash(&main_tree_sha, directory)?;
+    let tree = object_database.read_object(&main_tree_sha)?;
 
     // restores the working dir
-    tree.restore_directory(directory)?;
+    tree.restore_directory(repository.work_tree()?, object_database)?;
+
+    // the index matches the restored files
+    let index = GitIndex::from_tree(&tree, object_database, repository.work_tree()?)?;
+    index.write(&repository)?;
 
-    Ok(())
+    Ok(repository)
 }
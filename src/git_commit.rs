@@ -0,0 +1,112 @@
+use crate::git_commit_tree::git_commit_tree;
+use crate::git_index::GitIndex;
+use crate::git_object::GitObjectContent;
+use crate::git_refs::NULL_HASH;
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// Result of a commit, with what is needed to describe it
+#[derive(Debug)]
+pub struct CommitOutput {
+    /// hash of the new commit
+    pub hash: String,
+    /// ref that was advanced (for ex refs/heads/main), None if HEAD is detached
+    pub head_ref: Option<String>,
+    /// true if the commit has no parent
+    pub is_root: bool,
+}
+
+/// Commits the content of the index:
+/// - the trees are written from the index
+/// - the parent is the commit HEAD points to, if any, followed by the merged
+///   commits when concluding a merge that stopped on conflicts (MERGE_HEAD)
+/// - the branch HEAD points to (or HEAD itself if detached) is advanced to
+///   the new commit, and the move is recorded in the reflogs
+pub fn git_commit(repository: &Repository, message: &str) -> Result<CommitOutput> {
+    let object_database = repository.object_database();
+
+    let mut index = GitIndex::read(repository)?;
+    let parent = repository.refs().resolve("HEAD")?;
+    let merge_heads = read_merge_heads(repository)?;
+    if parent.is_none() && index.entries.is_empty() {
+        return Err(Error::NothingToCommit);
+    }
+
+    let tree_sha = index.write_tree(object_database)?;
+    // keeps the cache tree for the next commit
+    index.write(repository)?;
+
+    if let Some(parent) = &parent {
+        let GitObjectContent::Commit { content } = object_database.read_object(parent)?.content
+        else {
+            return Err(Error::InvalidGitObject);
+        };
+        // a merge can be recorded even if it does not change the tree
+        if content.tree_sha == tree_sha && merge_heads.is_empty() {
+            return Err(Error::NothingToCommit);
+        }
+    }
+
+    let mut parents_sha = parent.iter().cloned().collect::<Vec<_>>();
+    parents_sha.extend(merge_heads.iter().cloned());
+    // the message always ends with a single newline
+    let message = format!("{}\n", message.trim_end());
+    let hash = git_commit_tree(repository, &tree_sha, &parents_sha, message.as_bytes())?;
+
+    // the reflog entry is made by the committer of the new commit
+    let GitObjectContent::Commit { content } = object_database.read_object(&hash)?.content else {
+        return Err(Error::Unreachable);
+    };
+    let identity = content.committer.to_string();
+    let subject = message.lines().next().unwrap_or_default();
+    let reflog_message = match parent {
+        None => format!("commit (initial): {}", subject),
+        Some(_) if !merge_heads.is_empty() => format!("commit (merge): {}", subject),
+        Some(_) => format!("commit: {}", subject),
+    };
+
+    // fails if the branch moved since HEAD was read
+    let refs = repository.refs();
+    let expected_old_hash = parent.as_deref().unwrap_or(NULL_HASH);
+    let head_ref = refs.head_ref()?;
+    if let Some(head_ref) = &head_ref {
+        refs.update(head_ref, &hash, Some(expected_old_hash))?;
+        refs.append_reflog(
+            head_ref,
+            parent.as_deref(),
+            &hash,
+            &identity,
+            &reflog_message,
+        )?;
+    } else {
+        refs.update("HEAD", &hash, Some(expected_old_hash))?;
+    }
+    refs.append_reflog("HEAD", parent.as_deref(), &hash, &identity, &reflog_message)?;
+
+    // the merge is concluded
+    for name in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"] {
+        let path = repository.git_dir().join(name);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(CommitOutput {
+        hash,
+        head_ref,
+        is_root: parent.is_none(),
+    })
+}
+
+/// Commits being merged, recorded by a merge that stopped on conflicts
+fn read_merge_heads(repository: &Repository) -> Result<Vec<String>> {
+    let path = repository.git_dir().join("MERGE_HEAD");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
@@ -1,22 +1,31 @@
+use crate::git_ident::{author_signature, committer_signature};
 use crate::git_object::{CommitObjects, GitObject};
+use crate::git_repository::Repository;
 use crate::Result;
 
-/// Writes the given commit to the .git folder of the current directory
-pub fn git_commit_tree(tree_sha: &str, parent_commit_sha: &str, message: &str) -> Result<()> {
+/// Writes the given commit to the .git folder of the repository
+/// and returns its hash.
+/// The author and the committer are resolved from the environment and the config.
+/// The message is written as is: it should end with a newline
+pub fn git_commit_tree(
+    repository: &Repository,
+    tree_sha: &str,
+    parents_sha: &[String],
+    message: &[u8],
+) -> Result<String> {
+    let config = repository.config()?;
     let commit_objects = CommitObjects {
-        timestamp: 1714305310,
-        author_name: "Fake author".to_string(),
-        author_email: "fake_author@gmail.com".to_string(),
-        author_timezone: "+0200".to_string(),
-        commit_msg: message.to_string(),
+        author: author_signature(&config)?,
+        committer: committer_signature(&config)?,
+        extra_headers: Vec::new(),
+        commit_msg: Some(message.to_vec()),
+        headers_end_with_newline: true,
         tree_sha: tree_sha.to_string(),
-        parents_sha: vec![parent_commit_sha.to_string()],
+        parents_sha: parents_sha.to_vec(),
     };
 
     let git_obj = GitObject::from_commit_objects(commit_objects)?;
 
-    git_obj.write(".", false)?; //
-    println!("{}", git_obj.hash);
-
-    Ok(())
+    git_obj.write(repository.object_database())?;
+    Ok(git_obj.hash)
 }
@@ -0,0 +1,501 @@
+use std::path::{Path, PathBuf};
+
+use crate::git_refs::FileLock;
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// A variable of a config file, for ex `[branch "main"] remote = origin`
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    /// lowercased, as section names are case-insensitive
+    pub section: String,
+    /// case-sensitive
+    pub subsection: Option<String>,
+    /// lowercased, as keys are case-insensitive
+    pub key: String,
+    /// None for a key without `=`, which means true for booleans
+    pub value: Option<String>,
+}
+
+/// Git configuration, read from the system, global and repository config files.
+/// see https://git-scm.com/docs/git-config#_configuration_file
+/// Later files take precedence: when a variable is set several times, the last
+/// value is the one used
+#[derive(Debug, Default)]
+pub struct GitConfig {
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl GitConfig {
+    /// Reads /etc/gitconfig, the global config (~/.gitconfig and
+    /// $XDG_CONFIG_HOME/git/config) then the config of the repository if any.
+    /// GIT_CONFIG_NOSYSTEM and GIT_CONFIG_GLOBAL are respected
+    pub fn load(repository: Option<&Repository>) -> Result<Self> {
+        let mut paths = Vec::new();
+        if std::env::var_os("GIT_CONFIG_NOSYSTEM").is_none() {
+            paths.push(PathBuf::from("/etc/gitconfig"));
+        }
+        match std::env::var_os("GIT_CONFIG_GLOBAL") {
+            Some(global) => paths.push(PathBuf::from(global)),
+            None => {
+                let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")));
+                if let Some(xdg_config_home) = xdg_config_home {
+                    paths.push(xdg_config_home.join("git").join("config"));
+                }
+                if let Some(home) = std::env::var_os("HOME") {
+                    paths.push(Path::new(&home).join(".gitconfig"));
+                }
+            }
+        }
+        if let Some(repository) = repository {
+            paths.push(repository.git_dir().join("config"));
+        }
+
+        let mut config = Self::default();
+        for path in paths {
+            if path.is_file() {
+                let content = std::fs::read(&path)?;
+                let file_config = Self::from_bytes(&content)
+                    .map_err(|_| Error::InvalidConfigFile(path.clone()))?;
+                config.entries.extend(file_config.entries);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parses the content of a config file
+    pub fn from_bytes(content: &[u8]) -> Result<Self> {
+        let content = String::from_utf8_lossy(content);
+        let mut lines = content.lines();
+        let mut entries = Vec::new();
+        let mut section: Option<(String, Option<String>)> = None;
+
+        while let Some(line) = lines.next() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let end = header.find(']').ok_or_else(|| Error::InvalidConfig)?;
+                section = Some(Self::parse_section_header(&header[..end])?);
+                continue;
+            }
+
+            let (name, subsection) = section.clone().ok_or_else(|| Error::InvalidConfig)?;
+            let (key, value) = match line.split_once('=') {
+                None => (line.trim_end(), None),
+                Some((key, value)) => {
+                    // a value ending with a backslash continues on the next line
+                    let mut value = value.to_string();
+                    while Self::has_continuation(&value) {
+                        value.pop();
+                        value.push_str(lines.next().unwrap_or_default());
+                    }
+                    (key.trim(), Some(Self::parse_value(&value)?))
+                }
+            };
+            let is_valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic())
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+            if !is_valid_key {
+                return Err(Error::InvalidConfig);
+            }
+            entries.push(ConfigEntry {
+                section: name,
+                subsection,
+                key: key.to_ascii_lowercase(),
+                value,
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    /// `section`, `section "subsection"` or the deprecated `section.subsection`
+    fn parse_section_header(header: &str) -> Result<(String, Option<String>)> {
+        if let Some((name, subsection)) = header.split_once(' ') {
+            let subsection = subsection
+                .trim()
+                .strip_prefix('"')
+                .and_then(|s| s.strip_suffix('"'))
+                .ok_or_else(|| Error::InvalidConfig)?;
+            let subsection = subsection.replace("\\\"", "\"").replace("\\\\", "\\");
+            return Ok((name.to_ascii_lowercase(), Some(subsection)));
+        }
+        match header.split_once('.') {
+            Some((name, subsection)) => Ok((
+                name.to_ascii_lowercase(),
+                Some(subsection.to_ascii_lowercase()),
+            )),
+            None => Ok((header.to_ascii_lowercase(), None)),
+        }
+    }
+
+    /// Checks if the line ends with a backslash that is not escaped
+    fn has_continuation(value: &str) -> bool {
+        let nb_backslashes = value.len() - value.trim_end_matches('\\').len();
+        nb_backslashes % 2 == 1
+    }
+
+    /// Removes the quotes, comments and surrounding whitespace, and interprets
+    /// the escape sequences
+    fn parse_value(raw_value: &str) -> Result<String> {
+        let mut value = String::new();
+        let mut in_quotes = false;
+        // whitespace is only kept if it is followed by something else
+        let mut pending_whitespace = String::new();
+        let mut chars = raw_value.trim_start().chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    value.push_str(&std::mem::take(&mut pending_whitespace));
+                    in_quotes = !in_quotes;
+                }
+                '#' | ';' if !in_quotes => break,
+                '\\' => {
+                    value.push_str(&std::mem::take(&mut pending_whitespace));
+                    match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('b') => {
+                            value.pop();
+                        }
+                        Some(c @ ('"' | '\\')) => value.push(c),
+                        _ => return Err(Error::InvalidConfig),
+                    }
+                }
+                c if c.is_whitespace() && !in_quotes => pending_whitespace.push(c),
+                c => {
+                    value.push_str(&std::mem::take(&mut pending_whitespace));
+                    value.push(c);
+                }
+            }
+        }
+        if in_quotes {
+            return Err(Error::InvalidConfig);
+        }
+        Ok(value)
+    }
+
+    /// Splits `section.subsection.key` in its parts. The subsection may contain dots
+    fn split_name(name: &str) -> Option<(String, Option<&str>, String)> {
+        let (section, rest) = name.split_once('.')?;
+        let (subsection, key) = match rest.rsplit_once('.') {
+            Some((subsection, key)) => (Some(subsection), key),
+            None => (None, rest),
+        };
+        Some((
+            section.to_ascii_lowercase(),
+            subsection,
+            key.to_ascii_lowercase(),
+        ))
+    }
+
+    /// All the values of a variable, for ex `remote.origin.fetch`, in the order
+    /// they were read. Keys without value are returned as "true"
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        let Some((section, subsection, key)) = Self::split_name(name) else {
+            return Vec::new();
+        };
+        self.entries
+            .iter()
+            .filter(|entry| {
+                entry.section == section
+                    && entry.subsection.as_deref() == subsection
+                    && entry.key == key
+            })
+            .map(|entry| entry.value.as_deref().unwrap_or("true"))
+            .collect()
+    }
+
+    /// Value of a variable, for ex `user.name`. The last value wins
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).pop()
+    }
+
+    /// Value of a boolean variable (true/yes/on/1 or false/no/off/0/empty)
+    pub fn get_bool(&self, name: &str) -> Result<Option<bool>> {
+        match self.get(name).map(|value| value.to_ascii_lowercase()) {
+            None => Ok(None),
+            Some(value) => match value.as_str() {
+                "true" | "yes" | "on" | "1" => Ok(Some(true)),
+                "false" | "no" | "off" | "0" | "" => Ok(Some(false)),
+                _ => Err(Error::InvalidConfigValue(name.to_string())),
+            },
+        }
+    }
+}
+
+/// A line of a config file being edited
+#[derive(Debug)]
+struct ConfigLine {
+    /// raw text with its newline, followed by the continuation lines of the
+    /// value if any
+    text: String,
+    /// section the line belongs to, or starts for a header. None before the
+    /// first header
+    section: Option<(String, Option<String>)>,
+    /// lowercased key of a variable, None for headers, comments and blank lines
+    key: Option<String>,
+    is_header: bool,
+}
+
+/// A config file (for ex .git/config) edited in place: the comments, the blank
+/// lines and the variables that are not changed are written back as they were.
+/// The file is locked (<file>.lock) until the changes are written or dropped
+pub struct ConfigFile {
+    lines: Vec<ConfigLine>,
+    lock: FileLock,
+}
+
+impl ConfigFile {
+    /// Locks and reads the file. A missing file is an empty config
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let lock = FileLock::acquire(path)?;
+        let content = match path.is_file() {
+            true => String::from_utf8_lossy(&std::fs::read(path)?).to_string(),
+            false => String::new(),
+        };
+
+        let mut lines: Vec<ConfigLine> = Vec::new();
+        let mut section = None;
+        let mut raw_lines = content.split_inclusive('\n');
+        while let Some(raw_line) = raw_lines.next() {
+            let line = raw_line.trim();
+            if let Some(header) = line.strip_prefix('[') {
+                let end = header.find(']').ok_or_else(|| Error::InvalidConfig)?;
+                section = Some(GitConfig::parse_section_header(&header[..end])?);
+                lines.push(ConfigLine {
+                    text: raw_line.to_string(),
+                    section: section.clone(),
+                    key: None,
+                    is_header: true,
+                });
+                continue;
+            }
+            let mut text = raw_line.to_string();
+            let key = match line {
+                _ if line.is_empty() || line.starts_with('#') || line.starts_with(';') => None,
+                _ => {
+                    let (key, value) = line.split_once('=').unwrap_or((line, ""));
+                    let mut value = value.to_string();
+                    while GitConfig::has_continuation(&value) {
+                        let Some(next_line) = raw_lines.next() else {
+                            break;
+                        };
+                        text.push_str(next_line);
+                        value = next_line.trim_end_matches(['\n', '\r']).to_string();
+                    }
+                    Some(key.trim().to_ascii_lowercase())
+                }
+            };
+            lines.push(ConfigLine {
+                text,
+                section: section.clone(),
+                key,
+                is_header: false,
+            });
+        }
+        Ok(Self { lines, lock })
+    }
+
+    /// Sets the variable (for ex `branch.main.remote`). The last line setting
+    /// it is replaced, otherwise the variable is added at the end of its
+    /// section, which is created at the end of the file if needed
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let (section, subsection, key) = Self::split_variable(name)?;
+        let line = ConfigLine {
+            text: format!("\t{} = {}\n", key, Self::format_value(value)),
+            section: Some((section.clone(), subsection.clone())),
+            key: Some(key.to_ascii_lowercase()),
+            is_header: false,
+        };
+        let in_section =
+            |l: &ConfigLine| l.section.as_ref() == Some(&(section.clone(), subsection.clone()));
+
+        let existing = self
+            .lines
+            .iter()
+            .rposition(|l| in_section(l) && l.key == line.key);
+        if let Some(position) = existing {
+            self.lines[position] = line;
+            return Ok(());
+        }
+        let end_of_section = self
+            .lines
+            .iter()
+            .rposition(|l| in_section(l) && (l.is_header || l.key.is_some()));
+        match end_of_section {
+            Some(position) => self.lines.insert(position + 1, line),
+            None => {
+                if let Some(last) = self.lines.last_mut() {
+                    if !last.text.ends_with('\n') {
+                        last.text.push('\n');
+                    }
+                }
+                self.lines.push(ConfigLine {
+                    text: Self::format_header(&section, subsection.as_deref()),
+                    section: line.section.clone(),
+                    key: None,
+                    is_header: true,
+                });
+                self.lines.push(line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes every line setting the variable. As with git, a section left
+    /// empty is removed too, with the blank lines around it, unless comments
+    /// may be about it. Returns false if the variable was not set
+    pub fn unset(&mut self, name: &str) -> Result<bool> {
+        let (section, subsection, key) = Self::split_variable(name)?;
+        let section = Some((section, subsection));
+        let key = Some(key.to_ascii_lowercase());
+        let is_unset = |line: &ConfigLine| line.section == section && line.key == key;
+        let is_comment = |line: &ConfigLine| {
+            !line.is_header && line.key.is_none() && !line.text.trim().is_empty()
+        };
+
+        let mut removed = vec![false; self.lines.len()];
+        for position in 0..self.lines.len() {
+            if removed[position] || !is_unset(&self.lines[position]) {
+                continue;
+            }
+            removed[position] = true;
+            // the variable must be the first of its section, without comments
+            // before the section...
+            let mut start = Some(0);
+            let mut is_section_seen = false;
+            for before in (0..position).rev() {
+                let line = &self.lines[before];
+                if is_comment(line) || (line.key.is_some() && !is_section_seen) {
+                    start = None;
+                    break;
+                }
+                if line.key.is_some() || (line.is_header && line.section != section) {
+                    start = Some(before + 1);
+                    break;
+                }
+                is_section_seen |= line.is_header;
+            }
+            // ...and only be followed by variables that are removed too, without
+            // comments
+            let mut end = Some(self.lines.len());
+            for after in position + 1..self.lines.len() {
+                let line = &self.lines[after];
+                if is_comment(line) || (line.key.is_some() && !is_unset(line)) {
+                    end = None;
+                    break;
+                }
+                if line.is_header && line.section != section {
+                    end = Some(after);
+                    break;
+                }
+            }
+            if let (Some(start), Some(end)) = (start, end) {
+                removed[start..end].fill(true);
+            }
+        }
+        if !removed.contains(&true) {
+            return Ok(false);
+        }
+        let mut removed = removed.into_iter();
+        self.lines.retain(|_| !removed.next().unwrap_or_default());
+        Ok(true)
+    }
+
+    /// Renames the section (for ex `branch.main` to `branch.dev`), or removes
+    /// it with all its lines when there is no new name
+    pub fn rename_section(&mut self, name: &str, new_name: Option<&str>) -> Result<()> {
+        let section = Some(Self::split_section(name));
+        let new_section = new_name.map(Self::split_section);
+        let mut kept = Vec::new();
+        let mut in_renamed_section = false;
+        for mut line in std::mem::take(&mut self.lines) {
+            if line.is_header {
+                in_renamed_section = line.section == section;
+            }
+            if !in_renamed_section {
+                kept.push(line);
+                continue;
+            }
+            let Some((new_section, new_subsection)) = &new_section else {
+                continue;
+            };
+            if line.is_header {
+                line.text = Self::format_header(new_section, new_subsection.as_deref());
+            }
+            line.section = Some((new_section.clone(), new_subsection.clone()));
+            kept.push(line);
+        }
+        self.lines = kept;
+        Ok(())
+    }
+
+    /// Writes the changes and releases the lock
+    pub fn write(self) -> Result<()> {
+        let content = self
+            .lines
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect::<String>();
+        self.lock.commit(content.as_bytes())
+    }
+
+    /// `section.subsection.key` in its lowercased section, its subsection and
+    /// its key as given
+    fn split_variable(name: &str) -> Result<(String, Option<String>, &str)> {
+        let (section, subsection, _) =
+            GitConfig::split_name(name).ok_or_else(|| Error::InvalidConfig)?;
+        let key = name.rsplit('.').next().unwrap_or_default();
+        let is_valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic())
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !is_valid_key {
+            return Err(Error::InvalidConfig);
+        }
+        Ok((section, subsection.map(str::to_string), key))
+    }
+
+    /// `section.subsection` in its lowercased section and its subsection
+    fn split_section(name: &str) -> (String, Option<String>) {
+        match name.split_once('.') {
+            Some((section, subsection)) => {
+                (section.to_ascii_lowercase(), Some(subsection.to_string()))
+            }
+            None => (name.to_ascii_lowercase(), None),
+        }
+    }
+
+    /// `[section "subsection"]`, with the quotes and backslashes of the
+    /// subsection escaped
+    fn format_header(section: &str, subsection: Option<&str>) -> String {
+        match subsection {
+            Some(subsection) => {
+                let subsection = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+                format!("[{} \"{}\"]\n", section, subsection)
+            }
+            None => format!("[{}]\n", section),
+        }
+    }
+
+    /// Escapes the value, and quotes it if it would otherwise lose its
+    /// surrounding whitespace or be cut by a comment
+    fn format_value(value: &str) -> String {
+        let needs_quotes = value.starts_with(char::is_whitespace)
+            || value.ends_with(char::is_whitespace)
+            || value.contains(['#', ';']);
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .replace('\t', "\\t");
+        match needs_quotes {
+            true => format!("\"{}\"", escaped),
+            false => escaped,
+        }
+    }
+}
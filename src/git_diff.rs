@@ -0,0 +1,739 @@
+use std::collections::BTreeMap;
+use std::os::unix::fs::MetadataExt;
+
+use crate::git_index::{GitIndex, IndexEntry};
+use crate::git_line_diff::{
+    common_tail_len, diff_lines, is_binary, split_lines, write_unified_diff, DiffAlgorithm,
+};
+use crate::git_object::{GitObject, GitObjectContent, TreeChild};
+use crate::git_object_database::GitObjectDatabase;
+use crate::git_repository::Repository;
+use crate::git_rev_walk::merge_bases;
+use crate::git_revision::resolve_revision;
+use crate::git_status::{quote_path, FileStatus};
+use crate::{Error, Result};
+
+/// What diff prints for each changed file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOutput {
+    /// unified diff of the content
+    #[default]
+    Patch,
+    /// number of changed lines with a +/- graph, and a summary
+    Stat,
+    /// status letter (A, D, M, T) and path
+    NameStatus,
+    /// path only
+    NameOnly,
+}
+
+/// Options of the diff command
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    pub output: DiffOutput,
+    /// number of unchanged lines shown around the changes
+    pub context: usize,
+    pub algorithm: DiffAlgorithm,
+    /// only the files under these paths (relative to the root of the work
+    /// tree) are compared. All of them if empty
+    pub paths: Vec<String>,
+    /// with the Stat output, the files created and deleted and the mode
+    /// changes are listed after the totals
+    pub summary: bool,
+    /// whether the bytes above 0x7f of the paths are quoted, as the special
+    /// characters always are (core.quotePath)
+    pub quote_non_ascii: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            output: DiffOutput::default(),
+            context: 3,
+            algorithm: DiffAlgorithm::default(),
+            paths: Vec::new(),
+            summary: false,
+            quote_non_ascii: true,
+        }
+    }
+}
+
+/// Version of a file on one side of the diff
+#[derive(Debug, Clone)]
+pub struct DiffFile {
+    pub mode: u32,
+    pub hash: String,
+    /// already read for files of the work tree that had to be hashed,
+    /// otherwise read from the object database when needed
+    pub content: Option<Vec<u8>>,
+}
+
+/// A file that differs between both sides. None on the side where it does
+/// not exist
+#[derive(Debug)]
+pub struct FileDiff {
+    /// path relative to the root of the repository
+    pub path: String,
+    pub old: Option<DiffFile>,
+    pub new: Option<DiffFile>,
+}
+
+impl FileDiff {
+    pub fn status(&self) -> FileStatus {
+        match (&self.old, &self.new) {
+            (None, _) => FileStatus::Added,
+            (_, None) => FileStatus::Deleted,
+            (Some(old), Some(new)) if file_kind(old.mode) != file_kind(new.mode) => {
+                FileStatus::TypeChanged
+            }
+            _ => FileStatus::Modified,
+        }
+    }
+}
+
+/// What is compared on each side
+#[derive(Debug)]
+enum DiffSide {
+    /// a tree, None for the empty tree
+    Tree(Option<String>),
+    Index,
+    WorkTree,
+}
+
+/// Compares two versions of the repository and formats the differences,
+/// depending on the revisions:
+/// - none: the index and the work tree (unstaged changes)
+/// - none and cached: HEAD and the index (staged changes)
+/// - `a`: the tree of a and the work tree, or the index if cached
+/// - `a b` or `a..b`: the trees of a and b
+/// - `a...b`: the tree of the merge base of a and b, and the tree of b
+///
+/// Returns the whole output, which may contain the non utf8 lines of the files
+pub fn git_diff(
+    repository: &Repository,
+    revisions: &[String],
+    cached: bool,
+    options: &DiffOptions,
+) -> Result<Vec<u8>> {
+    let file_diffs = diff_files(repository, revisions, cached, &options.paths)?;
+    let object_database = repository.object_database();
+    let mut output = Vec::new();
+    match options.output {
+        DiffOutput::Patch => {
+            for file_diff in &file_diffs {
+                write_patch(object_database, file_diff, options, &mut output)?;
+            }
+        }
+        DiffOutput::Stat => {
+            write_stat(object_database, &file_diffs, options, &mut output)?;
+            if options.summary {
+                write_summary(&file_diffs, &mut output);
+            }
+        }
+        DiffOutput::NameStatus => {
+            for file_diff in &file_diffs {
+                let path = quote_path(&file_diff.path, options.quote_non_ascii);
+                let line = format!("{}\t{}\n", file_diff.status().code(), path);
+                output.extend(line.as_bytes());
+            }
+        }
+        DiffOutput::NameOnly => {
+            for file_diff in &file_diffs {
+                let path = quote_path(&file_diff.path, options.quote_non_ascii);
+                output.extend(format!("{}\n", path).as_bytes());
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Files that differ between the sides selected by the revisions (see
+/// git_diff), sorted by path
+pub fn diff_files(
+    repository: &Repository,
+    revisions: &[String],
+    cached: bool,
+    paths: &[String],
+) -> Result<Vec<FileDiff>> {
+    let object_database = repository.object_database();
+    let tree = |revision: &str| -> Result<DiffSide> {
+        let revision = if revision.is_empty() {
+            "HEAD"
+        } else {
+            revision
+        };
+        let hash = resolve_revision(repository, revision)?;
+        let tree_hash = object_database
+            .peel(&hash, "tree")
+            .map_err(|_| Error::InvalidRevision(revision.to_string()))?;
+        Ok(DiffSide::Tree(Some(tree_hash)))
+    };
+    let commit = |revision: &str| -> Result<String> {
+        let revision = if revision.is_empty() {
+            "HEAD"
+        } else {
+            revision
+        };
+        object_database
+            .peel(&resolve_revision(repository, revision)?, "commit")
+            .map_err(|_| Error::InvalidRevision(revision.to_string()))
+    };
+
+    let (old, new) = match revisions {
+        [] if cached => match repository.refs().resolve("HEAD")? {
+            Some(_) => (tree("HEAD")?, DiffSide::Index),
+            None => (DiffSide::Tree(None), DiffSide::Index),
+        },
+        [] => (DiffSide::Index, DiffSide::WorkTree),
+        [range] if range.contains("...") => {
+            let (start, end) = range.split_once("...").ok_or(Error::Unreachable)?;
+            let (start, end) = (commit(start)?, commit(end)?);
+            let merge_base = merge_bases(repository, &start, std::slice::from_ref(&end))?
+                .into_iter()
+                .next()
+                .ok_or_else(|| Error::NoMergeBase(range.clone()))?;
+            (tree(&merge_base)?, tree(&end)?)
+        }
+        [range] if range.contains("..") => {
+            let (start, end) = range.split_once("..").ok_or(Error::Unreachable)?;
+            (tree(start)?, tree(end)?)
+        }
+        [revision] if cached => (tree(revision)?, DiffSide::Index),
+        [revision] => (tree(revision)?, DiffSide::WorkTree),
+        [start, end] => (tree(start)?, tree(end)?),
+        [_, _, revision, ..] => return Err(Error::InvalidRevision(revision.clone())),
+    };
+
+    let mut file_diffs = Vec::new();
+    if let (DiffSide::Tree(old_tree), DiffSide::Tree(new_tree)) = (&old, &new) {
+        diff_trees(
+            object_database,
+            old_tree.as_deref(),
+            new_tree.as_deref(),
+            "",
+            paths,
+            &mut file_diffs,
+        )?;
+    } else {
+        let old_files = side_files(repository, &old, paths)?;
+        let mut new_files = side_files(repository, &new, paths)?;
+        for (path, old_file) in old_files {
+            match new_files.remove(&path) {
+                Some(new_file)
+                    if new_file.hash == old_file.hash && new_file.mode == old_file.mode => {}
+                new_file => file_diffs.push(FileDiff {
+                    path,
+                    old: Some(old_file),
+                    new: new_file,
+                }),
+            }
+        }
+        file_diffs.extend(new_files.into_iter().map(|(path, new_file)| FileDiff {
+            path,
+            old: None,
+            new: Some(new_file),
+        }));
+    }
+    file_diffs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(file_diffs)
+}
+
+/// Whether the path is one of the paths, or under one of them
+pub fn matches_paths(path: &str, paths: &[String]) -> bool {
+    paths.is_empty()
+        || paths.iter().any(|prefix| {
+            prefix.is_empty()
+                || path == prefix
+                || path
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+}
+
+/// Whether some of the paths can be under the directory
+fn dir_matches_paths(dir: &str, paths: &[String]) -> bool {
+    matches_paths(dir, paths)
+        || paths.iter().any(|prefix| {
+            prefix
+                .strip_prefix(dir)
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+}
+
+/// Compares two trees, going only into the subtrees that differ
+fn diff_trees(
+    object_database: &GitObjectDatabase,
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+    prefix: &str,
+    paths: &[String],
+    file_diffs: &mut Vec<FileDiff>,
+) -> Result<()> {
+    let read_children = |tree: Option<&str>| -> Result<Vec<TreeChild>> {
+        let Some(tree) = tree else {
+            return Ok(Vec::new());
+        };
+        match object_database.read_object(tree)?.content {
+            GitObjectContent::Tree { content } => Ok(content),
+            _ => Err(Error::NotATreeGitObject),
+        }
+    };
+    let mut children: BTreeMap<String, (Option<TreeChild>, Option<TreeChild>)> = BTreeMap::new();
+    for child in read_children(old_tree)? {
+        let name = child.name.clone();
+        children.entry(name).or_default().0 = Some(child);
+    }
+    for child in read_children(new_tree)? {
+        let name = child.name.clone();
+        children.entry(name).or_default().1 = Some(child);
+    }
+
+    let is_tree =
+        |child: &Option<TreeChild>| child.as_ref().is_some_and(|child| child.mode == 40000);
+    let as_file = |child: TreeChild| DiffFile {
+        mode: child.mode,
+        hash: child.hash,
+        content: None,
+    };
+    for (name, (old, new)) in children {
+        let path = format!("{}{}", prefix, name);
+        let (old_tree, old_file) = match is_tree(&old) {
+            true => (old.map(|child| child.hash), None),
+            false => (None, old.map(as_file)),
+        };
+        let (new_tree, new_file) = match is_tree(&new) {
+            true => (new.map(|child| child.hash), None),
+            false => (None, new.map(as_file)),
+        };
+
+        if (old_tree.is_some() || new_tree.is_some())
+            && old_tree != new_tree
+            && dir_matches_paths(&path, paths)
+        {
+            diff_trees(
+                object_database,
+                old_tree.as_deref(),
+                new_tree.as_deref(),
+                &format!("{}/", path),
+                paths,
+                file_diffs,
+            )?;
+        }
+        let is_unchanged = match (&old_file, &new_file) {
+            (Some(old), Some(new)) => old.hash == new.hash && old.mode == new.mode,
+            (None, None) => true,
+            _ => false,
+        };
+        if !is_unchanged && matches_paths(&path, paths) {
+            file_diffs.push(FileDiff {
+                path,
+                old: old_file,
+                new: new_file,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Files of the index or of the work tree, or of a tree, by path.
+/// The files of the work tree are the files of the index that still exist.
+/// Their stat data is compared to the index to avoid hashing them
+fn side_files(
+    repository: &Repository,
+    side: &DiffSide,
+    paths: &[String],
+) -> Result<BTreeMap<String, DiffFile>> {
+    let mut files = BTreeMap::new();
+    let index = match side {
+        DiffSide::Tree(None) => return Ok(files),
+        DiffSide::Tree(Some(tree)) => {
+            for (path, child) in repository.object_database().read_tree_recursively(tree)? {
+                if matches_paths(&path, paths) {
+                    let file = DiffFile {
+                        mode: child.mode,
+                        hash: child.hash,
+                        content: None,
+                    };
+                    files.insert(path, file);
+                }
+            }
+            return Ok(files);
+        }
+        DiffSide::Index | DiffSide::WorkTree => GitIndex::read(repository)?,
+    };
+    // entries in conflict are not compared
+    let entries = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage() == 0 && matches_paths(&entry.path, paths));
+
+    if let DiffSide::Index = side {
+        for entry in entries {
+            let file = DiffFile {
+                mode: entry.mode,
+                hash: entry.hash.clone(),
+                content: None,
+            };
+            files.insert(entry.path.clone(), file);
+        }
+        return Ok(files);
+    }
+
+    let work_tree = repository.work_tree()?;
+    // files modified in the same second as the index was written may change
+    // again without their stat data changing: they are always hashed
+    let index_mtime = std::fs::metadata(repository.git_dir().join("index"))
+        .map(|metadata| (metadata.mtime() as u32, metadata.mtime_nsec() as u32))
+        .ok();
+    for entry in entries {
+        let file_path = work_tree.join(&entry.path);
+        let Ok(metadata) = std::fs::symlink_metadata(&file_path) else {
+            continue;
+        };
+        let file = if entry.mode == 160000 {
+            // submodules are not inspected
+            DiffFile {
+                mode: entry.mode,
+                hash: entry.hash.clone(),
+                content: None,
+            }
+        } else if metadata.is_dir() {
+            continue;
+        } else {
+            let is_racy = index_mtime
+                .is_some_and(|mtime| (entry.mtime_seconds, entry.mtime_nanoseconds) >= mtime);
+            let mode = IndexEntry::mode_from_metadata(&metadata);
+            if mode == entry.mode && entry.matches_metadata(&metadata) && !is_racy {
+                DiffFile {
+                    mode,
+                    hash: entry.hash.clone(),
+                    content: None,
+                }
+            } else {
+                let git_object = GitObject::from_work_tree_file(&file_path)?;
+                let GitObjectContent::Blob { content } = git_object.content else {
+                    return Err(Error::Unreachable);
+                };
+                DiffFile {
+                    mode,
+                    hash: git_object.hash,
+                    content: Some(content),
+                }
+            }
+        };
+        files.insert(entry.path.clone(), file);
+    }
+    Ok(files)
+}
+
+/// Files and executables are the same kind of file, symbolic links and
+/// submodules are not
+fn file_kind(mode: u32) -> u32 {
+    match mode {
+        100755 => 100644,
+        mode => mode,
+    }
+}
+
+/// Content of a file, read from the object database if it was not read yet.
+/// Submodules are shown as the commit they point to
+fn file_content(object_database: &GitObjectDatabase, file: &DiffFile) -> Result<Vec<u8>> {
+    if let Some(content) = &file.content {
+        return Ok(content.clone());
+    }
+    if file.mode == 160000 {
+        return Ok(format!("Subproject commit {}\n", file.hash).into_bytes());
+    }
+    match object_database.read_object(&file.hash)?.content {
+        GitObjectContent::Blob { content } => Ok(content),
+        _ => Err(Error::InvalidGitObject),
+    }
+}
+
+/// Writes the header and the hunks of a file. A file that became a symbolic
+/// link (or the opposite) is shown as deleted and added again
+fn write_patch(
+    object_database: &GitObjectDatabase,
+    file_diff: &FileDiff,
+    options: &DiffOptions,
+    output: &mut Vec<u8>,
+) -> Result<()> {
+    if file_diff.status() == FileStatus::TypeChanged {
+        for (old, new) in [(file_diff.old.clone(), None), (None, file_diff.new.clone())] {
+            let file_diff = FileDiff {
+                path: file_diff.path.clone(),
+                old,
+                new,
+            };
+            write_patch(object_database, &file_diff, options, output)?;
+        }
+        return Ok(());
+    }
+
+    let path = &file_diff.path;
+    // the prefix is quoted along with the path
+    let quote = |prefix: &str| quote_path(&format!("{}{}", prefix, path), options.quote_non_ascii);
+    let mut header = format!("diff --git {} {}\n", quote("a/"), quote("b/"));
+    let abbreviate = |file: &Option<DiffFile>| -> Result<String> {
+        match file {
+            Some(file) => object_database.abbreviate(&file.hash, 7),
+            None => Ok("0000000".to_string()),
+        }
+    };
+    let index_line = format!(
+        "index {}..{}",
+        abbreviate(&file_diff.old)?,
+        abbreviate(&file_diff.new)?
+    );
+    match (&file_diff.old, &file_diff.new) {
+        (None, Some(new)) => {
+            header += &format!("new file mode {:06}\n{}\n", new.mode, index_line);
+        }
+        (Some(old), None) => {
+            header += &format!("deleted file mode {:06}\n{}\n", old.mode, index_line);
+        }
+        (Some(old), Some(new)) => {
+            if old.mode != new.mode {
+                header += &format!("old mode {:06}\nnew mode {:06}\n", old.mode, new.mode);
+            }
+            if old.hash != new.hash {
+                header += &index_line;
+                if old.mode == new.mode {
+                    header += &format!(" {:06}", new.mode);
+                }
+                header.push('\n');
+            }
+        }
+        (None, None) => return Err(Error::Unreachable),
+    }
+    output.extend(header.as_bytes());
+
+    if file_diff
+        .old
+        .as_ref()
+        .zip(file_diff.new.as_ref())
+        .is_some_and(|(old, new)| old.hash == new.hash)
+    {
+        return Ok(());
+    }
+    let read = |file: &Option<DiffFile>| match file {
+        Some(file) => file_content(object_database, file),
+        None => Ok(Vec::new()),
+    };
+    let (old_content, new_content) = (read(&file_diff.old)?, read(&file_diff.new)?);
+    let old_name = match file_diff.old {
+        Some(_) => quote("a/"),
+        None => "/dev/null".to_string(),
+    };
+    let new_name = match file_diff.new {
+        Some(_) => quote("b/"),
+        None => "/dev/null".to_string(),
+    };
+
+    if is_binary(&old_content) || is_binary(&new_content) {
+        output.extend(format!("Binary files {} and {} differ\n", old_name, new_name).as_bytes());
+        return Ok(());
+    }
+    // the end of the files can be skipped when it is not shown, as git does
+    let tail_len = match options.context {
+        0 => common_tail_len(&old_content, &new_content),
+        _ => 0,
+    };
+    let (old_lines, new_lines) = (
+        split_lines(&old_content[..old_content.len() - tail_len]),
+        split_lines(&new_content[..new_content.len() - tail_len]),
+    );
+    let changes = diff_lines(&old_lines, &new_lines, options.algorithm);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    output.extend(format!("--- {}\n+++ {}\n", old_name, new_name).as_bytes());
+    write_unified_diff(&old_lines, &new_lines, &changes, options.context, output);
+    Ok(())
+}
+
+/// Changes of a file counted by --stat: lines added and deleted, or the sizes
+/// of binary files
+struct FileStat<'a> {
+    path: &'a str,
+    added: usize,
+    deleted: usize,
+    is_binary: bool,
+}
+
+/// Width of the output of --stat, as git uses when the output is not a
+/// terminal
+const STAT_WIDTH: usize = 80;
+
+/// Writes one line per file with the number of changed lines and a graph of
+/// the added and deleted lines, then the totals. Long paths are cut and the
+/// graph is scaled to fit in 80 columns
+fn write_stat(
+    object_database: &GitObjectDatabase,
+    file_diffs: &[FileDiff],
+    options: &DiffOptions,
+    output: &mut Vec<u8>,
+) -> Result<()> {
+    let mut stats = Vec::new();
+    for file_diff in file_diffs {
+        let read = |file: &Option<DiffFile>| match file {
+            Some(file) => file_content(object_database, file),
+            None => Ok(Vec::new()),
+        };
+        let (old_content, new_content) = (read(&file_diff.old)?, read(&file_diff.new)?);
+        let stat = if is_binary(&old_content) || is_binary(&new_content) {
+            FileStat {
+                path: &file_diff.path,
+                added: new_content.len(),
+                deleted: old_content.len(),
+                is_binary: true,
+            }
+        } else {
+            let tail_len = common_tail_len(&old_content, &new_content);
+            let (old_lines, new_lines) = (
+                split_lines(&old_content[..old_content.len() - tail_len]),
+                split_lines(&new_content[..new_content.len() - tail_len]),
+            );
+            let changes = diff_lines(&old_lines, &new_lines, options.algorithm);
+            FileStat {
+                path: &file_diff.path,
+                added: changes.iter().map(|change| change.new_len).sum(),
+                deleted: changes.iter().map(|change| change.old_len).sum(),
+                is_binary: false,
+            }
+        };
+        stats.push(stat);
+    }
+
+    let decimal_width = |n: usize| n.to_string().len();
+    let max_len = stats.iter().map(|stat| stat.path.chars().count()).max();
+    let max_len = max_len.unwrap_or(0);
+    let mut max_change = 0;
+    let mut number_width = 0;
+    let mut bin_width = 0;
+    for stat in &stats {
+        if stat.is_binary {
+            // "Bin <deleted> -> <added> bytes"
+            bin_width = bin_width.max(14 + decimal_width(stat.added) + decimal_width(stat.deleted));
+            number_width = 3;
+        } else {
+            max_change = max_change.max(stat.added + stat.deleted);
+        }
+    }
+    number_width = number_width.max(decimal_width(max_change));
+
+    let width = STAT_WIDTH.max(16 + 6 + number_width);
+    let mut graph_width = match max_change + 4 > bin_width {
+        true => max_change,
+        false => bin_width - 4,
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width > (width * 3 / 8).saturating_sub(number_width + 6) {
+            graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    let scale = |count: usize| match count {
+        0 => 0,
+        _ => 1 + count * (graph_width - 1) / max_change,
+    };
+    let (mut insertions, mut deletions) = (0, 0);
+    for stat in &stats {
+        let mut name = stat.path;
+        let mut prefix = "";
+        let mut len = name_width;
+        if name_width < name.chars().count() {
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let nb_chars = name.chars().count();
+            let cut = name
+                .char_indices()
+                .nth(nb_chars - len)
+                .map_or(name.len(), |(index, _)| index);
+            name = &name[cut..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let padding = len.saturating_sub(name.chars().count());
+        let name = format!("{}{}{:padding$}", prefix, name, "", padding = padding);
+
+        if stat.is_binary {
+            let line = format!(
+                " {} | {:>width$} {} -> {} bytes\n",
+                name,
+                "Bin",
+                stat.deleted,
+                stat.added,
+                width = number_width
+            );
+            output.extend(line.as_bytes());
+            continue;
+        }
+        insertions += stat.added;
+        deletions += stat.deleted;
+
+        let (mut added, mut deleted) = (stat.added, stat.deleted);
+        if graph_width <= max_change {
+            let mut total = scale(added + deleted);
+            if total < 2 && added > 0 && deleted > 0 {
+                total = 2;
+            }
+            if added < deleted {
+                added = scale(added);
+                deleted = total - added;
+            } else {
+                deleted = scale(deleted);
+                added = total - deleted;
+            }
+        }
+        let changes = stat.added + stat.deleted;
+        let line = format!(
+            " {} | {:>width$}{}{}{}\n",
+            name,
+            changes,
+            if changes > 0 { " " } else { "" },
+            "+".repeat(added),
+            "-".repeat(deleted),
+            width = number_width
+        );
+        output.extend(line.as_bytes());
+    }
+
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    let mut summary = format!(" {} file{} changed", stats.len(), plural(stats.len()));
+    if insertions > 0 || deletions == 0 {
+        summary += &format!(", {} insertion{}(+)", insertions, plural(insertions));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary += &format!(", {} deletion{}(-)", deletions, plural(deletions));
+    }
+    summary.push('\n');
+    output.extend(summary.as_bytes());
+    Ok(())
+}
+
+/// Writes the files created or deleted and the mode changes
+fn write_summary(file_diffs: &[FileDiff], output: &mut Vec<u8>) {
+    for file_diff in file_diffs {
+        let path = &file_diff.path;
+        let line = match (&file_diff.old, &file_diff.new) {
+            (None, Some(new)) => format!(" create mode {:06} {}\n", new.mode, path),
+            (Some(old), None) => format!(" delete mode {:06} {}\n", old.mode, path),
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                format!(" mode change {:06} => {:06} {}\n", old.mode, new.mode, path)
+            }
+            _ => continue,
+        };
+        output.extend(line.as_bytes());
+    }
+}
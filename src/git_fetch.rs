@@ -0,0 +1,844 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::git_ident::committer_signature;
+use crate::git_object::GitObjectContent;
+use crate::git_object_database::GitObjectDatabase;
+use crate::git_pack::{GitPack, UploadPackDiscovery};
+use crate::git_refs::RefValue;
+use crate::git_remote::{find_ref, Refspec, Remote};
+use crate::git_repository::Repository;
+use crate::git_rev_walk::is_ancestor;
+use crate::{Error, Result};
+
+/// Number of haves sent in the first request. Each following request sends
+/// twice as many, up to LARGE_FLUSH
+const INITIAL_FLUSH: usize = 16;
+const LARGE_FLUSH: usize = 16384;
+/// Haves sent since the last new common commit after which the negotiation
+/// gives up, once the server found at least one common commit
+const MAX_IN_VAIN: usize = 256;
+/// Capabilities requested when the server supports them, in the order git
+/// sends them. include-tag is only requested when tags are followed
+const CAPABILITIES: [&str; 5] = [
+    "multi_ack_detailed",
+    "no-done",
+    "thin-pack",
+    "include-tag",
+    "ofs-delta",
+];
+
+#[derive(Debug, Default)]
+pub struct FetchOptions {
+    /// updates the local refs even when it is not a fast-forward (--force)
+    pub force: bool,
+    /// start of the reflog messages: fetch followed by its arguments
+    pub reflog_action: String,
+}
+
+/// What happened to a fetched ref
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchedRefStatus {
+    /// the local ref already had the value
+    UpToDate,
+    FastForward,
+    /// not a fast-forward, but the refspec or --force allowed it
+    Forced,
+    /// the local ref was created
+    New,
+    /// an existing tag was replaced (with --force or a + refspec)
+    TagUpdate,
+    /// the local ref was not updated
+    Rejected,
+    /// the ref is not stored locally, only written in FETCH_HEAD
+    FetchHeadOnly,
+}
+
+#[derive(Debug)]
+pub struct FetchedRef {
+    /// name of the ref in the remote repository, for ex refs/heads/main
+    pub remote_ref: String,
+    /// ref that stores it, for ex refs/remotes/origin/main
+    pub local_ref: Option<String>,
+    /// value of the local ref before the fetch
+    pub old_hash: Option<String>,
+    pub new_hash: String,
+    pub status: FetchedRefStatus,
+    /// why the ref was rejected, or that it was forced
+    pub reason: Option<&'static str>,
+}
+
+#[derive(Debug)]
+pub struct FetchOutput {
+    /// url of the remote, as displayed by fetch (without .git)
+    pub url: String,
+    /// fetched refs, in the order they are written in FETCH_HEAD
+    pub refs: Vec<FetchedRef>,
+}
+
+/// How a fetched ref is written in FETCH_HEAD
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FetchHeadStatus {
+    /// to be merged by `git pull`
+    Merge,
+    /// written with not-for-merge
+    NotForMerge,
+    /// not written: only fetched to update a remote-tracking branch
+    Ignore,
+}
+
+/// A remote ref to fetch, and the local ref it is stored in
+#[derive(Debug)]
+struct RefMapEntry {
+    remote_ref: String,
+    hash: String,
+    local_ref: Option<String>,
+    /// `+` refspec: the local ref may be updated even if not a fast-forward
+    force: bool,
+    fetch_head: FetchHeadStatus,
+}
+
+/// Downloads the objects of the remote refs that are missing locally, then
+/// updates the local refs the refspecs map them to and writes FETCH_HEAD.
+/// The remote defaults to the remote of the current branch, or origin, and
+/// the refspecs to its remote.<name>.fetch refspecs.
+///
+/// Only the objects the repository lacks are downloaded: the local commits are
+/// announced to the server (have lines) until it knows which ones it shares with
+/// us, see https://git-scm.com/docs/pack-protocol#_packfile_negotiation.
+/// Tags pointing to fetched commits are followed, unless the refspecs given only
+/// fetch to FETCH_HEAD
+pub fn git_fetch(
+    repository: &Repository,
+    remote_name: Option<&str>,
+    refspecs: &[String],
+    options: &FetchOptions,
+) -> Result<FetchOutput> {
+    let remote_name = match remote_name {
+        Some(remote_name) => remote_name.to_string(),
+        None => Remote::default_name(repository)?,
+    };
+    let remote = Remote::load(repository, &remote_name)?;
+    let refspecs = refspecs
+        .iter()
+        .map(|refspec| Refspec::parse(refspec))
+        .collect::<Result<Vec<_>>>()?;
+
+    let discovery = UploadPackDiscovery::from_repository_url(&remote.url)?;
+    // a server advertises HEAD first, it is not part of the refs
+    let remote_refs = std::iter::once(("HEAD".to_string(), discovery.head_hash.clone()))
+        .chain(
+            discovery
+                .refs
+                .iter()
+                .map(|(hash, name)| (name.clone(), hash.clone())),
+        )
+        .collect::<Vec<_>>();
+
+    let (mut ref_map, follow_tags) = get_ref_map(repository, &remote, &remote_refs, &refspecs)?;
+    check_not_current_branch(repository, &ref_map)?;
+    if follow_tags {
+        let tags = find_non_local_tags(repository, &remote_refs, &ref_map)?;
+        ref_map.extend(tags);
+    }
+
+    let object_database = repository.object_database();
+    let mut wants: Vec<String> = Vec::new();
+    for entry in &ref_map {
+        if !wants.contains(&entry.hash) && !object_database.contains(&entry.hash)? {
+            wants.push(entry.hash.clone());
+        }
+    }
+    if !wants.is_empty() {
+        let capabilities = CAPABILITIES
+            .into_iter()
+            .filter(|capability| follow_tags || *capability != "include-tag")
+            .filter(|capability| discovery.supports(capability))
+            .collect::<Vec<_>>();
+        let git_pack = fetch_pack(repository, &discovery, &wants, &capabilities)?;
+        // the server may send deltas against objects we have without them
+        git_pack.fix_thin(object_database)?.write(object_database)?;
+    }
+
+    let refs = store_updated_refs(repository, &remote, &ref_map, options)?;
+    Ok(FetchOutput {
+        url: remote.display_url().to_string(),
+        refs,
+    })
+}
+
+/// The remote refs to fetch and where they are stored:
+/// - refspecs given on the command line are all written in FETCH_HEAD for
+///   merge. The remote-tracking branches of the refs they fetch are updated
+///   too, as mapped by the configured refspecs
+/// - otherwise the configured refspecs are used. The ref merged in the current
+///   branch (branch.<name>.merge) is the one for merge, or the first ref if the
+///   first refspec is not a pattern
+/// - without configured refspecs, the remote HEAD is fetched for merge
+///
+/// Also returns if tags must be followed: when a ref is stored locally
+fn get_ref_map(
+    repository: &Repository,
+    remote: &Remote,
+    remote_refs: &[(String, String)],
+    refspecs: &[Refspec],
+) -> Result<(Vec<RefMapEntry>, bool)> {
+    let mut ref_map = Vec::new();
+    let mut follow_tags = false;
+
+    if !refspecs.is_empty() {
+        for refspec in refspecs {
+            ref_map.extend(get_fetch_map(remote_refs, refspec, false)?);
+            follow_tags |= refspec.destination.is_some();
+        }
+        for entry in &mut ref_map {
+            entry.fetch_head = FetchHeadStatus::Merge;
+        }
+        // opportunistic updates of the remote-tracking branches
+        let fetched_refs = ref_map
+            .iter()
+            .map(|entry| (entry.remote_ref.clone(), entry.hash.clone()))
+            .collect::<Vec<_>>();
+        for refspec in &remote.fetch_refspecs {
+            for mut entry in get_fetch_map(&fetched_refs, refspec, true)? {
+                entry.fetch_head = FetchHeadStatus::Ignore;
+                ref_map.push(entry);
+            }
+        }
+    } else {
+        let merge_ref = merge_ref(repository, remote)?;
+        if !remote.fetch_refspecs.is_empty() || merge_ref.is_some() {
+            for (i, refspec) in remote.fetch_refspecs.iter().enumerate() {
+                ref_map.extend(get_fetch_map(remote_refs, refspec, false)?);
+                follow_tags |= refspec.destination.is_some();
+                if i == 0 && merge_ref.is_none() && !refspec.is_pattern() {
+                    if let Some(first) = ref_map.first_mut() {
+                        first.fetch_head = FetchHeadStatus::Merge;
+                    }
+                }
+            }
+            if let Some(merge_ref) = merge_ref {
+                if let Some(entry) = ref_map.iter_mut().find(|e| e.remote_ref == merge_ref) {
+                    entry.fetch_head = FetchHeadStatus::Merge;
+                }
+            }
+        } else {
+            let head = get_fetch_map(remote_refs, &Refspec::parse("HEAD")?, false)?;
+            for mut entry in head {
+                entry.fetch_head = FetchHeadStatus::Merge;
+                ref_map.push(entry);
+            }
+        }
+    }
+
+    // a local ref is only updated once, by its first entry
+    let mut deduplicated: Vec<RefMapEntry> = Vec::new();
+    for entry in ref_map {
+        let duplicate = deduplicated
+            .iter_mut()
+            .find(|previous| previous.local_ref.is_some() && previous.local_ref == entry.local_ref);
+        match duplicate {
+            Some(previous) => {
+                if entry.fetch_head == FetchHeadStatus::Merge {
+                    previous.fetch_head = FetchHeadStatus::Merge;
+                }
+            }
+            None => deduplicated.push(entry),
+        }
+    }
+    Ok((deduplicated, follow_tags))
+}
+
+/// Ref of the remote merged in the current branch (branch.<name>.merge), if
+/// the branch follows this remote
+fn merge_ref(repository: &Repository, remote: &Remote) -> Result<Option<String>> {
+    let config = repository.config()?;
+    let Some(head_ref) = repository.refs().head_ref()? else {
+        return Ok(None);
+    };
+    let Some(branch) = head_ref.strip_prefix("refs/heads/") else {
+        return Ok(None);
+    };
+    let branch_remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+    match (branch_remote, merge) {
+        (Some(branch_remote), Some(merge)) if branch_remote == remote.name => {
+            Ok(Some(merge.to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The refs a refspec fetches among the remote refs. A pattern matches any
+/// number of refs, other refspecs one ref, found with the same rules as
+/// revisions (main is refs/heads/main). Fails if the ref is not found, unless
+/// it may be missing
+fn get_fetch_map(
+    remote_refs: &[(String, String)],
+    refspec: &Refspec,
+    missing_ok: bool,
+) -> Result<Vec<RefMapEntry>> {
+    let entry = |remote_ref: &str, hash: &str, local_ref: Option<String>| RefMapEntry {
+        remote_ref: remote_ref.to_string(),
+        hash: hash.to_string(),
+        local_ref,
+        force: refspec.force,
+        fetch_head: FetchHeadStatus::NotForMerge,
+    };
+
+    if refspec.is_pattern() {
+        let mut entries = Vec::new();
+        // tags are advertised with the object they point to as refs/tags/x^{}
+        for (name, hash) in remote_refs.iter().filter(|(name, _)| !name.contains('^')) {
+            let local_ref = match &refspec.destination {
+                Some(_) => match refspec.map_source(name) {
+                    Some(local_ref) if Remote::is_valid_destination(&local_ref) => Some(local_ref),
+                    _ => continue,
+                },
+                None if refspec.matches_source(name) => None,
+                None => continue,
+            };
+            entries.push(entry(name, hash, local_ref));
+        }
+        return Ok(entries);
+    }
+
+    let Some((name, hash)) = find_ref(remote_refs, &refspec.source) else {
+        if missing_ok {
+            return Ok(Vec::new());
+        }
+        return Err(Error::RemoteRefNotFound(refspec.source.clone()));
+    };
+    let local_ref = match refspec.destination.as_deref() {
+        None => None,
+        Some(destination) => {
+            let local_ref = if destination.starts_with("refs/") {
+                destination.to_string()
+            } else if ["heads/", "tags/", "remotes/"]
+                .iter()
+                .any(|prefix| destination.starts_with(prefix))
+            {
+                format!("refs/{}", destination)
+            } else {
+                format!("refs/heads/{}", destination)
+            };
+            if !Remote::is_valid_destination(&local_ref) {
+                return Err(Error::InvalidRefName(local_ref));
+            }
+            Some(local_ref)
+        }
+    };
+    Ok(vec![entry(name, hash, local_ref)])
+}
+
+/// Refuses to update the branch checked out, as the work tree would not
+/// match it anymore
+fn check_not_current_branch(repository: &Repository, ref_map: &[RefMapEntry]) -> Result<()> {
+    let head_ref = repository.refs().head_ref()?;
+    for entry in ref_map {
+        if let Some(local_ref) = entry.local_ref.as_deref() {
+            if local_ref.starts_with("refs/heads/") && head_ref.as_deref() == Some(local_ref) {
+                return Err(Error::BranchCheckedOut(local_ref.to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Tags of the remote that do not exist locally and that point to an object we
+/// have or are fetching: they are fetched too, to refs/tags/
+fn find_non_local_tags(
+    repository: &Repository,
+    remote_refs: &[(String, String)],
+    ref_map: &[RefMapEntry],
+) -> Result<Vec<RefMapEntry>> {
+    let object_database = repository.object_database();
+    let refs = repository.refs();
+    let is_available = |hash: &str| -> Result<bool> {
+        Ok(ref_map.iter().any(|entry| entry.hash == hash) || object_database.contains(hash)?)
+    };
+
+    let mut tags: Vec<RefMapEntry> = Vec::new();
+    for (i, (name, hash)) in remote_refs.iter().enumerate() {
+        if !name.starts_with("refs/tags/") || name.ends_with("^{}") {
+            continue;
+        }
+        let already_fetched = ref_map
+            .iter()
+            .chain(&tags)
+            .any(|entry| entry.local_ref.as_deref() == Some(name.as_str()));
+        if already_fetched || refs.read(name)?.is_some() {
+            continue;
+        }
+        // the object an annotated tag points to is advertised right after it
+        let peeled = remote_refs
+            .get(i + 1)
+            .filter(|(peeled_name, _)| *peeled_name == format!("{}^{{}}", name))
+            .map(|(_, peeled)| peeled);
+        let is_followed = match peeled {
+            Some(peeled) => is_available(hash)? || is_available(peeled)?,
+            None => is_available(hash)?,
+        };
+        if is_followed {
+            tags.push(RefMapEntry {
+                remote_ref: name.clone(),
+                hash: hash.clone(),
+                local_ref: Some(name.clone()),
+                force: false,
+                fetch_head: FetchHeadStatus::NotForMerge,
+            });
+        }
+    }
+    Ok(tags)
+}
+
+/// Negotiates the objects to send with the server, then downloads the pack.
+/// With smart http, every request is independent (stateless): each one repeats
+/// the wants and the haves the server acknowledged as common so far, followed
+/// by a new batch of haves. The server answers "ACK <hash> common" for the
+/// haves it has, and "ACK <hash> ready" once it knows enough to build a small
+/// pack. The last request ends with done instead of a flush, and is answered
+/// with the pack. With no-done, the server sends the pack right after ready
+fn fetch_pack(
+    repository: &Repository,
+    discovery: &UploadPackDiscovery,
+    wants: &[String],
+    capabilities: &[&str],
+) -> Result<GitPack> {
+    let object_database = repository.object_database();
+    let mut negotiator = Negotiator::new(object_database);
+    // the remote refs we already have are common for sure
+    for (hash, _) in &discovery.refs {
+        if !object_database.contains(hash)? {
+            continue;
+        }
+        if let Ok(commit) = object_database.peel(hash, "commit") {
+            negotiator.known_common(&commit)?;
+        }
+    }
+    for (_, hash) in repository.refs().list("refs/")? {
+        if let Ok(commit) = object_database.peel(&hash, "commit") {
+            negotiator.add_tip(&commit)?;
+        }
+    }
+
+    let mut state = String::new();
+    for (i, want) in wants.iter().enumerate() {
+        let mut line = format!("want {}", want);
+        if i == 0 {
+            for capability in capabilities {
+                line.push(' ');
+                line.push_str(capability);
+            }
+        }
+        line.push('\n');
+        state.push_str(&UploadPackDiscovery::create_line(&line));
+    }
+    state.push_str("0000");
+
+    let mut haves = String::new();
+    let mut count = 0;
+    let mut flush_at = INITIAL_FLUSH;
+    let mut in_vain = 0;
+    let mut got_continue = false;
+    let mut ready_response = None;
+    while let Some(have) = negotiator.next()? {
+        haves.push_str(&UploadPackDiscovery::create_line(&format!(
+            "have {}\n",
+            have
+        )));
+        in_vain += 1;
+        count += 1;
+        if count < flush_at {
+            continue;
+        }
+        let request = format!("{}{}0000", state, haves);
+        haves.clear();
+        flush_at = if count < LARGE_FLUSH {
+            count * 2
+        } else {
+            count * 11 / 10
+        };
+
+        let mut res = GitPack::send_upload_pack_request(&discovery.repository_url, &request)?;
+        let mut got_ready = false;
+        while let Some((hash, status)) = read_ack(&mut res)? {
+            let was_common = negotiator.ack(&hash)?;
+            if status == "common" && !was_common {
+                // the next requests tell the server this commit is common
+                state.push_str(&UploadPackDiscovery::create_line(&format!(
+                    "have {}\n",
+                    hash
+                )));
+                in_vain = 0;
+            } else if status != "common" {
+                in_vain = 0;
+            }
+            got_continue = true;
+            got_ready |= status == "ready";
+        }
+        if got_ready && capabilities.contains(&"no-done") {
+            ready_response = Some(res);
+            break;
+        }
+        if (got_continue && in_vain > MAX_IN_VAIN) || got_ready {
+            break;
+        }
+    }
+
+    let mut res = match ready_response {
+        Some(res) => res,
+        None => {
+            let request = format!(
+                "{}{}{}",
+                state,
+                haves,
+                UploadPackDiscovery::create_line("done\n")
+            );
+            GitPack::send_upload_pack_request(&discovery.repository_url, &request)?
+        }
+    };
+    // acknowledgments of the common commits, until the final ACK or NAK
+    while let Some((_, status)) = read_ack(&mut res)? {
+        if status.is_empty() {
+            break;
+        }
+    }
+    GitPack::from_reader(&mut res)
+}
+
+/// Reads an acknowledgment of the server: `ACK <hash> <status>` gives the hash
+/// and the status (common, ready, continue or empty for the final ACK).
+/// Returns None for NAK, which ends the acknowledgments
+fn read_ack<R: std::io::Read>(reader: &mut R) -> Result<Option<(String, String)>> {
+    let size = UploadPackDiscovery::get_line_size(reader)?;
+    if size == 0 {
+        return Err(Error::InvalidSmartHttpRes);
+    }
+    let line = UploadPackDiscovery::get_line_content(reader, size)?;
+    if line == "NAK" {
+        return Ok(None);
+    }
+    let ack = line
+        .strip_prefix("ACK ")
+        .ok_or_else(|| Error::InvalidSmartHttpRes)?;
+    let (hash, status) = ack.split_once(' ').unwrap_or((ack, ""));
+    Ok(Some((hash.to_string(), status.to_string())))
+}
+
+/// Writes FETCH_HEAD and updates the local refs. The refs for merge come first,
+/// as `git pull` merges the first lines of FETCH_HEAD
+fn store_updated_refs(
+    repository: &Repository,
+    remote: &Remote,
+    ref_map: &[RefMapEntry],
+    options: &FetchOptions,
+) -> Result<Vec<FetchedRef>> {
+    let identity = committer_signature(&repository.config()?)?.to_string();
+    let url = remote.display_url();
+
+    let mut fetch_head = String::new();
+    let mut fetched_refs = Vec::new();
+    for fetch_head_status in [
+        FetchHeadStatus::Merge,
+        FetchHeadStatus::NotForMerge,
+        FetchHeadStatus::Ignore,
+    ] {
+        for entry in ref_map
+            .iter()
+            .filter(|entry| entry.fetch_head == fetch_head_status)
+        {
+            if fetch_head_status != FetchHeadStatus::Ignore {
+                let marker = match fetch_head_status {
+                    FetchHeadStatus::Merge => "",
+                    _ => "not-for-merge",
+                };
+                let note = match describe_remote_ref(&entry.remote_ref) {
+                    (_, "") => String::new(),
+                    ("", what) => format!("'{}' of ", what),
+                    (kind, what) => format!("{} '{}' of ", kind, what),
+                };
+                fetch_head.push_str(&format!("{}\t{}\t{}{}\n", entry.hash, marker, note, url));
+            }
+            match &entry.local_ref {
+                Some(local_ref) => fetched_refs.push(update_local_ref(
+                    repository, entry, local_ref, options, &identity,
+                )?),
+                None if fetch_head_status != FetchHeadStatus::Ignore => {
+                    fetched_refs.push(FetchedRef {
+                        remote_ref: entry.remote_ref.clone(),
+                        local_ref: None,
+                        old_hash: None,
+                        new_hash: entry.hash.clone(),
+                        status: FetchedRefStatus::FetchHeadOnly,
+                        reason: None,
+                    })
+                }
+                None => {}
+            }
+        }
+    }
+    std::fs::write(repository.git_dir().join("FETCH_HEAD"), fetch_head)?;
+    Ok(fetched_refs)
+}
+
+/// Kind of a remote ref and its short name, as written in FETCH_HEAD: for ex
+/// branch and main for refs/heads/main. Both are empty for HEAD
+pub fn describe_remote_ref(name: &str) -> (&'static str, &str) {
+    if name == "HEAD" {
+        return ("", "");
+    }
+    [
+        ("refs/heads/", "branch"),
+        ("refs/tags/", "tag"),
+        ("refs/remotes/", "remote-tracking branch"),
+    ]
+    .into_iter()
+    .find_map(|(prefix, kind)| name.strip_prefix(prefix).map(|what| (kind, what)))
+    .unwrap_or(("", name))
+}
+
+/// Updates the local ref to the fetched value, if it is a fast-forward or the
+/// update is forced. An existing tag is only replaced if forced
+fn update_local_ref(
+    repository: &Repository,
+    entry: &RefMapEntry,
+    local_ref: &str,
+    options: &FetchOptions,
+    identity: &str,
+) -> Result<FetchedRef> {
+    let object_database = repository.object_database();
+    let refs = repository.refs();
+    if !object_database.contains(&entry.hash)? {
+        return Err(Error::ObjectNotFound(entry.hash.clone()));
+    }
+    let old_hash = match refs.read(local_ref)? {
+        Some(RefValue::Hash(hash)) => Some(hash),
+        _ => None,
+    };
+    let mut fetched_ref = FetchedRef {
+        remote_ref: entry.remote_ref.clone(),
+        local_ref: Some(local_ref.to_string()),
+        old_hash: old_hash.clone(),
+        new_hash: entry.hash.clone(),
+        status: FetchedRefStatus::UpToDate,
+        reason: None,
+    };
+    if old_hash.as_deref() == Some(entry.hash.as_str()) {
+        return Ok(fetched_ref);
+    }
+
+    let force = options.force || entry.force;
+    let (status, message) = match &old_hash {
+        Some(_) if local_ref.starts_with("refs/tags/") => match force {
+            true => (FetchedRefStatus::TagUpdate, "updating tag"),
+            false => {
+                fetched_ref.status = FetchedRefStatus::Rejected;
+                fetched_ref.reason = Some("would clobber existing tag");
+                return Ok(fetched_ref);
+            }
+        },
+        _ => {
+            let old_commit = old_hash
+                .as_deref()
+                .and_then(|old_hash| object_database.peel(old_hash, "commit").ok());
+            let new_commit = object_database.peel(&entry.hash, "commit").ok();
+            match (old_commit, new_commit) {
+                (Some(old_commit), Some(new_commit)) => {
+                    if is_ancestor(repository, &old_commit, &new_commit)? {
+                        (FetchedRefStatus::FastForward, "fast-forward")
+                    } else if force {
+                        fetched_ref.reason = Some("forced update");
+                        (FetchedRefStatus::Forced, "forced-update")
+                    } else {
+                        fetched_ref.status = FetchedRefStatus::Rejected;
+                        fetched_ref.reason = Some("non-fast-forward");
+                        return Ok(fetched_ref);
+                    }
+                }
+                // described from the remote ref, whatever it is stored in
+                _ if entry.remote_ref.starts_with("refs/tags/") => {
+                    (FetchedRefStatus::New, "storing tag")
+                }
+                _ if entry.remote_ref.starts_with("refs/heads/") => {
+                    (FetchedRefStatus::New, "storing head")
+                }
+                _ => (FetchedRefStatus::New, "storing ref"),
+            }
+        }
+    };
+
+    // fast-forwards and forced updates check that the ref did not move meanwhile
+    let expected_old_hash = match status {
+        FetchedRefStatus::FastForward | FetchedRefStatus::Forced => old_hash.as_deref(),
+        _ => None,
+    };
+    refs.update(local_ref, &entry.hash, expected_old_hash)?;
+    if refs.has_reflog(local_ref) {
+        let message = format!("{}: {}", options.reflog_action, message);
+        refs.append_reflog(
+            local_ref,
+            old_hash.as_deref(),
+            &entry.hash,
+            identity,
+            &message,
+        )?;
+    }
+    fetched_ref.status = status;
+    Ok(fetched_ref)
+}
+
+/// the commit was queued
+const SEEN: u8 = 1;
+/// the server has the commit, so it has its ancestors too
+const COMMON: u8 = 1 << 1;
+/// a remote ref points to the commit: it is common but the server does not
+/// know that we have it yet
+const COMMON_REF: u8 = 1 << 2;
+/// the commit was taken from the queue
+const POPPED: u8 = 1 << 3;
+
+/// Chooses the haves to send, as git's default negotiator: the local commits
+/// are walked by decreasing commit date from the refs, skipping the ancestors
+/// of the commits known to be common
+struct Negotiator<'a> {
+    object_database: &'a GitObjectDatabase,
+    /// commit date and parents of the commits read so far
+    commits: HashMap<String, (i64, Vec<String>)>,
+    flags: HashMap<String, u8>,
+    /// commit date, then reversed push order
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    nb_pushed: usize,
+    /// queued commits that are not known to be common. Nothing is left to
+    /// tell the server when there is none
+    nb_non_common: usize,
+}
+
+impl<'a> Negotiator<'a> {
+    fn new(object_database: &'a GitObjectDatabase) -> Self {
+        Self {
+            object_database,
+            commits: HashMap::new(),
+            flags: HashMap::new(),
+            queue: BinaryHeap::new(),
+            nb_pushed: 0,
+            nb_non_common: 0,
+        }
+    }
+
+    fn flags(&self, hash: &str) -> u8 {
+        self.flags.get(hash).copied().unwrap_or_default()
+    }
+
+    /// Reads the commit, or returns it if it was already read
+    fn commit(&mut self, hash: &str) -> Result<&(i64, Vec<String>)> {
+        if !self.commits.contains_key(hash) {
+            let commit = match self.object_database.read_object(hash)?.content {
+                GitObjectContent::Commit { content } => {
+                    (content.committer.timestamp, content.parents_sha)
+                }
+                _ => return Err(Error::InvalidRevision(hash.to_string())),
+            };
+            self.commits.insert(hash.to_string(), commit);
+        }
+        self.commits.get(hash).ok_or(Error::Unreachable)
+    }
+
+    /// Queues the commit with the flags, unless it already has one of them
+    fn push(&mut self, hash: &str, mark: u8) -> Result<()> {
+        if self.flags(hash) & mark != 0 {
+            return Ok(());
+        }
+        *self.flags.entry(hash.to_string()).or_default() |= mark;
+        let date = self.commit(hash)?.0;
+        self.nb_pushed += 1;
+        self.queue
+            .push((date, Reverse(self.nb_pushed), hash.to_string()));
+        if mark & COMMON == 0 {
+            self.nb_non_common += 1;
+        }
+        Ok(())
+    }
+
+    /// Starts the walk from a local ref
+    fn add_tip(&mut self, hash: &str) -> Result<()> {
+        self.push(hash, SEEN)
+    }
+
+    /// A remote ref points to a commit we have: it is sent, but not its ancestors
+    fn known_common(&mut self, hash: &str) -> Result<()> {
+        if self.flags(hash) & SEEN == 0 {
+            self.push(hash, COMMON_REF | SEEN)?;
+            self.mark_common(hash, true)?;
+        }
+        Ok(())
+    }
+
+    /// The server acknowledged the commit. Returns if it was already known
+    /// to be common
+    fn ack(&mut self, hash: &str) -> Result<bool> {
+        let was_common = self.flags(hash) & COMMON != 0;
+        self.mark_common(hash, false)?;
+        Ok(was_common)
+    }
+
+    /// Marks the commit (unless only its ancestors are) and its ancestors as
+    /// common, so that they are not sent
+    fn mark_common(&mut self, hash: &str, ancestors_only: bool) -> Result<()> {
+        let mut stack = vec![(hash.to_string(), ancestors_only)];
+        while let Some((hash, ancestors_only)) = stack.pop() {
+            let flags = self.flags(&hash);
+            if flags & COMMON != 0 {
+                continue;
+            }
+            if !ancestors_only {
+                *self.flags.entry(hash.clone()).or_default() |= COMMON;
+            }
+            if flags & SEEN == 0 {
+                self.push(&hash, SEEN)?;
+                continue;
+            }
+            if !ancestors_only && flags & POPPED == 0 {
+                self.nb_non_common = self.nb_non_common.saturating_sub(1);
+            }
+            let parents = self.commit(&hash)?.1.clone();
+            stack.extend(parents.into_iter().rev().map(|parent| (parent, false)));
+        }
+        Ok(())
+    }
+
+    /// Next commit to send as a have, most recent first. The ancestors of common
+    /// commits are walked to mark them common, without being sent
+    fn next(&mut self) -> Result<Option<String>> {
+        loop {
+            if self.nb_non_common == 0 {
+                return Ok(None);
+            }
+            let Some((_, _, hash)) = self.queue.pop() else {
+                return Ok(None);
+            };
+            let parents = self.commit(&hash)?.1.clone();
+            let flags = self.flags(&hash) | POPPED;
+            self.flags.insert(hash.clone(), flags);
+            if flags & COMMON == 0 {
+                self.nb_non_common = self.nb_non_common.saturating_sub(1);
+            }
+            let mark = if flags & (COMMON | COMMON_REF) != 0 {
+                COMMON | SEEN
+            } else {
+                SEEN
+            };
+            for parent in parents {
+                if self.flags(&parent) & SEEN == 0 {
+                    self.push(&parent, mark)?;
+                }
+                if mark & COMMON != 0 {
+                    self.mark_common(&parent, true)?;
+                }
+            }
+            if flags & COMMON == 0 {
+                return Ok(Some(hash));
+            }
+        }
+    }
+}
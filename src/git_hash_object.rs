@@ -1,17 +1,16 @@
 use std::path::Path;
 
 use crate::git_object::GitObject;
+use crate::git_repository::Repository;
 use crate::Result;
 
 /// Returns hash of blob.
-/// Optionally writes it
-pub fn git_hash_object<P: AsRef<Path>>(write_obj: bool, file: P) -> Result<()> {
+/// Optionally writes it, if a repository is given
+pub fn git_hash_object<P: AsRef<Path>>(repository: Option<&Repository>, file: P) -> Result<String> {
     let git_obj = GitObject::from_blob(file)?;
 
-    if write_obj {
-        git_obj.write(".", true)?;
+    if let Some(repository) = repository {
+        git_obj.write(repository.object_database())?;
     }
-    println!("{}", git_obj.hash);
-
-    Ok(())
+    Ok(git_obj.hash)
 }
@@ -0,0 +1,440 @@
+use std::ffi::CStr;
+
+use crate::git_config::GitConfig;
+use crate::git_object::Signature;
+use crate::{Error, Result};
+
+/// Identity of the author of a new commit, from GIT_AUTHOR_NAME, GIT_AUTHOR_EMAIL
+/// and GIT_AUTHOR_DATE, or the config (author.name, then user.name...)
+pub fn author_signature(config: &GitConfig) -> Result<Signature> {
+    signature(config, "author")
+}
+
+/// Identity of the committer of a new commit, from GIT_COMMITTER_NAME,
+/// GIT_COMMITTER_EMAIL and GIT_COMMITTER_DATE, or the config (committer.name,
+/// then user.name...)
+pub fn committer_signature(config: &GitConfig) -> Result<Signature> {
+    signature(config, "committer")
+}
+
+/// Resolves the identity the same way git does:
+/// - the environment variable (GIT_AUTHOR_NAME)
+/// - the config of the role (author.name), then user.name
+/// - for the email, the EMAIL environment variable
+/// - the system: name of the user and user@hostname
+///
+/// The date is the current time with the local timezone, unless overridden by
+/// the environment (GIT_AUTHOR_DATE)
+fn signature(config: &GitConfig, role: &str) -> Result<Signature> {
+    let env_prefix = format!("GIT_{}", role.to_ascii_uppercase());
+    let system_user = system_user();
+
+    let name = std::env::var(format!("{}_NAME", env_prefix))
+        .ok()
+        .or_else(|| config.get(&format!("{}.name", role)).map(str::to_string))
+        .or_else(|| config.get("user.name").map(str::to_string))
+        .or_else(|| system_user.as_ref().map(|(_, full_name)| full_name.clone()))
+        .unwrap_or_default();
+    if name.trim().is_empty() {
+        return Err(Error::EmptyIdentName);
+    }
+
+    let email = std::env::var(format!("{}_EMAIL", env_prefix))
+        .ok()
+        .or_else(|| config.get(&format!("{}.email", role)).map(str::to_string))
+        .or_else(|| config.get("user.email").map(str::to_string))
+        .or_else(|| std::env::var("EMAIL").ok())
+        .or_else(|| {
+            system_user
+                .as_ref()
+                .map(|(login, _)| format!("{}@{}", login, hostname()))
+        })
+        .unwrap_or_default();
+
+    let (timestamp, timezone) = match std::env::var(format!("{}_DATE", env_prefix)) {
+        Ok(date) => parse_date(&date)?,
+        Err(_) => current_date(),
+    };
+
+    // <, > and newlines would make the signature ambiguous
+    let sanitize = |value: &str| {
+        value
+            .chars()
+            .filter(|c| !matches!(c, '<' | '>' | '\n'))
+            .collect::<String>()
+            .trim()
+            .to_string()
+    };
+    Ok(Signature {
+        name: sanitize(&name).into_bytes(),
+        email: sanitize(&email).into_bytes(),
+        timestamp,
+        timezone,
+        raw: None,
+    })
+}
+
+/// Login and full name of the current user, from the password database
+fn system_user() -> Option<(String, String)> {
+    // SAFETY: getpwuid returns either null or a pointer to a static struct whose
+    // strings are null terminated. They are copied right away
+    unsafe {
+        let passwd = libc::getpwuid(libc::getuid());
+        if passwd.is_null() {
+            return None;
+        }
+        let login = CStr::from_ptr((*passwd).pw_name)
+            .to_string_lossy()
+            .to_string();
+        // the gecos field may contain other information after a comma
+        let full_name = match (*passwd).pw_gecos.is_null() {
+            true => String::new(),
+            false => CStr::from_ptr((*passwd).pw_gecos)
+                .to_string_lossy()
+                .split(',')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        };
+        match full_name.is_empty() {
+            true => Some((login.clone(), login)),
+            false => Some((login, full_name)),
+        }
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length, and the last byte is kept
+    // to 0 so that the name is null terminated even if it was truncated
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) };
+    if result != 0 {
+        return "(none)".to_string();
+    }
+    CStr::from_bytes_until_nul(&buf)
+        .map(|hostname| hostname.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Current time, with the offset of the local timezone
+pub fn current_date() -> (i64, String) {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default();
+    (timestamp, format_timezone(local_utc_offset(timestamp)))
+}
+
+/// Offset in seconds of the local timezone at the given time (it changes with
+/// daylight saving time)
+fn local_utc_offset(timestamp: i64) -> i64 {
+    let time = timestamp as libc::time_t;
+    // SAFETY: localtime_r only writes to the struct we give it
+    unsafe {
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return 0;
+        }
+        tm.tm_gmtoff as i64
+    }
+}
+
+/// Formats an offset in seconds as +hhmm
+pub fn format_timezone(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Parses +hhmm, +hh:mm, +hh or Z as an offset in seconds
+fn parse_timezone(timezone: &str) -> Option<i64> {
+    if timezone == "Z" {
+        return Some(0);
+    }
+    let sign = match timezone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = timezone[1..].replace(':', "");
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i64>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i64>().ok()?,
+            digits[2..].parse::<i64>().ok()?,
+        ),
+        _ => return None,
+    };
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Number of days since 1970-01-01 of a date of the proleptic Gregorian calendar
+/// see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Date of the proleptic Gregorian calendar from the number of days since
+/// 1970-01-01, the inverse of days_from_civil
+/// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Timestamp of a date and time expressed in the given timezone
+fn to_timestamp(date: (i64, i64, i64), time: (i64, i64, i64), offset: i64) -> i64 {
+    let (year, month, day) = date;
+    let (hours, minutes, seconds) = time;
+    days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds - offset
+}
+
+/// Parses HH:MM:SS (or HH:MM)
+fn parse_time(time: &str) -> Option<(i64, i64, i64)> {
+    // fractional seconds are ignored
+    let time = time.split('.').next()?;
+    let mut parts = time.split(':').map(|part| part.parse::<i64>().ok());
+    let hours = parts.next()??;
+    let minutes = parts.next()??;
+    let seconds = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some((hours, minutes, seconds))
+}
+
+/// Parses a date given in GIT_AUTHOR_DATE or GIT_COMMITTER_DATE.
+/// Supported formats:
+/// - git internal format: `1714305310 +0200` (optionally prefixed with @)
+/// - RFC 2822: `Sun, 28 Apr 2024 13:55:10 +0200`
+/// - ISO 8601: `2024-04-28 13:55:10 +0200` or `2024-04-28T13:55:10+02:00`
+///
+/// Without timezone, the date is in the local timezone
+pub fn parse_date(date: &str) -> Result<(i64, String)> {
+    parse_internal_date(date)
+        .or_else(|| parse_rfc2822_date(date))
+        .or_else(|| parse_iso8601_date(date))
+        .ok_or_else(|| Error::InvalidDate(date.to_string()))
+}
+
+fn parse_internal_date(date: &str) -> Option<(i64, String)> {
+    let date = date.trim();
+    let date = date.strip_prefix('@').unwrap_or(date);
+    let (timestamp, timezone) = date.split_once(' ').unwrap_or((date, "+0000"));
+    let timestamp = timestamp.parse::<i64>().ok()?;
+    let offset = parse_timezone(timezone.trim())?;
+    Some((timestamp, format_timezone(offset)))
+}
+
+fn parse_rfc2822_date(date: &str) -> Option<(i64, String)> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    // the day of the week is optional
+    let date = date.split_once(", ").map_or(date, |(_, date)| date);
+    let parts = date.split_whitespace().collect::<Vec<_>>();
+    let [day, month, year, time, rest @ ..] = parts.as_slice() else {
+        return None;
+    };
+    let month = MONTHS
+        .iter()
+        .position(|m| month.to_ascii_lowercase().starts_with(m))? as i64
+        + 1;
+    let day = day.parse::<i64>().ok()?;
+    let year = year.parse::<i64>().ok()?;
+    let time = parse_time(time)?;
+    Some(with_offset((year, month, day), time, rest.first().copied()))
+}
+
+fn parse_iso8601_date(date: &str) -> Option<(i64, String)> {
+    let date = date.trim();
+    let (day_part, rest) = date.split_once(['T', ' '])?;
+    let mut day_parts = day_part.split('-').map(|part| part.parse::<i64>().ok());
+    let year = day_parts.next()??;
+    let month = day_parts.next()??;
+    let day = day_parts.next()??;
+    if day_parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // the timezone is either separated by a space or stuck to the time
+    let rest = rest.trim();
+    let (time, timezone) = match rest.find(['+', '-', 'Z', ' ']) {
+        Some(pos) => (&rest[..pos], Some(rest[pos..].trim())),
+        None => (rest, None),
+    };
+    let time = parse_time(time)?;
+    Some(with_offset((year, month, day), time, timezone))
+}
+
+/// Converts a local date, using the given timezone or the local one
+fn with_offset(
+    date: (i64, i64, i64),
+    time: (i64, i64, i64),
+    timezone: Option<&str>,
+) -> (i64, String) {
+    let offset = match timezone.and_then(parse_timezone) {
+        Some(offset) => offset,
+        None => {
+            // the offset depends on the date itself
+            let utc_timestamp = to_timestamp(date, time, 0);
+            local_utc_offset(utc_timestamp - local_utc_offset(utc_timestamp))
+        }
+    };
+    (to_timestamp(date, time, offset), format_timezone(offset))
+}
+
+/// How dates are displayed, see the --date option of git log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DateFormat {
+    /// `Sun Apr 28 13:55:10 2024 +0200`
+    #[default]
+    Default,
+    /// `2024-04-28 13:55:10 +0200`
+    Iso,
+    /// `2024-04-28T13:55:10+02:00`
+    IsoStrict,
+    /// `Sun, 28 Apr 2024 13:55:10 +0200`
+    Rfc2822,
+    /// `2024-04-28`
+    Short,
+    /// `1714305310 +0200`
+    Raw,
+    /// `1714305310`
+    Unix,
+    /// `3 hours ago`
+    Relative,
+}
+
+impl DateFormat {
+    /// Parses the name of a format, as given to --date
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "default" => Ok(Self::Default),
+            "iso" | "iso8601" => Ok(Self::Iso),
+            "iso-strict" | "iso8601-strict" => Ok(Self::IsoStrict),
+            "rfc" | "rfc2822" => Ok(Self::Rfc2822),
+            "short" => Ok(Self::Short),
+            "raw" => Ok(Self::Raw),
+            "unix" => Ok(Self::Unix),
+            "relative" => Ok(Self::Relative),
+            _ => Err(Error::InvalidDateFormat(name.to_string())),
+        }
+    }
+}
+
+/// Formats a date, in the timezone it was recorded with
+pub fn format_date(timestamp: i64, timezone: &str, format: DateFormat) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let offset = parse_timezone(timezone).unwrap_or_default();
+    // a date out of range is shown as the epoch, as git does
+    let (local_timestamp, offset) = match timestamp.checked_add(offset) {
+        Some(local_timestamp) => (local_timestamp, offset),
+        None => (0, 0),
+    };
+    let days = local_timestamp.div_euclid(86400);
+    let seconds_of_day = local_timestamp.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let (hours, minutes, seconds) = (
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+    );
+    // 1970-01-01 was a Thursday
+    let day_name = DAYS[days.rem_euclid(7) as usize];
+    let month_name = MONTHS[(month - 1) as usize];
+    let time = format!("{:02}:{:02}:{:02}", hours, minutes, seconds);
+    let timezone = format_timezone(offset);
+
+    match format {
+        DateFormat::Default => format!(
+            "{} {} {} {} {} {}",
+            day_name, month_name, day, time, year, timezone
+        ),
+        DateFormat::Iso => format!("{}-{:02}-{:02} {} {}", year, month, day, time, timezone),
+        DateFormat::IsoStrict => format!(
+            "{}-{:02}-{:02}T{}{}:{}",
+            year,
+            month,
+            day,
+            time,
+            &timezone[..3],
+            &timezone[3..]
+        ),
+        DateFormat::Rfc2822 => format!(
+            "{}, {} {} {} {} {}",
+            day_name, day, month_name, year, time, timezone
+        ),
+        DateFormat::Short => format!("{}-{:02}-{:02}", year, month, day),
+        DateFormat::Raw => format!("{} {}", timestamp, timezone),
+        DateFormat::Unix => timestamp.to_string(),
+        DateFormat::Relative => format_relative_date(current_date().0.saturating_sub(timestamp)),
+    }
+}
+
+/// Describes how long ago something happened, rounded as git does
+fn format_relative_date(seconds: i64) -> String {
+    let plural = |count: i64, unit: &str| match count {
+        1 => format!("{} {}", count, unit),
+        _ => format!("{} {}s", count, unit),
+    };
+    if seconds < 0 {
+        return "in the future".to_string();
+    }
+    if seconds < 90 {
+        return format!("{} ago", plural(seconds, "second"));
+    }
+    let minutes = seconds.saturating_add(30) / 60;
+    if minutes < 90 {
+        return format!("{} ago", plural(minutes, "minute"));
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return format!("{} ago", plural(hours, "hour"));
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return format!("{} ago", plural(days, "day"));
+    }
+    if days < 70 {
+        return format!("{} ago", plural((days + 3) / 7, "week"));
+    }
+    if days < 365 {
+        return format!("{} ago", plural((days + 15) / 30, "month"));
+    }
+    if days < 1825 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        return match months {
+            0 => format!("{} ago", plural(years, "year")),
+            _ => format!("{}, {} ago", plural(years, "year"), plural(months, "month")),
+        };
+    }
+    format!("{} ago", plural((days + 183) / 365, "year"))
+}
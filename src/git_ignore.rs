@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::git_repository::Repository;
+use crate::Result;
+
+/// A line of an ignore file
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    /// directory of the .gitignore file, relative to the work tree (empty for
+    /// the root and the other ignore files)
+    base: String,
+    pattern: String,
+    /// `!pattern`: the path is not ignored, even if a previous pattern
+    /// ignored it
+    negated: bool,
+    /// `pattern/`: only matches directories
+    dir_only: bool,
+    /// the pattern contains a `/`: it matches the path relative to the base,
+    /// instead of the name of the file at any depth
+    anchored: bool,
+}
+
+impl IgnorePattern {
+    /// Parses a line of an ignore file. Returns None for blank lines and
+    /// comments
+    fn parse(line: &str, base: &str) -> Option<Self> {
+        // trailing spaces are ignored unless escaped
+        let line = match line.trim_end().ends_with('\\') {
+            true => line.trim_end_matches(|c| c != ' ' && c != '\\'),
+            false => line.trim_end(),
+        };
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+        Some(Self {
+            base: base.to_string(),
+            pattern: line.trim_start_matches('/').to_string(),
+            negated,
+            dir_only,
+            anchored: line.contains('/'),
+        })
+    }
+
+    /// Whether the pattern matches the path (relative to the work tree)
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative_path = match self.base.is_empty() {
+            true => path,
+            false => match path
+                .strip_prefix(self.base.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(relative_path) => relative_path,
+                None => return false,
+            },
+        };
+        let text = match self.anchored {
+            true => relative_path,
+            false => relative_path.rsplit('/').next().unwrap_or(relative_path),
+        };
+        wildmatch(self.pattern.as_bytes(), text.as_bytes())
+    }
+}
+
+/// Rules telling which untracked files are ignored, read from the .gitignore
+/// files of the work tree, .git/info/exclude and core.excludesFile (by default
+/// $XDG_CONFIG_HOME/git/ignore)
+#[derive(Debug)]
+pub struct IgnoreRules {
+    work_tree: PathBuf,
+    /// patterns of the .gitignore file of each directory already looked at
+    gitignores: HashMap<String, Vec<IgnorePattern>>,
+    /// patterns of .git/info/exclude then core.excludesFile
+    excludes: Vec<Vec<IgnorePattern>>,
+}
+
+impl IgnoreRules {
+    pub fn load(repository: &Repository) -> Result<Self> {
+        let excludes_file = match repository.config()?.get("core.excludesFile") {
+            Some(path) => match path.strip_prefix("~/") {
+                Some(path) => std::env::var_os("HOME").map(|home| Path::new(&home).join(path)),
+                None => Some(PathBuf::from(path)),
+            },
+            None => std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+                .map(|config_home| config_home.join("git").join("ignore")),
+        };
+        let mut excludes = vec![read_patterns(
+            &repository.git_dir().join("info").join("exclude"),
+            "",
+        )?];
+        if let Some(excludes_file) = excludes_file {
+            excludes.push(read_patterns(&excludes_file, "")?);
+        }
+        Ok(Self {
+            work_tree: repository.work_tree()?.to_path_buf(),
+            gitignores: HashMap::new(),
+            excludes,
+        })
+    }
+
+    /// Whether the path (relative to the work tree) is ignored, or is in an
+    /// ignored directory
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> Result<bool> {
+        let mut dir_end = 0;
+        while let Some(slash) = path[dir_end..].find('/') {
+            dir_end += slash;
+            if self.is_excluded(&path[..dir_end], true)? {
+                return Ok(true);
+            }
+            dir_end += 1;
+        }
+        self.is_excluded(path, is_dir)
+    }
+
+    /// Whether the last pattern matching the path ignores it. The .gitignore
+    /// files of the deepest directories come first, then the other files
+    fn is_excluded(&mut self, path: &str, is_dir: bool) -> Result<bool> {
+        let mut dir = path;
+        while !dir.is_empty() {
+            dir = dir.rsplit_once('/').map_or("", |(parent, _)| parent);
+            if !self.gitignores.contains_key(dir) {
+                let gitignore = self.work_tree.join(dir).join(".gitignore");
+                self.gitignores
+                    .insert(dir.to_string(), read_patterns(&gitignore, dir)?);
+            }
+            if let Some(pattern) = last_match(&self.gitignores[dir], path, is_dir) {
+                return Ok(!pattern.negated);
+            }
+        }
+        for patterns in &self.excludes {
+            if let Some(pattern) = last_match(patterns, path, is_dir) {
+                return Ok(!pattern.negated);
+            }
+        }
+        Ok(false)
+    }
+}
+
+fn last_match<'a>(
+    patterns: &'a [IgnorePattern],
+    path: &str,
+    is_dir: bool,
+) -> Option<&'a IgnorePattern> {
+    patterns
+        .iter()
+        .rev()
+        .find(|pattern| pattern.matches(path, is_dir))
+}
+
+/// Patterns of an ignore file, none if it does not exist
+fn read_patterns(path: &Path, base: &str) -> Result<Vec<IgnorePattern>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read(path)?;
+    Ok(String::from_utf8_lossy(&content)
+        .lines()
+        .filter_map(|line| IgnorePattern::parse(line, base))
+        .collect())
+}
+
+/// Matches the text against a glob pattern: `*` matches anything but `/`, `**`
+/// anything including `/` (`a/**/b` matches `a/b`), `?` one character but `/`,
+/// `[a-z]` or `[!a-z]` a class of characters, and `\` escapes the next one
+fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[pattern.iter().take_while(|c| **c == b'*').count()..];
+            // `**/` also matches no directory at all
+            if rest.first() == Some(&b'/') && wildmatch(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|i| wildmatch(rest, &text[i..]))
+        }
+        Some(b'*') => {
+            let max = text.iter().position(|c| *c == b'/').unwrap_or(text.len());
+            (0..=max).any(|i| wildmatch(&pattern[1..], &text[i..]))
+        }
+        Some(b'?') => match text.first() {
+            Some(c) if *c != b'/' => wildmatch(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => {
+            let Some(&c) = text.first().filter(|c| **c != b'/') else {
+                return false;
+            };
+            match match_class(&pattern[1..], c) {
+                Some((true, rest)) => wildmatch(rest, &text[1..]),
+                Some((false, _)) => false,
+                // no closing bracket: the [ is a normal character
+                None => c == b'[' && wildmatch(&pattern[1..], &text[1..]),
+            }
+        }
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && wildmatch(&pattern[2..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && wildmatch(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Whether the character is in the class starting after the `[`, and the
+/// pattern after the closing `]`. None if the class is not closed
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut i) = match pattern.first() {
+        Some(b'!' | b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = *pattern.get(i)?;
+        // a ] right after the [ is part of the class
+        if start == b']' && !first {
+            return Some((matched != negated, &pattern[i + 1..]));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|end| *end != b']') {
+            matched |= (start..=pattern[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_ignored(lines: &[&str], path: &str, is_dir: bool) -> bool {
+        let patterns = lines
+            .iter()
+            .filter_map(|line| IgnorePattern::parse(line, ""))
+            .collect::<Vec<_>>();
+        last_match(&patterns, path, is_dir).is_some_and(|pattern| !pattern.negated)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildmatch(b"*.log", b"debug.log"));
+        assert!(!wildmatch(b"*.log", b"logs/debug.txt"));
+        assert!(!wildmatch(b"*.log", b"dir/debug.log"));
+        assert!(wildmatch(b"a?c", b"abc"));
+        assert!(!wildmatch(b"a?c", b"a/c"));
+        assert!(wildmatch(b"[a-c]x", b"bx"));
+        assert!(!wildmatch(b"[!a-c]x", b"bx"));
+        assert!(wildmatch(b"[]]", b"]"));
+        assert!(wildmatch(b"\\*", b"*"));
+        assert!(!wildmatch(b"\\*", b"a"));
+        assert!(wildmatch(b"docs/**/tmp", b"docs/tmp"));
+        assert!(wildmatch(b"docs/**/tmp", b"docs/a/b/tmp"));
+        assert!(wildmatch(b"**/tmp", b"a/tmp"));
+        assert!(wildmatch(b"a/**", b"a/b/c"));
+    }
+
+    #[test]
+    fn patterns() {
+        let lines = [
+            "# comment",
+            "",
+            "*.log",
+            "!keep.log",
+            "target/",
+            "/root-only",
+        ];
+        assert!(is_ignored(&lines, "x.log", false));
+        assert!(is_ignored(&lines, "src/x.log", false));
+        assert!(!is_ignored(&lines, "keep.log", false));
+        assert!(!is_ignored(&lines, "src/keep.log", false));
+        assert!(is_ignored(&lines, "target", true));
+        assert!(is_ignored(&lines, "src/target", true));
+        assert!(!is_ignored(&lines, "target", false));
+        assert!(is_ignored(&lines, "root-only", false));
+        assert!(!is_ignored(&lines, "src/root-only", false));
+        assert!(!is_ignored(&lines, "# comment", false));
+    }
+
+    #[test]
+    fn trailing_spaces() {
+        assert_eq!(
+            IgnorePattern::parse("a.txt  ", "").unwrap().pattern,
+            "a.txt"
+        );
+        assert_eq!(IgnorePattern::parse("a\\ ", "").unwrap().pattern, "a\\ ");
+        assert!(IgnorePattern::parse("   ", "").is_none());
+    }
+
+    #[test]
+    fn patterns_of_nested_gitignore() {
+        let pattern = IgnorePattern::parse("/gen.c", "src/sub").unwrap();
+        assert!(pattern.matches("src/sub/gen.c", false));
+        assert!(!pattern.matches("src/gen.c", false));
+        assert!(!pattern.matches("src/sub/dir/gen.c", false));
+        let pattern = IgnorePattern::parse("*.c", "src").unwrap();
+        assert!(pattern.matches("src/sub/gen.c", false));
+        assert!(!pattern.matches("other/gen.c", false));
+    }
+}
@@ -0,0 +1,692 @@
+use std::collections::BTreeSet;
+use std::fs::Metadata;
+use std::io::{BufRead, Cursor, Read};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+
+use crate::git_object::{GitObject, GitObjectContent};
+use crate::git_object_database::GitObjectDatabase;
+use crate::git_pack::GitPack;
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// First 4 bytes of the index file
+const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+/// Signature of the cache tree extension
+const TREE_EXTENSION_SIGNATURE: &[u8; 4] = b"TREE";
+
+/// flags of an entry: 1 bit assume-valid, 1 bit extended, 2 bits stage,
+/// 12 bits name length
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+
+/// A file tracked by the index, with the stat data of the file when it was added.
+/// The stat data allows to know if a file changed without hashing it again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime_seconds: u32,
+    pub ctime_nanoseconds: u32,
+    pub mtime_seconds: u32,
+    pub mtime_nanoseconds: u32,
+    pub dev: u32,
+    pub ino: u32,
+    /// Same convention as in trees: 100644 for files, 100755 for executables,
+    /// 120000 for symbolic links and 160000 for submodules
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// size of the file, truncated to 32 bits
+    pub size: u32,
+    /// hash of the blob
+    pub hash: String,
+    /// assume-valid, extended and stage bits. The name length is computed
+    /// from the path when writing
+    pub flags: u16,
+    /// Only present in version 3 and later, when the extended flag is set
+    /// (skip-worktree, intent-to-add)
+    pub extended_flags: u16,
+    /// path relative to the root of the repository, with / as separator
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// Creates an entry for a file of the working tree with its stat data
+    pub fn from_metadata(path: &str, hash: &str, metadata: &Metadata) -> Self {
+        Self {
+            ctime_seconds: metadata.ctime() as u32,
+            ctime_nanoseconds: metadata.ctime_nsec() as u32,
+            mtime_seconds: metadata.mtime() as u32,
+            mtime_nanoseconds: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode: Self::mode_from_metadata(metadata),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.len() as u32,
+            hash: hash.to_string(),
+            flags: 0,
+            extended_flags: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// Creates an entry without stat data, at the given stage. The file is
+    /// hashed again the next time it is compared to the working tree
+    pub fn new(path: &str, mode: u32, hash: &str, stage: u16) -> Self {
+        Self {
+            ctime_seconds: 0,
+            ctime_nanoseconds: 0,
+            mtime_seconds: 0,
+            mtime_nanoseconds: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash: hash.to_string(),
+            flags: (stage << 12) & FLAG_STAGE_MASK,
+            extended_flags: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// Mode as stored in trees, based on the type and permissions of the file
+    pub fn mode_from_metadata(metadata: &Metadata) -> u32 {
+        if metadata.file_type().is_symlink() {
+            120000
+        } else if metadata.permissions().mode() & 0o111 != 0 {
+            100755
+        } else {
+            100644
+        }
+    }
+
+    /// 0 for normal entries, 1 to 3 for the base, ours and theirs versions
+    /// of a file in conflict
+    pub fn stage(&self) -> u16 {
+        (self.flags & FLAG_STAGE_MASK) >> 12
+    }
+
+    /// Checks if the stat data of the file still matches the entry, in which
+    /// case the file is considered unchanged and does not need to be hashed
+    pub fn matches_metadata(&self, metadata: &Metadata) -> bool {
+        self.mtime_seconds == metadata.mtime() as u32
+            && self.mtime_nanoseconds == metadata.mtime_nsec() as u32
+            && self.ctime_seconds == metadata.ctime() as u32
+            && self.ctime_nanoseconds == metadata.ctime_nsec() as u32
+            && self.ino == metadata.ino() as u32
+            && self.size == metadata.len() as u32
+            && self.mode == Self::mode_from_metadata(metadata)
+    }
+}
+
+/// Cache tree (TREE extension): the hashes of the trees that would be written
+/// for each directory of the index, so that write-tree does not need to
+/// recompute the trees of unchanged directories.
+/// A directory whose content changed is invalidated with an entry_count of -1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheTree {
+    /// name of the directory (empty for the root)
+    pub name: String,
+    /// number of index entries covered by this tree, -1 if invalid
+    pub entry_count: i32,
+    /// hash of the tree, only if valid
+    pub hash: Option<String>,
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    /// Parses a node and its subtrees (stored in pre-order)
+    fn from_reader<R: BufRead>(reader: &mut R) -> Result<Self> {
+        let mut name = Vec::new();
+        reader.read_until(0, &mut name)?;
+        if name.pop() != Some(0) {
+            return Err(Error::InvalidIndex);
+        }
+        let mut counts = Vec::new();
+        reader.read_until(b'\n', &mut counts)?;
+        counts.pop();
+        let counts = String::from_utf8(counts).map_err(|_| Error::InvalidIndex)?;
+        let (entry_count, subtree_count) =
+            counts.split_once(' ').ok_or_else(|| Error::InvalidIndex)?;
+        let entry_count = entry_count
+            .parse::<i32>()
+            .map_err(|_| Error::InvalidIndex)?;
+        let subtree_count = subtree_count
+            .parse::<usize>()
+            .map_err(|_| Error::InvalidIndex)?;
+
+        let hash = if entry_count >= 0 {
+            let mut buf = [0u8; 20];
+            reader.read_exact(&mut buf)?;
+            Some(hex::encode(buf))
+        } else {
+            None
+        };
+
+        let mut subtrees = Vec::with_capacity(subtree_count);
+        for _ in 0..subtree_count {
+            subtrees.push(Self::from_reader(reader)?);
+        }
+
+        Ok(Self {
+            name: String::from_utf8_lossy(&name).to_string(),
+            entry_count,
+            hash,
+            subtrees,
+        })
+    }
+
+    fn write_to(&self, bytes: &mut Vec<u8>) -> Result<()> {
+        bytes.extend(self.name.as_bytes());
+        bytes.push(0);
+        bytes.extend(format!("{} {}\n", self.entry_count, self.subtrees.len()).as_bytes());
+        if let (true, Some(hash)) = (self.entry_count >= 0, &self.hash) {
+            bytes.extend(hex::decode(hash).map_err(|_| Error::InvalidHash(hash.clone()))?);
+        }
+        for subtree in &self.subtrees {
+            subtree.write_to(bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Invalidates the trees containing the path: the root and every directory
+    /// of the path
+    fn invalidate(&mut self, path: &str) {
+        self.entry_count = -1;
+        self.hash = None;
+        if let Some((dir, rest)) = path.split_once('/') {
+            if let Some(subtree) = self.subtrees.iter_mut().find(|t| t.name == dir) {
+                subtree.invalidate(rest);
+            }
+        }
+    }
+}
+
+/// The staging area (.git/index).
+/// see https://github.com/git/git/blob/795ea8776befc95ea2becd8020c7a284677b4161/Documentation/gitformat-index.txt
+/// - 12 bytes header: DIRC, version (2, 3 or 4) and number of entries
+/// - the entries, sorted by path then stage
+/// - extensions: 4 bytes signature, 4 bytes size and the data
+/// - the sha1 of all of the above
+#[derive(Debug, Clone)]
+pub struct GitIndex {
+    pub version: u32,
+    pub entries: Vec<IndexEntry>,
+    pub cache_tree: Option<CacheTree>,
+    /// optional extensions we do not handle (signature, data). They are kept
+    /// as is, until the entries change and they may no longer be valid
+    pub other_extensions: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Default for GitIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GitIndex {
+    /// Empty index
+    pub fn new() -> Self {
+        Self {
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+            other_extensions: Vec::new(),
+        }
+    }
+
+    /// Reads .git/index. If there is no index yet, returns an empty one
+    pub fn read(repository: &Repository) -> Result<Self> {
+        let index_path = repository.git_dir().join("index");
+        if !index_path.exists() {
+            return Ok(Self::new());
+        }
+        Self::from_bytes(&std::fs::read(index_path)?)
+    }
+
+    /// Writes .git/index. The content is first written to .git/index.lock, which
+    /// is then renamed so that the index is never partially written
+    pub fn write(&self, repository: &Repository) -> Result<()> {
+        let index_path = repository.git_dir().join("index");
+        let lock_path = repository.git_dir().join("index.lock");
+        std::fs::write(&lock_path, self.to_bytes()?)?;
+        std::fs::rename(lock_path, index_path)?;
+        Ok(())
+    }
+
+    fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    /// Parses the content of an index file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 12 + 20 {
+            return Err(Error::InvalidIndex);
+        }
+        let (content, checksum) = bytes.split_at(bytes.len() - 20);
+        let expected_checksum = hex::encode(checksum);
+        let computed_checksum = GitObject::get_hash_from_bytes(content);
+        if expected_checksum != computed_checksum {
+            return Err(Error::IndexChecksumMismatch {
+                expected: expected_checksum,
+                got: computed_checksum,
+            });
+        }
+
+        let mut reader = Cursor::new(content);
+        let mut signature = [0u8; 4];
+        reader.read_exact(&mut signature)?;
+        if &signature != INDEX_SIGNATURE {
+            return Err(Error::InvalidIndex);
+        }
+        let version = Self::read_u32(&mut reader)?;
+        if !(2..=4).contains(&version) {
+            return Err(Error::UnsupportedIndexVersion(version));
+        }
+        let nb_entries = Self::read_u32(&mut reader)?;
+
+        let mut entries = Vec::with_capacity(nb_entries as usize);
+        let mut previous_path: Vec<u8> = Vec::new();
+        for _ in 0..nb_entries {
+            let entry_start = reader.position();
+            let ctime_seconds = Self::read_u32(&mut reader)?;
+            let ctime_nanoseconds = Self::read_u32(&mut reader)?;
+            let mtime_seconds = Self::read_u32(&mut reader)?;
+            let mtime_nanoseconds = Self::read_u32(&mut reader)?;
+            let dev = Self::read_u32(&mut reader)?;
+            let ino = Self::read_u32(&mut reader)?;
+            let mode = Self::read_u32(&mut reader)?;
+            let uid = Self::read_u32(&mut reader)?;
+            let gid = Self::read_u32(&mut reader)?;
+            let size = Self::read_u32(&mut reader)?;
+            let mut hash = [0u8; 20];
+            reader.read_exact(&mut hash)?;
+            let flags = Self::read_u16(&mut reader)?;
+            let extended_flags = if version >= 3 && flags & FLAG_EXTENDED != 0 {
+                Self::read_u16(&mut reader)?
+            } else {
+                0
+            };
+
+            let path = if version == 4 {
+                // the path is compressed: we get the number of bytes to remove from
+                // the end of the previous path, then the suffix to add
+                let nb_removed = GitPack::get_next_negative_offset(&mut reader)?;
+                let kept = previous_path
+                    .len()
+                    .checked_sub(nb_removed)
+                    .ok_or_else(|| Error::InvalidIndex)?;
+                let mut path = previous_path[..kept].to_vec();
+                let mut suffix = Vec::new();
+                reader.read_until(0, &mut suffix)?;
+                suffix.pop();
+                path.extend(suffix);
+                path
+            } else {
+                let mut path = Vec::new();
+                reader.read_until(0, &mut path)?;
+                path.pop();
+                // entries are padded with 1 to 8 null bytes so that their size is
+                // a multiple of 8. One of them was already read
+                let entry_size = reader.position() - entry_start;
+                let padding = (8 - entry_size % 8) % 8;
+                reader.set_position(reader.position() + padding);
+                path
+            };
+
+            entries.push(IndexEntry {
+                ctime_seconds,
+                ctime_nanoseconds,
+                mtime_seconds,
+                mtime_nanoseconds,
+                dev,
+                ino,
+                // stored as an octal value in the index
+                mode: format!("{:o}", mode)
+                    .parse::<u32>()
+                    .map_err(|_| Error::InvalidIndex)?,
+                uid,
+                gid,
+                size,
+                hash: hex::encode(hash),
+                flags: flags & !FLAG_NAME_MASK,
+                extended_flags,
+                path: String::from_utf8_lossy(&path).to_string(),
+            });
+            previous_path = path;
+        }
+
+        // then the extensions, until the checksum
+        let mut cache_tree = None;
+        let mut other_extensions = Vec::new();
+        while (reader.position() as usize) < content.len() {
+            let mut signature = [0u8; 4];
+            reader.read_exact(&mut signature)?;
+            let size = Self::read_u32(&mut reader)? as usize;
+            let mut data = vec![0; size];
+            reader.read_exact(&mut data)?;
+
+            if &signature == TREE_EXTENSION_SIGNATURE {
+                if !data.is_empty() {
+                    cache_tree = Some(CacheTree::from_reader(&mut Cursor::new(&data[..]))?);
+                }
+            } else if signature[0].is_ascii_uppercase() {
+                // optional extension
+                other_extensions.push((signature, data));
+            } else {
+                return Err(Error::UnsupportedIndexExtension(
+                    String::from_utf8_lossy(&signature).to_string(),
+                ));
+            }
+        }
+
+        Ok(Self {
+            version,
+            entries,
+            cache_tree,
+            other_extensions,
+        })
+    }
+
+    /// Serializes the index, with its checksum
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        // extended flags are only supported since version 3
+        let has_extended_flags = self.entries.iter().any(|e| e.extended_flags != 0);
+        let version = if has_extended_flags && self.version == 2 {
+            3
+        } else {
+            self.version
+        };
+
+        let mut bytes = Vec::new();
+        bytes.extend(INDEX_SIGNATURE);
+        bytes.extend(version.to_be_bytes());
+        bytes.extend((self.entries.len() as u32).to_be_bytes());
+
+        let mut previous_path: &[u8] = &[];
+        for entry in &self.entries {
+            let entry_start = bytes.len();
+            let mode =
+                u32::from_str_radix(&entry.mode.to_string(), 8).map_err(|_| Error::InvalidIndex)?;
+            for value in [
+                entry.ctime_seconds,
+                entry.ctime_nanoseconds,
+                entry.mtime_seconds,
+                entry.mtime_nanoseconds,
+                entry.dev,
+                entry.ino,
+                mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                bytes.extend(value.to_be_bytes());
+            }
+            bytes.extend(
+                hex::decode(&entry.hash).map_err(|_| Error::InvalidHash(entry.hash.clone()))?,
+            );
+
+            let path = entry.path.as_bytes();
+            let name_length = path.len().min(FLAG_NAME_MASK as usize) as u16;
+            let mut flags = (entry.flags & !FLAG_NAME_MASK & !FLAG_EXTENDED) | name_length;
+            if entry.extended_flags != 0 {
+                flags |= FLAG_EXTENDED;
+            }
+            bytes.extend(flags.to_be_bytes());
+            if entry.extended_flags != 0 {
+                bytes.extend(entry.extended_flags.to_be_bytes());
+            }
+
+            if version == 4 {
+                let common_prefix = previous_path
+                    .iter()
+                    .zip(path)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                bytes.extend(GitPack::encode_negative_offset(
+                    previous_path.len() - common_prefix,
+                ));
+                bytes.extend(&path[common_prefix..]);
+                bytes.push(0);
+            } else {
+                bytes.extend(path);
+                // 1 to 8 null bytes so that the size of the entry is a multiple of 8
+                let entry_size = bytes.len() - entry_start;
+                let padding = 8 - entry_size % 8;
+                bytes.extend(vec![0; padding]);
+            }
+            previous_path = path;
+        }
+
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut data = Vec::new();
+            cache_tree.write_to(&mut data)?;
+            bytes.extend(TREE_EXTENSION_SIGNATURE);
+            bytes.extend((data.len() as u32).to_be_bytes());
+            bytes.extend(data);
+        }
+        for (signature, data) in &self.other_extensions {
+            bytes.extend(signature);
+            bytes.extend((data.len() as u32).to_be_bytes());
+            bytes.extend(data);
+        }
+
+        let checksum = GitObject::get_hash_from_bytes(&bytes);
+        bytes.extend(hex::decode(checksum).map_err(|_| Error::Unreachable)?);
+        Ok(bytes)
+    }
+
+    /// Finds the entry at stage 0 for the path
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.path == path && entry.stage() == 0)
+    }
+
+    /// Adds or replaces the entry of a path. Entries of the other stages of the
+    /// same path are removed, as adding a file resolves its conflict.
+    /// A file replacing a directory (or the opposite) removes the entries of
+    /// the directory (or the file)
+    pub fn add_entry(&mut self, entry: IndexEntry) {
+        self.remove_entry(&entry.path);
+        let dir_prefix = format!("{}/", entry.path);
+        let conflicting_paths = self
+            .entries
+            .iter()
+            .filter(|e| {
+                e.path.starts_with(&dir_prefix) || entry.path.starts_with(&format!("{}/", e.path))
+            })
+            .map(|e| e.path.clone())
+            .collect::<Vec<_>>();
+        for path in conflicting_paths {
+            self.remove_entry(&path);
+        }
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(&entry.path);
+        }
+        self.other_extensions.clear();
+        let pos = self
+            .entries
+            .partition_point(|e| (e.path.as_bytes(), e.stage()) < (entry.path.as_bytes(), 0));
+        self.entries.insert(pos, entry);
+    }
+
+    /// Adds or replaces the entry of a stage of a path in conflict. The entry
+    /// at stage 0 of the path is removed, as the path is not merged anymore
+    pub fn add_unmerged_entry(&mut self, entry: IndexEntry) {
+        let (path, stage) = (entry.path.clone(), entry.stage());
+        self.entries
+            .retain(|e| e.path != path || (e.stage() != 0 && e.stage() != stage));
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(&path);
+        }
+        self.other_extensions.clear();
+        let pos = self
+            .entries
+            .partition_point(|e| (e.path.as_bytes(), e.stage()) < (path.as_bytes(), stage));
+        self.entries.insert(pos, entry);
+    }
+
+    /// Removes every entry (all stages) of the path
+    pub fn remove_entry(&mut self, path: &str) {
+        let nb_entries = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        if self.entries.len() != nb_entries {
+            self.other_extensions.clear();
+            if let Some(cache_tree) = &mut self.cache_tree {
+                cache_tree.invalidate(path);
+            }
+        }
+    }
+
+    /// Creates the index corresponding to a tree that was just restored in the
+    /// working tree, so that the stat data matches the files
+    pub fn from_tree(
+        tree: &GitObject,
+        object_database: &GitObjectDatabase,
+        work_tree: &Path,
+    ) -> Result<Self> {
+        let mut index = Self::new();
+        index.add_tree(tree, object_database, work_tree, "")?;
+        index
+            .entries
+            .sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        Ok(index)
+    }
+
+    fn add_tree(
+        &mut self,
+        tree: &GitObject,
+        object_database: &GitObjectDatabase,
+        work_tree: &Path,
+        prefix: &str,
+    ) -> Result<()> {
+        let GitObjectContent::Tree { content } = &tree.content else {
+            return Err(Error::NotATreeGitObject);
+        };
+        for tree_child in content {
+            let path = format!("{}{}", prefix, tree_child.name);
+            if tree_child.mode == 40000 {
+                let subtree = tree_child.load(object_database)?;
+                self.add_tree(&subtree, object_database, work_tree, &format!("{}/", path))?;
+            } else {
+                let metadata = std::fs::symlink_metadata(work_tree.join(&path))?;
+                let mut entry = IndexEntry::from_metadata(&path, &tree_child.hash, &metadata);
+                entry.mode = tree_child.mode;
+                self.entries.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the trees corresponding to the entries of the index and returns
+    /// the hash of the root tree.
+    /// Directories whose cache tree is still valid are not recomputed, and the
+    /// cache tree is updated with the new trees
+    pub fn write_tree(&mut self, object_database: &GitObjectDatabase) -> Result<String> {
+        if self.entries.iter().any(|entry| entry.stage() != 0) {
+            return Err(Error::UnmergedIndex);
+        }
+        let cache_tree = self.cache_tree.take();
+        let cache_tree = Self::write_subtree(
+            &self.entries,
+            "",
+            String::new(),
+            cache_tree.as_ref(),
+            object_database,
+        )?;
+        let hash = cache_tree.hash.clone().ok_or_else(|| Error::Unreachable)?;
+        self.cache_tree = Some(cache_tree);
+        Ok(hash)
+    }
+
+    /// Writes the tree of the entries, which all start with prefix
+    fn write_subtree(
+        entries: &[IndexEntry],
+        prefix: &str,
+        name: String,
+        cache_tree: Option<&CacheTree>,
+        object_database: &GitObjectDatabase,
+    ) -> Result<CacheTree> {
+        if let Some(cache_tree) = cache_tree {
+            if cache_tree.entry_count == entries.len() as i32 && cache_tree.hash.is_some() {
+                return Ok(CacheTree {
+                    name,
+                    ..cache_tree.clone()
+                });
+            }
+        }
+
+        let mut content_bytes = Vec::new();
+        let mut subtrees = Vec::new();
+        // directories already written, as entries of a directory are contiguous
+        let mut seen_dirs = BTreeSet::new();
+
+        let mut pos = 0;
+        while pos < entries.len() {
+            let entry = &entries[pos];
+            let relative_path = &entry.path[prefix.len()..];
+            match relative_path.split_once('/') {
+                None => {
+                    content_bytes.extend(format!("{} {}", entry.mode, relative_path).as_bytes());
+                    content_bytes.push(0);
+                    content_bytes.extend(
+                        hex::decode(&entry.hash)
+                            .map_err(|_| Error::InvalidHash(entry.hash.clone()))?,
+                    );
+                    pos += 1;
+                }
+                Some((dir, _)) => {
+                    if !seen_dirs.insert(dir.to_string()) {
+                        return Err(Error::InvalidIndex);
+                    }
+                    let dir_prefix = format!("{}{}/", prefix, dir);
+                    let nb_dir_entries = entries[pos..]
+                        .iter()
+                        .take_while(|e| e.path.starts_with(&dir_prefix))
+                        .count();
+                    let subtree_cache = cache_tree
+                        .and_then(|cache_tree| cache_tree.subtrees.iter().find(|t| t.name == dir));
+                    let subtree = Self::write_subtree(
+                        &entries[pos..pos + nb_dir_entries],
+                        &dir_prefix,
+                        dir.to_string(),
+                        subtree_cache,
+                        object_database,
+                    )?;
+                    let subtree_hash = subtree.hash.clone().ok_or_else(|| Error::Unreachable)?;
+
+                    content_bytes.extend(format!("40000 {}", dir).as_bytes());
+                    content_bytes.push(0);
+                    content_bytes.extend(
+                        hex::decode(&subtree_hash)
+                            .map_err(|_| Error::InvalidHash(subtree_hash.clone()))?,
+                    );
+                    subtrees.push(subtree);
+                    pos += nb_dir_entries;
+                }
+            }
+        }
+
+        let tree = GitObject::from_tree_content_bytes(content_bytes)?;
+        tree.write(object_database)?;
+
+        Ok(CacheTree {
+            name,
+            entry_count: entries.len() as i32,
+            hash: Some(tree.hash),
+            subtrees,
+        })
+    }
+}
@@ -0,0 +1,41 @@
+use std::path::{Path, PathBuf};
+
+use crate::git_pack::GitPack;
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// Checks the pack file and writes its index next to it (`x.pack` -> `x.idx`),
+/// or to the given path. Returns the checksum of the pack
+pub fn git_index_pack<P: AsRef<Path>>(pack_file: P, index_file: Option<&Path>) -> Result<String> {
+    let pack_file = pack_file.as_ref();
+    let index_file = match index_file {
+        Some(index_file) => index_file.to_path_buf(),
+        None if pack_file
+            .extension()
+            .is_some_and(|extension| extension == "pack") =>
+        {
+            pack_file.with_extension("idx")
+        }
+        None => return Err(Error::InvalidPackFileName(PathBuf::from(pack_file))),
+    };
+    let git_pack = GitPack::from_pack_bytes(std::fs::read(pack_file)?)?;
+    let (_, index) = git_pack.into_index()?;
+    std::fs::write(index_file, index.to_bytes()?)?;
+    Ok(index.pack_checksum)
+}
+
+/// Stores the pack read from the standard input in the repository, along with
+/// its index. With fix_thin, the bases of a thin pack are added from the
+/// objects of the repository. Returns the checksum of the stored pack
+pub fn git_index_pack_stdin(
+    repository: &Repository,
+    pack_bytes: Vec<u8>,
+    fix_thin: bool,
+) -> Result<String> {
+    let object_database = repository.object_database();
+    let mut git_pack = GitPack::from_pack_bytes(pack_bytes)?;
+    if fix_thin {
+        git_pack = git_pack.fix_thin(object_database)?;
+    }
+    git_pack.write(object_database)
+}
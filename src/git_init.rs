@@ -17,6 +17,5 @@ pub fn git_init<P: ?Sized + AsRef<Path>>(directory_path: &P) -> Result<()> {
     let head_file = git_dir.join("HEAD");
 
     fs::write(head_file, "ref: refs/heads/main\n")?;
-    println!("Initialized git directory");
     Ok(())
 }
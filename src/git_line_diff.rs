@@ -0,0 +1,1418 @@
+use std::collections::HashMap;
+
+use crate::{Error, Result};
+
+/// Algorithm used to find the lines that changed between two versions of a
+/// file. They all follow the ones of git (xdiff), so that the same hunks are
+/// produced
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DiffAlgorithm {
+    /// Myers' algorithm, giving up on the shortest diff for very different files
+    #[default]
+    Myers,
+    /// Myers' algorithm, always finding the shortest diff
+    Minimal,
+    /// matches the lines that are unique in both files first
+    Patience,
+    /// matches the least frequent lines first
+    Histogram,
+}
+
+impl DiffAlgorithm {
+    /// Parses the name of an algorithm, as given to --diff-algorithm
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "myers" | "default" => Ok(Self::Myers),
+            "minimal" => Ok(Self::Minimal),
+            "patience" => Ok(Self::Patience),
+            "histogram" => Ok(Self::Histogram),
+            _ => Err(Error::InvalidDiffAlgorithm(name.to_string())),
+        }
+    }
+}
+
+/// old_len lines of the old file starting at old_start are replaced by new_len
+/// lines of the new file starting at new_start (0-based)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+/// Splits a file in lines, each one with its final \n (the last line may not
+/// have one)
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&byte| byte == b'\n').collect()
+}
+
+/// Files with a NUL byte in their first 8000 bytes are binary, as for git
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&byte| byte == 0)
+}
+
+/// Length of the end common to both files that git leaves out of diffs
+/// without context lines: whole blocks of 1024 bytes, except the beginning of
+/// the first block up to its first line end
+pub fn common_tail_len(old: &[u8], new: &[u8]) -> usize {
+    const BLOCK: usize = 1024;
+    let smaller = old.len().min(new.len());
+    let mut trimmed = 0;
+    while trimmed + BLOCK <= smaller
+        && old[old.len() - trimmed - BLOCK..old.len() - trimmed]
+            == new[new.len() - trimmed - BLOCK..new.len() - trimmed]
+    {
+        trimmed += BLOCK;
+    }
+    let tail = &old[old.len() - trimmed..];
+    let recovered = tail
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(trimmed, |position| position + 1);
+    trimmed - recovered
+}
+
+/// Finds the changes between two files split in lines, in order.
+/// Blocks of changed lines that could be moved up or down (for ex an added
+/// function surrounded by empty lines) are placed where they look the most
+/// natural, based on the indentation of the lines around them
+pub fn diff_lines<'a>(old: &[&'a [u8]], new: &[&'a [u8]], algorithm: DiffAlgorithm) -> Vec<Change> {
+    diff_lines_with_heuristic(old, new, algorithm, true)
+}
+
+/// Finds the changes between two files split in lines, like diff_lines, but
+/// blocks of changed lines that could be moved are placed as low as possible,
+/// without looking at the indentation, as git does for merges
+pub fn diff_lines_without_indent_heuristic<'a>(
+    old: &[&'a [u8]],
+    new: &[&'a [u8]],
+    algorithm: DiffAlgorithm,
+) -> Vec<Change> {
+    diff_lines_with_heuristic(old, new, algorithm, false)
+}
+
+fn diff_lines_with_heuristic<'a>(
+    old: &[&'a [u8]],
+    new: &[&'a [u8]],
+    algorithm: DiffAlgorithm,
+    indent_heuristic: bool,
+) -> Vec<Change> {
+    // lines are compared through the id of their content
+    let mut ids: HashMap<&[u8], usize> = HashMap::new();
+    let mut line_id = |line: &&'a [u8]| {
+        let nb_ids = ids.len();
+        *ids.entry(*line).or_insert(nb_ids)
+    };
+    let old_ids = old.iter().map(&mut line_id).collect::<Vec<_>>();
+    let new_ids = new.iter().map(&mut line_id).collect::<Vec<_>>();
+
+    let mut old_changed = vec![false; old.len()];
+    let mut new_changed = vec![false; new.len()];
+    match algorithm {
+        DiffAlgorithm::Myers | DiffAlgorithm::Minimal => myers_diff(
+            &old_ids,
+            &new_ids,
+            algorithm == DiffAlgorithm::Minimal,
+            &mut old_changed,
+            &mut new_changed,
+        ),
+        DiffAlgorithm::Patience => {
+            let mut patience = PatienceDiff {
+                old: &old_ids,
+                new: &new_ids,
+                old_changed: &mut old_changed,
+                new_changed: &mut new_changed,
+            };
+            patience.diff(0, old.len(), 0, new.len());
+        }
+        DiffAlgorithm::Histogram => {
+            let mut histogram = HistogramDiff {
+                old: &old_ids,
+                new: &new_ids,
+                old_changed: &mut old_changed,
+                new_changed: &mut new_changed,
+            };
+            histogram.diff(1, old.len(), 1, new.len());
+        }
+    }
+
+    compact_changes(
+        old,
+        &old_ids,
+        &mut old_changed,
+        &new_changed,
+        indent_heuristic,
+    );
+    compact_changes(
+        new,
+        &new_ids,
+        &mut new_changed,
+        &old_changed,
+        indent_heuristic,
+    );
+    build_changes(&old_changed, &new_changed)
+}
+
+/// Pairs the runs of changed lines of both files: the unchanged lines between
+/// them are the same in both files
+fn build_changes(old_changed: &[bool], new_changed: &[bool]) -> Vec<Change> {
+    let is_changed = |changed: &[bool], line: usize| line > 0 && changed[line - 1];
+    let mut changes = Vec::new();
+    let (mut old_line, mut new_line) = (old_changed.len(), new_changed.len());
+    loop {
+        if is_changed(old_changed, old_line) || is_changed(new_changed, new_line) {
+            let (old_end, new_end) = (old_line, new_line);
+            while is_changed(old_changed, old_line) {
+                old_line -= 1;
+            }
+            while is_changed(new_changed, new_line) {
+                new_line -= 1;
+            }
+            changes.push(Change {
+                old_start: old_line,
+                old_len: old_end - old_line,
+                new_start: new_line,
+                new_len: new_end - new_line,
+            });
+        }
+        if old_line == 0 || new_line == 0 {
+            break;
+        }
+        old_line -= 1;
+        new_line -= 1;
+    }
+    changes.reverse();
+    changes
+}
+
+/// Writes the hunks of a unified diff: the changes with context lines before
+/// and after them. Changes closer than twice the context are in the same hunk.
+/// The header of the hunks ends with the last line before the hunk that starts
+/// with a letter, _ or $ (usually a function)
+pub fn write_unified_diff(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    changes: &[Change],
+    context: usize,
+    output: &mut Vec<u8>,
+) {
+    let mut function_line: &[u8] = &[];
+    // lines already searched for the function line
+    let mut searched_until = 0;
+    let mut hunk_start = 0;
+    while hunk_start < changes.len() {
+        let mut hunk_end = hunk_start;
+        while hunk_end + 1 < changes.len() {
+            let (previous, next) = (&changes[hunk_end], &changes[hunk_end + 1]);
+            if next.old_start - (previous.old_start + previous.old_len) > 2 * context {
+                break;
+            }
+            hunk_end += 1;
+        }
+        let (first, last) = (&changes[hunk_start], &changes[hunk_end]);
+
+        let old_start = first.old_start.saturating_sub(context);
+        let new_start = first.new_start.saturating_sub(context);
+        let after = context
+            .min(old.len() - (last.old_start + last.old_len))
+            .min(new.len() - (last.new_start + last.new_len));
+        let old_end = last.old_start + last.old_len + after;
+        let new_end = last.new_start + last.new_len + after;
+
+        if let Some(line) = old[searched_until.min(old_start)..old_start]
+            .iter()
+            .rev()
+            .find_map(|line| function_name(line))
+        {
+            function_line = line;
+        }
+        searched_until = old_start;
+
+        let range = |start: usize, len: usize| match len {
+            0 => format!("{},0", start),
+            1 => format!("{}", start + 1),
+            _ => format!("{},{}", start + 1, len),
+        };
+        output.extend(
+            format!(
+                "@@ -{} +{} @@",
+                range(old_start, old_end - old_start),
+                range(new_start, new_end - new_start)
+            )
+            .as_bytes(),
+        );
+        if !function_line.is_empty() {
+            output.push(b' ');
+            output.extend(function_line);
+        }
+        output.push(b'\n');
+
+        let mut write_line = |prefix: u8, line: &[u8]| {
+            output.push(prefix);
+            output.extend(line);
+            if !line.ends_with(b"\n") {
+                output.extend(b"\n\\ No newline at end of file\n");
+            }
+        };
+        let mut new_line = new_start;
+        for change in &changes[hunk_start..=hunk_end] {
+            for line in &new[new_line..change.new_start] {
+                write_line(b' ', line);
+            }
+            for line in &old[change.old_start..change.old_start + change.old_len] {
+                write_line(b'-', line);
+            }
+            for line in &new[change.new_start..change.new_start + change.new_len] {
+                write_line(b'+', line);
+            }
+            new_line = change.new_start + change.new_len;
+        }
+        for line in &new[new_line..new_end] {
+            write_line(b' ', line);
+        }
+        hunk_start = hunk_end + 1;
+    }
+}
+
+/// The line, cut to 80 bytes and without its trailing whitespace, if it starts
+/// with a letter, _ or $
+fn function_name(line: &[u8]) -> Option<&[u8]> {
+    let first = *line.first()?;
+    if !first.is_ascii_alphabetic() && first != b'_' && first != b'$' {
+        return None;
+    }
+    let line = &line[..line.len().min(80)];
+    let end = line
+        .iter()
+        .rposition(|&byte| !is_space(byte))
+        .map_or(0, |end| end + 1);
+    Some(&line[..end])
+}
+
+/// Whitespace as understood by git (no vertical tab or form feed)
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Approximation of the square root used to bound the work of the diff
+fn bogo_sqrt(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// Past this cost, Myers' algorithm looks for a good enough split
+const HEURISTIC_MIN_COST: i64 = 256;
+/// Minimum length of a common run of lines considered as a good split
+const SNAKE_COUNT: i64 = 20;
+/// Lines matching more than this number of lines are not discarded early
+const MAX_EQUAL_LIMIT: usize = 1024;
+/// Window around a line to decide if it is discarded early
+const SIMILAR_SCAN_WINDOW: usize = 100;
+
+/// Myers' diff of two ranges of lines (see "An O(ND) Difference Algorithm and
+/// Its Variations"), in linear space.
+/// The common lines at the start and at the end are skipped, and lines without
+/// any match in the other file are marked as changed beforehand
+fn myers_diff(
+    old: &[usize],
+    new: &[usize],
+    minimal: bool,
+    old_changed: &mut [bool],
+    new_changed: &mut [bool],
+) {
+    let common_start = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let common_end = old[common_start..]
+        .iter()
+        .rev()
+        .zip(new[common_start..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut counts: HashMap<usize, (usize, usize)> = HashMap::new();
+    for id in old {
+        counts.entry(*id).or_default().0 += 1;
+    }
+    for id in new {
+        counts.entry(*id).or_default().1 += 1;
+    }
+
+    // lines kept for the diff, by index in the file
+    let keep_lines = |lines: &[usize], is_old: bool, changed: &mut [bool]| {
+        let end = lines.len() - common_end;
+        let max_matches = bogo_sqrt(lines.len()).min(MAX_EQUAL_LIMIT);
+        // 0: no match, 1: some matches, 2: many matches
+        let mut matches = vec![0u8; lines.len()];
+        for index in common_start..end {
+            let (old_count, new_count) = counts[&lines[index]];
+            let nb_matches = if is_old { new_count } else { old_count };
+            matches[index] = match nb_matches {
+                0 => 0,
+                n if n >= max_matches => 2,
+                _ => 1,
+            };
+        }
+        let mut kept = Vec::new();
+        for index in common_start..end {
+            let is_kept = matches[index] == 1
+                || (matches[index] == 2
+                    && !is_surrounded_by_unmatched(&matches, index, common_start, end - 1));
+            match is_kept {
+                true => kept.push(index),
+                false => changed[index] = true,
+            }
+        }
+        kept
+    };
+    let old_index = keep_lines(old, true, old_changed);
+    let new_index = keep_lines(new, false, new_changed);
+    let old_kept = old_index.iter().map(|&i| old[i]).collect::<Vec<_>>();
+    let new_kept = new_index.iter().map(|&i| new[i]).collect::<Vec<_>>();
+
+    let mut myers = Myers::new(&old_kept, &new_kept);
+    let mut kept_old_changed = vec![false; old_kept.len()];
+    let mut kept_new_changed = vec![false; new_kept.len()];
+    let mut ranges = vec![(0, old_kept.len() as i64, 0, new_kept.len() as i64, minimal)];
+    while let Some((mut off1, mut lim1, mut off2, mut lim2, need_min)) = ranges.pop() {
+        while off1 < lim1 && off2 < lim2 && old_kept[off1 as usize] == new_kept[off2 as usize] {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1
+            && off2 < lim2
+            && old_kept[lim1 as usize - 1] == new_kept[lim2 as usize - 1]
+        {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+        if off1 == lim1 {
+            kept_new_changed[off2 as usize..lim2 as usize].fill(true);
+        } else if off2 == lim2 {
+            kept_old_changed[off1 as usize..lim1 as usize].fill(true);
+        } else {
+            let split = myers.split(off1, lim1, off2, lim2, need_min);
+            ranges.push((off1, split.old, off2, split.new, split.min_before));
+            ranges.push((split.old, lim1, split.new, lim2, split.min_after));
+        }
+    }
+    for (kept, &index) in old_index.iter().enumerate() {
+        old_changed[index] = kept_old_changed[kept];
+    }
+    for (kept, &index) in new_index.iter().enumerate() {
+        new_changed[index] = kept_new_changed[kept];
+    }
+}
+
+/// Whether a line with many matches is in the middle of lines without match,
+/// in which case it is most likely changed as well
+fn is_surrounded_by_unmatched(matches: &[u8], index: usize, start: usize, end: usize) -> bool {
+    let start = start.max(index.saturating_sub(SIMILAR_SCAN_WINDOW));
+    let end = end.min(index + SIMILAR_SCAN_WINDOW);
+
+    let (mut unmatched_before, mut multiple_before) = (0, 1);
+    for kind in matches[start..index].iter().rev() {
+        match kind {
+            0 => unmatched_before += 1,
+            2 => multiple_before += 1,
+            _ => break,
+        }
+    }
+    if unmatched_before == 0 {
+        return false;
+    }
+    let (mut unmatched_after, mut multiple_after) = (0, 1);
+    for kind in &matches[index + 1..=end] {
+        match kind {
+            0 => unmatched_after += 1,
+            2 => multiple_after += 1,
+            _ => break,
+        }
+    }
+    if unmatched_after == 0 {
+        return false;
+    }
+    let unmatched = unmatched_before + unmatched_after;
+    let multiple = multiple_before + multiple_after;
+    multiple * 4 < multiple + unmatched
+}
+
+/// Where to split the ranges compared by Myers' algorithm, and whether the
+/// shortest diff is still needed before and after the split
+struct Split {
+    old: i64,
+    new: i64,
+    min_before: bool,
+    min_after: bool,
+}
+
+/// State of Myers' algorithm: the furthest reaching paths on each diagonal,
+/// forward and backward
+struct Myers<'a> {
+    old: &'a [usize],
+    new: &'a [usize],
+    forward: Vec<i64>,
+    backward: Vec<i64>,
+    /// index of the diagonal 0 in forward and backward
+    offset: i64,
+    /// cost after which a split is taken even if it is not the best one
+    max_cost: i64,
+}
+
+impl<'a> Myers<'a> {
+    fn new(old: &'a [usize], new: &'a [usize]) -> Self {
+        let nb_diagonals = old.len() + new.len() + 3;
+        Self {
+            old,
+            new,
+            forward: vec![0; nb_diagonals],
+            backward: vec![0; nb_diagonals],
+            offset: new.len() as i64 + 1,
+            max_cost: (bogo_sqrt(nb_diagonals) as i64).max(HEURISTIC_MIN_COST),
+        }
+    }
+
+    fn old_id(&self, line: i64) -> usize {
+        self.old[line as usize]
+    }
+
+    fn new_id(&self, line: i64) -> usize {
+        self.new[line as usize]
+    }
+
+    fn forward(&mut self, diagonal: i64) -> &mut i64 {
+        &mut self.forward[(diagonal + self.offset) as usize]
+    }
+
+    fn backward(&mut self, diagonal: i64) -> &mut i64 {
+        &mut self.backward[(diagonal + self.offset) as usize]
+    }
+
+    /// Finds the middle of the shortest path going from (off1, off2) to
+    /// (lim1, lim2), extending the forward and backward paths until they meet.
+    /// Unless need_min is set, a good enough split is taken when the search
+    /// becomes too expensive
+    fn split(&mut self, off1: i64, lim1: i64, off2: i64, lim2: i64, need_min: bool) -> Split {
+        let (min_diagonal, max_diagonal) = (off1 - lim2, lim1 - off2);
+        let (forward_mid, backward_mid) = (off1 - off2, lim1 - lim2);
+        let is_odd = (forward_mid - backward_mid) & 1 != 0;
+        let (mut forward_min, mut forward_max) = (forward_mid, forward_mid);
+        let (mut backward_min, mut backward_max) = (backward_mid, backward_mid);
+        let found = |old: i64, new: i64| Split {
+            old,
+            new,
+            min_before: true,
+            min_after: true,
+        };
+
+        *self.forward(forward_mid) = off1;
+        *self.backward(backward_mid) = lim1;
+
+        let mut cost = 1;
+        loop {
+            let mut got_snake = false;
+
+            if forward_min > min_diagonal {
+                forward_min -= 1;
+                *self.forward(forward_min - 1) = -1;
+            } else {
+                forward_min += 1;
+            }
+            if forward_max < max_diagonal {
+                forward_max += 1;
+                *self.forward(forward_max + 1) = -1;
+            } else {
+                forward_max -= 1;
+            }
+            let mut diagonal = forward_max;
+            while diagonal >= forward_min {
+                let mut i1 = match *self.forward(diagonal - 1) >= *self.forward(diagonal + 1) {
+                    true => *self.forward(diagonal - 1) + 1,
+                    false => *self.forward(diagonal + 1),
+                };
+                let previous = i1;
+                let mut i2 = i1 - diagonal;
+                while i1 < lim1 && i2 < lim2 && self.old_id(i1) == self.new_id(i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - previous > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                *self.forward(diagonal) = i1;
+                if is_odd
+                    && backward_min <= diagonal
+                    && diagonal <= backward_max
+                    && *self.backward(diagonal) <= i1
+                {
+                    return found(i1, i2);
+                }
+                diagonal -= 2;
+            }
+
+            if backward_min > min_diagonal {
+                backward_min -= 1;
+                *self.backward(backward_min - 1) = i64::MAX;
+            } else {
+                backward_min += 1;
+            }
+            if backward_max < max_diagonal {
+                backward_max += 1;
+                *self.backward(backward_max + 1) = i64::MAX;
+            } else {
+                backward_max -= 1;
+            }
+            let mut diagonal = backward_max;
+            while diagonal >= backward_min {
+                let mut i1 = match *self.backward(diagonal - 1) < *self.backward(diagonal + 1) {
+                    true => *self.backward(diagonal - 1),
+                    false => *self.backward(diagonal + 1) - 1,
+                };
+                let previous = i1;
+                let mut i2 = i1 - diagonal;
+                while i1 > off1 && i2 > off2 && self.old_id(i1 - 1) == self.new_id(i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if previous - i1 > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                *self.backward(diagonal) = i1;
+                if !is_odd
+                    && forward_min <= diagonal
+                    && diagonal <= forward_max
+                    && i1 <= *self.forward(diagonal)
+                {
+                    return found(i1, i2);
+                }
+                diagonal -= 2;
+            }
+
+            if need_min {
+                cost += 1;
+                continue;
+            }
+
+            // a long enough common run of lines far enough on the path is a
+            // good split
+            if got_snake && cost > HEURISTIC_MIN_COST {
+                let mut best = 0;
+                let mut split = found(0, 0);
+                let mut diagonal = forward_max;
+                while diagonal >= forward_min {
+                    let distance = (diagonal - forward_mid).abs();
+                    let i1 = *self.forward(diagonal);
+                    let i2 = i1 - diagonal;
+                    let value = (i1 - off1) + (i2 - off2) - distance;
+                    if value > 4 * cost
+                        && value > best
+                        && off1 + SNAKE_COUNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_COUNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_COUNT).all(|k| self.old_id(i1 - k) == self.new_id(i2 - k))
+                    {
+                        best = value;
+                        split = found(i1, i2);
+                    }
+                    diagonal -= 2;
+                }
+                if best > 0 {
+                    split.min_after = false;
+                    return split;
+                }
+
+                let mut diagonal = backward_max;
+                while diagonal >= backward_min {
+                    let distance = (diagonal - backward_mid).abs();
+                    let i1 = *self.backward(diagonal);
+                    let i2 = i1 - diagonal;
+                    let value = (lim1 - i1) + (lim2 - i2) - distance;
+                    if value > 4 * cost
+                        && value > best
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_COUNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_COUNT
+                        && (0..SNAKE_COUNT).all(|k| self.old_id(i1 + k) == self.new_id(i2 + k))
+                    {
+                        best = value;
+                        split = found(i1, i2);
+                    }
+                    diagonal -= 2;
+                }
+                if best > 0 {
+                    split.min_before = false;
+                    return split;
+                }
+            }
+
+            // too expensive: takes the furthest reaching path
+            if cost >= self.max_cost {
+                let (mut forward_best, mut forward_best_old) = (-1, -1);
+                let mut diagonal = forward_max;
+                while diagonal >= forward_min {
+                    let mut i1 = (*self.forward(diagonal)).min(lim1);
+                    let mut i2 = i1 - diagonal;
+                    if lim2 < i2 {
+                        i1 = lim2 + diagonal;
+                        i2 = lim2;
+                    }
+                    if forward_best < i1 + i2 {
+                        forward_best = i1 + i2;
+                        forward_best_old = i1;
+                    }
+                    diagonal -= 2;
+                }
+                let (mut backward_best, mut backward_best_old) = (i64::MAX, i64::MAX);
+                let mut diagonal = backward_max;
+                while diagonal >= backward_min {
+                    let mut i1 = (*self.backward(diagonal)).max(off1);
+                    let mut i2 = i1 - diagonal;
+                    if i2 < off2 {
+                        i1 = off2 + diagonal;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < backward_best {
+                        backward_best = i1 + i2;
+                        backward_best_old = i1;
+                    }
+                    diagonal -= 2;
+                }
+                return match (lim1 + lim2) - backward_best < forward_best - (off1 + off2) {
+                    true => Split {
+                        old: forward_best_old,
+                        new: forward_best - forward_best_old,
+                        min_before: true,
+                        min_after: false,
+                    },
+                    false => Split {
+                        old: backward_best_old,
+                        new: backward_best - backward_best_old,
+                        min_before: false,
+                        min_after: true,
+                    },
+                };
+            }
+            cost += 1;
+        }
+    }
+}
+
+/// Patience diff: the lines that appear exactly once in both ranges are
+/// matched first (keeping the longest sequence of them in the same order), then
+/// the ranges between them are compared recursively. Ranges without such lines
+/// fall back to Myers' algorithm
+struct PatienceDiff<'a> {
+    old: &'a [usize],
+    new: &'a [usize],
+    old_changed: &'a mut [bool],
+    new_changed: &'a mut [bool],
+}
+
+impl PatienceDiff<'_> {
+    fn diff(&mut self, old_start: usize, old_len: usize, new_start: usize, new_len: usize) {
+        if old_len == 0 || new_len == 0 {
+            self.old_changed[old_start..old_start + old_len].fill(true);
+            self.new_changed[new_start..new_start + new_len].fill(true);
+            return;
+        }
+
+        // lines of old by first occurrence, with the line of new they match:
+        // None if there is no match, Some(None) if they are not unique
+        let mut entries: Vec<(usize, Option<Option<usize>>)> = Vec::new();
+        let mut entry_of_line: HashMap<usize, usize> = HashMap::new();
+        for line in old_start..old_start + old_len {
+            match entry_of_line.get(&self.old[line]) {
+                Some(&entry) => entries[entry].1 = Some(None),
+                None => {
+                    entry_of_line.insert(self.old[line], entries.len());
+                    entries.push((line, None));
+                }
+            }
+        }
+        let mut has_matches = false;
+        for line in new_start..new_start + new_len {
+            if let Some(&entry) = entry_of_line.get(&self.new[line]) {
+                has_matches = true;
+                entries[entry].1 = match entries[entry].1 {
+                    None => Some(Some(line)),
+                    Some(_) => Some(None),
+                };
+            }
+        }
+        if !has_matches {
+            self.old_changed[old_start..old_start + old_len].fill(true);
+            self.new_changed[new_start..new_start + new_len].fill(true);
+            return;
+        }
+
+        let unique = entries
+            .iter()
+            .filter_map(|&(old_line, new_line)| Some((old_line, new_line??)))
+            .collect::<Vec<_>>();
+        let sequence = longest_increasing_sequence(&unique);
+        if sequence.is_empty() {
+            myers_diff(
+                &self.old[old_start..old_start + old_len],
+                &self.new[new_start..new_start + new_len],
+                false,
+                &mut self.old_changed[old_start..old_start + old_len],
+                &mut self.new_changed[new_start..new_start + new_len],
+            );
+            return;
+        }
+
+        let (old_end, new_end) = (old_start + old_len, new_start + new_len);
+        let (mut old_line, mut new_line) = (old_start, new_start);
+        let mut index = 0;
+        loop {
+            // extends the matched lines backward, then the previous ones forward
+            let (mut next_old, mut next_new) = match sequence.get(index) {
+                Some(&(next_old, next_new)) => (next_old, next_new),
+                None => (old_end, new_end),
+            };
+            if index < sequence.len() {
+                while next_old > old_line
+                    && next_new > new_line
+                    && self.old[next_old - 1] == self.new[next_new - 1]
+                {
+                    next_old -= 1;
+                    next_new -= 1;
+                }
+            }
+            while old_line < next_old
+                && new_line < next_new
+                && self.old[old_line] == self.new[new_line]
+            {
+                old_line += 1;
+                new_line += 1;
+            }
+            if next_old > old_line || next_new > new_line {
+                self.diff(old_line, next_old - old_line, new_line, next_new - new_line);
+            }
+            if index == sequence.len() {
+                return;
+            }
+            while index + 1 < sequence.len()
+                && sequence[index + 1].0 == sequence[index].0 + 1
+                && sequence[index + 1].1 == sequence[index].1 + 1
+            {
+                index += 1;
+            }
+            old_line = sequence[index].0 + 1;
+            new_line = sequence[index].1 + 1;
+            index += 1;
+        }
+    }
+}
+
+/// Longest sequence of pairs, in the given order, whose second element is
+/// increasing, found by patience sorting
+fn longest_increasing_sequence(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // top of each pile, and the top of the previous pile when each pair was
+    // placed
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous = vec![None; pairs.len()];
+    for (index, &(_, value)) in pairs.iter().enumerate() {
+        let pile = piles.partition_point(|&top| pairs[top].1 <= value);
+        previous[index] = pile.checked_sub(1).map(|pile| piles[pile]);
+        match pile == piles.len() {
+            true => piles.push(index),
+            false => piles[pile] = index,
+        }
+    }
+    let mut sequence = Vec::new();
+    let mut index = piles.last().copied();
+    while let Some(current) = index {
+        sequence.push(pairs[current]);
+        index = previous[current];
+    }
+    sequence.reverse();
+    sequence
+}
+
+/// Lines occurring more often than this in a range are not used as anchors by
+/// the histogram diff
+const HISTOGRAM_MAX_CHAIN: usize = 64;
+
+/// Histogram diff: the longest run of common lines containing the least
+/// frequent line of the old range is matched, then the ranges before and after
+/// it are compared recursively. Lines are numbered from 1
+struct HistogramDiff<'a> {
+    old: &'a [usize],
+    new: &'a [usize],
+    old_changed: &'a mut [bool],
+    new_changed: &'a mut [bool],
+}
+
+/// Matched run of lines, inclusive. 0 when nothing was found
+#[derive(Default)]
+struct Region {
+    old_start: usize,
+    old_end: usize,
+    new_start: usize,
+    new_end: usize,
+}
+
+impl HistogramDiff<'_> {
+    fn diff(
+        &mut self,
+        mut old_start: usize,
+        mut old_len: usize,
+        mut new_start: usize,
+        mut new_len: usize,
+    ) {
+        loop {
+            if old_len == 0 || new_len == 0 {
+                self.old_changed[old_start - 1..old_start - 1 + old_len].fill(true);
+                self.new_changed[new_start - 1..new_start - 1 + new_len].fill(true);
+                return;
+            }
+            let Some(region) = self.find_lcs(old_start, old_len, new_start, new_len) else {
+                // only too frequent lines in common
+                myers_diff(
+                    &self.old[old_start - 1..old_start - 1 + old_len],
+                    &self.new[new_start - 1..new_start - 1 + new_len],
+                    false,
+                    &mut self.old_changed[old_start - 1..old_start - 1 + old_len],
+                    &mut self.new_changed[new_start - 1..new_start - 1 + new_len],
+                );
+                return;
+            };
+            if region.old_start == 0 && region.new_start == 0 {
+                self.old_changed[old_start - 1..old_start - 1 + old_len].fill(true);
+                self.new_changed[new_start - 1..new_start - 1 + new_len].fill(true);
+                return;
+            }
+            self.diff(
+                old_start,
+                region.old_start - old_start,
+                new_start,
+                region.new_start - new_start,
+            );
+            old_len = old_start + old_len - 1 - region.old_end;
+            old_start = region.old_end + 1;
+            new_len = new_start + new_len - 1 - region.new_end;
+            new_start = region.new_end + 1;
+        }
+    }
+
+    /// Finds the longest run of common lines, preferring runs whose lines are
+    /// less frequent in the old range. None if the ranges only have too
+    /// frequent lines in common
+    fn find_lcs(
+        &self,
+        old_start: usize,
+        old_len: usize,
+        new_start: usize,
+        new_len: usize,
+    ) -> Option<Region> {
+        let (old_end, new_end) = (old_start + old_len - 1, new_start + new_len - 1);
+        // first occurrence and number of occurrences of each line in old, and
+        // the next occurrence of each line
+        let mut occurrences: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut next_occurrence = vec![0; old_len];
+        for line in (old_start..=old_end).rev() {
+            let occurrence = occurrences.entry(self.old[line - 1]).or_insert((0, 0));
+            next_occurrence[line - old_start] = occurrence.0;
+            *occurrence = (line, occurrence.1 + 1);
+        }
+        let count = |line: usize| occurrences[&self.old[line - 1]].1;
+
+        let mut region = Region::default();
+        let mut min_count = HISTOGRAM_MAX_CHAIN + 1;
+        let mut has_common = false;
+        let mut new_line = new_start;
+        while new_line <= new_end {
+            let mut next_new_line = new_line + 1;
+            if let Some(&(first, nb_occurrences)) = occurrences.get(&self.new[new_line - 1]) {
+                has_common = true;
+                if nb_occurrences <= min_count {
+                    let mut old_line = first;
+                    loop {
+                        let next = next_occurrence[old_line - old_start];
+                        let (mut start1, mut start2) = (old_line, new_line);
+                        let (mut end1, mut end2) = (old_line, new_line);
+                        let mut run_count = nb_occurrences;
+                        while old_start < start1
+                            && new_start < start2
+                            && self.old[start1 - 2] == self.new[start2 - 2]
+                        {
+                            start1 -= 1;
+                            start2 -= 1;
+                            if run_count > 1 {
+                                run_count = run_count.min(count(start1));
+                            }
+                        }
+                        while end1 < old_end && end2 < new_end && self.old[end1] == self.new[end2] {
+                            end1 += 1;
+                            end2 += 1;
+                            if run_count > 1 {
+                                run_count = run_count.min(count(end1));
+                            }
+                        }
+                        if next_new_line <= end2 {
+                            next_new_line = end2 + 1;
+                        }
+                        if region.old_end - region.old_start < end1 - start1
+                            || run_count < min_count
+                        {
+                            region = Region {
+                                old_start: start1,
+                                old_end: end1,
+                                new_start: start2,
+                                new_end: end2,
+                            };
+                            min_count = run_count;
+                        }
+                        // next occurrence after the run
+                        let mut next = next;
+                        while next != 0 && next <= end1 {
+                            next = next_occurrence[next - old_start];
+                        }
+                        if next == 0 {
+                            break;
+                        }
+                        old_line = next;
+                    }
+                }
+            }
+            new_line = next_new_line;
+        }
+        match has_common && min_count > HISTOGRAM_MAX_CHAIN {
+            true => None,
+            false => Some(region),
+        }
+    }
+}
+
+/// Indentation of a line, with tabs to the next multiple of 8. None for blank
+/// lines
+fn indentation(line: &[u8]) -> Option<i32> {
+    let mut indent = 0;
+    for &byte in line {
+        if !is_space(byte) {
+            return Some(indent);
+        }
+        match byte {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            _ => {}
+        }
+        if indent >= MAX_INDENT {
+            return Some(MAX_INDENT);
+        }
+    }
+    None
+}
+
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+
+/// Surroundings of a split between two lines, to score how natural it looks
+struct SplitMeasurement {
+    end_of_file: bool,
+    /// indentation of the line after the split, -1 if it is blank
+    indent: i32,
+    /// blank lines just before the split
+    pre_blank: i32,
+    /// indentation of the first non-blank line before the split
+    pre_indent: i32,
+    /// blank lines after the line after the split
+    post_blank: i32,
+    /// indentation of the first non-blank line after the line after the split
+    post_indent: i32,
+}
+
+impl SplitMeasurement {
+    fn new(lines: &[&[u8]], split: usize) -> Self {
+        let indent = |line: &[u8]| indentation(line).unwrap_or(-1);
+        let mut measurement = Self {
+            end_of_file: split >= lines.len(),
+            indent: lines.get(split).map_or(-1, |line| indent(line)),
+            pre_blank: 0,
+            pre_indent: -1,
+            post_blank: 0,
+            post_indent: -1,
+        };
+        for line in lines[..split.min(lines.len())].iter().rev() {
+            measurement.pre_indent = indent(line);
+            if measurement.pre_indent != -1 {
+                break;
+            }
+            measurement.pre_blank += 1;
+            if measurement.pre_blank == MAX_BLANKS {
+                measurement.pre_indent = 0;
+                break;
+            }
+        }
+        for line in lines.iter().skip(split + 1) {
+            measurement.post_indent = indent(line);
+            if measurement.post_indent != -1 {
+                break;
+            }
+            measurement.post_blank += 1;
+            if measurement.post_blank == MAX_BLANKS {
+                measurement.post_indent = 0;
+                break;
+            }
+        }
+        measurement
+    }
+
+    /// Adds the badness of the split to the score: (effective indent, penalty)
+    fn add_score(&self, score: &mut (i32, i32)) {
+        if self.pre_indent == -1 && self.pre_blank == 0 {
+            score.1 += 1;
+        }
+        if self.end_of_file {
+            score.1 += 21;
+        }
+        let post_blank = if self.indent == -1 {
+            1 + self.post_blank
+        } else {
+            0
+        };
+        let total_blank = self.pre_blank + post_blank;
+        score.1 += -30 * total_blank + 6 * post_blank;
+
+        let indent = if self.indent != -1 {
+            self.indent
+        } else {
+            self.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        score.0 += indent;
+        if indent == -1 || self.pre_indent == -1 || indent == self.pre_indent {
+            return;
+        }
+        score.1 += if indent > self.pre_indent {
+            if any_blanks {
+                10
+            } else {
+                -4
+            }
+        } else if self.post_indent != -1 && self.post_indent > indent {
+            if any_blanks {
+                17
+            } else {
+                24
+            }
+        } else if any_blanks {
+            17
+        } else {
+            23
+        };
+    }
+}
+
+/// A run of changed lines, possibly empty: [start, end)
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Self {
+        let mut group = Group { start: 0, end: 0 };
+        group.extend_end(changed);
+        group
+    }
+
+    fn extend_end(&mut self, changed: &[bool]) {
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+    }
+
+    fn extend_start(&mut self, changed: &[bool]) {
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+    }
+
+    /// Moves to the next group, false if this is the last one
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        self.extend_end(changed);
+        true
+    }
+
+    /// Moves to the previous group, false if this is the first one
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        self.extend_start(changed);
+        true
+    }
+
+    /// Moves the changed lines one line down if the line after them is the same
+    /// as their first line, merging with the next group if they touch
+    fn slide_down(&mut self, ids: &[usize], changed: &mut [bool]) -> bool {
+        if self.end < ids.len() && ids[self.start] == ids[self.end] {
+            changed[self.start] = false;
+            changed[self.end] = true;
+            self.start += 1;
+            self.end += 1;
+            self.extend_end(changed);
+            return true;
+        }
+        false
+    }
+
+    /// Moves the changed lines one line up if the line before them is the same
+    /// as their last line, merging with the previous group if they touch
+    fn slide_up(&mut self, ids: &[usize], changed: &mut [bool]) -> bool {
+        if self.start > 0 && ids[self.start - 1] == ids[self.end - 1] {
+            self.start -= 1;
+            self.end -= 1;
+            changed[self.start] = true;
+            changed[self.end] = false;
+            self.extend_start(changed);
+            return true;
+        }
+        false
+    }
+}
+
+/// Slides the groups of changed lines of a file up and down when the lines
+/// around them allow it, to align them with the changes of the other file or,
+/// for pure additions or deletions, to the position whose indentation looks
+/// the most natural (or the lowest one without the indent heuristic)
+fn compact_changes(
+    lines: &[&[u8]],
+    ids: &[usize],
+    changed: &mut [bool],
+    other_changed: &[bool],
+    indent_heuristic: bool,
+) {
+    let mut group = Group::first(changed);
+    let mut other_group = Group::first(other_changed);
+
+    loop {
+        if group.end != group.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let size = group.end - group.start;
+                end_matching_other = None;
+
+                while group.slide_up(ids, changed) {
+                    other_group.previous(other_changed);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+
+                while group.slide_down(ids, changed) {
+                    other_group.next(other_changed);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            if group.end == earliest_end {
+                // the group cannot move
+            } else if end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    group.slide_up(ids, changed);
+                    other_group.previous(other_changed);
+                }
+            } else if indent_heuristic {
+                let size = group.end - group.start;
+                let mut shift = earliest_end
+                    .max(group.end.saturating_sub(size + 1))
+                    .max(group.end.saturating_sub(INDENT_HEURISTIC_MAX_SLIDING));
+                let mut best: Option<(usize, (i32, i32))> = None;
+                while shift <= group.end {
+                    let mut score = (0, 0);
+                    SplitMeasurement::new(lines, shift).add_score(&mut score);
+                    SplitMeasurement::new(lines, shift - size).add_score(&mut score);
+                    let is_better = best.is_none_or(|(_, best_score)| {
+                        let indent_cmp =
+                            (score.0 > best_score.0) as i32 - (score.0 < best_score.0) as i32;
+                        60 * indent_cmp + (score.1 - best_score.1) <= 0
+                    });
+                    if is_better {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+                if let Some((best_shift, _)) = best {
+                    while group.end > best_shift {
+                        group.slide_up(ids, changed);
+                        other_group.previous(other_changed);
+                    }
+                }
+            }
+        }
+
+        if !group.next(changed) {
+            break;
+        }
+        other_group.next(other_changed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unified diff of the files without its header, as written by
+    /// `git diff --no-index --diff-algorithm=<algorithm>`
+    fn unified_diff(old: &str, new: &str, algorithm: DiffAlgorithm, context: usize) -> String {
+        let old_lines = split_lines(old.as_bytes());
+        let new_lines = split_lines(new.as_bytes());
+        let changes = diff_lines(&old_lines, &new_lines, algorithm);
+        let mut output = Vec::new();
+        write_unified_diff(&old_lines, &new_lines, &changes, context, &mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Lines of the string, one character each
+    fn char_lines(chars: &str) -> String {
+        chars.chars().map(|c| format!("{}\n", c)).collect()
+    }
+
+    #[test]
+    fn split_lines_keeps_line_ends() {
+        assert_eq!(split_lines(b"a\nb\r\nc"), vec![&b"a\n"[..], b"b\r\n", b"c"]);
+        assert!(split_lines(b"").is_empty());
+    }
+
+    #[test]
+    fn algorithm_names() {
+        assert_eq!(
+            DiffAlgorithm::from_name("default").unwrap(),
+            DiffAlgorithm::Myers
+        );
+        assert_eq!(
+            DiffAlgorithm::from_name("histogram").unwrap(),
+            DiffAlgorithm::Histogram
+        );
+        assert!(DiffAlgorithm::from_name("unknown").is_err());
+    }
+
+    #[test]
+    fn simple_changes() {
+        let old = split_lines(b"a\nb\nc\nd\ne\nf\n");
+        let new = split_lines(b"a\nc\nd\nx\ne\nf\n");
+        let expected = vec![
+            Change {
+                old_start: 1,
+                old_len: 1,
+                new_start: 1,
+                new_len: 0,
+            },
+            Change {
+                old_start: 4,
+                old_len: 0,
+                new_start: 3,
+                new_len: 1,
+            },
+        ];
+        for algorithm in [
+            DiffAlgorithm::Myers,
+            DiffAlgorithm::Minimal,
+            DiffAlgorithm::Patience,
+            DiffAlgorithm::Histogram,
+        ] {
+            assert_eq!(diff_lines(&old, &new, algorithm), expected);
+        }
+        assert!(diff_lines(&old, &old, DiffAlgorithm::Myers).is_empty());
+    }
+
+    #[test]
+    fn algorithms_match_git() {
+        let old = char_lines("bbceaca");
+        let new = char_lines("accdcdbe");
+        assert_eq!(
+            unified_diff(&old, &new, DiffAlgorithm::Myers, 0),
+            "@@ -1,4 +0,0 @@\n-b\n-b\n-c\n-e\n\
+             @@ -7 +3,6 @@ c\n-a\n+c\n+d\n+c\n+d\n+b\n+e\n"
+        );
+        assert_eq!(
+            unified_diff(&old, &new, DiffAlgorithm::Patience, 0),
+            "@@ -1,2 +1,2 @@\n-b\n-b\n+a\n+c\n\
+             @@ -3,0 +4,4 @@ c\n+d\n+c\n+d\n+b\n\
+             @@ -5,3 +8,0 @@ e\n-a\n-c\n-a\n"
+        );
+        assert_eq!(
+            unified_diff(&old, &new, DiffAlgorithm::Histogram, 0),
+            "@@ -1,2 +1 @@\n-b\n-b\n+a\n\
+             @@ -3,0 +3,5 @@ c\n+c\n+d\n+c\n+d\n+b\n\
+             @@ -5,3 +8,0 @@ e\n-a\n-c\n-a\n"
+        );
+    }
+
+    #[test]
+    fn hunks_with_context_and_function_names() {
+        let old = "#include <stdio.h>\n\nvoid f()\n{\n    return 1;\n}\n\n\
+                   int main()\n{\n    f();\n    return 0;\n}\n";
+        let new = "#include <stdio.h>\n\nint main()\n{\n    f();\n    return 0;\n}\n\n\
+                   void f()\n{\n    return 1;\n}\n";
+        assert_eq!(
+            unified_diff(old, new, DiffAlgorithm::Myers, 1),
+            "@@ -2,7 +2,2 @@\n \n-void f()\n-{\n-    return 1;\n-}\n-\n int main()\n\
+             @@ -12 +7,6 @@ int main()\n }\n+\n+void f()\n+{\n+    return 1;\n+}\n"
+        );
+    }
+
+    #[test]
+    fn indent_heuristic() {
+        let old_lines = ["    x();", "", "    x();", "f() {", "    x();"];
+        let new_lines = [
+            "    x();", "", "    x();", "f() {", "", "    x();", "    x();", "f() {", "    x();",
+        ];
+        let old = old_lines.map(|line| format!("{}\n", line)).concat();
+        let new = new_lines.map(|line| format!("{}\n", line)).concat();
+        let old = split_lines(old.as_bytes());
+        let new = split_lines(new.as_bytes());
+        let inserted_at = |old_start, new_start| {
+            vec![Change {
+                old_start,
+                old_len: 0,
+                new_start,
+                new_len: 4,
+            }]
+        };
+        assert_eq!(
+            diff_lines(&old, &new, DiffAlgorithm::Myers),
+            inserted_at(2, 2)
+        );
+        assert_eq!(
+            diff_lines_without_indent_heuristic(&old, &new, DiffAlgorithm::Myers),
+            inserted_at(4, 4)
+        );
+    }
+
+    #[test]
+    fn common_tail() {
+        let old = [b"old\n".repeat(10), b"common\n".repeat(1000)].concat();
+        let new = [b"new\n".repeat(10), b"common\n".repeat(1000)].concat();
+        // 6 blocks of 1024 bytes are common, the first one starts 2 bytes
+        // into a line: its 5 bytes up to the line end are kept
+        let tail = common_tail_len(&old, &new);
+        assert_eq!(tail, 6 * 1024 - 5);
+        assert_eq!(old[old.len() - tail - 1], b'\n');
+        assert_eq!(common_tail_len(b"a\n", b"a\n"), 0);
+    }
+}
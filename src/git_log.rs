@@ -0,0 +1,372 @@
+use crate::git_ident::{format_date, DateFormat};
+use crate::git_object::{CommitObjects, Signature};
+use crate::git_repository::Repository;
+use crate::git_rev_walk::{rev_walk, RevWalkOptions};
+use crate::{Error, Result};
+
+/// How each commit is printed, see the --pretty option of git log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `<hash> <subject>`
+    Oneline,
+    /// hash, author and subject
+    Short,
+    /// hash, author, author date and message
+    Medium,
+    /// hash, author, committer and message
+    Full,
+    /// hash, author, author date, committer, commit date and message
+    Fuller,
+    /// hash, headers of the commit as stored and message
+    Raw,
+    /// a template with placeholders (%H, %an, %s...). With a terminator, each
+    /// commit is followed by a newline, otherwise commits are separated by one
+    Format { template: String, terminator: bool },
+}
+
+impl LogFormat {
+    /// Parses the value of --pretty: the name of a format, `format:<template>`,
+    /// `tformat:<template>` or a template with at least one placeholder
+    pub fn from_name(name: &str) -> Result<Self> {
+        let template = |template: &str, terminator| Self::Format {
+            template: template.to_string(),
+            terminator,
+        };
+        match name {
+            "oneline" => Ok(Self::Oneline),
+            "short" => Ok(Self::Short),
+            "medium" => Ok(Self::Medium),
+            "full" => Ok(Self::Full),
+            "fuller" => Ok(Self::Fuller),
+            "raw" => Ok(Self::Raw),
+            _ if name.starts_with("format:") => Ok(template(&name["format:".len()..], false)),
+            _ if name.starts_with("tformat:") => Ok(template(&name["tformat:".len()..], true)),
+            _ if name.contains('%') => Ok(template(name, true)),
+            _ => Err(Error::InvalidPrettyFormat(name.to_string())),
+        }
+    }
+}
+
+/// How log prints the commits it lists
+#[derive(Debug)]
+pub struct LogOptions {
+    pub walk: RevWalkOptions,
+    pub format: LogFormat,
+    pub date_format: DateFormat,
+    /// abbreviates the hash of the commits (--oneline is --pretty=oneline
+    /// --abbrev-commit)
+    pub abbrev_commit: bool,
+}
+
+/// Lists the commits reachable from the revisions (HEAD if there is none and
+/// not --all), see rev_walk, and formats them. Returns the whole output, as
+/// bytes since `%x` placeholders can insert any byte
+pub fn git_log(
+    repository: &Repository,
+    revisions: &[String],
+    options: &LogOptions,
+) -> Result<Vec<u8>> {
+    let head = ["HEAD".to_string()];
+    let revisions = match revisions.is_empty() && !options.walk.all {
+        true => &head[..],
+        false => revisions,
+    };
+    let commits = rev_walk(repository, revisions, &options.walk)?;
+
+    let mut entries = Vec::new();
+    for (hash, commit) in &commits {
+        entries.push(format_commit(repository, hash, commit, options)?);
+    }
+    Ok(match &options.format {
+        LogFormat::Oneline
+        | LogFormat::Format {
+            terminator: true, ..
+        } => entries
+            .into_iter()
+            .flat_map(|entry| entry.into_iter().chain(*b"\n"))
+            .collect(),
+        _ => entries.join(&b'\n'),
+    })
+}
+
+/// The commit as printed by log, without the separator from the next commit
+fn format_commit(
+    repository: &Repository,
+    hash: &str,
+    commit: &CommitObjects,
+    options: &LogOptions,
+) -> Result<Vec<u8>> {
+    let object_database = repository.object_database();
+    let abbreviated_hash = |hash: &str| object_database.abbreviate(hash, 7);
+    let commit_hash = match options.abbrev_commit {
+        true => abbreviated_hash(hash)?,
+        false => hash.to_string(),
+    };
+    let message = String::from_utf8_lossy(commit.message());
+    let date = |signature: &Signature| {
+        format_date(
+            signature.timestamp,
+            &signature.timezone,
+            options.date_format,
+        )
+    };
+    let identity = |signature: &Signature| {
+        format!(
+            "{} <{}>",
+            String::from_utf8_lossy(&signature.name),
+            String::from_utf8_lossy(&signature.email)
+        )
+    };
+
+    let mut entry = match &options.format {
+        LogFormat::Oneline => {
+            return Ok(format!("{} {}", commit_hash, subject(&message)).into_bytes())
+        }
+        LogFormat::Format { template, .. } => {
+            return expand_template(repository, template, hash, commit, options)
+        }
+        _ => format!("commit {}\n", commit_hash),
+    };
+
+    if options.format == LogFormat::Raw {
+        let raw = commit.to_bytes();
+        let headers_end = raw
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .map_or(raw.len(), |position| position + 1);
+        entry.push_str(&String::from_utf8_lossy(&raw[..headers_end]));
+    } else {
+        if commit.parents_sha.len() > 1 {
+            let mut parents = Vec::new();
+            for parent in &commit.parents_sha {
+                parents.push(abbreviated_hash(parent)?);
+            }
+            entry.push_str(&format!("Merge: {}\n", parents.join(" ")));
+        }
+        let (author, committer) = (identity(&commit.author), identity(&commit.committer));
+        match options.format {
+            LogFormat::Short => entry.push_str(&format!("Author: {}\n", author)),
+            LogFormat::Medium => {
+                entry.push_str(&format!("Author: {}\n", author));
+                entry.push_str(&format!("Date:   {}\n", date(&commit.author)));
+            }
+            LogFormat::Full => {
+                entry.push_str(&format!("Author: {}\n", author));
+                entry.push_str(&format!("Commit: {}\n", committer));
+            }
+            _ => {
+                entry.push_str(&format!("Author:     {}\n", author));
+                entry.push_str(&format!("AuthorDate: {}\n", date(&commit.author)));
+                entry.push_str(&format!("Commit:     {}\n", committer));
+                entry.push_str(&format!("CommitDate: {}\n", date(&commit.committer)));
+            }
+        }
+    }
+
+    entry.push('\n');
+    let lines = message_lines(&message);
+    let nb_lines = match options.format {
+        // only the first paragraph
+        LogFormat::Short => lines
+            .iter()
+            .position(|line| line.trim().is_empty())
+            .unwrap_or(lines.len()),
+        _ => lines.len(),
+    };
+    for line in &lines[..nb_lines] {
+        entry.push_str(&format!("    {}\n", line));
+    }
+    Ok(entry.into_bytes())
+}
+
+/// Lines of the message, without the empty lines at the start and at the end
+fn message_lines(message: &str) -> Vec<&str> {
+    let lines = message.lines().collect::<Vec<_>>();
+    let start = lines
+        .iter()
+        .position(|line| !line.trim().is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty())
+        .map_or(start, |end| end + 1);
+    lines[start..end].to_vec()
+}
+
+/// The first paragraph of the message, its lines joined with spaces
+fn subject(message: &str) -> String {
+    message_lines(message)
+        .into_iter()
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The message after its first paragraph and the empty lines that follow it
+fn body(message: &str) -> String {
+    let lines = message_lines(message);
+    let body_start = lines
+        .iter()
+        .position(|line| line.trim().is_empty())
+        .and_then(|end| {
+            lines[end..]
+                .iter()
+                .position(|line| !line.trim().is_empty())
+                .map(|start| end + start)
+        });
+    match body_start {
+        Some(start) => lines[start..]
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Replaces the placeholders of the template with the values of the commit:
+/// - `%H` / `%h`: hash / abbreviated hash, `%T` / `%t` for the tree and `%P` /
+///   `%p` for the parents (separated by spaces)
+/// - `%an`, `%ae`, `%ad`, `%aD`, `%ar`, `%at`, `%ai`, `%aI`, `%as`: author
+///   name, email and date (in the --date format, RFC 2822, relative, unix
+///   timestamp, ISO 8601, strict ISO 8601, short). `%c..` for the committer
+/// - `%s`: subject, `%b`: body, `%B`: raw message
+/// - `%n`: newline, `%%`: %, `%x41`: byte from its hexadecimal code
+/// - `%Cred`, `%Cgreen`, `%Cblue`, `%Creset`, `%C(<color>)`: colors
+///
+/// Unknown placeholders are kept as is
+fn expand_template(
+    repository: &Repository,
+    template: &str,
+    hash: &str,
+    commit: &CommitObjects,
+    options: &LogOptions,
+) -> Result<Vec<u8>> {
+    let object_database = repository.object_database();
+    let message = String::from_utf8_lossy(commit.message());
+    let mut expanded = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('%') {
+        expanded.extend(&rest.as_bytes()[..start]);
+        rest = &rest[start + 1..];
+        let placeholder_len = placeholder_len(rest);
+        let placeholder = &rest[..placeholder_len];
+        rest = &rest[placeholder_len..];
+
+        let value = match placeholder {
+            "%" => "%".to_string(),
+            "n" => "\n".to_string(),
+            "H" => hash.to_string(),
+            "h" => object_database.abbreviate(hash, 7)?,
+            "T" => commit.tree_sha.clone(),
+            "t" => object_database.abbreviate(&commit.tree_sha, 7)?,
+            "P" => commit.parents_sha.join(" "),
+            "p" => {
+                let mut parents = Vec::new();
+                for parent in &commit.parents_sha {
+                    parents.push(object_database.abbreviate(parent, 7)?);
+                }
+                parents.join(" ")
+            }
+            "s" => subject(&message),
+            "b" => body(&message),
+            "B" => message.to_string(),
+            _ if placeholder.starts_with('a') || placeholder.starts_with('c') => {
+                let signature = match placeholder.starts_with('a') {
+                    true => &commit.author,
+                    false => &commit.committer,
+                };
+                match format_signature(signature, &placeholder[1..], options.date_format) {
+                    Some(value) => value,
+                    None => format!("%{}", placeholder),
+                }
+            }
+            _ if placeholder.starts_with('x') => match u8::from_str_radix(&placeholder[1..], 16) {
+                // the byte as is, even if it is not valid utf-8 on its own
+                Ok(byte) => {
+                    expanded.push(byte);
+                    continue;
+                }
+                Err(_) => format!("%{}", placeholder),
+            },
+            _ if placeholder.starts_with('C') => color(&placeholder[1..]),
+            _ => format!("%{}", placeholder),
+        };
+        expanded.extend(value.into_bytes());
+    }
+    expanded.extend(rest.as_bytes());
+    Ok(expanded)
+}
+
+/// Length of the placeholder at the start of the text, after the %
+fn placeholder_len(text: &str) -> usize {
+    let is_hex = |index: usize| {
+        text.as_bytes()
+            .get(index)
+            .is_some_and(|byte| byte.is_ascii_hexdigit())
+    };
+    match text.chars().next() {
+        None => 0,
+        // the field is the next character, whatever its length in bytes
+        Some('a' | 'c') => 1 + text[1..].chars().next().map_or(0, char::len_utf8),
+        Some('x') if is_hex(1) && is_hex(2) => 3,
+        Some('C') if text.starts_with("C(") => text.find(')').map_or(1, |end| end + 1),
+        Some('C') => ["red", "green", "blue", "reset"]
+            .iter()
+            .find(|name| text[1..].starts_with(*name))
+            .map_or(1, |name| name.len() + 1),
+        Some(c) => c.len_utf8(),
+    }
+}
+
+/// Value of an author or committer placeholder (`n` for %an...)
+fn format_signature(signature: &Signature, field: &str, date_format: DateFormat) -> Option<String> {
+    let date = |format| format_date(signature.timestamp, &signature.timezone, format);
+    Some(match field {
+        "n" | "N" => String::from_utf8_lossy(&signature.name).into_owned(),
+        "e" | "E" => String::from_utf8_lossy(&signature.email).into_owned(),
+        "d" => date(date_format),
+        "D" => date(DateFormat::Rfc2822),
+        "r" => date(DateFormat::Relative),
+        "t" => date(DateFormat::Unix),
+        "i" => date(DateFormat::Iso),
+        "I" => date(DateFormat::IsoStrict),
+        "s" => date(DateFormat::Short),
+        _ => return None,
+    })
+}
+
+/// ANSI escape sequence of a color placeholder (`red` for %Cred, `(bold blue)`
+/// for %C(bold blue)). `auto` colors are never used, as the output is not
+/// colored by default
+fn color(name: &str) -> String {
+    let name = name.trim_start_matches('(').trim_end_matches(')');
+    if name.starts_with("auto") {
+        return String::new();
+    }
+    let codes = name
+        .split_whitespace()
+        .filter_map(|word| match word {
+            "reset" => Some("0"),
+            "bold" => Some("1"),
+            "dim" => Some("2"),
+            "italic" => Some("3"),
+            "ul" => Some("4"),
+            "black" => Some("30"),
+            "red" => Some("31"),
+            "green" => Some("32"),
+            "yellow" => Some("33"),
+            "blue" => Some("34"),
+            "magenta" => Some("35"),
+            "cyan" => Some("36"),
+            "white" => Some("37"),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    match codes.as_slice() {
+        [] => String::new(),
+        ["0"] => "\x1b[m".to_string(),
+        _ => format!("\x1b[{}m", codes.join(";")),
+    }
+}
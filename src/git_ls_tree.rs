@@ -1,39 +1,86 @@
 use crate::git_object::{GitObject, GitObjectContent};
+use crate::git_object_database::GitObjectDatabase;
+use crate::git_repository::Repository;
 use crate::{Error, Result};
 
-/// Prints informations about a tree
-pub fn git_ls_tree(name_only: bool, recursive: bool, long: bool, hash: &str) -> Result<()> {
-    let git_obj = GitObject::from_hash(hash, ".")?;
+/// A line of ls-tree
+#[derive(Debug)]
+pub struct LsTreeEntry {
+    pub mode: u32,
+    /// blob, tree or commit (submodules)
+    pub content_type: String,
+    pub hash: String,
+    /// Only computed for blobs, when requested
+    pub size: Option<usize>,
+    /// path relative to the listed tree
+    pub path: String,
+}
+
+/// Lists the content of a tree (or of the tree of a commit or a tag).
+/// Children are only loaded when needed: sub-trees when recursive is set and
+/// blobs to get their size when with_size is set
+pub fn git_ls_tree(
+    repository: &Repository,
+    hash: &str,
+    recursive: bool,
+    with_size: bool,
+) -> Result<Vec<LsTreeEntry>> {
+    // commits and tags are accepted, and peeled to their tree
+    let hash = repository.find_object(hash)?.hash;
+    let tree_hash = repository.object_database().peel(&hash, "tree")?;
+    let git_obj = repository.object_database().read_object(&tree_hash)?;
+
+    let mut entries = Vec::new();
+    list_tree(
+        &git_obj,
+        repository.object_database(),
+        "",
+        recursive,
+        with_size,
+        &mut entries,
+    )?;
+    Ok(entries)
+}
 
-    match git_obj.content {
+fn list_tree(
+    git_obj: &GitObject,
+    object_database: &GitObjectDatabase,
+    prefix: &str,
+    recursive: bool,
+    with_size: bool,
+    entries: &mut Vec<LsTreeEntry>,
+) -> Result<()> {
+    match &git_obj.content {
         GitObjectContent::Tree { content } => {
             for tree_child in content {
-                if let Some(git_obj) = tree_child.git_object {
-                    if recursive && git_obj.content_type() == "tree" {
-                        git_ls_tree(name_only, recursive, long, &git_obj.hash)?;
-                    } else if name_only {
-                        println!("{}", tree_child.name);
-                    } else if long {
-                        println!(
-                            "{:0>6} {} {} {:>8}\t{}",
-                            tree_child.mode,
-                            git_obj.content_type(),
-                            git_obj.hash,
-                            git_obj.size,
-                            tree_child.name
-                        );
-                    } else {
-                        println!(
-                            "{:0>6} {} {}\t{}",
-                            tree_child.mode,
-                            git_obj.content_type(),
-                            git_obj.hash,
-                            tree_child.name
-                        );
-                    }
-                } else {
-                    Err(Error::TreeChildNotLoaded)?;
+                let content_type = tree_child.content_type();
+                let path = format!("{}{}", prefix, tree_child.name);
+                if recursive && content_type == "tree" {
+                    let child_tree = tree_child.load(object_database)?;
+                    let prefix = format!("{}/", path);
+                    list_tree(
+                        &child_tree,
+                        object_database,
+                        &prefix,
+                        recursive,
+                        with_size,
+                        entries,
+                    )?;
+                    continue;
                 }
+
+                let size = if with_size && content_type == "blob" {
+                    Some(tree_child.load(object_database)?.size)
+                } else {
+                    None
+                };
+                entries.push(LsTreeEntry {
+                    mode: tree_child.mode,
+                    content_type,
+                    hash: tree_child.hash.clone(),
+                    size,
+                    path,
+                });
             }
         } // Return Ok(()) on success
         _ => Err(Error::NotATreeGitObject)?,
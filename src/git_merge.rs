@@ -0,0 +1,1654 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::git_checkout::{switch_trees, unstaged_changes};
+use crate::git_commit_tree::git_commit_tree;
+use crate::git_ident::committer_signature;
+use crate::git_index::{GitIndex, IndexEntry};
+use crate::git_line_diff::{
+    diff_lines_without_indent_heuristic, is_binary, split_lines, Change, DiffAlgorithm,
+};
+use crate::git_object::{GitObject, GitObjectContent};
+use crate::git_refs::NULL_HASH;
+use crate::git_repository::Repository;
+use crate::git_rev_walk::merge_bases;
+use crate::git_revision::{resolve_ref_name, resolve_revision};
+use crate::{Error, Result};
+
+/// How the conflicts are written in the merged files (merge.conflictStyle)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictStyle {
+    /// our lines and their lines, between <<<<<<<, ======= and >>>>>>>
+    #[default]
+    Merge,
+    /// the lines of the merge base as well, after |||||||
+    Diff3,
+}
+
+impl ConflictStyle {
+    /// Parses the value of merge.conflictStyle or --conflict. zdiff3 (diff3
+    /// with the lines common to both sides moved out of the conflict) is
+    /// written as diff3
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "merge" => Some(Self::Merge),
+            "diff3" | "zdiff3" => Some(Self::Diff3),
+            _ => None,
+        }
+    }
+}
+
+/// Names written after the conflict markers
+#[derive(Debug, Clone, Copy)]
+pub struct ConflictLabels<'a> {
+    /// after |||||||, with the diff3 style
+    pub base: &'a str,
+    /// after <<<<<<<
+    pub ours: &'a str,
+    /// after >>>>>>>
+    pub theirs: &'a str,
+}
+
+/// Length of the conflict markers. Merges of merge bases use longer ones, so
+/// that their conflicts can be told apart from the ones of the final merge
+pub const CONFLICT_MARKER_SIZE: usize = 7;
+
+/// Algorithm of the diffs between the base and each side of a line merge
+const MERGE_DIFF_ALGORITHM: DiffAlgorithm = DiffAlgorithm::Myers;
+
+/// Where the lines of a merge hunk come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HunkKind {
+    /// both sides changed the lines differently
+    Conflict,
+    /// only our side changed them
+    Ours,
+    /// only their side changed them
+    Theirs,
+    /// a conflict where both sides turned out to make the same change
+    Identical,
+}
+
+/// Lines changed by a side, or by both, in a line merge: chg0 lines of the
+/// base starting at i0 became chg1 lines of our file starting at i1 and chg2
+/// lines of their file starting at i2 (0-based)
+#[derive(Debug, Clone, Copy)]
+struct MergeHunk {
+    kind: HunkKind,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+}
+
+/// Merges three versions of a file line by line, as git (xdiff) does: the
+/// changes made by each side to the base are applied, and the changes of both
+/// sides that overlap or touch are conflicts, unless they are the same.
+/// Conflicts are then narrowed to the lines that differ between both sides,
+/// and conflicts separated by 3 lines or less are joined, except with the
+/// diff3 style that shows the lines of the base.
+/// Returns the merged content and the number of conflicts
+pub fn merge_lines(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &ConflictLabels,
+    style: ConflictStyle,
+    marker_size: usize,
+) -> (Vec<u8>, usize) {
+    let base_lines = split_lines(base);
+    let our_lines = split_lines(ours);
+    let their_lines = split_lines(theirs);
+    let our_changes =
+        diff_lines_without_indent_heuristic(&base_lines, &our_lines, MERGE_DIFF_ALGORITHM);
+    let their_changes =
+        diff_lines_without_indent_heuristic(&base_lines, &their_lines, MERGE_DIFF_ALGORITHM);
+    if our_changes.is_empty() {
+        return (theirs.to_vec(), 0);
+    }
+    if their_changes.is_empty() {
+        return (ours.to_vec(), 0);
+    }
+
+    let mut hunks = pair_changes(
+        &our_changes,
+        &their_changes,
+        &our_lines,
+        &their_lines,
+        base_lines.len(),
+    );
+    if style == ConflictStyle::Merge {
+        refine_conflicts(&mut hunks, &our_lines, &their_lines);
+        join_close_conflicts(&mut hunks);
+    }
+    let nb_conflicts = hunks
+        .iter()
+        .filter(|hunk| hunk.kind == HunkKind::Conflict)
+        .count();
+
+    let mut output = Vec::new();
+    let lines = |lines: &[&[u8]], start: isize, len: isize| -> Vec<u8> {
+        lines[start as usize..(start + len) as usize].concat()
+    };
+    let copy = |output: &mut Vec<u8>, content: Vec<u8>, newline: Option<bool>| {
+        let needs_newline = !content.is_empty() && !content.ends_with(b"\n");
+        output.extend(content);
+        if let (true, Some(needs_cr)) = (needs_newline, newline) {
+            output.extend(if needs_cr { &b"\r\n"[..] } else { b"\n" });
+        }
+    };
+    let marker = |output: &mut Vec<u8>, marker: u8, label: Option<&str>, needs_cr: bool| {
+        output.extend(std::iter::repeat_n(marker, marker_size));
+        if let Some(label) = label {
+            output.push(b' ');
+            output.extend(label.as_bytes());
+        }
+        output.extend(if needs_cr { &b"\r\n"[..] } else { b"\n" });
+    };
+
+    // next line of our file to copy
+    let mut line = 0;
+    for hunk in &hunks {
+        match hunk.kind {
+            HunkKind::Identical => continue,
+            HunkKind::Conflict => {
+                let needs_cr = needs_cr(&base_lines, &our_lines, &their_lines, hunk);
+                copy(&mut output, lines(&our_lines, line, hunk.i1 - line), None);
+                marker(&mut output, b'<', Some(labels.ours), needs_cr);
+                let content = lines(&our_lines, hunk.i1, hunk.chg1);
+                copy(&mut output, content, Some(needs_cr));
+                if style == ConflictStyle::Diff3 {
+                    marker(&mut output, b'|', Some(labels.base), needs_cr);
+                    let content = lines(&base_lines, hunk.i0, hunk.chg0);
+                    copy(&mut output, content, Some(needs_cr));
+                }
+                marker(&mut output, b'=', None, needs_cr);
+                let content = lines(&their_lines, hunk.i2, hunk.chg2);
+                copy(&mut output, content, Some(needs_cr));
+                marker(&mut output, b'>', Some(labels.theirs), needs_cr);
+            }
+            HunkKind::Ours => {
+                let content = lines(&our_lines, line, hunk.i1 + hunk.chg1 - line);
+                copy(&mut output, content, None);
+            }
+            HunkKind::Theirs => {
+                copy(&mut output, lines(&our_lines, line, hunk.i1 - line), None);
+                copy(&mut output, lines(&their_lines, hunk.i2, hunk.chg2), None);
+            }
+        }
+        line = hunk.i1 + hunk.chg1;
+    }
+    let nb_lines = our_lines.len() as isize;
+    copy(&mut output, lines(&our_lines, line, nb_lines - line), None);
+    (output, nb_conflicts)
+}
+
+/// Walks the changes of both sides together, in the order of the base: a
+/// change that does not touch any change of the other side is taken as is,
+/// the others are conflicts, unless both sides made the same change
+fn pair_changes(
+    our_changes: &[Change],
+    their_changes: &[Change],
+    our_lines: &[&[u8]],
+    their_lines: &[&[u8]],
+    nb_base_lines: usize,
+) -> Vec<MergeHunk> {
+    let range = |change: &Change| {
+        (
+            change.old_start as isize,
+            change.old_len as isize,
+            change.new_start as isize,
+            change.new_len as isize,
+        )
+    };
+    let mut hunks = Vec::new();
+    let (mut our_pos, mut their_pos) = (0, 0);
+    while let (Some(ours), Some(theirs)) = (our_changes.get(our_pos), their_changes.get(their_pos))
+    {
+        let (o_base, o_base_len, o_start, o_len) = range(ours);
+        let (t_base, t_base_len, t_start, t_len) = range(theirs);
+        if o_base + o_base_len < t_base {
+            append_hunk(
+                &mut hunks,
+                MergeHunk {
+                    kind: HunkKind::Ours,
+                    i0: o_base,
+                    chg0: o_base_len,
+                    i1: o_start,
+                    chg1: o_len,
+                    i2: t_start - t_base + o_base,
+                    chg2: o_base_len,
+                },
+            );
+            our_pos += 1;
+            continue;
+        }
+        if t_base + t_base_len < o_base {
+            append_hunk(
+                &mut hunks,
+                MergeHunk {
+                    kind: HunkKind::Theirs,
+                    i0: t_base,
+                    chg0: t_base_len,
+                    i1: o_start - o_base + t_base,
+                    chg1: t_base_len,
+                    i2: t_start,
+                    chg2: t_len,
+                },
+            );
+            their_pos += 1;
+            continue;
+        }
+
+        let is_same_change = o_base == t_base
+            && o_base_len == t_base_len
+            && o_len == t_len
+            && our_lines[ours.new_start..ours.new_start + ours.new_len]
+                == their_lines[theirs.new_start..theirs.new_start + theirs.new_len];
+        if !is_same_change {
+            // the conflict covers both changes, and the same lines of the base
+            // on each side
+            let start_offset = o_base - t_base;
+            let end_offset = start_offset + o_base_len - t_base_len;
+            let (mut i0, mut i1, mut i2) = (o_base, o_start, t_start);
+            if start_offset > 0 {
+                i0 -= start_offset;
+                i1 -= start_offset;
+            } else {
+                i2 += start_offset;
+            }
+            let mut chg0 = o_base + o_base_len - i0;
+            let mut chg1 = o_start + o_len - i1;
+            let mut chg2 = t_start + t_len - i2;
+            if end_offset < 0 {
+                chg0 -= end_offset;
+                chg1 -= end_offset;
+            } else {
+                chg2 += end_offset;
+            }
+            append_hunk(
+                &mut hunks,
+                MergeHunk {
+                    kind: HunkKind::Conflict,
+                    i0,
+                    chg0,
+                    i1,
+                    chg1,
+                    i2,
+                    chg2,
+                },
+            );
+        }
+
+        let (our_end, their_end) = (o_base + o_base_len, t_base + t_base_len);
+        if our_end >= their_end {
+            their_pos += 1;
+        }
+        if their_end >= our_end {
+            our_pos += 1;
+        }
+    }
+
+    let base_len = nb_base_lines as isize;
+    for ours in &our_changes[our_pos..] {
+        let (o_base, o_base_len, o_start, o_len) = range(ours);
+        let hunk = MergeHunk {
+            kind: HunkKind::Ours,
+            i0: o_base,
+            chg0: o_base_len,
+            i1: o_start,
+            chg1: o_len,
+            i2: o_base + their_lines.len() as isize - base_len,
+            chg2: o_base_len,
+        };
+        append_hunk(&mut hunks, hunk);
+    }
+    for theirs in &their_changes[their_pos..] {
+        let (t_base, t_base_len, t_start, t_len) = range(theirs);
+        let hunk = MergeHunk {
+            kind: HunkKind::Theirs,
+            i0: t_base,
+            chg0: t_base_len,
+            i1: t_base + our_lines.len() as isize - base_len,
+            chg1: t_base_len,
+            i2: t_start,
+            chg2: t_len,
+        };
+        append_hunk(&mut hunks, hunk);
+    }
+    hunks
+}
+
+/// Adds a hunk, or extends the last one if they touch. Hunks of different
+/// sides that touch are a conflict
+fn append_hunk(hunks: &mut Vec<MergeHunk>, hunk: MergeHunk) {
+    if let Some(last) = hunks.last_mut() {
+        if hunk.i1 <= last.i1 + last.chg1 || hunk.i2 <= last.i2 + last.chg2 {
+            if hunk.kind != last.kind {
+                last.kind = HunkKind::Conflict;
+            }
+            last.chg0 = hunk.i0 + hunk.chg0 - last.i0;
+            last.chg1 = hunk.i1 + hunk.chg1 - last.i1;
+            last.chg2 = hunk.i2 + hunk.chg2 - last.i2;
+            return;
+        }
+    }
+    hunks.push(hunk);
+}
+
+/// Compares the lines of both sides of each conflict, to only keep as
+/// conflicts the lines that differ
+fn refine_conflicts(hunks: &mut Vec<MergeHunk>, our_lines: &[&[u8]], their_lines: &[&[u8]]) {
+    let mut refined = Vec::with_capacity(hunks.len());
+    for hunk in hunks.drain(..) {
+        if hunk.kind != HunkKind::Conflict || hunk.chg1 == 0 || hunk.chg2 == 0 {
+            refined.push(hunk);
+            continue;
+        }
+        let ours = &our_lines[hunk.i1 as usize..(hunk.i1 + hunk.chg1) as usize];
+        let theirs = &their_lines[hunk.i2 as usize..(hunk.i2 + hunk.chg2) as usize];
+        let changes = diff_lines_without_indent_heuristic(ours, theirs, MERGE_DIFF_ALGORITHM);
+        if changes.is_empty() {
+            refined.push(MergeHunk {
+                kind: HunkKind::Identical,
+                ..hunk
+            });
+        }
+        for change in changes {
+            refined.push(MergeHunk {
+                i1: hunk.i1 + change.old_start as isize,
+                chg1: change.old_len as isize,
+                i2: hunk.i2 + change.new_start as isize,
+                chg2: change.new_len as isize,
+                ..hunk
+            });
+        }
+    }
+    *hunks = refined;
+}
+
+/// Joins the conflicts separated by 3 unchanged lines or less: the conflict
+/// is not longer with the lines in it
+fn join_close_conflicts(hunks: &mut Vec<MergeHunk>) {
+    let mut pos = 0;
+    while pos + 1 < hunks.len() {
+        let (hunk, next) = (hunks[pos], hunks[pos + 1]);
+        if hunk.kind != HunkKind::Conflict
+            || next.kind != HunkKind::Conflict
+            || next.i1 - (hunk.i1 + hunk.chg1) > 3
+        {
+            pos += 1;
+            continue;
+        }
+        hunks[pos].chg1 = next.i1 + next.chg1 - hunk.i1;
+        hunks[pos].chg2 = next.i2 + next.chg2 - hunk.i2;
+        hunks.remove(pos + 1);
+    }
+}
+
+/// Whether the markers of a conflict end with \r\n: when none of the lines
+/// before the conflict on both sides and the first line of the base ends with
+/// \n alone, and the line of the base ends with \r\n
+fn needs_cr(
+    base_lines: &[&[u8]],
+    our_lines: &[&[u8]],
+    their_lines: &[&[u8]],
+    hunk: &MergeHunk,
+) -> bool {
+    let previous_line = |start: isize| (start - 1).max(0) as usize;
+    [
+        (our_lines, previous_line(hunk.i1)),
+        (their_lines, previous_line(hunk.i2)),
+        (base_lines, 0),
+    ]
+    .into_iter()
+    .map(|(lines, line)| ends_with_crlf(lines, line))
+    .try_fold(None, |_, ends_with_crlf| match ends_with_crlf {
+        Some(false) => Err(()),
+        ends_with_crlf => Ok(ends_with_crlf),
+    })
+    .is_ok_and(|ends_with_crlf| ends_with_crlf == Some(true))
+}
+
+/// Whether the line ends with \r\n. The last line, if it has no end, has the
+/// ending of the line before it. None if it cannot be determined
+fn ends_with_crlf(lines: &[&[u8]], line: usize) -> Option<bool> {
+    let is_crlf = |line: &[u8]| line.ends_with(b"\r\n");
+    if line + 1 < lines.len() {
+        return Some(is_crlf(lines[line]));
+    }
+    let last_line = lines.get(line)?;
+    if last_line.ends_with(b"\n") {
+        return Some(is_crlf(last_line));
+    }
+    if line == 0 {
+        return None;
+    }
+    Some(is_crlf(lines[line - 1]))
+}
+
+/// A file of a tree: its mode and the hash of its blob
+type TreeFile = (u32, String);
+
+/// Version of a path in a tree, for the trivial merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TreeEntry {
+    Missing,
+    /// the tree has a directory at the path, or a file at one of its parents
+    DirectoryConflict,
+    File(TreeFile),
+}
+
+/// Outcome of the trivial merge of a path
+#[derive(Debug)]
+enum TrivialMerge {
+    Take(TreeFile),
+    Delete,
+    /// base, our and their versions, to merge file by file
+    Unmerged([Option<TreeFile>; 3]),
+}
+
+/// Resolves the paths that do not need a file merge (git's three-way
+/// read-tree with --aggressive): the same change on both sides, a change on
+/// one side only, or a deletion on one side of a file unchanged on the other.
+/// Paths that are both a file and a directory are never resolved here
+fn trivial_merge(base: &TreeEntry, head: &TreeEntry, remote: &TreeEntry) -> TrivialMerge {
+    let file = |entry: &TreeEntry| match entry {
+        TreeEntry::File(file) => Some(file.clone()),
+        _ => None,
+    };
+    let head_conflict = *head == TreeEntry::DirectoryConflict;
+    let remote_conflict = *remote == TreeEntry::DirectoryConflict;
+    let (head, remote) = (file(head), file(remote));
+
+    // a directory conflict in the base matches nothing
+    let (mut head_match, mut remote_match) = (false, false);
+    if head != remote && *base != TreeEntry::DirectoryConflict {
+        head_match = file(base) == head;
+        remote_match = file(base) == remote;
+    }
+
+    if let (Some(remote), false, true, false) = (&remote, head_conflict, head_match, remote_match) {
+        return TrivialMerge::Take(remote.clone());
+    }
+    if let Some(head) = &head {
+        if Some(head) == remote.as_ref() || (!remote_conflict && remote_match && !head_match) {
+            return TrivialMerge::Take(head.clone());
+        }
+    }
+    if (head.is_none() && remote.is_none())
+        || (head.is_none() && remote_match)
+        || (remote.is_none() && head_match)
+    {
+        return TrivialMerge::Delete;
+    }
+
+    TrivialMerge::Unmerged([file(base), head, remote])
+}
+
+/// Kind of file of a mode: regular file (executable or not), symbolic link or
+/// submodule
+fn file_kind(mode: u32) -> u32 {
+    match mode {
+        120000 | 160000 => mode,
+        _ => 100000,
+    }
+}
+
+/// Result of the merge of the versions of a file
+#[derive(Debug)]
+struct FileMerge {
+    file: TreeFile,
+    is_clean: bool,
+}
+
+/// Files of the tree by path
+fn tree_files(repository: &Repository, tree: &str) -> Result<BTreeMap<String, TreeFile>> {
+    Ok(repository
+        .object_database()
+        .read_tree_recursively(tree)?
+        .into_iter()
+        .map(|(path, child)| (path, (child.mode, child.hash)))
+        .collect())
+}
+
+/// Directories of the files
+fn tree_dirs(files: &BTreeMap<String, TreeFile>) -> BTreeSet<String> {
+    let mut dirs = BTreeSet::new();
+    for path in files.keys() {
+        for (pos, _) in path.match_indices('/') {
+            dirs.insert(path[..pos].to_string());
+        }
+    }
+    dirs
+}
+
+/// Writes the trees of the files and returns the hash of the root tree
+fn write_tree(repository: &Repository, files: &BTreeMap<String, TreeFile>) -> Result<String> {
+    let mut index = GitIndex::new();
+    index.entries = files
+        .iter()
+        .map(|(path, (mode, hash))| IndexEntry::new(path, *mode, hash, 0))
+        .collect();
+    index.write_tree(repository.object_database())
+}
+
+/// A commit taking part in a merge: a real one, or the merge of several merge
+/// bases, which is never written
+#[derive(Debug, Clone)]
+enum MergeCommit {
+    Commit(String),
+    Virtual {
+        tree: String,
+        parents: Vec<MergeCommit>,
+    },
+}
+
+impl MergeCommit {
+    fn tree(&self, repository: &Repository) -> Result<String> {
+        match self {
+            Self::Commit(hash) => repository.object_database().peel(hash, "tree"),
+            Self::Virtual { tree, .. } => Ok(tree.clone()),
+        }
+    }
+
+    /// The real commits this one is made of
+    fn real_commits(&self) -> Vec<String> {
+        match self {
+            Self::Commit(hash) => vec![hash.clone()],
+            Self::Virtual { parents, .. } => parents.iter().flat_map(Self::real_commits).collect(),
+        }
+    }
+}
+
+/// What stays the same during a merge and the merges of its merge bases
+struct MergeContext<'a> {
+    repository: &'a Repository,
+    work_tree: &'a Path,
+    /// files of HEAD before the merge
+    head_files: &'a BTreeMap<String, TreeFile>,
+    style: ConflictStyle,
+}
+
+/// Merges two commits (git's merge-recursive without rename detection). When
+/// there are several merge bases, they are first merged together, from the
+/// oldest one, into a virtual commit used as the base
+fn merge_commits<'a>(
+    context: &'a MergeContext,
+    ours: &MergeCommit,
+    theirs: &MergeCommit,
+    labels: (&'a str, &'a str),
+    depth: usize,
+) -> Result<TreeMerge<'a>> {
+    let repository = context.repository;
+    let MergeCommit::Commit(their_hash) = theirs else {
+        return Err(Error::Unreachable);
+    };
+    let mut bases = merge_bases(repository, their_hash, &ours.real_commits())?;
+    bases.reverse();
+    let nb_bases = bases.len();
+    let mut bases = bases.into_iter().map(MergeCommit::Commit);
+
+    let (mut merged_base, ancestor) = match bases.next() {
+        None => {
+            let empty_tree = GitObject::from_tree_content_bytes(Vec::new())?;
+            empty_tree.write(repository.object_database())?;
+            let base = MergeCommit::Virtual {
+                tree: empty_tree.hash,
+                parents: Vec::new(),
+            };
+            (base, "empty tree".to_string())
+        }
+        Some(base) if nb_bases > 1 => (base, "merged common ancestors".to_string()),
+        Some(base) => {
+            let hash = base.real_commits().concat();
+            let ancestor = repository.object_database().abbreviate(&hash, 7)?;
+            (base, ancestor)
+        }
+    };
+
+    let mut warnings = Vec::new();
+    for base in bases {
+        let labels = ("Temporary merge branch 1", "Temporary merge branch 2");
+        let merge = merge_commits(context, &merged_base, &base, labels, depth + 1)?;
+        warnings.extend(merge.warnings.iter().cloned());
+        let tree = merge.write_tree()?;
+        merged_base = MergeCommit::Virtual {
+            tree,
+            parents: vec![merged_base, base],
+        };
+    }
+
+    let mut merge = TreeMerge {
+        context,
+        depth,
+        ours: labels.0,
+        theirs: labels.1,
+        ancestor,
+        index: BTreeMap::new(),
+        work_tree: BTreeMap::new(),
+        used_paths: BTreeSet::new(),
+        unmerged_paths: Vec::new(),
+        messages: Vec::new(),
+        warnings,
+        is_clean: true,
+    };
+    merge.run(
+        &ours.tree(repository)?,
+        &theirs.tree(repository)?,
+        &merged_base.tree(repository)?,
+    )?;
+    Ok(merge)
+}
+
+/// Merge of two trees from their base: the index with the stages of the
+/// files in conflict and, for the final merge, the files of the work tree
+struct TreeMerge<'a> {
+    context: &'a MergeContext<'a>,
+    /// 0 for the final merge, more for the merges of merge bases, that have
+    /// no message and no stage: conflicts are committed as they are
+    depth: usize,
+    /// names of both sides and of the base, in messages and conflict markers
+    ours: &'a str,
+    theirs: &'a str,
+    ancestor: String,
+    /// stages 0 (merged) to 3 of each path
+    index: BTreeMap<String, [Option<TreeFile>; 4]>,
+    /// files of the work tree once merged, only used for the final merge
+    work_tree: BTreeMap<String, TreeFile>,
+    /// files and directories of both trees, and paths already given to files
+    /// moved out of the way
+    used_paths: BTreeSet<String>,
+    /// paths that needed a file merge
+    unmerged_paths: Vec<String>,
+    /// what happened to the files, as git prints it
+    messages: Vec<String>,
+    warnings: Vec<String>,
+    is_clean: bool,
+}
+
+impl TreeMerge<'_> {
+    fn run(&mut self, our_tree: &str, their_tree: &str, base_tree: &str) -> Result<()> {
+        let repository = self.context.repository;
+        let our_files = tree_files(repository, our_tree)?;
+        self.work_tree = our_files.clone();
+        if base_tree == their_tree {
+            self.output("Already up to date.".to_string());
+            for (path, file) in our_files {
+                self.index.insert(path, [Some(file), None, None, None]);
+            }
+            return Ok(());
+        }
+        let their_files = tree_files(repository, their_tree)?;
+        let base_files = tree_files(repository, base_tree)?;
+        let our_dirs = tree_dirs(&our_files);
+        let their_dirs = tree_dirs(&their_files);
+        let base_dirs = tree_dirs(&base_files);
+        for files in [&our_files, &their_files] {
+            self.used_paths.extend(files.keys().cloned());
+        }
+        self.used_paths.extend(our_dirs.iter().cloned());
+        self.used_paths.extend(their_dirs.iter().cloned());
+
+        let entry = |files: &BTreeMap<String, TreeFile>, dirs: &BTreeSet<String>, path: &str| {
+            if let Some(file) = files.get(path) {
+                return TreeEntry::File(file.clone());
+            }
+            let is_in_file = path
+                .match_indices('/')
+                .any(|(pos, _)| files.contains_key(&path[..pos]));
+            match dirs.contains(path) || is_in_file {
+                true => TreeEntry::DirectoryConflict,
+                false => TreeEntry::Missing,
+            }
+        };
+        let mut paths = base_files.keys().collect::<BTreeSet<_>>();
+        paths.extend(our_files.keys());
+        paths.extend(their_files.keys());
+        for path in paths {
+            let merge = trivial_merge(
+                &entry(&base_files, &base_dirs, path),
+                &entry(&our_files, &our_dirs, path),
+                &entry(&their_files, &their_dirs, path),
+            );
+            match merge {
+                TrivialMerge::Take(file) => {
+                    self.work_tree.insert(path.clone(), file.clone());
+                    self.index
+                        .insert(path.clone(), [Some(file), None, None, None]);
+                }
+                TrivialMerge::Delete => {
+                    self.work_tree.remove(path);
+                }
+                TrivialMerge::Unmerged([base, ours, theirs]) => {
+                    self.index.insert(path.clone(), [None, base, ours, theirs]);
+                    self.unmerged_paths.push(path.clone());
+                }
+            }
+        }
+
+        // the files of a directory are merged before a file at its place
+        for path in self.unmerged_paths.clone().iter().rev() {
+            let is_clean = self.merge_path(path)?;
+            self.is_clean &= is_clean;
+        }
+        Ok(())
+    }
+
+    /// Merges the versions of a path that could not be trivially merged.
+    /// Returns whether it merged cleanly
+    fn merge_path(&mut self, path: &str) -> Result<bool> {
+        let [_, base, ours, theirs] = self.index.get(path).cloned().unwrap_or_default();
+        match (base, ours, theirs) {
+            (Some(base), ours, theirs) if ours.is_none() || theirs.is_none() => {
+                // deleted on one side, maybe changed on the other
+                let is_unchanged = |file: &Option<TreeFile>| file.as_ref() == Some(&base);
+                if (ours.is_none() && theirs.is_none())
+                    || (theirs.is_none() && is_unchanged(&ours))
+                    || (ours.is_none() && is_unchanged(&theirs))
+                {
+                    if ours.is_some() {
+                        self.output(format!("Removing {}", path));
+                        self.work_tree.remove(path);
+                    }
+                    self.index.remove(path);
+                    return Ok(true);
+                }
+                self.merge_modify_delete(path, base, ours, theirs);
+                Ok(false)
+            }
+            (None, Some(file), None) | (None, None, Some(file)) => {
+                let is_ours = self.index[path][2].is_some();
+                Ok(self.merge_added(path, file, is_ours))
+            }
+            (None, Some(ours), Some(theirs)) => {
+                self.output(format!("CONFLICT (add/add): Merge conflict in {}", path));
+                self.merge_added_twice(path, ours, theirs)
+            }
+            (Some(base), Some(ours), Some(theirs)) => {
+                let merge = self.merge_file(path, Some(&base), &ours, &theirs)?;
+                let is_head_file = self.context.head_files.get(path) == Some(&merge.file);
+                if merge.is_clean && is_head_file {
+                    self.index
+                        .insert(path.to_string(), [Some(merge.file), None, None, None]);
+                    return Ok(true);
+                }
+                if !merge.is_clean {
+                    let reason = match merge.file.0 {
+                        160000 => "submodule",
+                        _ => "content",
+                    };
+                    self.output(format!("CONFLICT ({}): Merge conflict in {}", reason, path));
+                }
+                self.update_file(merge.is_clean, merge.file, path);
+                Ok(merge.is_clean)
+            }
+            _ => {
+                self.index.remove(path);
+                Ok(true)
+            }
+        }
+    }
+
+    /// A file deleted on one side and changed on the other is left in the
+    /// work tree, or next to its path if a directory is in the way
+    fn merge_modify_delete(
+        &mut self,
+        path: &str,
+        base: TreeFile,
+        ours: Option<TreeFile>,
+        theirs: Option<TreeFile>,
+    ) {
+        let (changed, modify_branch, delete_branch) = match (ours, theirs) {
+            (Some(ours), _) => (ours, self.ours, self.theirs),
+            (None, theirs) => (theirs.unwrap_or_default(), self.theirs, self.ours),
+        };
+        let is_final = self.depth == 0;
+        let alt_path = match self.dir_in_way(path, is_final)
+            || (is_final && self.would_lose_untracked(path))
+        {
+            true => Some(self.unique_path(path, modify_branch)),
+            false => None,
+        };
+        let update_path = alt_path.clone().unwrap_or_else(|| path.to_string());
+
+        if !is_final {
+            // neither side is better than the other for the base of a merge
+            self.index.remove(path);
+            self.update_file(false, base, &update_path);
+            return;
+        }
+        let location = match &alt_path {
+            Some(alt_path) => format!(" at {}", alt_path),
+            None => String::new(),
+        };
+        self.output(format!(
+            "CONFLICT (modify/delete): {} deleted in {} and modified in {}. Version {} of {} left in tree{}.",
+            path, delete_branch, modify_branch, modify_branch, path, location
+        ));
+        if modify_branch != self.ours || alt_path.is_some() {
+            self.update_file(false, changed, &update_path);
+        }
+    }
+
+    /// A file added on one side only, which is in conflict if the other side
+    /// added a directory at its place: it is then written next to its path.
+    /// Returns whether it merged cleanly
+    fn merge_added(&mut self, path: &str, file: TreeFile, is_ours: bool) -> bool {
+        let (add_branch, other_branch, kind) = match is_ours {
+            true => (self.ours, self.theirs, "file/directory"),
+            false => (self.theirs, self.ours, "directory/file"),
+        };
+        let check_work_tree = self.depth == 0 && !(is_ours && file.0 == 160000);
+        if self.dir_in_way(path, check_work_tree) {
+            let new_path = self.unique_path(path, add_branch);
+            self.output(format!(
+                "CONFLICT ({}): There is a directory with name {} in {}. Adding {} as {}",
+                kind, path, other_branch, path, new_path
+            ));
+            self.update_file(false, file, &new_path);
+            if self.depth > 0 {
+                self.index.remove(path);
+            }
+            return false;
+        }
+        self.output(format!("Adding {}", path));
+        if !is_ours {
+            self.work_tree.insert(path.to_string(), file.clone());
+        }
+        self.index
+            .insert(path.to_string(), [Some(file), None, None, None]);
+        true
+    }
+
+    /// A file added on both sides, merged from an empty base.
+    /// Returns whether it merged cleanly
+    fn merge_added_twice(&mut self, path: &str, ours: TreeFile, theirs: TreeFile) -> Result<bool> {
+        let mut update_path = path.to_string();
+        if self.would_lose_untracked(path) {
+            self.output(format!(
+                "Refusing to lose untracked file at {}, even though it's in the way.",
+                path
+            ));
+            update_path = self.unique_path(path, "merged");
+        } else {
+            if self.depth > 0 {
+                self.index.remove(path);
+            }
+            self.work_tree.remove(path);
+        }
+        let merge = self.merge_file(path, None, &ours, &theirs)?;
+        let is_clean = merge.is_clean && update_path == path;
+        self.update_file(is_clean, merge.file, &update_path);
+        if !is_clean && self.depth == 0 {
+            self.index
+                .insert(path.to_string(), [None, None, Some(ours), Some(theirs)]);
+        }
+        Ok(is_clean)
+    }
+
+    /// Merges the modes and the contents of the versions of a file changed on
+    /// both sides. Files of different kinds, or symbolic links and submodules
+    /// changed differently, are conflicts
+    fn merge_file(
+        &mut self,
+        path: &str,
+        base: Option<&TreeFile>,
+        ours: &TreeFile,
+        theirs: &TreeFile,
+    ) -> Result<FileMerge> {
+        let null = (0, NULL_HASH.to_string());
+        let base = base.unwrap_or(&null);
+        if file_kind(ours.0) != file_kind(theirs.0) {
+            // the regular file is kept
+            let file = match file_kind(ours.0) {
+                100000 => ours.clone(),
+                _ => theirs.clone(),
+            };
+            return Ok(FileMerge {
+                file,
+                is_clean: false,
+            });
+        }
+
+        let mut is_clean = true;
+        let mut is_merged = ours.1 != base.1 && theirs.1 != base.1;
+        let mode = if ours.0 == theirs.0 || ours.0 == base.0 {
+            theirs.0
+        } else {
+            if theirs.0 != base.0 {
+                is_clean = false;
+                is_merged = true;
+            }
+            ours.0
+        };
+
+        let hash = if ours.1 == theirs.1 || ours.1 == base.1 {
+            theirs.1.clone()
+        } else if theirs.1 == base.1 {
+            ours.1.clone()
+        } else if file_kind(ours.0) == 100000 {
+            let (content, is_merge_clean) = self.merge_blobs(path, &base.1, &ours.1, &theirs.1)?;
+            let blob = GitObject::from_blob_content_bytes(content)?;
+            blob.write(self.context.repository.object_database())?;
+            is_clean = is_merge_clean;
+            blob.hash
+        } else {
+            is_clean = false;
+            ours.1.clone()
+        };
+
+        if is_merged {
+            self.output(format!("Auto-merging {}", path));
+        }
+        Ok(FileMerge {
+            file: (mode, hash),
+            is_clean,
+        })
+    }
+
+    /// Merges the lines of the blobs. Binary files are not merged: our version
+    /// is kept, as a conflict, or the base for a merge of merge bases.
+    /// Returns the content and whether it has no conflict
+    fn merge_blobs(
+        &mut self,
+        path: &str,
+        base: &str,
+        ours: &str,
+        theirs: &str,
+    ) -> Result<(Vec<u8>, bool)> {
+        let read = |hash: &str| -> Result<Vec<u8>> {
+            if hash == NULL_HASH {
+                return Ok(Vec::new());
+            }
+            match self
+                .context
+                .repository
+                .object_database()
+                .read_object(hash)?
+                .content
+            {
+                GitObjectContent::Blob { content } => Ok(content),
+                _ => Err(Error::InvalidGitObject),
+            }
+        };
+        let (base, ours, theirs) = (read(base)?, read(ours)?, read(theirs)?);
+        if is_binary(&base) || is_binary(&ours) || is_binary(&theirs) {
+            if self.depth > 0 {
+                return Ok((base, true));
+            }
+            self.warnings.push(format!(
+                "Cannot merge binary files: {} ({} vs. {})",
+                path, self.ours, self.theirs
+            ));
+            return Ok((ours, false));
+        }
+        let labels = ConflictLabels {
+            base: &self.ancestor,
+            ours: self.ours,
+            theirs: self.theirs,
+        };
+        let marker_size = CONFLICT_MARKER_SIZE + 2 * self.depth;
+        let (content, nb_conflicts) = merge_lines(
+            &base,
+            &ours,
+            &theirs,
+            &labels,
+            self.context.style,
+            marker_size,
+        );
+        Ok((content, nb_conflicts == 0))
+    }
+
+    /// Records the merged version of a file: in the index if clean (always for
+    /// merges of merge bases), and in the work tree for the final merge
+    fn update_file(&mut self, is_clean: bool, file: TreeFile, path: &str) {
+        if is_clean || self.depth > 0 {
+            self.index
+                .insert(path.to_string(), [Some(file.clone()), None, None, None]);
+        }
+        if self.depth == 0 {
+            self.work_tree.insert(path.to_string(), file);
+        }
+    }
+
+    /// Whether there is a directory at the path: files under it in the index,
+    /// or in the work tree (merged or not tracked) for the final merge
+    fn dir_in_way(&self, path: &str, check_work_tree: bool) -> bool {
+        let prefix = format!("{}/", path);
+        let has_files = |files: Vec<&String>| files.iter().any(|file| file.starts_with(&prefix));
+        if has_files(
+            self.index
+                .range(prefix.clone()..)
+                .map(|(p, _)| p)
+                .take(1)
+                .collect(),
+        ) {
+            return true;
+        }
+        if !check_work_tree {
+            return false;
+        }
+        if has_files(
+            self.work_tree
+                .range(prefix.clone()..)
+                .map(|(p, _)| p)
+                .take(1)
+                .collect(),
+        ) {
+            return true;
+        }
+        let work_tree = self.context.work_tree;
+        let is_dir = std::fs::symlink_metadata(work_tree.join(path)).is_ok_and(|m| m.is_dir());
+        is_dir && self.has_untracked_files(path)
+    }
+
+    /// Whether the directory of the work tree contains files that HEAD does
+    /// not track
+    fn has_untracked_files(&self, dir: &str) -> bool {
+        let work_tree = self.context.work_tree;
+        let mut dirs = vec![dir.to_string()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(work_tree.join(&dir)) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    dirs.push(path);
+                } else if !self.context.head_files.contains_key(&path) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Whether something that HEAD does not track is at the path in the work
+    /// tree
+    fn would_lose_untracked(&self, path: &str) -> bool {
+        !self.context.head_files.contains_key(path)
+            && std::fs::symlink_metadata(self.context.work_tree.join(path)).is_ok()
+    }
+
+    /// A path for a file that cannot be written at its place: `path~branch`,
+    /// followed by `_<n>` if it is already used
+    fn unique_path(&mut self, path: &str, branch: &str) -> String {
+        let base = format!("{}~{}", path, branch.replace('/', "_"));
+        let mut new_path = base.clone();
+        let mut suffix = 0;
+        let exists =
+            |path: &str| std::fs::symlink_metadata(self.context.work_tree.join(path)).is_ok();
+        while self.used_paths.contains(&new_path) || (self.depth == 0 && exists(&new_path)) {
+            new_path = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        self.used_paths.insert(new_path.clone());
+        new_path
+    }
+
+    /// Messages are only shown for the final merge
+    fn output(&mut self, message: String) {
+        if self.depth == 0 {
+            self.messages.push(message);
+        }
+    }
+
+    /// Writes the tree of the merged files
+    fn write_tree(&self) -> Result<String> {
+        let files = self
+            .index
+            .iter()
+            .filter_map(|(path, stages)| Some((path.clone(), stages[0].clone()?)))
+            .collect();
+        write_tree(self.context.repository, &files)
+    }
+
+    /// Paths left in conflict, sorted
+    fn conflicts(&self) -> Vec<String> {
+        self.index
+            .iter()
+            .filter(|(_, stages)| stages[1..].iter().any(Option::is_some))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Writes the merged files in the work tree and the index, going from the
+    /// tree of HEAD, then records the stages of the files in conflict. Files
+    /// moved out of the way are not tracked
+    fn apply(&self, index: &mut GitIndex, head_tree: &str) -> Result<()> {
+        let repository = self.context.repository;
+        // a file where a directory is written is removed, as git does
+        let mut files = self.work_tree.clone();
+        let files_in_the_way = files
+            .keys()
+            .filter(|path| {
+                let prefix = format!("{}/", path);
+                files
+                    .range(prefix.clone()..)
+                    .next()
+                    .is_some_and(|(file, _)| file.starts_with(&prefix))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in files_in_the_way {
+            files.remove(&path);
+        }
+        let tree = write_tree(repository, &files)?;
+        switch_trees(repository, index, Some(head_tree), &tree, false)?;
+
+        for path in files.keys() {
+            if !self.index.contains_key(path) {
+                index.remove_entry(path);
+            }
+        }
+        for (path, stages) in &self.index {
+            match stages {
+                [Some(_), None, None, None] => {
+                    if !files.contains_key(path) {
+                        index.remove_entry(path);
+                    }
+                }
+                _ => {
+                    index.remove_entry(path);
+                    for (stage, file) in stages.iter().enumerate() {
+                        if let Some((mode, hash)) = file {
+                            let entry = IndexEntry::new(path, *mode, hash, stage as u16);
+                            index.add_unmerged_entry(entry);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options of the merge command
+#[derive(Debug, Default, Clone)]
+pub struct MergeOptions {
+    /// creates a merge commit even when the branch can be fast-forwarded
+    pub no_ff: bool,
+    /// merges histories without common ancestor, as if they had an empty one
+    pub allow_unrelated_histories: bool,
+    /// overrides merge.conflictStyle (--conflict)
+    pub conflict_style: Option<ConflictStyle>,
+}
+
+/// How the merge ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStatus {
+    /// the commit is already in the history of HEAD
+    UpToDate,
+    /// HEAD was behind the commit, and now points to it
+    FastForward,
+    /// a merge commit was created
+    Merged,
+    /// some files are in conflict: nothing is committed, the merge is
+    /// concluded by a commit once they are resolved
+    Conflicts,
+}
+
+/// What a merge did, to describe it
+#[derive(Debug)]
+pub struct MergeOutput {
+    pub status: MergeStatus,
+    /// commit HEAD pointed to before the merge, None on a branch without
+    /// commits
+    pub previous_hash: Option<String>,
+    /// commit HEAD points to after the merge
+    pub hash: String,
+    /// what happened to the files (Auto-merging, CONFLICT...), in the order
+    /// git prints them
+    pub messages: Vec<String>,
+    /// files that could not be merged, such as binary files
+    pub warnings: Vec<String>,
+    /// paths in conflict, sorted
+    pub conflicts: Vec<String>,
+}
+
+/// Merges a commit into HEAD:
+/// - nothing is done if it is already in the history of HEAD
+/// - HEAD is fast-forwarded to the commit if it is a descendant of HEAD,
+///   unless no_ff is set
+/// - otherwise the trees are merged from their merge base, file by file, then
+///   line by line. A clean merge is committed with both commits as parents.
+///   Conflicts are left in the work tree with markers and in the index as
+///   stages 1 (base), 2 (ours) and 3 (theirs); MERGE_HEAD and MERGE_MSG are
+///   written for the commit that concludes the merge
+///
+/// The index must match HEAD, and the files the merge writes must not have
+/// local changes
+pub fn git_merge(
+    repository: &Repository,
+    revision: &str,
+    options: &MergeOptions,
+) -> Result<MergeOutput> {
+    let refs = repository.refs();
+    let object_database = repository.object_database();
+    let git_dir = repository.git_dir();
+    if git_dir.join("MERGE_HEAD").exists() {
+        return Err(Error::MergeInProgress);
+    }
+    let mut index = GitIndex::read(repository)?;
+    if index.entries.iter().any(|entry| entry.stage() != 0) {
+        return Err(Error::UnmergedIndex);
+    }
+
+    let revision = match revision {
+        "-" => "@{-1}",
+        revision => revision,
+    };
+    let their_hash = object_database
+        .peel(&resolve_revision(repository, revision)?, "commit")
+        .map_err(|_| Error::InvalidRevision(revision.to_string()))?;
+    // @{-1} is described by the branch it designates
+    let name = match revision.starts_with("@{-") {
+        true => resolve_ref_name(repository, revision)?
+            .map(|name| name.trim_start_matches("refs/heads/").to_string())
+            .unwrap_or_else(|| revision.to_string()),
+        false => revision.to_string(),
+    };
+    let reflog_action = format!("merge {}", revision);
+    let mut output = MergeOutput {
+        status: MergeStatus::UpToDate,
+        previous_hash: None,
+        hash: their_hash.clone(),
+        messages: Vec::new(),
+        warnings: Vec::new(),
+        conflicts: Vec::new(),
+    };
+
+    let their_tree = object_database.peel(&their_hash, "tree")?;
+    let Some(head) = refs.resolve("HEAD")? else {
+        // a branch without commits starts at the merged commit
+        switch_trees(repository, &mut index, None, &their_tree, false)?;
+        index.write(repository)?;
+        update_head(repository, None, &their_hash, "initial pull")?;
+        output.status = MergeStatus::FastForward;
+        return Ok(output);
+    };
+    output.previous_hash = Some(head.clone());
+    output.hash = head.clone();
+
+    let bases = merge_bases(repository, &head, std::slice::from_ref(&their_hash))?;
+    refs.update("ORIG_HEAD", &head, None)?;
+    if bases.contains(&their_hash) {
+        return Ok(output);
+    }
+    let head_tree = object_database.peel(&head, "tree")?;
+    if bases == [head.clone()] && !options.no_ff {
+        switch_trees(repository, &mut index, Some(&head_tree), &their_tree, false)?;
+        index.write(repository)?;
+        let message = format!("{}: Fast-forward", reflog_action);
+        update_head(repository, Some(&head), &their_hash, &message)?;
+        output.status = MergeStatus::FastForward;
+        output.hash = their_hash;
+        return Ok(output);
+    }
+    if bases.is_empty() && !options.allow_unrelated_histories {
+        return Err(Error::UnrelatedHistories(revision.to_string()));
+    }
+
+    // the merge starts from the index: it must not have staged changes
+    let head_files = tree_files(repository, &head_tree)?;
+    let mut staged_files = index
+        .entries
+        .iter()
+        .filter(|entry| head_files.get(&entry.path) != Some(&(entry.mode, entry.hash.clone())))
+        .map(|entry| entry.path.clone())
+        .collect::<BTreeSet<_>>();
+    staged_files.extend(
+        head_files
+            .keys()
+            .filter(|path| index.get(path).is_none())
+            .cloned(),
+    );
+    if !staged_files.is_empty() {
+        return Err(Error::LocalChangesWouldBeOverwritten(
+            staged_files.into_iter().collect(),
+        ));
+    }
+
+    // an unknown style in the configuration does not prevent merging
+    let config = repository.config()?;
+    let style = match (options.conflict_style, config.get("merge.conflictStyle")) {
+        (Some(style), _) => style,
+        (None, Some(name)) => ConflictStyle::from_name(name).unwrap_or_else(|| {
+            output.warnings.push(format!(
+                "unknown style '{}' given for 'merge.conflictstyle'",
+                name
+            ));
+            ConflictStyle::default()
+        }),
+        (None, None) => ConflictStyle::default(),
+    };
+    let context = MergeContext {
+        repository,
+        work_tree: repository.work_tree()?,
+        head_files: &head_files,
+        style,
+    };
+    let merge = merge_commits(
+        &context,
+        &MergeCommit::Commit(head.clone()),
+        &MergeCommit::Commit(their_hash.clone()),
+        ("HEAD", &name),
+        0,
+    )?;
+    // files merged from their versions must not have local changes, even if
+    // the result is the version of HEAD
+    let changed_files = unstaged_changes(repository, &index, &merge.unmerged_paths)?;
+    if !changed_files.is_empty() {
+        return Err(Error::LocalChangesWouldBeOverwritten(changed_files));
+    }
+    merge.apply(&mut index, &head_tree)?;
+    index.write(repository)?;
+    output.messages = merge.messages.clone();
+    output.warnings.extend(merge.warnings.iter().cloned());
+
+    let message = merge_message(repository, revision, &name)?;
+    if !merge.is_clean {
+        output.conflicts = merge.conflicts();
+        let mut merge_message = format!("{}\n# Conflicts:\n", message);
+        for path in &output.conflicts {
+            merge_message += &format!("#\t{}\n", path);
+        }
+        std::fs::write(git_dir.join("MERGE_HEAD"), format!("{}\n", their_hash))?;
+        std::fs::write(git_dir.join("MERGE_MSG"), merge_message)?;
+        let mode = match options.no_ff {
+            true => "no-ff",
+            false => "",
+        };
+        std::fs::write(git_dir.join("MERGE_MODE"), mode)?;
+        output.status = MergeStatus::Conflicts;
+        return Ok(output);
+    }
+
+    let tree = index.write_tree(object_database)?;
+    // keeps the cache tree for the next commit
+    index.write(repository)?;
+    let parents = [head.clone(), their_hash];
+    let hash = git_commit_tree(repository, &tree, &parents, message.as_bytes())?;
+    let reflog_message = format!("{}: Merge made by the 'recursive' strategy.", reflog_action);
+    update_head(repository, Some(&head), &hash, &reflog_message)?;
+    output.status = MergeStatus::Merged;
+    output.hash = hash;
+    Ok(output)
+}
+
+/// Moves the branch HEAD points to (or HEAD itself if detached) from the old
+/// commit to the new one, and records it in the reflogs
+fn update_head(repository: &Repository, old: Option<&str>, new: &str, message: &str) -> Result<()> {
+    let refs = repository.refs();
+    let identity = committer_signature(&repository.config()?)?.to_string();
+    let name = refs.head_ref()?.unwrap_or_else(|| "HEAD".to_string());
+    refs.update(&name, new, Some(old.unwrap_or(NULL_HASH)))?;
+    if name != "HEAD" {
+        refs.append_reflog(&name, old, new, &identity, message)?;
+    }
+    refs.append_reflog("HEAD", old, new, &identity, message)
+}
+
+/// Message of the merge commit, as git writes it: `Merge branch 'side'`, or
+/// `tag`, `remote-tracking branch` or `commit` depending on the revision,
+/// followed by ` into <branch>` unless the current branch is one of
+/// merge.suppressDest (main and master by default). The message of an
+/// annotated tag is added after it
+fn merge_message(repository: &Repository, revision: &str, name: &str) -> Result<String> {
+    let refs = repository.refs();
+    let object_database = repository.object_database();
+    let ref_name = match revision.starts_with("@{-") {
+        true => resolve_ref_name(repository, revision)?,
+        false => refs.expand_name(revision)?,
+    };
+
+    let kinds = [
+        ("refs/heads/", "branch"),
+        ("refs/tags/", "tag"),
+        ("refs/remotes/", "remote-tracking branch"),
+    ];
+    let kind = ref_name.as_deref().and_then(|ref_name| {
+        kinds
+            .iter()
+            .find(|(prefix, _)| ref_name.starts_with(prefix))
+            .map(|(_, kind)| *kind)
+    });
+    let hash = resolve_revision(repository, revision)?;
+    let tag = match object_database.read_object(&hash)?.content {
+        GitObjectContent::Tag { content } => Some(content),
+        _ => None,
+    };
+    let mut title = match kind {
+        Some(kind) => format!("Merge {} '{}'", kind, name),
+        None => match early_part(repository, name)? {
+            Some(branch) => format!("Merge branch '{}'{}", branch.0, branch.1),
+            None if tag.is_some() => format!("Merge tag '{}'", name),
+            None => format!("Merge commit '{}'", name),
+        },
+    };
+
+    let config = repository.config()?;
+    let mut suppressed = Vec::new();
+    for value in config.get_all("merge.suppressDest") {
+        match value {
+            "" => suppressed.clear(),
+            value => suppressed.push(value),
+        }
+    }
+    if config.get_all("merge.suppressDest").is_empty() {
+        suppressed = vec!["main", "master"];
+    }
+    match refs.head_ref()? {
+        Some(head_ref) => {
+            let branch = head_ref.trim_start_matches("refs/heads/");
+            if !suppressed.contains(&branch) {
+                title += &format!(" into {}", branch);
+            }
+        }
+        None => title += " into HEAD",
+    }
+
+    let mut message = format!("{}\n", title);
+    if let Some(tag) = tag {
+        let (tag_message, _) = tag.message_and_signature();
+        let tag_message = String::from_utf8_lossy(tag_message);
+        if !tag_message.trim_end().is_empty() {
+            message += &format!("\n{}\n", tag_message.trim_end());
+        }
+    }
+    Ok(message)
+}
+
+/// For `<branch>^`, `<branch>~<n>` or `<branch>~0`: the branch, with
+/// ` (early part)` when the commit is not the tip of the branch
+fn early_part(repository: &Repository, name: &str) -> Result<Option<(String, &'static str)>> {
+    let without_carets = name.trim_end_matches('^');
+    let (branch, is_early) = if without_carets.len() != name.len() {
+        (without_carets, true)
+    } else if let Some((branch, number)) = name.rsplit_once('~') {
+        if !number.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Ok(None);
+        }
+        // `name~` is `name~1`
+        let is_early = number.is_empty() || number.bytes().any(|byte| byte != b'0');
+        (branch, is_early)
+    } else {
+        return Ok(None);
+    };
+    let full_name = format!("refs/heads/{}", branch);
+    if repository.refs().resolve(&full_name)?.is_none() {
+        return Ok(None);
+    }
+    let early = match is_early {
+        true => " (early part)",
+        false => "",
+    };
+    Ok(Some((branch.to_string(), early)))
+}
+
+/// Message prepared by a merge that stopped on conflicts (MERGE_MSG), for
+/// the commit that concludes it. Like git without editor, its comment lines
+/// listing the conflicts are kept. None if there is no merge in progress
+pub fn merge_in_progress_message(repository: &Repository) -> Result<Option<String>> {
+    let git_dir = repository.git_dir();
+    if !git_dir.join("MERGE_HEAD").exists() {
+        return Ok(None);
+    }
+    let message = std::fs::read_to_string(git_dir.join("MERGE_MSG")).unwrap_or_default();
+    Ok(Some(message.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: ConflictLabels = ConflictLabels {
+        base: "base",
+        ours: "ours",
+        theirs: "theirs",
+    };
+
+    const BASE: &str = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+
+    /// Merges the files as `git merge-file -p -L ours -L base -L theirs` does
+    fn merge(ours: &str, theirs: &str, style: ConflictStyle) -> (String, usize) {
+        let (merged, nb_conflicts) = merge_lines(
+            BASE.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            &LABELS,
+            style,
+            CONFLICT_MARKER_SIZE,
+        );
+        (String::from_utf8(merged).unwrap(), nb_conflicts)
+    }
+
+    #[test]
+    fn conflict_style_names() {
+        assert_eq!(
+            ConflictStyle::from_name("merge"),
+            Some(ConflictStyle::Merge)
+        );
+        assert_eq!(
+            ConflictStyle::from_name("diff3"),
+            Some(ConflictStyle::Diff3)
+        );
+        assert_eq!(
+            ConflictStyle::from_name("zdiff3"),
+            Some(ConflictStyle::Diff3)
+        );
+        assert_eq!(ConflictStyle::from_name("unknown"), None);
+    }
+
+    #[test]
+    fn clean_merge() {
+        let ours = "1\nA\n3\n4\n5\n6\n7\n8\n9\n";
+        let theirs = "1\n2\n3\n4\n5\n6\n7\n8\nZ\n";
+        let merged = "1\nA\n3\n4\n5\n6\n7\n8\nZ\n";
+        assert_eq!(
+            merge(ours, theirs, ConflictStyle::Merge),
+            (merged.to_string(), 0)
+        );
+        assert_eq!(
+            merge(BASE, theirs, ConflictStyle::Merge),
+            (theirs.to_string(), 0)
+        );
+        assert_eq!(
+            merge(ours, BASE, ConflictStyle::Merge),
+            (ours.to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn conflict_and_identical_change() {
+        let ours = "1\nA\n3\n4\n5\n6\n7\n8\nX\n";
+        let theirs = "1\nB\n3\n4\n5\n6\n7\n8\nX\n";
+        assert_eq!(
+            merge(ours, theirs, ConflictStyle::Merge),
+            (
+                "1\n<<<<<<< ours\nA\n=======\nB\n>>>>>>> theirs\n3\n4\n5\n6\n7\n8\nX\n".to_string(),
+                1
+            )
+        );
+        assert_eq!(
+            merge(ours, theirs, ConflictStyle::Diff3),
+            (
+                "1\n<<<<<<< ours\nA\n||||||| base\n2\n=======\nB\n>>>>>>> theirs\n\
+                 3\n4\n5\n6\n7\n8\nX\n"
+                    .to_string(),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn close_conflicts_are_joined_except_with_diff3() {
+        let ours = "1\nA\n3\n4\nC\n6\n7\n8\n9\n";
+        let theirs = "1\nB\n3\n4\nD\n6\n7\n8\n9\n";
+        assert_eq!(
+            merge(ours, theirs, ConflictStyle::Merge),
+            (
+                "1\n<<<<<<< ours\nA\n3\n4\nC\n=======\nB\n3\n4\nD\n>>>>>>> theirs\n\
+                 6\n7\n8\n9\n"
+                    .to_string(),
+                1
+            )
+        );
+        assert_eq!(
+            merge(ours, theirs, ConflictStyle::Diff3),
+            (
+                "1\n<<<<<<< ours\nA\n||||||| base\n2\n=======\nB\n>>>>>>> theirs\n3\n4\n\
+                 <<<<<<< ours\nC\n||||||| base\n5\n=======\nD\n>>>>>>> theirs\n6\n7\n8\n9\n"
+                    .to_string(),
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn conflicts_are_narrowed_to_the_differing_lines() {
+        let ours = "1\nsame\nA\nsame2\n5\n6\n7\n8\n9\n";
+        let theirs = "1\nsame\nB\nsame2\n5\n6\n7\n8\n9\n";
+        assert_eq!(
+            merge(ours, theirs, ConflictStyle::Merge),
+            (
+                "1\nsame\n<<<<<<< ours\nA\n=======\nB\n>>>>>>> theirs\nsame2\n5\n6\n7\n8\n9\n"
+                    .to_string(),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn crlf_markers_and_marker_size() {
+        let (merged, nb_conflicts) = merge_lines(
+            b"1\r\n2\r\n3\r\n",
+            b"1\r\nA\r\n3\r\n",
+            b"1\r\nB\r\n3\r\n",
+            &LABELS,
+            ConflictStyle::Merge,
+            CONFLICT_MARKER_SIZE,
+        );
+        assert_eq!(nb_conflicts, 1);
+        assert_eq!(
+            merged,
+            b"1\r\n<<<<<<< ours\r\nA\r\n=======\r\nB\r\n>>>>>>> theirs\r\n3\r\n"
+        );
+
+        let (merged, _) = merge_lines(
+            b"1\n2\n",
+            b"1\nA\n",
+            b"1\nB\n",
+            &LABELS,
+            ConflictStyle::Merge,
+            10,
+        );
+        assert_eq!(
+            merged,
+            b"1\n<<<<<<<<<< ours\nA\n==========\nB\n>>>>>>>>>> theirs\n"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use crate::git_repository::Repository;
+use crate::git_rev_walk::{is_ancestor, merge_bases, octopus_merge_bases};
+use crate::git_revision::resolve_revision;
+use crate::{Error, Result};
+
+/// The best common ancestors of the first commit and the others, most recent
+/// first. When octopus is set, the best common ancestors of all the commits.
+/// Empty if the histories are unrelated
+pub fn git_merge_base(
+    repository: &Repository,
+    revisions: &[String],
+    octopus: bool,
+) -> Result<Vec<String>> {
+    let commits = revisions
+        .iter()
+        .map(|revision| resolve_commit(repository, revision))
+        .collect::<Result<Vec<_>>>()?;
+    if octopus {
+        return octopus_merge_bases(repository, &commits);
+    }
+    let Some((first, others)) = commits.split_first() else {
+        return Ok(Vec::new());
+    };
+    merge_bases(repository, first, others)
+}
+
+/// Whether the first commit is an ancestor of the second one (or the same)
+pub fn git_merge_base_is_ancestor(
+    repository: &Repository,
+    ancestor: &str,
+    descendant: &str,
+) -> Result<bool> {
+    let ancestor = resolve_commit(repository, ancestor)?;
+    let descendant = resolve_commit(repository, descendant)?;
+    is_ancestor(repository, &ancestor, &descendant)
+}
+
+fn resolve_commit(repository: &Repository, revision: &str) -> Result<String> {
+    let hash = resolve_revision(repository, revision)?;
+    repository
+        .object_database()
+        .peel(&hash, "commit")
+        .map_err(|_| Error::InvalidRevision(revision.to_string()))
+}
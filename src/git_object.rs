@@ -1,44 +1,45 @@
 use sha1::{Digest, Sha1};
-use std::fs::{create_dir_all, read_dir, File};
+use std::fs::{read_dir, File};
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Read;
-use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
-use std::path::PathBuf;
 
+use crate::git_object_database::GitObjectDatabase;
 use crate::{Error, Result};
 
 #[derive(Debug)]
 /// Content of a given GitObject
 pub enum GitObjectContent {
     Blob {
-        content: String,
+        /// raw bytes of the file, which may not be valid utf8 (images, binaries...)
+        content: Vec<u8>,
     },
     Tree {
         content: Vec<TreeChild>,
     },
+    /// boxed as it is much bigger than the other variants
     Commit {
-        content: CommitObjects,
+        content: Box<CommitObjects>,
     },
     /// only annotated tags are stored as objects
     Tag {
-        /// For now we stores the full content as a string
-        /// but we could do like the commit object and store it as
-        /// its constituents
-        content: String,
+        content: Box<TagObjects>,
     },
 }
 
 /// Tree points to a git object but also have additional information
-/// such as the name and the mode of said object
+/// such as the name and the mode of said object.
+/// The underlying object is not loaded so that reading a tree does not read
+/// the whole repository
 #[derive(Debug)]
 pub struct TreeChild {
     /// Different values for dir and blob (100644 for files, 40000 for dirs)
     pub mode: u32,
-    /// Do not store the full child object when unnecessary
-    pub git_object: Option<GitObject>,
-    /// hash of underlying git object
+    /// hash of underlying git object. The object itself is only loaded on demand
+    /// through the object database
     pub hash: String,
     /// name of the file
     pub name: String,
@@ -73,24 +74,52 @@ impl TreeChild {
             mode,
             name: name.to_string(),
             hash: child_hash.to_string(),
-            git_object: None,
         }))
     }
 
+    /// Type of the underlying object, deduced from the mode so that the object
+    /// does not need to be loaded
+    pub fn content_type(&self) -> String {
+        match self.mode {
+            40000 => "tree".to_owned(),
+            // submodules
+            160000 => "commit".to_owned(),
+            _ => "blob".to_owned(),
+        }
+    }
+
+    /// Loads the underlying git object from the object database
+    pub fn load(&self, object_database: &GitObjectDatabase) -> Result<GitObject> {
+        object_database.read_object(&self.hash)
+    }
+
     /// uses name and child content to recreate it in the target dir
-    pub fn restore_directory<P: AsRef<Path> + ?Sized>(self, parent_dir: &P) -> Result<()> {
+    pub fn restore_directory<P: AsRef<Path> + ?Sized>(
+        &self,
+        parent_dir: &P,
+        object_database: &GitObjectDatabase,
+    ) -> Result<()> {
         std::fs::create_dir_all(parent_dir.as_ref())?;
-        let git_object = self.git_object.ok_or_else(|| Error::TreeChildNotLoaded)?;
+        let git_object = self.load(object_database)?;
 
-        let child = parent_dir.as_ref().join(self.name);
+        let child = parent_dir.as_ref().join(&self.name);
 
         match git_object.content {
-            GitObjectContent::Blob { content } => {
-                std::fs::write(child, content)?;
-            }
+            GitObjectContent::Blob { content } => match self.mode {
+                120000 => {
+                    let target = std::ffi::OsStr::from_bytes(&content);
+                    std::os::unix::fs::symlink(target, child)?;
+                }
+                100755 => {
+                    std::fs::write(&child, content)?;
+                    let permissions = std::fs::Permissions::from_mode(0o755);
+                    std::fs::set_permissions(child, permissions)?;
+                }
+                _ => std::fs::write(child, content)?,
+            },
             GitObjectContent::Tree { content } => {
                 for tree_child in content {
-                    tree_child.restore_directory(&child)?;
+                    tree_child.restore_directory(&child, object_database)?;
                 }
             }
             _ => Err(Error::InvalidGitObject)?,
@@ -99,82 +128,336 @@ impl TreeChild {
     }
 }
 
-#[derive(Debug)]
+/// Identity of the author or the committer of a commit, with the date:
+/// `Name <email> timestamp timezone`.
+/// The name and the email are kept as raw bytes as they are not required to be
+/// valid utf8 (see the encoding header of commits)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: Vec<u8>,
+    pub email: Vec<u8>,
+    /// seconds since the epoch. Can be negative for dates before 1970
+    pub timestamp: i64,
+    /// offset from UTC, for ex +0200
+    pub timezone: String,
+    /// bytes the signature was parsed from, written back as they are: the
+    /// date may be written in several ways (0123, +123, without space before
+    /// it...) and the hash of the object must not change. None for new
+    /// signatures
+    pub raw: Option<Vec<u8>>,
+}
+
+impl Signature {
+    /// Parses `Name <email> timestamp timezone`
+    pub fn from_bytes(signature: &[u8]) -> Result<Self> {
+        let email_start = signature
+            .iter()
+            .position(|&b| b == b'<')
+            .ok_or_else(|| Error::InvalidGitObject)?;
+        let email_end = signature
+            .iter()
+            .rposition(|&b| b == b'>')
+            .ok_or_else(|| Error::InvalidGitObject)?;
+        if email_end < email_start {
+            return Err(Error::InvalidGitObject);
+        }
+        // only the space separating the name from the email is removed
+        let name = signature[..email_start]
+            .strip_suffix(b" ")
+            .unwrap_or(&signature[..email_start]);
+
+        let date = String::from_utf8_lossy(&signature[email_end + 1..]);
+        let (timestamp, timezone) = date
+            .trim_start()
+            .split_once(' ')
+            .ok_or_else(|| Error::InvalidGitObject)?;
+        Ok(Self {
+            name: name.to_vec(),
+            email: signature[email_start + 1..email_end].to_vec(),
+            timestamp: timestamp
+                .parse::<i64>()
+                .map_err(|_| Error::InvalidGitObject)?,
+            timezone: timezone.to_string(),
+            raw: Some(signature.to_vec()),
+        })
+    }
+
+    /// Serializes the signature as it is written in objects
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if let Some(raw) = &self.raw {
+            return raw.clone();
+        }
+        let mut bytes = self.name.clone();
+        if !self.name.is_empty() {
+            bytes.push(b' ');
+        }
+        bytes.push(b'<');
+        bytes.extend(&self.email);
+        bytes.extend(format!("> {} {}", self.timestamp, self.timezone).as_bytes());
+        bytes
+    }
+}
+
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.to_bytes()))
+    }
+}
+
+/// A commit. Parsing then serializing a commit gives back the exact same bytes,
+/// so that its hash does not change
+#[derive(Debug, Clone)]
 pub struct CommitObjects {
-    pub timestamp: u32,
-    // we'll have author and committer be the same
-    pub author_email: String,
-    pub author_name: String,
-    pub author_timezone: String,
-    // support for multiple parents. But in our case, we will have only 1
-    pub parents_sha: Vec<String>,
     pub tree_sha: String,
-    pub commit_msg: String,
+    /// several parents for merge commits, none for root commits
+    pub parents_sha: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    /// headers found after the committer, in order (encoding, mergetag,
+    /// gpgsig...). The continuation lines of multi-line values are joined with
+    /// \n, without the space they start with
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    /// kept as raw bytes as the message is not required to be valid utf8
+    /// (see the encoding header). Includes the final newline, if any.
+    /// None if there is not even the empty line separating the headers from
+    /// the message
+    pub commit_msg: Option<Vec<u8>>,
+    /// false when the last header has no final newline, which is only
+    /// possible without message
+    pub headers_end_with_newline: bool,
 }
 
-impl CommitObjects {
-    /// Parses a string (decoded by zlib) to create the objects
-    pub fn from_content(content: &str) -> Result<Self> {
-        // TODO: may fail if empty commit msg
-        let (beginning, commit_msg) = content
-            .split_once("\n\n")
+/// Headers of a commit or a tag (name and value), in order
+type Headers = Vec<(String, Vec<u8>)>;
+
+/// Splits the content of a commit or a tag in its headers, in order, and its
+/// message.
+/// The continuation lines of multi-line values (starting with a space) are
+/// joined with \n, without the space.
+/// The message is None if there is not even the empty line separating it from
+/// the headers
+fn split_headers(content: &[u8]) -> Result<(Headers, Option<Vec<u8>>)> {
+    let mut headers: Headers = Vec::new();
+
+    let mut pos = 0;
+    while pos < content.len() {
+        let line_end = content[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(content.len(), |end| pos + end);
+        let line = &content[pos..line_end];
+        pos = line_end + 1;
+
+        // an empty line separates the headers from the message
+        if line.is_empty() {
+            return Ok((
+                headers,
+                Some(content.get(pos..).unwrap_or_default().to_vec()),
+            ));
+        }
+
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = headers.last_mut().ok_or_else(|| Error::InvalidGitObject)?;
+            value.push(b'\n');
+            value.extend(continuation);
+            continue;
+        }
+
+        let separator = line
+            .iter()
+            .position(|&b| b == b' ')
             .ok_or_else(|| Error::InvalidGitObject)?;
-        let commit_msg = commit_msg.to_string();
-        let mut timestamp = 0;
-        let mut author_email = String::from("");
-        let mut author_name = String::from("");
-        let mut author_timezone = String::from("");
-        let mut parents_sha = Vec::new();
-        let mut tree_sha = String::from("");
+        let key = String::from_utf8_lossy(&line[..separator]).to_string();
+        headers.push((key, line[separator + 1..].to_vec()));
+    }
+    Ok((headers, None))
+}
 
-        for line in beginning.lines() {
-            let (head, tail) = line
-                .split_once(' ')
-                .ok_or_else(|| Error::InvalidGitObject)?;
+/// Writes a header, the opposite of split_headers
+fn write_header(bytes: &mut Vec<u8>, key: &str, value: &[u8]) {
+    bytes.extend(key.as_bytes());
+    bytes.push(b' ');
+    for (i, line) in value.split(|&b| b == b'\n').enumerate() {
+        if i > 0 {
+            bytes.extend(b"\n ");
+        }
+        bytes.extend(line);
+    }
+    bytes.push(b'\n');
+}
 
-            match head {
-                "tree" => {
-                    tree_sha = tail.to_string();
+impl CommitObjects {
+    /// Parses the bytes (decoded by zlib) to create the objects
+    pub fn from_content(content: &[u8]) -> Result<Self> {
+        let (headers, commit_msg) = split_headers(content)?;
+        let mut tree_sha = None;
+        let mut parents_sha = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "tree" if tree_sha.is_none() => {
+                    tree_sha = Some(String::from_utf8_lossy(&value).to_string());
                 }
-                "parent" => {
-                    parents_sha.push(tail.to_string());
+                "parent" if author.is_none() => {
+                    parents_sha.push(String::from_utf8_lossy(&value).to_string());
                 }
-                "author" => {
-                    let mut author_info = tail.split(' ').collect::<Vec<_>>();
-
-                    author_timezone = author_info
-                        .pop()
-                        .ok_or_else(|| Error::InvalidGitObject)?
-                        .to_string();
-                    timestamp = author_info
-                        .pop()
-                        .ok_or_else(|| Error::InvalidGitObject)?
-                        .parse::<u32>()
-                        .map_err(|_| Error::InvalidGitObject)?;
-                    let author_email_enclosing =
-                        author_info.pop().ok_or_else(|| Error::InvalidGitObject)?;
-
-                    author_email =
-                        author_email_enclosing[1..author_email_enclosing.len() - 1].to_string();
-                    author_name = author_info.join(" ");
+                "author" if author.is_none() => {
+                    author = Some(Signature::from_bytes(&value)?);
                 }
-
-                "committer" => {
-                    // same as author for us
+                "committer" if committer.is_none() => {
+                    committer = Some(Signature::from_bytes(&value)?);
+                }
+                _ => {
+                    // other headers are only expected after the committer
+                    if committer.is_none() {
+                        return Err(Error::InvalidGitObject);
+                    }
+                    extra_headers.push((key, value));
                 }
-                _ => {}
             }
         }
+
         Ok(CommitObjects {
-            timestamp,
-            author_name,
-            author_email,
-            author_timezone,
+            headers_end_with_newline: commit_msg.is_some() || content.ends_with(b"\n"),
+            tree_sha: tree_sha.ok_or_else(|| Error::InvalidGitObject)?,
             parents_sha,
-            tree_sha,
+            author: author.ok_or_else(|| Error::InvalidGitObject)?,
+            committer: committer.ok_or_else(|| Error::InvalidGitObject)?,
+            extra_headers,
             commit_msg,
         })
     }
+
+    /// Serializes the commit (header of the object not included)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, "tree", self.tree_sha.as_bytes());
+        for parent_sha in &self.parents_sha {
+            write_header(&mut bytes, "parent", parent_sha.as_bytes());
+        }
+        write_header(&mut bytes, "author", &self.author.to_bytes());
+        write_header(&mut bytes, "committer", &self.committer.to_bytes());
+        for (key, value) in &self.extra_headers {
+            write_header(&mut bytes, key, value);
+        }
+        if !self.headers_end_with_newline {
+            bytes.pop();
+        }
+
+        if let Some(commit_msg) = &self.commit_msg {
+            bytes.push(b'\n');
+            bytes.extend(commit_msg);
+        }
+        bytes
+    }
+
+    /// Value of the first header with the given name among the extra headers,
+    /// for ex encoding
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.extra_headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// The message, or an empty one
+    pub fn message(&self) -> &[u8] {
+        self.commit_msg.as_deref().unwrap_or_default()
+    }
+}
+
+/// An annotated tag. As for commits, parsing then serializing a tag gives back
+/// the exact same bytes
+#[derive(Debug, Clone)]
+pub struct TagObjects {
+    /// hash of the tagged object
+    pub object_sha: String,
+    /// type of the tagged object (usually commit)
+    pub object_type: String,
+    /// name of the tag
+    pub tag_name: String,
+    /// very old tags do not have a tagger
+    pub tagger: Option<Signature>,
+    /// other headers after the tagger, in order
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    /// the message, followed by the signature for signed tags. Includes the
+    /// final newline, if any
+    pub tag_msg: Option<Vec<u8>>,
+}
+
+impl TagObjects {
+    /// Parses the content of a tag object
+    pub fn from_content(content: &[u8]) -> Result<Self> {
+        let (headers, tag_msg) = split_headers(content)?;
+        let mut headers = headers.into_iter().peekable();
+
+        let mut next_header = |expected_key: &str| match headers.next() {
+            Some((key, value)) if key == expected_key => Ok(value),
+            _ => Err(Error::InvalidGitObject),
+        };
+        let object_sha = String::from_utf8_lossy(&next_header("object")?).to_string();
+        let object_type = String::from_utf8_lossy(&next_header("type")?).to_string();
+        let tag_name = String::from_utf8_lossy(&next_header("tag")?).to_string();
+
+        let tagger = match headers.next_if(|(key, _)| key == "tagger") {
+            Some((_, value)) => Some(Signature::from_bytes(&value)?),
+            None => None,
+        };
+
+        Ok(Self {
+            object_sha,
+            object_type,
+            tag_name,
+            tagger,
+            extra_headers: headers.collect(),
+            tag_msg,
+        })
+    }
+
+    /// Serializes the tag (header of the object not included)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, "object", self.object_sha.as_bytes());
+        write_header(&mut bytes, "type", self.object_type.as_bytes());
+        write_header(&mut bytes, "tag", self.tag_name.as_bytes());
+        if let Some(tagger) = &self.tagger {
+            write_header(&mut bytes, "tagger", &tagger.to_bytes());
+        }
+        for (key, value) in &self.extra_headers {
+            write_header(&mut bytes, key, value);
+        }
+        if let Some(tag_msg) = &self.tag_msg {
+            bytes.push(b'\n');
+            bytes.extend(tag_msg);
+        }
+        bytes
+    }
+
+    /// Splits the message in the message itself and the signature that is
+    /// appended to it for signed tags (PGP, SSH or X.509)
+    pub fn message_and_signature(&self) -> (&[u8], Option<&[u8]>) {
+        let tag_msg = self.tag_msg.as_deref().unwrap_or_default();
+        let signature_start = [
+            b"-----BEGIN PGP SIGNATURE-----".as_slice(),
+            b"-----BEGIN SSH SIGNATURE-----",
+            b"-----BEGIN SIGNED MESSAGE-----",
+        ]
+        .iter()
+        .filter_map(|marker| {
+            // the signature starts at the beginning of a line
+            (0..tag_msg.len()).find(|&pos| {
+                (pos == 0 || tag_msg[pos - 1] == b'\n') && tag_msg[pos..].starts_with(marker)
+            })
+        })
+        .min();
+        match signature_start {
+            Some(pos) => (&tag_msg[..pos], Some(&tag_msg[pos..])),
+            None => (tag_msg, None),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -183,8 +466,7 @@ pub struct GitObject {
     pub hash: String,
     pub content: GitObjectContent,
     /// Contains the bytes that are used to compute the hash
-    /// Only set when loading from blob, or dir but not from hash
-    /// to avoid unnecessary storage
+    /// Needed to write the object to the object database
     pub object_bytes: Option<Vec<u8>>,
 }
 
@@ -198,136 +480,15 @@ impl GitObject {
         format!("{digest:x}")
     }
 
-    pub fn find_hash_branch_or_tag_points_to<P: AsRef<Path> + ?Sized>(
-        name: &str,
-        repository_directory: &P,
-    ) -> Result<String> {
-        let branch_location: PathBuf = [".git", "refs", "heads", name].iter().collect();
-        let branch_location = repository_directory.as_ref().join(branch_location);
-
-        let tag_location: PathBuf = [".git", "refs", "tags", name].iter().collect();
-        let tag_location = repository_directory.as_ref().join(tag_location);
-
-        let location = if branch_location.exists() {
-            branch_location
-        } else if tag_location.exists() {
-            tag_location
-        } else {
-            return Err(Error::InvalidBranchOrTag);
-        };
-
-        let mut content = std::fs::read_to_string(location)?;
-        // removes the \n
-        content.pop();
-
-        Ok(content)
-    }
-
-    /// Create a git object based on the hash in hexadecimal format.
-    /// Under the hood, locates the file in the .git/objects folder of the
-    /// repository_directory
-    /// Note: If given hash is not 40 chars, we instead try to get it from branch ref
-    /// first and tag second
-    pub fn from_hash<P: AsRef<Path> + ?Sized>(
-        hash: &str,
-        repository_directory: &P,
-    ) -> Result<Self> {
-        if hash.len() != 40 {
-            let hash = Self::find_hash_branch_or_tag_points_to(hash, repository_directory)?;
-            if hash.len() != 40 {
-                Err(Error::InvalidHash(hash.clone()))?;
-            }
-
-            return Self::from_hash(&hash, repository_directory);
-        }
-        let (subdir, filename) = hash.split_at(2);
-
-        let location: PathBuf = [".git", "objects", subdir, filename].iter().collect();
-        let location = repository_directory.as_ref().join(location);
-
-        let file = File::open(location)?;
-
-        let decoder = flate2::read::ZlibDecoder::new(file);
-        let mut reader = BufReader::new(decoder);
-
-        let mut header_bytes = Vec::new();
-        reader.read_until(0, &mut header_bytes)?;
-
-        header_bytes.pop();
-        let header = String::from_utf8_lossy(&header_bytes).to_string();
-
-        let (type_obj, size_str) = header
-            .split_once(' ')
-            .ok_or_else(|| Error::InvalidGitObject)?;
-
-        let size = size_str
-            .parse::<usize>()
-            .map_err(|_| Error::InvalidGitObject)?;
-
-        match type_obj {
-            // content is actually just permission name hash for trees
-            "tree" => {
-                let mut content = Vec::new();
-                loop {
-                    let tree_child = TreeChild::from_reader(&mut reader)?;
-                    match tree_child {
-                        None => break,
-                        Some(mut tree_child) => {
-                            // loads the underlying git object
-                            tree_child.git_object = Some(GitObject::from_hash(
-                                &tree_child.hash,
-                                repository_directory,
-                            )?);
-                            content.push(tree_child);
-                        }
-                    }
-                }
-                Ok(GitObject {
-                    size,
-                    content: GitObjectContent::Tree { content },
-                    hash: hash.to_string(),
-                    object_bytes: None,
-                })
-            }
-            "blob" => {
-                let mut content = String::new();
-                reader.read_to_string(&mut content)?;
-                Ok(GitObject {
-                    size,
-                    content: GitObjectContent::Blob { content },
-                    hash: hash.to_string(),
-                    object_bytes: None,
-                })
-            }
-            "commit" => {
-                let mut content = String::new();
-                reader.read_to_string(&mut content)?;
-
-                let commit_objects = CommitObjects::from_content(&content)?;
-
-                Ok(GitObject {
-                    size,
-                    hash: hash.to_string(),
-                    object_bytes: None,
-                    content: GitObjectContent::Commit {
-                        content: commit_objects,
-                    },
-                })
-            }
-
-            "tag" => {
-                let mut content = String::new();
-                reader.read_to_string(&mut content)?;
-                Ok(GitObject {
-                    size,
-                    content: GitObjectContent::Tag { content },
-                    hash: hash.to_string(),
-                    object_bytes: None,
-                })
-            }
-
-            // are there actually other objects?
-            _ => todo!(),
+    /// Creates an object of the given type ("blob", "tree", "commit" or "tag")
+    /// from content bytes (header not included)
+    pub fn from_content_bytes(content_type: &str, content_bytes: Vec<u8>) -> Result<Self> {
+        match content_type {
+            "blob" => Self::from_blob_content_bytes(content_bytes),
+            "tree" => Self::from_tree_content_bytes(content_bytes),
+            "commit" => Self::from_commit_content_bytes(content_bytes),
+            "tag" => Self::from_tag_content_bytes(content_bytes),
+            _ => Err(Error::InvalidGitObject),
         }
     }
 
@@ -341,12 +502,13 @@ impl GitObject {
         object_bytes.extend(&content_bytes);
 
         let hash = GitObject::get_hash_from_bytes(&object_bytes);
-        let content = String::from_utf8(content_bytes).map_err(|_| Error::InvalidSmartHttpRes)?;
 
         Ok(GitObject {
             object_bytes: Some(object_bytes),
             hash,
-            content: GitObjectContent::Blob { content },
+            content: GitObjectContent::Blob {
+                content: content_bytes,
+            },
             size,
         })
     }
@@ -390,15 +552,14 @@ impl GitObject {
         object_bytes.extend(&content_bytes);
 
         let hash = GitObject::get_hash_from_bytes(&object_bytes);
-        let content = String::from_utf8(content_bytes).map_err(|_| Error::InvalidSmartHttpRes)?;
 
-        let commit_objects = CommitObjects::from_content(&content)?;
+        let commit_objects = CommitObjects::from_content(&content_bytes)?;
 
         Ok(GitObject {
             object_bytes: Some(object_bytes),
             hash,
             content: GitObjectContent::Commit {
-                content: commit_objects,
+                content: Box::new(commit_objects),
             },
             size,
         })
@@ -414,12 +575,13 @@ impl GitObject {
         object_bytes.extend(&content_bytes);
 
         let hash = GitObject::get_hash_from_bytes(&object_bytes);
-        let content = String::from_utf8(content_bytes).map_err(|_| Error::InvalidSmartHttpRes)?;
 
         Ok(GitObject {
             object_bytes: Some(object_bytes),
             hash,
-            content: GitObjectContent::Tag { content },
+            content: GitObjectContent::Tag {
+                content: Box::new(TagObjects::from_content(&content_bytes)?),
+            },
             size,
         })
     }
@@ -440,8 +602,25 @@ impl GitObject {
         Self::from_blob_content_bytes(content_bytes)
     }
 
+    /// Creates the blob of a file of the working tree.
+    /// The blob of a symbolic link contains the path it points to
+    pub fn from_work_tree_file<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let file_path = file_path.as_ref();
+        if file_path.symlink_metadata()?.file_type().is_symlink() {
+            let target = std::fs::read_link(file_path)?;
+            return Self::from_blob_content_bytes(target.as_os_str().as_bytes().to_vec());
+        }
+        Self::from_blob(file_path)
+    }
+
     /// Creates a tree object from a directory
-    pub fn from_dir<P: AsRef<Path>>(dir_path: P) -> Result<Self> {
+    /// As trees do not keep their children in memory, the blobs and subtrees are
+    /// written to the object database while they are created if one is given.
+    /// Note that the tree itself is not written
+    pub fn from_dir<P: AsRef<Path>>(
+        dir_path: P,
+        object_database: Option<&GitObjectDatabase>,
+    ) -> Result<Self> {
         let parent_dir = read_dir(dir_path)?;
 
         let mut paths = parent_dir
@@ -470,7 +649,10 @@ impl GitObject {
                     git_object = GitObject::from_blob(&path)?;
                 } else {
                     mode = 40000;
-                    git_object = GitObject::from_dir(&path)?;
+                    git_object = GitObject::from_dir(&path, object_database)?;
+                }
+                if let Some(object_database) = object_database {
+                    git_object.write(object_database)?;
                 }
                 content_bytes.extend(format!("{} {}", mode, name).as_bytes());
                 content_bytes.push(0);
@@ -483,7 +665,6 @@ impl GitObject {
                     mode,
                     name: name.to_string(),
                     hash: git_object_hash,
-                    git_object: Some(git_object),
                 });
             }
         }
@@ -504,46 +685,33 @@ impl GitObject {
     }
 
     pub fn from_commit_objects(commit_objects: CommitObjects) -> Result<Self> {
-        let mut content_bytes: Vec<u8> = Vec::new();
+        let content_bytes = commit_objects.to_bytes();
 
-        // Should i use the hash as a str or bytes here?
-        content_bytes.extend(Vec::from(
-            format!("tree {}\n", commit_objects.tree_sha).as_bytes(),
-        ));
+        let size = content_bytes.len();
 
-        for parent_sha in &commit_objects.parents_sha {
-            content_bytes.extend(Vec::from(format!("parent {}\n", parent_sha).as_bytes()));
-        }
+        let mut object_bytes = Vec::from(format!("commit {}", size));
+        object_bytes.push(0);
+        object_bytes.extend(&content_bytes);
+
+        let hash = GitObject::get_hash_from_bytes(&object_bytes);
+
+        Ok(GitObject {
+            size,
+            hash,
+            content: GitObjectContent::Commit {
+                content: Box::new(commit_objects),
+            },
+            object_bytes: Some(object_bytes),
+        })
+    }
 
-        content_bytes.extend(Vec::from(
-            format!(
-                "author {} <{}> {} {}\n",
-                commit_objects.author_name,
-                commit_objects.author_email,
-                commit_objects.timestamp,
-                commit_objects.author_timezone
-            )
-            .as_bytes(),
-        ));
-
-        content_bytes.extend(Vec::from(
-            format!(
-                "committer {} <{}> {} {}\n\n",
-                commit_objects.author_name,
-                commit_objects.author_email,
-                commit_objects.timestamp,
-                commit_objects.author_timezone
-            )
-            .as_bytes(),
-        ));
-
-        content_bytes.extend(Vec::from(
-            format!("{}\n", commit_objects.commit_msg).as_bytes(),
-        ));
+    /// Creates a tag object from its constituents
+    pub fn from_tag_objects(tag_objects: TagObjects) -> Result<Self> {
+        let content_bytes = tag_objects.to_bytes();
 
         let size = content_bytes.len();
 
-        let mut object_bytes = Vec::from(format!("commit {}", size));
+        let mut object_bytes = Vec::from(format!("tag {}", size));
         object_bytes.push(0);
         object_bytes.extend(&content_bytes);
 
@@ -552,19 +720,23 @@ impl GitObject {
         Ok(GitObject {
             size,
             hash,
-            content: GitObjectContent::Commit {
-                content: commit_objects,
+            content: GitObjectContent::Tag {
+                content: Box::new(tag_objects),
             },
             object_bytes: Some(object_bytes),
         })
     }
 
     /// Restores a tree object in a directory
-    pub fn restore_directory<P: AsRef<Path> + ?Sized>(self, directory: &P) -> Result<()> {
-        match self.content {
+    pub fn restore_directory<P: AsRef<Path> + ?Sized>(
+        &self,
+        directory: &P,
+        object_database: &GitObjectDatabase,
+    ) -> Result<()> {
+        match &self.content {
             GitObjectContent::Tree { content } => {
                 for tree_child in content {
-                    tree_child.restore_directory(directory)?;
+                    tree_child.restore_directory(directory, object_database)?;
                 }
             }
             _ => Err(Error::NotATreeGitObject)?,
@@ -572,48 +744,24 @@ impl GitObject {
         Ok(())
     }
 
-    /// Writes the object to the .git folder of the repository_directory
-    /// If recursive is set to true, tree objects will also write all their child objects
-    /// Note that recursive has no effects on commit objects as they only store the
-    /// sha of the main tree and not the actual object itself in this implementation
-    pub fn write<P: AsRef<Path> + ?Sized>(
-        &self,
-        repository_directory: &P,
-        recursive: bool,
-    ) -> Result<()> {
-        let (subdir, filename) = self.hash.split_at(2);
-
-        let location: PathBuf = [".git", "objects", subdir, filename].iter().collect();
-        let location = repository_directory.as_ref().join(location);
-
-        let parent = location.parent().ok_or_else(|| Error::InvalidGitObject)?;
-        create_dir_all(parent)?;
-        let output = File::create(location)?;
+    /// Writes the object to the object database as a loose object.
+    /// As trees do not keep their children, only the object itself is written
+    pub fn write(&self, object_database: &GitObjectDatabase) -> Result<()> {
+        object_database.write_object(self)
+    }
 
-        // write can only occur if the bytes were loaded when we got the object.
-        // So it won't work if we got it from its hash, which is actually ok because
-        // in this case, it already exists in the .git/objects folder
+    /// Returns the content of the object, without the header.
+    /// Only works if the object bytes were loaded
+    pub fn content_bytes(&self) -> Result<&[u8]> {
         let object_bytes = self
             .object_bytes
-            .clone()
+            .as_ref()
             .ok_or_else(|| Error::ObjectBytesNotLoaded)?;
-
-        let mut encoder = flate2::write::ZlibEncoder::new(output, flate2::Compression::default());
-        encoder.write_all(&object_bytes)?;
-
-        // only applies to trees
-        if recursive {
-            if let GitObjectContent::Tree { content } = &self.content {
-                for tree_child in content {
-                    tree_child
-                        .git_object
-                        .as_ref()
-                        .ok_or_else(|| Error::TreeChildNotLoaded)?
-                        .write(repository_directory, true)?;
-                }
-            }
-        }
-        Ok(())
+        let null_pos = object_bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| Error::InvalidGitObject)?;
+        Ok(&object_bytes[null_pos + 1..])
     }
 
     /// Blob => "blob", Tree => "tree", Commit => "commit"
@@ -626,3 +774,61 @@ impl GitObject {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREE: &str = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n";
+
+    #[test]
+    fn signatures_round_trip() {
+        for signature in [
+            "A U Thor <author@example.com> 1700000000 +0200",
+            "A U Thor <author@example.com> 0123 +0000",
+            "A U Thor <author@example.com> +123 +0000",
+            "A U Thor <author@example.com>  1700000000 +0000",
+            "A U Thor <author@example.com>1700000000 +0000",
+            " <author@example.com> 1700000000 +0000",
+            "<author@example.com> -1 -0130",
+        ] {
+            let parsed = Signature::from_bytes(signature.as_bytes()).unwrap();
+            assert_eq!(parsed.to_bytes(), signature.as_bytes());
+        }
+
+        let parsed = Signature::from_bytes(b"A U Thor <a@b>  0123 +0000").unwrap();
+        assert_eq!(parsed.name, b"A U Thor");
+        assert_eq!(parsed.timestamp, 123);
+        let parsed = Signature::from_bytes(b"A <a@b>+123 -0100").unwrap();
+        assert_eq!(parsed.timestamp, 123);
+        assert_eq!(parsed.timezone, "-0100");
+    }
+
+    #[test]
+    fn commits_round_trip() {
+        for signature in [
+            "A U Thor <author@example.com> 0123 +0000",
+            "A U Thor <author@example.com> +123 +0000",
+            "A U Thor <author@example.com>  1700000000 +0000",
+            "A U Thor <author@example.com>1700000000 +0000",
+            " <author@example.com> 1700000000 +0000",
+        ] {
+            let content = format!(
+                "{}author {}\ncommitter {}\n\nmessage\n",
+                TREE, signature, signature
+            );
+            let commit = CommitObjects::from_content(content.as_bytes()).unwrap();
+            assert_eq!(commit.to_bytes(), content.as_bytes());
+        }
+
+        let signature = "A U Thor <author@example.com> 1700000000 +0000";
+        for end in ["", "\n", "\n\n", "\n\nmessage"] {
+            let content = format!(
+                "{}author {}\ncommitter {}{}",
+                TREE, signature, signature, end
+            );
+            let commit = CommitObjects::from_content(content.as_bytes()).unwrap();
+            assert_eq!(commit.to_bytes(), content.as_bytes());
+        }
+    }
+}
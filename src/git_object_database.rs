@@ -0,0 +1,286 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{create_dir_all, read_dir, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::git_object::{GitObject, GitObjectContent, TreeChild};
+use crate::git_pack::GitPack;
+use crate::git_pack_index::GitPackIndexFile;
+use crate::{Error, Result};
+
+/// Handle on the objects folder of a repository (.git/objects).
+/// Objects are either stored as loose objects (.git/objects/xx/yyyy) or in packs
+/// (.git/objects/pack). The loose objects are always checked first.
+/// The indexes of the packs are only read once, on first use.
+#[derive(Debug)]
+pub struct GitObjectDatabase {
+    objects_dir: PathBuf,
+    /// each pack index is stored with the path of the corresponding .pack
+    pack_indexes: RefCell<Option<Vec<(PathBuf, GitPackIndexFile)>>>,
+}
+
+impl GitObjectDatabase {
+    /// Creates a handle on the given objects folder
+    pub fn from_objects_dir<P: AsRef<Path>>(objects_dir: P) -> Self {
+        Self {
+            objects_dir: objects_dir.as_ref().to_path_buf(),
+            pack_indexes: RefCell::new(None),
+        }
+    }
+
+    /// Folder containing the .pack and .idx files
+    pub fn pack_dir(&self) -> PathBuf {
+        self.objects_dir.join("pack")
+    }
+
+    /// Location of the loose object: first 2 chars of the hash are the folder
+    fn loose_object_path(&self, hash: &str) -> Result<PathBuf> {
+        if hash.len() != 40 {
+            Err(Error::InvalidHash(hash.to_string()))?;
+        }
+        let (subdir, filename) = hash.split_at(2);
+        Ok(self.objects_dir.join(subdir).join(filename))
+    }
+
+    /// Loads the indexes of all the packs of the repository if it was not done yet
+    fn load_pack_indexes(&self) -> Result<()> {
+        if self.pack_indexes.borrow().is_some() {
+            return Ok(());
+        }
+        let mut pack_indexes = Vec::new();
+        let pack_dir = self.pack_dir();
+        if pack_dir.is_dir() {
+            for entry in read_dir(pack_dir)? {
+                let index_path = entry?.path();
+                if index_path.extension().and_then(|e| e.to_str()) != Some("idx") {
+                    continue;
+                }
+                let index = GitPackIndexFile::from_file(&index_path)?;
+                pack_indexes.push((index_path.with_extension("pack"), index));
+            }
+        }
+        *self.pack_indexes.borrow_mut() = Some(pack_indexes);
+        Ok(())
+    }
+
+    /// Forgets the loaded pack indexes, for ex after a new pack was written
+    pub fn reload_packs(&self) {
+        *self.pack_indexes.borrow_mut() = None;
+    }
+
+    /// Looks for the object in every pack.
+    /// Returns the type of the object and its content (header not included),
+    /// or None if no index contains the object
+    fn read_packed_object(&self, hash: &str) -> Result<Option<(String, Vec<u8>)>> {
+        self.load_pack_indexes()?;
+        let pack_indexes = self.pack_indexes.borrow();
+        for (pack_path, index) in pack_indexes.iter().flatten() {
+            if let Some(offset) = index.find_offset(hash)? {
+                let pack_file = File::open(pack_path)?;
+                let mut reader = BufReader::new(pack_file);
+
+                let mut signature = [0u8; 4];
+                reader.read_exact(&mut signature)?;
+                if &signature != b"PACK" {
+                    return Err(Error::InvalidPackFile);
+                }
+
+                return Ok(Some(GitPack::read_object_at_offset(
+                    &mut reader,
+                    offset,
+                    index,
+                    self,
+                )?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks if the object is stored in the database
+    pub fn contains(&self, hash: &str) -> Result<bool> {
+        if self.loose_object_path(hash)?.exists() {
+            return Ok(true);
+        }
+        self.load_pack_indexes()?;
+        for (_, index) in self.pack_indexes.borrow().iter().flatten() {
+            if index.find_offset(hash)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Hashes of the objects (loose or packed) starting with the given
+    /// hexadecimal prefix, sorted and without duplicates
+    pub fn find_hashes_with_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() < 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(Vec::new());
+        }
+        let mut hashes = BTreeSet::new();
+
+        let (subdir, filename_prefix) = prefix.split_at(2);
+        let loose_dir = self.objects_dir.join(subdir);
+        if loose_dir.is_dir() {
+            for entry in read_dir(loose_dir)? {
+                let filename = entry?.file_name().to_string_lossy().to_string();
+                if filename.len() == 38 && filename.starts_with(filename_prefix) {
+                    hashes.insert(format!("{}{}", subdir, filename));
+                }
+            }
+        }
+
+        self.load_pack_indexes()?;
+        for (_, index) in self.pack_indexes.borrow().iter().flatten() {
+            hashes.extend(index.find_hashes_with_prefix(&prefix)?);
+        }
+        Ok(hashes.into_iter().collect())
+    }
+
+    /// Shortest prefix of the hash, of at least min_len characters, that does
+    /// not match any other object
+    pub fn abbreviate(&self, hash: &str, min_len: usize) -> Result<String> {
+        for len in min_len.max(4)..hash.len() {
+            if self.find_hashes_with_prefix(&hash[..len])?.len() <= 1 {
+                return Ok(hash[..len].to_string());
+            }
+        }
+        Ok(hash.to_string())
+    }
+
+    /// Reads the type and the content (header not included) of an object, either
+    /// from the loose objects or from the packs.
+    /// The content is not parsed
+    pub fn read_raw_object(&self, hash: &str) -> Result<(String, Vec<u8>)> {
+        let location = self.loose_object_path(hash)?;
+
+        if !location.exists() {
+            return self
+                .read_packed_object(hash)?
+                .ok_or_else(|| Error::ObjectNotFound(hash.to_string()));
+        }
+
+        let file = File::open(location)?;
+        let decoder = flate2::read::ZlibDecoder::new(file);
+        let mut reader = BufReader::new(decoder);
+
+        let mut header_bytes = Vec::new();
+        reader.read_until(0, &mut header_bytes)?;
+        header_bytes.pop();
+        let header = String::from_utf8_lossy(&header_bytes).to_string();
+
+        let (type_obj, size_str) = header
+            .split_once(' ')
+            .ok_or_else(|| Error::InvalidGitObject)?;
+        let size = size_str
+            .parse::<usize>()
+            .map_err(|_| Error::InvalidGitObject)?;
+
+        let mut content_bytes = Vec::new();
+        reader.read_to_end(&mut content_bytes)?;
+        if content_bytes.len() != size {
+            return Err(Error::WrongObjectSize {
+                expected: size,
+                got: content_bytes.len(),
+            });
+        }
+        Ok((type_obj.to_string(), content_bytes))
+    }
+
+    /// Reads and parses an object.
+    /// Trees are not loaded recursively: their children only carry their hash
+    /// and can be loaded on demand with this same function
+    pub fn read_object(&self, hash: &str) -> Result<GitObject> {
+        let (content_type, content_bytes) = self.read_raw_object(hash)?;
+        GitObject::from_content_bytes(&content_type, content_bytes)
+    }
+
+    /// Follows annotated tags, and commits to their tree, until an object of the
+    /// given type (commit or tree) is found. Returns its hash
+    pub fn peel(&self, hash: &str, target_type: &str) -> Result<String> {
+        let mut hash = hash.to_string();
+        loop {
+            let git_object = self.read_object(&hash)?;
+            if git_object.content_type() == target_type {
+                return Ok(hash);
+            }
+            hash = match git_object.content {
+                GitObjectContent::Tag { content } => content.object_sha,
+                GitObjectContent::Commit { content } if target_type == "tree" => content.tree_sha,
+                _ => {
+                    return Err(Error::CannotPeel {
+                        hash,
+                        target_type: target_type.to_string(),
+                    })
+                }
+            };
+        }
+    }
+
+    /// Follows annotated tags until an object that is not a tag is found.
+    /// Returns its hash (the given hash if it is not a tag)
+    pub fn peel_tags(&self, hash: &str) -> Result<String> {
+        let mut hash = hash.to_string();
+        while let GitObjectContent::Tag { content } = self.read_object(&hash)?.content {
+            hash = content.object_sha;
+        }
+        Ok(hash)
+    }
+
+    /// Reads a tree and all its subtrees.
+    /// Returns the blobs (and submodules) of the tree by path, with / as separator
+    pub fn read_tree_recursively(&self, tree_hash: &str) -> Result<BTreeMap<String, TreeChild>> {
+        let mut tree_children = BTreeMap::new();
+        self.read_subtree(tree_hash, "", &mut tree_children)?;
+        Ok(tree_children)
+    }
+
+    fn read_subtree(
+        &self,
+        tree_hash: &str,
+        prefix: &str,
+        tree_children: &mut BTreeMap<String, TreeChild>,
+    ) -> Result<()> {
+        let GitObjectContent::Tree { content } = self.read_object(tree_hash)?.content else {
+            return Err(Error::NotATreeGitObject);
+        };
+        for tree_child in content {
+            let path = format!("{}{}", prefix, tree_child.name);
+            // such a name would not be a single component of the path
+            if tree_child.name.is_empty() || tree_child.name.contains(['/', '\0']) {
+                return Err(Error::InvalidPath(path));
+            }
+            if tree_child.mode == 40000 {
+                self.read_subtree(&tree_child.hash, &format!("{}/", path), tree_children)?;
+            } else {
+                tree_children.insert(path, tree_child);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the object as a loose object.
+    /// Nothing is done if the object is already in the database
+    pub fn write_object(&self, git_object: &GitObject) -> Result<()> {
+        if self.contains(&git_object.hash)? {
+            return Ok(());
+        }
+        let location = self.loose_object_path(&git_object.hash)?;
+
+        // write can only occur if the bytes were loaded when we got the object.
+        let object_bytes = git_object
+            .object_bytes
+            .as_ref()
+            .ok_or_else(|| Error::ObjectBytesNotLoaded)?;
+
+        let parent = location.parent().ok_or_else(|| Error::InvalidGitObject)?;
+        create_dir_all(parent)?;
+        let output = File::create(location)?;
+
+        let mut encoder = flate2::write::ZlibEncoder::new(output, flate2::Compression::default());
+        encoder.write_all(object_bytes)?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
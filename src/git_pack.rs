@@ -1,11 +1,15 @@
 use std::{
-    collections::HashMap,
-    io::{BufRead, BufReader, Read},
-    path::Path,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::create_dir_all,
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write},
 };
 
 use crate::{
-    git_object::{GitObject, GitObjectContent},
+    git_object::GitObject,
+    git_object_database::GitObjectDatabase,
+    git_pack_index::{GitPackIndex, GitPackIndexFile},
+    git_refs::PackedRef,
+    git_repository::Repository,
     Error, Result,
 };
 
@@ -36,20 +40,93 @@ pub struct UploadPackDiscovery {
     pub refs: Vec<(String, String)>,
 }
 impl UploadPackDiscovery {
-    /// Writes the commit HEAD points to to the HEAD file and all the references in the
-    pub fn write_head_and_refs<P: AsRef<Path> + ?Sized>(&self, repo_dir: &P) -> Result<()> {
-        let git_dir = repo_dir.as_ref().join(".git");
-        let head_file = git_dir.join("HEAD");
+    /// Writes the advertised refs as git clone does: branches become
+    /// remote-tracking branches of the remote (refs/remotes/<remote>/x) and tags
+    /// are kept, all in packed-refs. Tags are followed by the object they point
+    /// to (advertised as refs/tags/x^{}).
+    /// The branch the remote HEAD points to is created locally and checked out,
+    /// and refs/remotes/<remote>/HEAD points to its remote-tracking branch.
+    /// HEAD is detached if there is no such branch.
+    /// Returns the local branch, if any
+    pub fn write_head_and_refs(
+        &self,
+        repository: &Repository,
+        remote_name: &str,
+    ) -> Result<Option<String>> {
+        let refs = repository.refs();
+        let remote_ref_name = |name: &str| -> Option<String> {
+            if let Some(branch) = name.strip_prefix("refs/heads/") {
+                return Some(format!("refs/remotes/{}/{}", remote_name, branch));
+            }
+            name.starts_with("refs/tags/").then(|| name.to_string())
+        };
 
-        std::fs::write(head_file, self.head_hash.clone())?;
+        let mut packed_refs: Vec<PackedRef> = Vec::new();
         for (hash, name) in &self.refs {
-            // Note: name can contain slash and  starts with refs/
-            let filename = git_dir.join(name);
-            let parent = std::path::Path::new(&filename).parent().unwrap();
-            std::fs::create_dir_all(parent)?;
-            std::fs::write(filename, hash)?;
+            if let Some(tag_name) = name.strip_suffix("^{}") {
+                if let Some(packed_ref) = packed_refs.last_mut().filter(|r| r.name == tag_name) {
+                    packed_ref.peeled = Some(hash.clone());
+                }
+                continue;
+            }
+            let Some(name) = remote_ref_name(name) else {
+                continue;
+            };
+            packed_refs.push(PackedRef {
+                name,
+                hash: hash.clone(),
+                peeled: None,
+            });
+        }
+        refs.write_packed_refs(&packed_refs)?;
+
+        let Some(branch) = self.head_branch() else {
+            refs.update("HEAD", &self.head_hash, None)?;
+            return Ok(None);
+        };
+        refs.update(&branch, &self.head_hash, None)?;
+        refs.write_symbolic("HEAD", &branch)?;
+        if let Some(remote_head) = remote_ref_name(&branch) {
+            refs.write_symbolic(&format!("refs/remotes/{}/HEAD", remote_name), &remote_head)?;
+        }
+        Ok(Some(branch))
+    }
+
+    /// Branch the remote HEAD points to: given by the symref capability if the
+    /// server sends it, otherwise guessed from the branches pointing to the same
+    /// commit as HEAD (master and main first)
+    pub fn head_branch(&self) -> Option<String> {
+        let symref = self
+            .parameters
+            .iter()
+            .find_map(|parameter| parameter.strip_prefix("symref=HEAD:"));
+        if let Some(branch) = symref {
+            return Some(branch.to_string());
         }
-        Ok(())
+        let branches = self
+            .refs
+            .iter()
+            .filter(|(hash, name)| *hash == self.head_hash && name.starts_with("refs/heads/"))
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<_>>();
+        ["refs/heads/master", "refs/heads/main"]
+            .into_iter()
+            .find(|name| branches.contains(name))
+            .or(branches.first().copied())
+            .map(|name| name.to_string())
+    }
+
+    /// Checks if the server advertised the given capability (for ex ofs-delta)
+    pub fn supports(&self, capability: &str) -> bool {
+        self.parameters
+            .iter()
+            .any(|parameter| parameter == capability)
+    }
+
+    /// Creates a pkt-line: the content is prefixed by its size (including the
+    /// 4 bytes of the size itself), encoded on 4 bytes in hexadecimal
+    pub fn create_line(content: &str) -> String {
+        format!("{:04x}{}", content.len() + 4, content)
     }
 
     /// Size is encoded on 4 bytes, in hexadecimal
@@ -82,9 +159,18 @@ impl UploadPackDiscovery {
     /// Builds the UploadPackDiscovery from a given repository url.
     /// Assumes smart http protocol is used
     pub fn from_repository_url(repository_url: &str) -> Result<Self> {
+        Self::from_repository_url_and_service(repository_url, "git-upload-pack")
+    }
+
+    /// Same as from_repository_url for another service: git-receive-pack
+    /// advertises the refs the same way before a push. Its first ref is not
+    /// HEAD, and an empty repository advertises a `capabilities^{}` ref
+    pub fn from_repository_url_and_service(repository_url: &str, service: &str) -> Result<Self> {
         let client = reqwest::blocking::Client::new();
+        // the service paths are appended to the url
+        let repository_url = repository_url.trim_end_matches('/');
 
-        let url = format!("{}/info/refs?service=git-upload-pack", repository_url);
+        let url = format!("{}/info/refs?service={}", repository_url, service);
         let mut res = client.get(&url).send()?;
 
         // status code must be 200 or 304
@@ -93,7 +179,7 @@ impl UploadPackDiscovery {
             return Err(Error::InvalidDiscoveryUrl(url));
         }
 
-        // content type must be application/x-git-upload-pack-advertisement for smart http
+        // content type must be application/x-<service>-advertisement for smart http
         // we don't support dumb http protocol
         let content_type = res
             .headers()
@@ -102,9 +188,10 @@ impl UploadPackDiscovery {
             .to_str()
             .map_err(|_| Error::ContentTypeInvalid)?;
 
-        if content_type != "application/x-git-upload-pack-advertisement" {
+        let expected_content_type = format!("application/x-{}-advertisement", service);
+        if content_type != expected_content_type {
             return Err(Error::WrongContentType {
-                expected: "application/x-git-upload-pack-advertisement".to_string(),
+                expected: expected_content_type,
                 got: content_type.to_string(),
             });
         };
@@ -114,9 +201,10 @@ impl UploadPackDiscovery {
         let initial_size = Self::get_line_size(&mut res)?;
         let initial_content = Self::get_line_content(&mut res, initial_size)?;
 
-        if initial_content != "# service=git-upload-pack" {
+        let expected_service = format!("# service={}", service);
+        if initial_content != expected_service {
             return Err(Error::InvalidDiscoveryService {
-                expected: "# service=git-upload-pack".to_string(),
+                expected: expected_service,
                 got: initial_content,
             });
         }
@@ -137,14 +225,19 @@ impl UploadPackDiscovery {
             .ok_or_else(|| Error::InvalidSmartHttpRes)?;
 
         // sometimes we have HEAD before \0, sometimes we have refs/heads/main
-
-        let head_hash = head[..40].to_owned();
+        let (head_hash, first_name) = head
+            .split_once(' ')
+            .ok_or_else(|| Error::InvalidSmartHttpRes)?;
+        let head_hash = head_hash.to_owned();
 
         // Not sure what to do with the parameters
         let parameters = tail.split(' ').map(|v| v.to_string()).collect::<Vec<_>>();
 
         // other rows only contain hash and ref
         let mut refs = Vec::new();
+        if first_name != "HEAD" && first_name != "capabilities^{}" {
+            refs.push((head_hash.clone(), first_name.to_string()));
+        }
         loop {
             // 003fd049f6c27a2244e12041955e262a404c7faba355 refs/heads/master\n
             // 03c2cb58b79488a98d2721cea644875a8dd0026b115 refs/tags/v1.0\n
@@ -169,7 +262,7 @@ impl UploadPackDiscovery {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Object type is encoded on 3 bits after the Most Significant Bit
 pub enum GitPackObject {
     ///  OBJ_COMMIT (1)
@@ -180,6 +273,13 @@ pub enum GitPackObject {
     Blob { content_bytes: Vec<u8> },
     /// OBJ_TAG (4)
     Tag { content_bytes: Vec<u8> },
+    /// OBJ_OFS_DELTA (6)
+    OfsDelta {
+        /// offset of the base object in the pack, computed from the negative
+        /// offset stored after the size
+        base_object_offset: usize,
+        content_bytes: Vec<u8>,
+    },
     /// OBJ_REF_DELTA (7)
     RefDelta {
         base_object_hash: String,
@@ -187,23 +287,203 @@ pub enum GitPackObject {
         // reconstructed_object_size: usize,
         content_bytes: Vec<u8>,
     },
-    // OBJ_OFS_DELTA (6) - Not supported
+}
+
+/// Objects of a pack, with their offset from the start of the pack
+type PackObjects = Vec<(usize, GitPackObject)>;
+
+/// Objects smaller than this are not worth a delta
+const MIN_DELTA_SIZE: usize = 50;
+/// Size of the blocks of a delta base that are searched in the target
+const DELTA_BLOCK_SIZE: usize = 16;
+/// Positions kept for a block content found many times in a delta base
+const DELTA_INDEX_LIMIT: usize = 64;
+/// Maximum size of a copy instruction of a delta
+const MAX_COPY_SIZE: usize = 0x10000;
+/// Maximum size of an insert instruction of a delta
+const MAX_INSERT_SIZE: usize = 0x7f;
+/// Bytes reserved for the result of a delta before applying it, whatever size
+/// its header announces
+const MAX_DELTA_PREALLOCATION: usize = 1 << 20;
+
+/// Positions of the blocks of a delta base, by content
+type DeltaIndex = HashMap<[u8; DELTA_BLOCK_SIZE], Vec<usize>>;
+
+/// How GitPack::from_git_objects writes a pack
+#[derive(Debug, Clone)]
+pub struct PackWriteOptions {
+    /// number of objects preceding an object in the sorted list that are tried
+    /// as its delta base. 0 disables deltas
+    pub window: usize,
+    /// maximum length of the delta chains: number of deltas to apply to
+    /// rebuild an object
+    pub depth: usize,
+    /// zlib compression level, from 0 (none) to 9 (smallest)
+    pub compression: u32,
+    /// deltas against objects of the pack give the offset of their base
+    /// (OBJ_OFS_DELTA) instead of its hash (OBJ_REF_DELTA)
+    pub ofs_delta: bool,
+}
+
+impl Default for PackWriteOptions {
+    fn default() -> Self {
+        Self {
+            window: 10,
+            depth: 50,
+            compression: 6,
+            ofs_delta: false,
+        }
+    }
+}
+
+/// Object of a pack being written
+struct PackWriterEntry {
+    object_type: usize,
+    hash: String,
+    content: Vec<u8>,
+    /// sortable number made of the last characters of the path: files
+    /// with the same name or extension are next to each other
+    name_hash: u32,
+    /// base of a thin pack: known to the reader of the pack, not written
+    is_thin_base: bool,
+    /// index of the delta base, and the delta
+    delta: Option<(usize, Vec<u8>)>,
+    /// number of deltas to apply to rebuild the object
+    depth: usize,
+}
+
+impl PackWriterEntry {
+    fn new(git_object: &GitObject, path: &str, is_thin_base: bool) -> Result<Self> {
+        let name_hash = path
+            .bytes()
+            .filter(|c| !c.is_ascii_whitespace())
+            .fold(0u32, |hash, c| (hash >> 2).wrapping_add((c as u32) << 24));
+        Ok(Self {
+            object_type: GitPack::object_type_number(&git_object.content_type())?,
+            hash: git_object.hash.clone(),
+            content: git_object.content_bytes()?.to_vec(),
+            name_hash,
+            is_thin_base,
+            delta: None,
+            depth: 0,
+        })
+    }
+}
+
+/// Writes the entries of a pack, each delta base before its deltas
+struct PackWriter<'a> {
+    entries: &'a [PackWriterEntry],
+    options: &'a PackWriteOptions,
+    /// offset of the entries already written
+    offsets: Vec<Option<usize>>,
+    pack_bytes: Vec<u8>,
+    pack_objects: PackObjects,
+}
+
+impl PackWriter<'_> {
+    /// Writes the entry, after its delta base if it is part of the pack.
+    /// Returns its offset
+    fn write_entry(&mut self, i: usize) -> Result<usize> {
+        if let Some(offset) = self.offsets[i] {
+            return Ok(offset);
+        }
+        let entry = &self.entries[i];
+        let base_offset = match &entry.delta {
+            Some((base, _)) if !self.entries[*base].is_thin_base => Some(self.write_entry(*base)?),
+            _ => None,
+        };
+
+        let offset = self.pack_bytes.len();
+        let (pack_object, data) = match &entry.delta {
+            None => {
+                self.pack_bytes.extend(GitPack::encode_object_type_and_size(
+                    entry.object_type,
+                    entry.content.len(),
+                ));
+                let content_bytes = entry.content.clone();
+                let pack_object = match entry.object_type {
+                    1 => GitPackObject::Commit { content_bytes },
+                    2 => GitPackObject::Tree { content_bytes },
+                    3 => GitPackObject::Blob { content_bytes },
+                    4 => GitPackObject::Tag { content_bytes },
+                    _ => Err(Error::Unreachable)?,
+                };
+                (pack_object, &entry.content)
+            }
+            Some((base, delta)) => {
+                let content_bytes = delta.clone();
+                match base_offset {
+                    Some(base_object_offset) if self.options.ofs_delta => {
+                        self.pack_bytes
+                            .extend(GitPack::encode_object_type_and_size(6, delta.len()));
+                        self.pack_bytes
+                            .extend(GitPack::encode_negative_offset(offset - base_object_offset));
+                        let pack_object = GitPackObject::OfsDelta {
+                            base_object_offset,
+                            content_bytes,
+                        };
+                        (pack_object, delta)
+                    }
+                    _ => {
+                        let base_object_hash = self.entries[*base].hash.clone();
+                        self.pack_bytes
+                            .extend(GitPack::encode_object_type_and_size(7, delta.len()));
+                        self.pack_bytes.extend(
+                            hex::decode(&base_object_hash).map_err(|_| Error::InvalidPackFile)?,
+                        );
+                        let pack_object = GitPackObject::RefDelta {
+                            base_object_hash,
+                            content_bytes,
+                        };
+                        (pack_object, delta)
+                    }
+                }
+            }
+        };
+        self.pack_bytes
+            .extend(GitPack::compress(data, self.options.compression)?);
+        self.offsets[i] = Some(offset);
+        self.pack_objects.push((offset, pack_object));
+        Ok(offset)
+    }
 }
 
 #[derive(Debug)]
 pub struct GitPack {
-    pack_objects: Vec<GitPackObject>,
+    /// raw content of the pack, including the trailing checksum.
+    /// Kept to store the pack as is in .git/objects/pack
+    pack_bytes: Vec<u8>,
+    /// each object is stored with its offset from the start of the pack,
+    /// which is needed to resolve OBJ_OFS_DELTA
+    pack_objects: PackObjects,
 }
 impl GitPack {
     /// creates the most minimal pack content to send
     /// 0032 = size
-    /// want {head_hash} \n = sequence to send
+    /// want {hash} {capabilities}\n = sequence to send, once per wanted object
     /// 0000 = separator
     /// 0009 = size
     /// done\n = instruction end
+    /// Capabilities are only sent on the first want line.
     /// used in git clone
-    pub fn create_minimal_pack_content_from_head_hash(head_hash: &str) -> String {
-        format!("0032want {}\n00000009done\n", head_hash).to_string()
+    pub fn create_minimal_pack_content(wants: &[&str], capabilities: &[&str]) -> String {
+        let mut content = String::new();
+        for (i, hash) in wants.iter().enumerate() {
+            let mut want = format!("want {}", hash);
+            if i == 0 {
+                for capability in capabilities {
+                    want.push(' ');
+                    want.push_str(capability);
+                }
+            }
+            want.push('\n');
+            content.push_str(&UploadPackDiscovery::create_line(&want));
+        }
+        format!(
+            "{}0000{}",
+            content,
+            UploadPackDiscovery::create_line("done\n")
+        )
     }
 
     /// For most of the objects, in the first byte, we look at the MSB (leftmost bit).
@@ -235,6 +515,9 @@ impl GitPack {
             reader.read_exact(&mut buf)?;
             cur_byte = buf[0] as usize;
             // the reason we need to cast as usize is to avoid overflow
+            if shift >= usize::BITS {
+                return Err(Error::InvalidPackFile);
+            }
             let additional_size = (cur_byte & 0b01111111) << shift;
             shift += 7;
             cur_size += additional_size;
@@ -242,6 +525,22 @@ impl GitPack {
         Ok((object_type, cur_size))
     }
 
+    /// Encodes the type and the size of an object the way
+    /// get_next_object_type_and_size reads them
+    pub fn encode_object_type_and_size(object_type: usize, size: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut cur_byte = ((object_type & 0b0111) << 4 | (size & 0b1111)) as u8;
+        let mut size = size >> 4;
+        // the MSB tells that the next byte holds 7 more bits of the size
+        while size > 0 {
+            bytes.push(cur_byte | 0b10000000);
+            cur_byte = (size & 0b01111111) as u8;
+            size >>= 7;
+        }
+        bytes.push(cur_byte);
+        bytes
+    }
+
     /// same as get_next_object_type_and_size but adapted for cases where the
     /// type is not encoded in the first byte
     /// After OBS_REF_DELTA (7), we have the base object (20 bits)
@@ -256,6 +555,9 @@ impl GitPack {
         loop {
             reader.read_exact(&mut buf)?;
             let cur_byte = buf[0] as usize;
+            if shift >= usize::BITS {
+                return Err(Error::InvalidPackFile);
+            }
             let additional_size = (cur_byte & 0b01111111) << shift;
             shift += 7;
             cur_size += additional_size;
@@ -266,51 +568,167 @@ impl GitPack {
         Ok(cur_size)
     }
 
+    /// After OBJ_OFS_DELTA (6), we have the negative offset of the base object
+    /// relative to the start of the delta object.
+    /// It is encoded differently from the sizes: bytes are big endian and
+    /// each continuation adds 1 before shifting, so that there is only one
+    /// way to encode a given offset.
+    /// For ex 1000_0001 0000_0000 => ((1 + 1) << 7) + 0 = 256
+    pub fn get_next_negative_offset<R: Read>(reader: &mut R) -> Result<usize> {
+        let mut buf = [0];
+        reader.read_exact(&mut buf)?;
+        let mut cur_byte = buf[0] as usize;
+        let mut offset = cur_byte & 0b01111111;
+
+        while cur_byte >= 128 {
+            reader.read_exact(&mut buf)?;
+            cur_byte = buf[0] as usize;
+            if offset > usize::MAX >> 8 {
+                return Err(Error::InvalidPackFile);
+            }
+            offset = ((offset + 1) << 7) + (cur_byte & 0b01111111);
+        }
+        Ok(offset)
+    }
+
+    /// Encodes an offset the way get_next_negative_offset reads it
+    pub fn encode_negative_offset(offset: usize) -> Vec<u8> {
+        let mut offset = offset;
+        let mut bytes = vec![(offset & 0b01111111) as u8];
+        while offset >= 128 {
+            offset = (offset >> 7) - 1;
+            bytes.push(0b10000000 | (offset & 0b01111111) as u8);
+        }
+        bytes.reverse();
+        bytes
+    }
+
+    /// Reads zlib compressed data until the end of the zlib stream.
+    /// The reader is moved into the decoder and given back afterwards, positioned
+    /// right after the compressed data
+    fn read_zlib_data<R: BufRead>(reader: R) -> Result<(Vec<u8>, R)> {
+        let mut buf = Vec::new();
+        let mut z = flate2::bufread::ZlibDecoder::new(reader);
+        // zlib will actually stop on EOF
+        z.read_to_end(&mut buf)?;
+        // important to release the inner reader because it is moved in the
+        // zlib decoder.
+        Ok((buf, z.into_inner()))
+    }
+
     pub fn from_repository_url_and_pack_content(
         repository_url: &str,
         pack_content: &str,
     ) -> Result<Self> {
+        let mut res = Self::send_upload_pack_request(repository_url, pack_content)?;
+
+        // res starts with 0008NAK\n
+        let mut buf = vec![0; 8];
+        res.read_exact(&mut buf)?;
+        let val = String::from_utf8(buf).map_err(|_| Error::InvalidSmartHttpRes)?;
+        if val != "0008NAK\n" {
+            return Err(Error::InvalidSmartHttpRes);
+        }
+
+        Self::from_reader(&mut res)
+    }
+
+    /// Posts the pkt-lines of a request (wants, haves, done) to
+    /// $GIT_URL/git-upload-pack. The response starts with the acknowledgments
+    /// of the server, followed by the packfile once the request ends with done
+    pub fn send_upload_pack_request(
+        repository_url: &str,
+        request: &str,
+    ) -> Result<reqwest::blocking::Response> {
         let url = format!("{}/git-upload-pack", repository_url);
         let client = reqwest::blocking::Client::new();
-        let mut res = client
+        let res = client
             .post(url)
             .header("Content-Type", "application/x-git-upload-pack-request")
-            .body(pack_content.to_string())
+            .body(request.to_string())
             .send()?;
+        Ok(res)
+    }
 
-        // res starts with 0008NAK\nPACK
-        let mut buf = vec![0; 12];
-        res.read_exact(&mut buf)?;
-        let val = String::from_utf8(buf).map_err(|_| Error::InvalidSmartHttpRes)?;
-        if val != "0008NAK\nPACK" {
-            return Err(Error::InvalidSmartHttpRes);
+    /// Posts the ref update commands of a push, followed by the pack of the
+    /// objects the remote lacks, to $GIT_URL/git-receive-pack. With
+    /// report-status, the response tells if the pack was unpacked and if each
+    /// ref was updated
+    pub fn send_receive_pack_request(
+        repository_url: &str,
+        request: Vec<u8>,
+    ) -> Result<reqwest::blocking::Response> {
+        let url = format!("{}/git-receive-pack", repository_url);
+        let client = reqwest::blocking::Client::new();
+        let res = client
+            .post(url)
+            .header("Content-Type", "application/x-git-receive-pack-request")
+            .body(request)
+            .send()?;
+        Ok(res)
+    }
+
+    /// Reads a packfile until the end of the reader.
+    /// We keep it in memory as we need to know the offset of each object to
+    /// resolve OBJ_OFS_DELTA
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut pack_bytes = Vec::new();
+        reader.read_to_end(&mut pack_bytes)?;
+
+        Self::from_pack_bytes(pack_bytes)
+    }
+
+    /// Parses the content of a packfile, see
+    /// https://github.com/git/git/blob/795ea8776befc95ea2becd8020c7a284677b4161/Documentation/gitformat-pack.txt
+    pub fn from_pack_bytes(pack_bytes: Vec<u8>) -> Result<Self> {
+        // the last 20 bytes are the sha1 of everything before them.
+        // We check it first so that a corrupted pack is never unpacked
+        if pack_bytes.len() < 20 {
+            return Err(Error::InvalidPackFile);
+        }
+        let (pack_content, checksum) = pack_bytes.split_at(pack_bytes.len() - 20);
+        let expected_checksum = hex::encode(checksum);
+        let computed_checksum = GitObject::get_hash_from_bytes(pack_content);
+        if expected_checksum != computed_checksum {
+            return Err(Error::PackChecksumMismatch {
+                expected: expected_checksum,
+                got: computed_checksum,
+            });
         }
 
-        // then 4 bytes containing the version number
-        // for ex [0,0,0,2]
+        let mut reader = Cursor::new(pack_content);
+
+        // packfile starts with PACK
         let mut buf = vec![0; 4];
-        res.read_exact(&mut buf)?;
+        reader.read_exact(&mut buf)?;
+        if buf != b"PACK" {
+            return Err(Error::InvalidPackFile);
+        }
 
-        // then 4 bytes containing the number of objects in the pack
+        // then 4 bytes containing the version number
+        // for ex [0,0,0,2]. Only versions 2 and 3 exist
         let mut buf = [0u8; 4];
-        res.read_exact(&mut buf)?;
+        reader.read_exact(&mut buf)?;
+        let version = u32::from_be_bytes(buf);
+        if version != 2 && version != 3 {
+            return Err(Error::UnsupportedPackVersion(version));
+        }
+
+        // then 4 bytes containing the number of objects in the pack
+        reader.read_exact(&mut buf)?;
         // let nb_objects = buf[3] + buf[2] * 2 + buf[1] * 4 + buf[0] * 8;
         let nb_objects = u32::from_be_bytes(buf);
 
-        // create a bufreader for more control on the decoding
-        let mut reader = BufReader::new(res);
-
         // then for the packfile itself, we iterate through all the objects
         let mut pack_objects = Vec::new();
         for _ in 0..nb_objects {
+            let object_offset = reader.position() as usize;
             let (object_type, cur_size) = Self::get_next_object_type_and_size(&mut reader)?;
 
-            match object_type {
+            let git_pack_object = match object_type {
                 1..=4 => {
-                    let mut buf = Vec::new();
-                    let mut z = flate2::bufread::ZlibDecoder::new(reader);
-                    // zlib will actually stop on EOF
-                    z.read_to_end(&mut buf)?;
+                    let (buf, inner) = Self::read_zlib_data(reader)?;
+                    reader = inner;
 
                     if buf.len() != cur_size {
                         // check that the uncompressed length corresponds to what was
@@ -321,61 +739,468 @@ impl GitPack {
                         });
                     }
 
-                    let git_pack_object = match object_type {
+                    match object_type {
                         1 => GitPackObject::Commit { content_bytes: buf },
                         2 => GitPackObject::Tree { content_bytes: buf },
                         3 => GitPackObject::Blob { content_bytes: buf },
                         4 => GitPackObject::Tag { content_bytes: buf },
                         _ => Err(Error::Unreachable)?,
-                    };
-                    pack_objects.push(git_pack_object);
+                    }
+                }
+                6 => {
+                    // after the size, we get the offset of the base object,
+                    // relative to the current object
+                    let negative_offset = Self::get_next_negative_offset(&mut reader)?;
+                    // the base is always before the delta
+                    let base_object_offset = object_offset
+                        .checked_sub(negative_offset)
+                        .filter(|_| negative_offset > 0)
+                        .ok_or_else(|| Error::InvalidPackFile)?;
 
-                    // important to release the inner reader because it is moved in the
-                    // zlib decoder.
-                    reader = z.into_inner();
+                    // then the diff as zlib compressed data
+                    let (buf, inner) = Self::read_zlib_data(reader)?;
+                    reader = inner;
+
+                    GitPackObject::OfsDelta {
+                        base_object_offset,
+                        content_bytes: buf,
+                    }
                 }
-                6 => todo!(),
                 7 => {
                     // after the size, we get the base object name
                     let mut base_object = vec![0; 20];
                     reader.read_exact(&mut base_object)?;
 
                     // then the diff as zlib compressed data
-                    let mut buf = Vec::new();
-                    let mut z = flate2::bufread::ZlibDecoder::new(reader);
+                    let (buf, inner) = Self::read_zlib_data(reader)?;
+                    reader = inner;
 
-                    let _ = z.read_to_end(&mut buf)?;
-                    let git_pack_object = GitPackObject::RefDelta {
+                    GitPackObject::RefDelta {
                         base_object_hash: hex::encode(base_object),
                         content_bytes: buf,
-                    };
-
-                    pack_objects.push(git_pack_object);
-                    reader = z.into_inner();
+                    }
                 }
                 x => Err(Error::InvalidPackObjectType(x))?,
+            };
+            pack_objects.push((object_offset, git_pack_object));
+        }
+        // all the objects announced in the header were read, so we must be
+        // right before the checksum
+        let nb_remaining_bytes = pack_content.len() - reader.position() as usize;
+        if nb_remaining_bytes != 0 {
+            Err(Error::IncorrectPackObjectCount {
+                expected: nb_objects,
+                remaining_bytes: nb_remaining_bytes,
+            })?
+        }
+
+        Ok(GitPack {
+            pack_bytes,
+            pack_objects,
+        })
+    }
+
+    /// Creates a pack of the objects, given with the path they were found at
+    /// (empty if unknown), as git pack-objects does:
+    /// - the objects are sorted by type, then by name (objects of the same name
+    ///   are likely versions of the same file), then biggest first
+    /// - each object is compared with the objects before it in a sliding
+    ///   window, and stored as a delta against the one giving the smallest
+    ///   delta, unless the chain of deltas to rebuild it would get too deep
+    /// - the objects are written in the given order, each delta base before
+    ///   its deltas
+    ///
+    /// The thin bases are objects the reader of the pack already has. They are
+    /// not written, but deltas can be made against them (as OBJ_REF_DELTA):
+    /// the pack is thin
+    pub fn from_git_objects(
+        git_objects: &[(GitObject, String)],
+        thin_bases: &[(GitObject, String)],
+        options: &PackWriteOptions,
+    ) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut seen = HashSet::new();
+        for (git_object, path) in git_objects {
+            if seen.insert(git_object.hash.as_str()) {
+                entries.push(PackWriterEntry::new(git_object, path, false)?);
             }
         }
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        if buf.len() != 20 {
-            // TODO:Add checksum validation
-            // I am not sure how the checksum is calculated for packfiles
-            Err(Error::InvalidPackFile)?
+        let nb_objects = entries.len();
+        for (git_object, path) in thin_bases {
+            if seen.insert(git_object.hash.as_str()) {
+                entries.push(PackWriterEntry::new(git_object, path, true)?);
+            }
+        }
+        if options.window > 0 && options.depth > 0 {
+            Self::find_deltas(&mut entries, options);
+        }
+
+        let mut writer = PackWriter {
+            entries: &entries,
+            options,
+            offsets: vec![None; entries.len()],
+            pack_bytes: b"PACK".to_vec(),
+            pack_objects: Vec::new(),
+        };
+        writer.pack_bytes.extend(2u32.to_be_bytes());
+        writer.pack_bytes.extend((nb_objects as u32).to_be_bytes());
+        for i in 0..nb_objects {
+            writer.write_entry(i)?;
         }
 
-        Ok(GitPack { pack_objects })
+        let mut pack_bytes = writer.pack_bytes;
+        let pack_objects = writer.pack_objects;
+        let checksum = hex::decode(GitObject::get_hash_from_bytes(&pack_bytes))
+            .map_err(|_| Error::Unreachable)?;
+        pack_bytes.extend(checksum);
+        Ok(GitPack {
+            pack_bytes,
+            pack_objects,
+        })
+    }
+
+    /// Chooses the delta base of each object. The objects are sorted so that
+    /// similar objects are next to each other, the biggest first: deltas then
+    /// mostly remove data, which takes less room than adding it
+    fn find_deltas(entries: &mut [PackWriterEntry], options: &PackWriteOptions) {
+        let mut order = (0..entries.len())
+            .filter(|&i| entries[i].content.len() >= MIN_DELTA_SIZE)
+            .collect::<Vec<_>>();
+        order.sort_by(|&a, &b| {
+            let (a_entry, b_entry) = (&entries[a], &entries[b]);
+            b_entry
+                .object_type
+                .cmp(&a_entry.object_type)
+                .then(b_entry.name_hash.cmp(&a_entry.name_hash))
+                .then(b_entry.is_thin_base.cmp(&a_entry.is_thin_base))
+                .then(b_entry.content.len().cmp(&a_entry.content.len()))
+                .then(a.cmp(&b))
+        });
+
+        // the last objects, with the index of their blocks
+        let mut window: VecDeque<(usize, DeltaIndex)> = VecDeque::new();
+        for target in order {
+            if !entries[target].is_thin_base {
+                // the most recent objects of the window are tried first
+                let mut best: Option<(usize, Vec<u8>)> = None;
+                for (base, index) in window.iter().rev() {
+                    let best_delta = best
+                        .as_ref()
+                        .map(|(base, delta)| (delta.as_slice(), entries[*base].depth + 1));
+                    let delta = Self::try_delta(
+                        &entries[target],
+                        best_delta,
+                        &entries[*base],
+                        index,
+                        options.depth,
+                    );
+                    if let Some(delta) = delta {
+                        best = Some((*base, delta));
+                    }
+                }
+                if let Some((base, delta)) = best {
+                    entries[target].depth = entries[base].depth + 1;
+                    entries[target].delta = Some((base, delta));
+                }
+            }
+            let index = Self::create_delta_index(&entries[target].content);
+            window.push_back((target, index));
+            if window.len() > options.window {
+                window.pop_front();
+            }
+        }
+    }
+
+    /// Delta rebuilding the target from the base, if it is worth it: smaller
+    /// than the best delta found so far (of the given depth), or than half of
+    /// the target. The allowed size shrinks as the base gets deeper, so that
+    /// long chains are only made for big gains
+    fn try_delta(
+        target: &PackWriterEntry,
+        best_delta: Option<(&[u8], usize)>,
+        base: &PackWriterEntry,
+        base_index: &DeltaIndex,
+        max_depth: usize,
+    ) -> Option<Vec<u8>> {
+        if base.object_type != target.object_type || base.depth >= max_depth {
+            return None;
+        }
+        let target_size = target.content.len();
+        let (max_size, ref_depth) = match best_delta {
+            None => ((target_size / 2).saturating_sub(20), 1),
+            Some((delta, depth)) => (delta.len(), depth),
+        };
+        let max_size = max_size * (max_depth - base.depth) / (max_depth - ref_depth + 1);
+        let base_size = base.content.len();
+        if max_size == 0
+            || target_size.saturating_sub(base_size) >= max_size
+            || target_size < base_size / 32
+        {
+            return None;
+        }
+        let delta = Self::create_delta(base_index, &base.content, &target.content, max_size)?;
+        match best_delta {
+            // same size: only a shallower delta is better
+            Some((best, depth)) if delta.len() == best.len() && base.depth + 1 >= depth => None,
+            _ => Some(delta),
+        }
+    }
+
+    /// Positions of the blocks of a delta base, by content
+    fn create_delta_index(base: &[u8]) -> DeltaIndex {
+        let mut index = DeltaIndex::new();
+        for (i, block) in base.chunks_exact(DELTA_BLOCK_SIZE).enumerate() {
+            let mut key = [0; DELTA_BLOCK_SIZE];
+            key.copy_from_slice(block);
+            let positions = index.entry(key).or_default();
+            if positions.len() < DELTA_INDEX_LIMIT {
+                positions.push(i * DELTA_BLOCK_SIZE);
+            }
+        }
+        index
     }
 
-    /// Writes the pack to .git directory.
-    /// Note that contrary to what is done in git, all objects are unpacked so there
-    /// is no pack folder in the objects folder
-    pub fn into_git_objects(self) -> Result<Vec<GitObject>> {
+    /// Creates the delta instructions rebuilding the target from the base, the
+    /// reverse of apply_delta: the blocks of the target found in the base are
+    /// copied from it, the rest is inserted as is. Returns None if the delta
+    /// would be bigger than max_size
+    fn create_delta(
+        base_index: &DeltaIndex,
+        base: &[u8],
+        target: &[u8],
+        max_size: usize,
+    ) -> Option<Vec<u8>> {
+        let mut delta = Self::encode_size_without_type(base.len());
+        delta.extend(Self::encode_size_without_type(target.len()));
+
+        // bytes from insert_start to pos are not found in the base yet
+        let mut insert_start = 0;
+        let mut pos = 0;
+        while pos + DELTA_BLOCK_SIZE <= target.len() {
+            let found = base_index
+                .get(&target[pos..pos + DELTA_BLOCK_SIZE])
+                .and_then(|positions| {
+                    positions
+                        .iter()
+                        .map(|&base_pos| {
+                            let len = base[base_pos..]
+                                .iter()
+                                .zip(&target[pos..])
+                                .take_while(|(a, b)| a == b)
+                                .count();
+                            (base_pos, len)
+                        })
+                        .reduce(|best, other| if other.1 > best.1 { other } else { best })
+                });
+            match found {
+                Some((mut base_pos, mut len)) => {
+                    // the match may start before the block
+                    let mut start = pos;
+                    while start > insert_start
+                        && base_pos > 0
+                        && base[base_pos - 1] == target[start - 1]
+                    {
+                        start -= 1;
+                        base_pos -= 1;
+                        len += 1;
+                    }
+                    Self::push_insert_instructions(&mut delta, &target[insert_start..start]);
+                    Self::push_copy_instructions(&mut delta, base_pos, len);
+                    pos = start + len;
+                    insert_start = pos;
+                }
+                None => pos += 1,
+            }
+            if delta.len() + (pos - insert_start) > max_size {
+                return None;
+            }
+        }
+        Self::push_insert_instructions(&mut delta, &target[insert_start..]);
+        (delta.len() <= max_size).then_some(delta)
+    }
+
+    /// Insert instructions: the size (at most 127) followed by the bytes
+    fn push_insert_instructions(delta: &mut Vec<u8>, data: &[u8]) {
+        for chunk in data.chunks(MAX_INSERT_SIZE) {
+            delta.push(chunk.len() as u8);
+            delta.extend(chunk);
+        }
+    }
+
+    /// Copy instructions: the MSB is set, then each of the 4 next bits tells
+    /// if a byte of the offset follows, and each of the 3 next bits if a byte
+    /// of the size follows. Zero bytes are omitted. A copy is at most 0x10000
+    /// bytes long, encoded as 0
+    fn push_copy_instructions(delta: &mut Vec<u8>, offset: usize, size: usize) {
+        let (mut offset, mut size) = (offset, size);
+        while size > 0 {
+            let copy_size = size.min(MAX_COPY_SIZE);
+            let mut instruction = vec![0b10000000];
+            for i in 0..4 {
+                let byte = (offset >> (8 * i)) as u8;
+                if byte != 0 {
+                    instruction[0] |= 1 << i;
+                    instruction.push(byte);
+                }
+            }
+            let encoded_size = copy_size % MAX_COPY_SIZE;
+            for i in 0..3 {
+                let byte = (encoded_size >> (8 * i)) as u8;
+                if byte != 0 {
+                    instruction[0] |= 1 << (i + 4);
+                    instruction.push(byte);
+                }
+            }
+            delta.extend(instruction);
+            offset += copy_size;
+            size -= copy_size;
+        }
+    }
+
+    /// Encodes a size the way get_next_size_without_type reads it
+    pub fn encode_size_without_type(size: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut size = size;
+        while size >= 128 {
+            bytes.push(0b10000000 | (size & 0b01111111) as u8);
+            size >>= 7;
+        }
+        bytes.push(size as u8);
+        bytes
+    }
+
+    /// Compresses the data with zlib at the given level (0 to 9)
+    fn compress(data: &[u8], level: u32) -> Result<Vec<u8>> {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Raw content of the pack, as sent over the network or stored in
+    /// .git/objects/pack
+    pub fn pack_bytes(&self) -> &[u8] {
+        &self.pack_bytes
+    }
+
+    /// Reconstructs the content of an object from the content of its base object
+    /// and the delta instructions.
+    /// The delta starts with the size of the base object and the size of the
+    /// reconstructed object, followed by copy and insert instructions
+    pub fn apply_delta(base_content: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+        let mut reader = BufReader::new(delta);
+        let expected_base_object_size = Self::get_next_size_without_type(&mut reader)?;
+        let reconstructed_object_size = Self::get_next_size_without_type(&mut reader)?;
+
+        if base_content.len() != expected_base_object_size {
+            Err(Error::WrongObjectSize {
+                expected: base_content.len(),
+                got: expected_base_object_size,
+            })?;
+        }
+
+        // the size comes from the pack: it is not trusted for the allocation
+        let mut reconstructed_content =
+            Vec::with_capacity(reconstructed_object_size.min(MAX_DELTA_PREALLOCATION));
+        while reconstructed_content.len() < reconstructed_object_size {
+            let mut buf = [0];
+            reader.read_exact(&mut buf)?;
+            let first_byte = buf[0];
+
+            let instruction = first_byte >> 7;
+
+            if instruction == 0 {
+                // insert instruction: the next `size` bytes are added as is
+                let size = first_byte as usize;
+                // The size must be non-zero
+                // see https://github.com/git/git/blob/795ea8776befc95ea2becd8020c7a284677b4161/Documentation/gitformat-pack.txt
+                if size == 0 {
+                    Err(Error::InvalidPackFile)?;
+                }
+
+                let mut data = vec![0; size];
+
+                reader.read_exact(&mut data)?;
+                reconstructed_content.extend(data);
+            } else {
+                // copy instruction: copy `size` bytes of the base, starting at `offset`
+                let mut buffer = [0u8; 1];
+                let mut offset = 0;
+                let mut size = 0;
+
+                for i in 0..4 {
+                    if first_byte & (1u8 << i) != 0 {
+                        reader.read_exact(&mut buffer)?;
+
+                        offset += (buffer[0] as usize) << (8 * i);
+                    }
+                }
+
+                for i in 0..3 {
+                    if first_byte & (1u8 << (i + 4)) != 0 {
+                        reader.read_exact(&mut buffer)?;
+
+                        size += (buffer[0] as usize) << (8 * i);
+                    }
+                }
+                // There is another exception: size zero is automatically converted to 0x10000
+                // see https://github.com/git/git/blob/795ea8776befc95ea2becd8020c7a284677b4161/Documentation/gitformat-pack.txt
+                if size == 0 {
+                    size = 0x10000;
+                }
+
+                let data = base_content
+                    .get(offset..offset + size)
+                    .ok_or_else(|| Error::InvalidPackFile)?;
+                reconstructed_content.extend(data);
+            }
+        }
+
+        if reconstructed_content.len() != reconstructed_object_size {
+            return Err(Error::WrongObjectSize {
+                expected: reconstructed_object_size,
+                got: reconstructed_content.len(),
+            });
+        }
+        Ok(reconstructed_content)
+    }
+
+    /// Transforms the pack objects into git objects, associated with their offset
+    /// in the pack
+    pub fn into_git_objects(self) -> Result<Vec<(usize, GitObject)>> {
+        let (git_objects, unresolved_deltas) = Self::resolve_objects(self.pack_objects, &[])?;
+        match unresolved_deltas.first() {
+            None => Ok(git_objects),
+            // some base objects are not in the pack
+            Some((
+                _,
+                GitPackObject::RefDelta {
+                    base_object_hash, ..
+                },
+            )) => Err(Error::ObjectNotFound(base_object_hash.to_string())),
+            Some(_) => Err(Error::InvalidPackFile),
+        }
+    }
+
+    /// Reconstructs the objects of the pack. The base of an OBJ_REF_DELTA is
+    /// searched in the pack, then in the given objects which are not part of
+    /// the result. Deltas whose base is found nowhere are returned apart
+    fn resolve_objects(
+        pack_objects: PackObjects,
+        external_bases: &[GitObject],
+    ) -> Result<(Vec<(usize, GitObject)>, PackObjects)> {
+        // base objects are found either by hash (OBJ_REF_DELTA)
+        // or by offset in the pack (OBJ_OFS_DELTA)
         let mut obj_map = HashMap::<String, usize>::new();
+        let mut offset_map = HashMap::<usize, usize>::new();
+
+        let mut git_objects: Vec<(usize, GitObject)> = Vec::new();
 
-        let mut git_objects: Vec<GitObject> = Vec::new();
+        // deltas whose base is not yet known are kept for later:
+        // OBJ_REF_DELTA may point to objects found further in the pack
+        let mut pending_deltas = Vec::new();
 
-        for git_pack_object in self.pack_objects {
+        for (offset, git_pack_object) in pack_objects {
             let git_object = match git_pack_object {
                 GitPackObject::Blob { content_bytes } => {
                     GitObject::from_blob_content_bytes(content_bytes)?
@@ -389,118 +1214,533 @@ impl GitPack {
                 GitPackObject::Tag { content_bytes } => {
                     GitObject::from_tag_content_bytes(content_bytes)?
                 }
-                // TODO: refactor in own function
-                GitPackObject::RefDelta {
-                    base_object_hash,
-                    content_bytes,
-                } => {
-                    let base_object = &git_objects[*obj_map
-                        .get(&base_object_hash)
-                        .ok_or_else(|| Error::ObjectNotFound(base_object_hash.to_string()))?];
-
-                    let base_bytes = base_object
-                        .object_bytes
-                        .clone()
-                        .ok_or_else(|| Error::ObjectBytesNotLoaded)?;
-
-                    let mut reader = BufReader::new(&content_bytes[..]);
-                    let expected_base_object_size = Self::get_next_size_without_type(&mut reader)?;
-                    let reconstructed_object_size = Self::get_next_size_without_type(&mut reader)?;
-
-                    if base_object.size != expected_base_object_size {
-                        Err(Error::WrongObjectSize {
-                            expected: base_object.size,
-                            got: expected_base_object_size,
-                        })?;
-                    }
+                delta => {
+                    pending_deltas.push((offset, delta));
+                    continue;
+                }
+            };
+            obj_map.insert(git_object.hash.clone(), git_objects.len());
+            offset_map.insert(offset, git_objects.len());
+            git_objects.push((offset, git_object));
+        }
+
+        // each pass resolves the deltas whose base is known. A delta can be the base
+        // of another delta so we loop until everything is resolved
+        while !pending_deltas.is_empty() {
+            let nb_pending = pending_deltas.len();
+            let mut still_pending = Vec::new();
+
+            for (offset, delta) in pending_deltas {
+                let (base_object, content_bytes) = match &delta {
+                    GitPackObject::OfsDelta {
+                        base_object_offset,
+                        content_bytes,
+                    } => (
+                        offset_map
+                            .get(base_object_offset)
+                            .map(|&index| &git_objects[index].1),
+                        content_bytes,
+                    ),
+                    GitPackObject::RefDelta {
+                        base_object_hash,
+                        content_bytes,
+                    } => (
+                        obj_map
+                            .get(base_object_hash)
+                            .map(|&index| &git_objects[index].1)
+                            .or_else(|| {
+                                external_bases
+                                    .iter()
+                                    .find(|base| base.hash == *base_object_hash)
+                            }),
+                        content_bytes,
+                    ),
+                    _ => Err(Error::Unreachable)?,
+                };
+                let Some(base_object) = base_object else {
+                    still_pending.push((offset, delta));
+                    continue;
+                };
+
+                let reconstructed_content =
+                    Self::apply_delta(base_object.content_bytes()?, content_bytes)?;
+
+                let git_object = GitObject::from_content_bytes(
+                    &base_object.content_type(),
+                    reconstructed_content,
+                )?;
+
+                obj_map.insert(git_object.hash.clone(), git_objects.len());
+                offset_map.insert(offset, git_objects.len());
+                git_objects.push((offset, git_object));
+            }
+
+            if still_pending.len() == nb_pending {
+                // no progress, the remaining deltas can not be resolved
+                return Ok((git_objects, still_pending));
+            }
+            pending_deltas = still_pending;
+        }
+        Ok((git_objects, Vec::new()))
+    }
 
-                    // TODO: actual construction of the object
-                    let mut cur_size = 0;
-
-                    let mut reconstructed_content = Vec::new();
-                    while cur_size != reconstructed_object_size {
-                        let mut buf = [0];
-                        reader.read_exact(&mut buf)?;
-                        let first_byte = buf[0];
-
-                        let instruction = first_byte >> 7;
-
-                        if instruction == 0 {
-                            let size = first_byte as usize;
-                            // The size must be non-zero
-                            // see https://github.com/git/git/blob/795ea8776befc95ea2becd8020c7a284677b4161/Documentation/gitformat-pack.txt
-                            if size == 0 {
-                                Err(Error::InvalidPackFile)?;
-                            }
-
-                            let mut data = vec![0; size];
-
-                            reader.read_exact(&mut data)?;
-                            cur_size += data.len();
-                            reconstructed_content.extend(data);
-                        } else {
-                            let mut buffer = [0u8; 1];
-                            let mut offset = 0;
-                            let mut size = 0;
-
-                            for i in 0..4 {
-                                if first_byte & (1u8 << i) != 0 {
-                                    reader.read_exact(&mut buffer)?;
-
-                                    offset += (buffer[0] as usize) << (8 * i);
-                                }
-                            }
-
-                            for i in 0..3 {
-                                if first_byte & (1u8 << (i + 4)) != 0 {
-                                    reader.read_exact(&mut buffer)?;
-
-                                    size += (buffer[0] as usize) << (8 * i);
-                                }
-                            }
-                            // There is another exception: size zero is automatically converted to 0x10000
-                            // see https://github.com/git/git/blob/795ea8776befc95ea2becd8020c7a284677b4161/Documentation/gitformat-pack.txt
-                            if size == 0 {
-                                size = 0x10000;
-                            }
-
-                            let mut base_reader = BufReader::new(&base_bytes[..]);
-                            let mut header_bytes = Vec::new();
-                            base_reader.read_until(0, &mut header_bytes)?;
-                            let mut data = Vec::new();
-
-                            base_reader.read_to_end(&mut data)?;
-
-                            reconstructed_content.extend(&data[offset..offset + size]);
-                            cur_size += size;
-                        }
+    /// Completes a thin pack, as `git index-pack --fix-thin` does: a server may
+    /// send deltas against objects the client already has, without sending
+    /// these objects. They are read from the object database and appended to
+    /// the pack, so that the pack can be used on its own. The object count of
+    /// the header and the trailing checksum are updated
+    pub fn fix_thin(self, object_database: &GitObjectDatabase) -> Result<Self> {
+        let mut thin_bases: Vec<GitObject> = Vec::new();
+        loop {
+            // a delta whose base is another delta against a missing object is
+            // only resolved once that object is found
+            let pack_objects = self.pack_objects.clone();
+            let (_, unresolved_deltas) = Self::resolve_objects(pack_objects, &thin_bases)?;
+            let mut missing_hashes = Vec::new();
+            for (_, delta) in &unresolved_deltas {
+                if let GitPackObject::RefDelta {
+                    base_object_hash, ..
+                } = delta
+                {
+                    if !missing_hashes.contains(base_object_hash)
+                        && object_database.contains(base_object_hash)?
+                    {
+                        missing_hashes.push(base_object_hash.clone());
                     }
-                    let git_object = match base_object.content {
-                        GitObjectContent::Blob { .. } => {
-                            GitObject::from_blob_content_bytes(reconstructed_content)?
-                        }
-                        GitObjectContent::Tree { .. } => {
-                            GitObject::from_tree_content_bytes(reconstructed_content)?
-                        }
-                        GitObjectContent::Commit { .. } => {
-                            GitObject::from_commit_content_bytes(reconstructed_content)?
-                        }
-                        GitObjectContent::Tag { .. } => {
-                            GitObject::from_tag_content_bytes(reconstructed_content)?
-                        }
-                    };
-                    if git_object.size != reconstructed_object_size {
-                        return Err(Error::WrongObjectSize {
-                            expected: reconstructed_object_size,
-                            got: git_object.size,
+                }
+            }
+            if missing_hashes.is_empty() {
+                break;
+            }
+            for hash in missing_hashes {
+                thin_bases.push(object_database.read_object(&hash)?);
+            }
+        }
+        if thin_bases.is_empty() {
+            return Ok(self);
+        }
+
+        let mut pack_bytes = self.pack_bytes;
+        pack_bytes.truncate(pack_bytes.len() - 20);
+        for base in &thin_bases {
+            let content = base.content_bytes()?;
+            let object_type = Self::object_type_number(&base.content_type())?;
+            pack_bytes.extend(Self::encode_object_type_and_size(
+                object_type,
+                content.len(),
+            ));
+            pack_bytes.extend(Self::compress(
+                content,
+                PackWriteOptions::default().compression,
+            )?);
+        }
+        let nb_objects = self.pack_objects.len() + thin_bases.len();
+        pack_bytes[8..12].copy_from_slice(&(nb_objects as u32).to_be_bytes());
+        let checksum = hex::decode(GitObject::get_hash_from_bytes(&pack_bytes))
+            .map_err(|_| Error::Unreachable)?;
+        pack_bytes.extend(checksum);
+        Self::from_pack_bytes(pack_bytes)
+    }
+
+    /// Stores the pack as is in .git/objects/pack along with its index, contrary to
+    /// unpacking every object as a loose object which is slow for big repositories.
+    /// The pack is named after its checksum: pack-{checksum}.pack and pack-{checksum}.idx
+    pub fn write(self, object_database: &GitObjectDatabase) -> Result<String> {
+        let (pack_bytes, index) = self.into_index()?;
+
+        let pack_dir = object_database.pack_dir();
+        create_dir_all(&pack_dir)?;
+
+        let pack_name = format!("pack-{}", index.pack_checksum);
+        std::fs::write(pack_dir.join(format!("{}.pack", pack_name)), &pack_bytes)?;
+        std::fs::write(
+            pack_dir.join(format!("{}.idx", pack_name)),
+            index.to_bytes()?,
+        )?;
+        // the new pack must be visible for the next reads
+        object_database.reload_packs();
+        Ok(index.pack_checksum)
+    }
+
+    /// Raw content of the pack, and its index. The deltas must have their base
+    /// in the pack
+    pub fn into_index(mut self) -> Result<(Vec<u8>, GitPackIndex)> {
+        let pack_bytes = std::mem::take(&mut self.pack_bytes);
+        let git_objects = self.into_git_objects()?;
+        let index = GitPackIndex::from_pack_bytes_and_objects(&pack_bytes, &git_objects)?;
+        Ok((pack_bytes, index))
+    }
+
+    /// Name of the object type as used in the header of loose objects
+    fn object_type_name(object_type: usize) -> Result<&'static str> {
+        match object_type {
+            1 => Ok("commit"),
+            2 => Ok("tree"),
+            3 => Ok("blob"),
+            4 => Ok("tag"),
+            x => Err(Error::InvalidPackObjectType(x)),
+        }
+    }
+
+    /// Object type as encoded in packs, from the name used in loose objects
+    fn object_type_number(object_type: &str) -> Result<usize> {
+        match object_type {
+            "commit" => Ok(1),
+            "tree" => Ok(2),
+            "blob" => Ok(3),
+            "tag" => Ok(4),
+            _ => Err(Error::InvalidGitObject),
+        }
+    }
+
+    /// Reads the object found at the given offset of a packfile.
+    /// Deltas are resolved by reading their base object in the same pack. For
+    /// OBJ_REF_DELTA, the base object is found with the index, or elsewhere in the
+    /// repository if the pack does not contain it.
+    /// Returns the type of the object and its content (header not included)
+    pub fn read_object_at_offset<R: BufRead + Seek>(
+        reader: &mut R,
+        offset: usize,
+        index: &GitPackIndexFile,
+        object_database: &GitObjectDatabase,
+    ) -> Result<(String, Vec<u8>)> {
+        // the deltas of the chain, from the object to its base. A corrupt pack
+        // could make the chain loop: each object is read at most once
+        let mut deltas = Vec::new();
+        let mut visited = HashSet::new();
+        let mut offset = offset;
+        let (object_type, mut content) = loop {
+            if !visited.insert(offset) {
+                return Err(Error::InvalidPackFile);
+            }
+            reader.seek(SeekFrom::Start(offset as u64))?;
+            let (object_type, cur_size) = Self::get_next_object_type_and_size(reader)?;
+
+            match object_type {
+                1..=4 => {
+                    let (buf, _) = Self::read_zlib_data(&mut *reader)?;
+                    if buf.len() != cur_size {
+                        return Err(Error::IncorrectPackObjectSize {
+                            expected: cur_size,
+                            got: buf.len(),
                         });
                     }
-                    git_object
+                    break (Self::object_type_name(object_type)?.to_string(), buf);
+                }
+                6 => {
+                    let negative_offset = Self::get_next_negative_offset(reader)?;
+                    // the base is always before the delta
+                    let base_object_offset = offset
+                        .checked_sub(negative_offset)
+                        .filter(|_| negative_offset > 0)
+                        .ok_or_else(|| Error::InvalidPackFile)?;
+                    let (delta, _) = Self::read_zlib_data(&mut *reader)?;
+                    deltas.push(delta);
+                    offset = base_object_offset;
                 }
+                7 => {
+                    let mut base_object = vec![0; 20];
+                    reader.read_exact(&mut base_object)?;
+                    let base_object_hash = hex::encode(base_object);
+                    let (delta, _) = Self::read_zlib_data(&mut *reader)?;
+                    deltas.push(delta);
+
+                    match index.find_offset(&base_object_hash)? {
+                        Some(base_object_offset) => offset = base_object_offset,
+                        None => break object_database.read_raw_object(&base_object_hash)?,
+                    }
+                }
+                x => return Err(Error::InvalidPackObjectType(x)),
+            }
+        };
+        for delta in deltas.iter().rev() {
+            content = Self::apply_delta(&content, delta)?;
+        }
+        Ok((object_type, content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_object::GitObjectContent;
+    use crate::git_pack_index::GitPackIndexEntry;
+
+    #[test]
+    fn object_types_and_sizes() {
+        // 1001_1111 0010_1100 => type is 001, size is 0010_1100_1111
+        let bytes = [0b1001_1111, 0b0010_1100];
+        let decoded = GitPack::get_next_object_type_and_size(&mut bytes.as_slice());
+        assert_eq!(decoded.unwrap(), (1, 0b0010_1100_1111));
+        let bytes = [0b1001_0000, 0b0000_0001];
+        let decoded = GitPack::get_next_size_without_type(&mut bytes.as_slice());
+        assert_eq!(decoded.unwrap(), 0b1001_0000);
+        assert!(GitPack::get_next_size_without_type(&mut [0x80].as_slice()).is_err());
+    }
+
+    #[test]
+    fn negative_offsets() {
+        let decode = |bytes: &[u8]| GitPack::get_next_negative_offset(&mut &bytes[..]).unwrap();
+        assert_eq!(decode(&[0x7f]), 127);
+        // each continuation adds 1 before shifting
+        assert_eq!(decode(&[0x80, 0]), 128);
+        // 1000_0001 0000_0000 => ((1 + 1) << 7) + 0
+        assert_eq!(decode(&[0b1000_0001, 0]), 256);
+        assert_eq!(decode(&[0x80, 0x80, 0]), (128 + 1) << 7);
+        assert!(GitPack::get_next_negative_offset(&mut [0x80].as_slice()).is_err());
+    }
+
+    #[test]
+    fn apply_delta_copies_and_inserts() {
+        let base = b"hello world";
+        // sizes, copy 5 bytes from offset 6, insert ", ", copy 5 bytes from 0
+        let delta = [11, 12, 0b1001_0001, 6, 5, 2, b',', b' ', 0b1001_0000, 5];
+        assert_eq!(GitPack::apply_delta(base, &delta).unwrap(), b"world, hello");
+
+        // wrong base size
+        assert!(GitPack::apply_delta(&base[1..], &delta).is_err());
+        // copy past the end of the base
+        assert!(GitPack::apply_delta(base, &[11, 5, 0b1001_0001, 8, 5]).is_err());
+        // insert of size 0
+        assert!(GitPack::apply_delta(base, &[11, 1, 0]).is_err());
+        // instructions missing
+        assert!(GitPack::apply_delta(base, &[11, 12, 1, b'x']).is_err());
+    }
+
+    /// Compresses the data as it is stored in a pack
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A pack made of the given packed objects (header and compressed data),
+    /// without its checksum
+    fn pack_content(packed_objects: &[Vec<u8>]) -> Vec<u8> {
+        let mut pack_bytes = b"PACK".to_vec();
+        pack_bytes.extend(2u32.to_be_bytes());
+        pack_bytes.extend((packed_objects.len() as u32).to_be_bytes());
+        for packed_object in packed_objects {
+            pack_bytes.extend(packed_object);
+        }
+        pack_bytes
+    }
+
+    /// Same as pack_content, with the checksum
+    fn pack_with(packed_objects: &[Vec<u8>]) -> Vec<u8> {
+        let mut pack_bytes = pack_content(packed_objects);
+        let checksum = hex::decode(GitObject::get_hash_from_bytes(&pack_bytes)).unwrap();
+        pack_bytes.extend(checksum);
+        pack_bytes
+    }
+
+    #[test]
+    fn overlong_sizes_are_rejected() {
+        let bytes = [0xff; 16];
+        assert!(GitPack::get_next_object_type_and_size(&mut bytes.as_slice()).is_err());
+        assert!(GitPack::get_next_size_without_type(&mut bytes.as_slice()).is_err());
+        assert!(GitPack::get_next_negative_offset(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn huge_delta_size_is_not_allocated() {
+        // the header announces an object of 2^63 bytes, and the instructions
+        // are missing
+        let mut delta = vec![4];
+        delta.extend([0xff; 8]);
+        delta.push(0x01);
+        assert!(GitPack::apply_delta(b"base", &delta).is_err());
+    }
+
+    #[test]
+    fn delta_on_itself_is_rejected() {
+        let mut blob = vec![0b0011_0100];
+        blob.extend(zlib(b"blob"));
+        assert!(GitPack::from_pack_bytes(pack_with(&[blob.clone()])).is_ok());
+
+        // copy the 4 bytes of the base, found at the offset of the delta
+        let mut delta = vec![0b0110_0100, 0];
+        delta.extend(zlib(&[4, 4, 0b1001_0000, 4]));
+        assert!(GitPack::from_pack_bytes(pack_with(&[blob, delta])).is_err());
+    }
+
+    #[test]
+    fn looping_delta_chain_is_rejected() {
+        // two OBJ_REF_DELTA, each one being the base of the other
+        let hashes = ["11".repeat(20), "22".repeat(20)];
+        let ref_delta = |base_hash: &str| {
+            let mut packed_object = vec![0b0111_0100];
+            packed_object.extend(hex::decode(base_hash).unwrap());
+            packed_object.extend(zlib(&[4, 4, 0b1001_0000, 4]));
+            packed_object
+        };
+        let packed_objects = [ref_delta(&hashes[1]), ref_delta(&hashes[0])];
+        let offsets = [12, 12 + packed_objects[0].len()];
+        let pack_bytes = pack_content(&packed_objects);
+
+        let index = GitPackIndex {
+            entries: (0..2)
+                .map(|i| GitPackIndexEntry {
+                    hash: hashes[i].clone(),
+                    crc32: 0,
+                    offset: offsets[i],
+                })
+                .collect(),
+            pack_checksum: "33".repeat(20),
+        };
+        let directory = std::env::temp_dir().join(format!("git-pack-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let index_path = directory.join("pack.idx");
+        std::fs::write(&index_path, index.to_bytes().unwrap()).unwrap();
+        let index = GitPackIndexFile::from_file(&index_path).unwrap();
+
+        let mut reader = Cursor::new(pack_bytes.as_slice());
+        let result = GitPack::read_object_at_offset(
+            &mut reader,
+            offsets[0],
+            &index,
+            &GitObjectDatabase::from_objects_dir(&directory),
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(result.is_err());
+    }
+
+    fn numbered_lines(range: std::ops::Range<usize>) -> Vec<u8> {
+        range
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Blobs similar enough to be stored as deltas of each other, and a small
+    /// one that is not
+    fn similar_blobs() -> Vec<(GitObject, String)> {
+        let mut edited = numbered_lines(0..300);
+        edited.splice(1000..1000, b"inserted in the middle\n".iter().copied());
+        let contents = [
+            numbered_lines(0..300),
+            edited,
+            numbered_lines(50..250),
+            b"too small for a delta\n".to_vec(),
+        ];
+        contents
+            .into_iter()
+            .map(|content| {
+                let blob = GitObject::from_blob_content_bytes(content).unwrap();
+                (blob, "file.txt".to_string())
+            })
+            .collect()
+    }
+
+    fn blob_content(git_object: &GitObject) -> &[u8] {
+        match &git_object.content {
+            GitObjectContent::Blob { content } => content,
+            _ => panic!("not a blob"),
+        }
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let base = numbered_lines(0..500);
+        let targets = [
+            numbered_lines(0..500),
+            numbered_lines(100..400),
+            [
+                numbered_lines(0..200),
+                b"new line\n".to_vec(),
+                numbered_lines(200..500),
+            ]
+            .concat(),
+            [numbered_lines(300..500), numbered_lines(0..300)].concat(),
+            b"nothing in common".repeat(100),
+            Vec::new(),
+        ];
+        let base_index = GitPack::create_delta_index(&base);
+        for target in targets {
+            let delta = GitPack::create_delta(&base_index, &base, &target, usize::MAX).unwrap();
+            assert_eq!(GitPack::apply_delta(&base, &delta).unwrap(), target);
+        }
+    }
+
+    #[test]
+    fn delta_bigger_than_max_size() {
+        let base = numbered_lines(0..100);
+        let base_index = GitPack::create_delta_index(&base);
+        let target = b"nothing in common".repeat(100);
+        assert!(GitPack::create_delta(&base_index, &base, &target, 100).is_none());
+    }
+
+    #[test]
+    fn size_and_offset_encodings() {
+        for size in [0, 1, 15, 16, 127, 128, 0x3fff, 0x4000, 1 << 40] {
+            for object_type in [1, 3, 6, 7] {
+                let bytes = GitPack::encode_object_type_and_size(object_type, size);
+                let decoded = GitPack::get_next_object_type_and_size(&mut bytes.as_slice());
+                assert_eq!(decoded.unwrap(), (object_type, size));
+            }
+            let bytes = GitPack::encode_size_without_type(size);
+            let decoded = GitPack::get_next_size_without_type(&mut bytes.as_slice());
+            assert_eq!(decoded.unwrap(), size);
+            let bytes = GitPack::encode_negative_offset(size);
+            let decoded = GitPack::get_next_negative_offset(&mut bytes.as_slice());
+            assert_eq!(decoded.unwrap(), size);
+        }
+    }
+
+    #[test]
+    fn written_pack_round_trip() {
+        let git_objects = similar_blobs();
+        for ofs_delta in [false, true] {
+            let options = PackWriteOptions {
+                ofs_delta,
+                ..Default::default()
             };
-            obj_map.insert(git_object.hash.clone(), git_objects.len());
-            git_objects.push(git_object);
+            let git_pack = GitPack::from_git_objects(&git_objects, &[], &options).unwrap();
+            let nb_deltas = git_pack
+                .pack_objects
+                .iter()
+                .filter(|(_, pack_object)| match pack_object {
+                    GitPackObject::OfsDelta { .. } => ofs_delta,
+                    GitPackObject::RefDelta { .. } => !ofs_delta,
+                    _ => false,
+                })
+                .count();
+            assert_eq!(nb_deltas, 2);
+
+            let read_objects = GitPack::from_pack_bytes(git_pack.pack_bytes().to_vec())
+                .unwrap()
+                .into_git_objects()
+                .unwrap();
+            assert_eq!(read_objects.len(), git_objects.len());
+            for (git_object, _) in &git_objects {
+                let (_, read_object) = read_objects
+                    .iter()
+                    .find(|(_, read_object)| read_object.hash == git_object.hash)
+                    .unwrap();
+                assert_eq!(blob_content(read_object), blob_content(git_object));
+            }
         }
-        Ok(git_objects)
+    }
+
+    #[test]
+    fn index_matches_git_index_pack() {
+        let options = PackWriteOptions {
+            ofs_delta: true,
+            ..Default::default()
+        };
+        let git_pack = GitPack::from_git_objects(&similar_blobs(), &[], &options).unwrap();
+        let pack_bytes = git_pack.pack_bytes().to_vec();
+        let (_, index) = git_pack.into_index().unwrap();
+        let index_bytes = index.to_bytes().unwrap();
+        // hash of the index written by `git index-pack` for the same pack
+        assert_eq!(
+            GitObject::get_hash_from_bytes(&index_bytes),
+            "af033f4b7f75903ac66d77fd979d258257091690"
+        );
+        assert_eq!(
+            index.pack_checksum,
+            hex::encode(&pack_bytes[pack_bytes.len() - 20..])
+        );
     }
 }
@@ -0,0 +1,292 @@
+use std::{fs::File, io::Read, path::Path};
+
+use crate::{git_object::GitObject, Error, Result};
+
+/// First 4 bytes of a v2 index: "\377tOc"
+const INDEX_V2_MAGIC: [u8; 4] = [0xff, 0x74, 0x4f, 0x63];
+
+/// Offsets greater than this value do not fit in the 31 bits of the offset
+/// table and are stored in the 64-bit offset table instead
+const MAX_SMALL_OFFSET: usize = 0x7fff_ffff;
+
+/// Entry of the index for a given object of the pack
+#[derive(Debug)]
+pub struct GitPackIndexEntry {
+    /// hash of the object in hexadecimal format
+    pub hash: String,
+    /// crc32 of the packed data of the object (header and compressed data)
+    pub crc32: u32,
+    /// offset of the object from the start of the packfile
+    pub offset: usize,
+}
+
+/// Index (.idx) associated to a packfile, allowing to find an object in the pack
+/// without reading the whole pack.
+/// see https://github.com/git/git/blob/795ea8776befc95ea2becd8020c7a284677b4161/Documentation/gitformat-pack.txt
+/// Only version 2 is written:
+/// - 4 bytes magic number \377tOc then 4 bytes version number (2)
+/// - fanout table: 256 entries of 4 bytes. Entry N is the number of objects whose
+///   hash first byte is less than or equal to N
+/// - the sorted hashes (20 bytes each)
+/// - the crc32 of each packed object (4 bytes each)
+/// - the offset of each object (4 bytes each). If the MSB is set, the 31 other bits
+///   are an index in the 64-bit offset table
+/// - the 64-bit offset table (8 bytes each)
+/// - the checksum of the packfile then the checksum of all of the above
+#[derive(Debug)]
+pub struct GitPackIndex {
+    /// entries are sorted by hash
+    pub entries: Vec<GitPackIndexEntry>,
+    /// checksum found at the end of the corresponding packfile
+    pub pack_checksum: String,
+}
+
+impl GitPackIndex {
+    /// Builds the index from the bytes of a packfile and the objects it contains,
+    /// associated with their offset in the pack
+    pub fn from_pack_bytes_and_objects(
+        pack_bytes: &[u8],
+        git_objects: &[(usize, GitObject)],
+    ) -> Result<Self> {
+        if pack_bytes.len() < 20 {
+            return Err(Error::InvalidPackFile);
+        }
+        let checksum_start = pack_bytes.len() - 20;
+        let pack_checksum = hex::encode(&pack_bytes[checksum_start..]);
+
+        // the packed data of an object ends where the next one starts
+        let mut offsets = git_objects
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect::<Vec<_>>();
+        offsets.sort();
+
+        let mut entries = Vec::new();
+        for (offset, git_object) in git_objects {
+            let next_object_pos = offsets.partition_point(|o| o <= offset);
+            let end = offsets
+                .get(next_object_pos)
+                .copied()
+                .unwrap_or(checksum_start);
+            let packed_data = pack_bytes
+                .get(*offset..end)
+                .ok_or_else(|| Error::InvalidPackFile)?;
+
+            entries.push(GitPackIndexEntry {
+                hash: git_object.hash.clone(),
+                crc32: crc32fast::hash(packed_data),
+                offset: *offset,
+            });
+        }
+        entries.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+        Ok(Self {
+            entries,
+            pack_checksum,
+        })
+    }
+
+    /// Serializes the index in the version 2 format
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.extend(INDEX_V2_MAGIC);
+        bytes.extend(2u32.to_be_bytes());
+
+        let mut hashes = Vec::new();
+        for entry in &self.entries {
+            hashes.push(
+                hex::decode(&entry.hash).map_err(|_| Error::InvalidHash(entry.hash.clone()))?,
+            );
+        }
+
+        // fanout table
+        let mut nb_objects = 0;
+        for first_byte in 0..=255u8 {
+            while nb_objects < hashes.len() && hashes[nb_objects][0] <= first_byte {
+                nb_objects += 1;
+            }
+            bytes.extend((nb_objects as u32).to_be_bytes());
+        }
+
+        for hash in &hashes {
+            bytes.extend(hash);
+        }
+
+        for entry in &self.entries {
+            bytes.extend(entry.crc32.to_be_bytes());
+        }
+
+        let mut large_offsets = Vec::new();
+        for entry in &self.entries {
+            if entry.offset > MAX_SMALL_OFFSET {
+                let large_offset_index = large_offsets.len() as u32 | 0x8000_0000;
+                bytes.extend(large_offset_index.to_be_bytes());
+                large_offsets.push(entry.offset as u64);
+            } else {
+                bytes.extend((entry.offset as u32).to_be_bytes());
+            }
+        }
+        for large_offset in large_offsets {
+            bytes.extend(large_offset.to_be_bytes());
+        }
+
+        bytes.extend(
+            hex::decode(&self.pack_checksum)
+                .map_err(|_| Error::InvalidHash(self.pack_checksum.clone()))?,
+        );
+        let index_checksum =
+            hex::decode(GitObject::get_hash_from_bytes(&bytes)).map_err(|_| Error::Unreachable)?;
+        bytes.extend(index_checksum);
+
+        Ok(bytes)
+    }
+}
+
+/// Index file (.idx) opened for lookups.
+/// Contrary to GitPackIndex, entries are not parsed upfront: the fanout table is
+/// used to narrow down the range of hashes to look at, then a binary search is
+/// done directly on the raw bytes.
+/// Both version 1 (no magic number, offset and hash stored together) and version 2
+/// are supported, as older repositories may still contain version 1 indexes
+#[derive(Debug)]
+pub struct GitPackIndexFile {
+    bytes: Vec<u8>,
+    version: u32,
+    nb_objects: usize,
+}
+
+impl GitPackIndexFile {
+    /// Reads an .idx file and checks its header
+    pub fn from_file<P: AsRef<Path>>(index_path: P) -> Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(index_path)?.read_to_end(&mut bytes)?;
+
+        let version = if bytes.starts_with(&INDEX_V2_MAGIC) {
+            let version = Self::read_u32(&bytes, 4)?;
+            if version != 2 {
+                return Err(Error::InvalidPackIndex);
+            }
+            version
+        } else {
+            1
+        };
+
+        let mut index_file = Self {
+            bytes,
+            version,
+            nb_objects: 0,
+        };
+        // the last entry of the fanout table is the total number of objects
+        index_file.nb_objects = index_file.fanout(255)?;
+        Ok(index_file)
+    }
+
+    fn read_u32(bytes: &[u8], pos: usize) -> Result<u32> {
+        let buf = bytes
+            .get(pos..pos + 4)
+            .ok_or_else(|| Error::InvalidPackIndex)?;
+        Ok(u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]))
+    }
+
+    /// Start of the fanout table, right after the header for version 2
+    fn fanout_start(&self) -> usize {
+        if self.version == 2 {
+            8
+        } else {
+            0
+        }
+    }
+
+    /// Number of objects whose hash first byte is less than or equal to first_byte
+    fn fanout(&self, first_byte: u8) -> Result<usize> {
+        Ok(Self::read_u32(&self.bytes, self.fanout_start() + 4 * first_byte as usize)? as usize)
+    }
+
+    /// Raw hash of the object at the given position in the sorted list
+    fn hash_at(&self, pos: usize) -> Result<&[u8]> {
+        let start = if self.version == 2 {
+            self.fanout_start() + 256 * 4 + 20 * pos
+        } else {
+            // each entry is 4 bytes of offset followed by the hash
+            self.fanout_start() + 256 * 4 + 24 * pos + 4
+        };
+        self.bytes
+            .get(start..start + 20)
+            .ok_or_else(|| Error::InvalidPackIndex)
+    }
+
+    /// Offset in the pack of the object at the given position in the sorted list
+    fn offset_at(&self, pos: usize) -> Result<usize> {
+        let tables_start = self.fanout_start() + 256 * 4;
+        if self.version == 1 {
+            return Ok(Self::read_u32(&self.bytes, tables_start + 24 * pos)? as usize);
+        }
+
+        // hashes then crc32s then offsets
+        let offsets_start = tables_start + 24 * self.nb_objects;
+        let small_offset = Self::read_u32(&self.bytes, offsets_start + 4 * pos)?;
+        if small_offset & 0x8000_0000 == 0 {
+            return Ok(small_offset as usize);
+        }
+
+        let large_offsets_start = offsets_start + 4 * self.nb_objects;
+        let pos = large_offsets_start + 8 * (small_offset & 0x7fff_ffff) as usize;
+        let buf = self
+            .bytes
+            .get(pos..pos + 8)
+            .ok_or_else(|| Error::InvalidPackIndex)?;
+        let mut large_offset = [0u8; 8];
+        large_offset.copy_from_slice(buf);
+        Ok(u64::from_be_bytes(large_offset) as usize)
+    }
+
+    /// Hashes of the objects of the index starting with the given hexadecimal
+    /// prefix (at least 2 characters)
+    pub fn find_hashes_with_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let Some(Ok(first_byte)) = prefix.get(..2).map(|b| u8::from_str_radix(b, 16)) else {
+            return Ok(Vec::new());
+        };
+        let low = match first_byte {
+            0 => 0,
+            first_byte => self.fanout(first_byte - 1)?,
+        };
+        let high = self.fanout(first_byte)?;
+
+        let mut hashes = Vec::new();
+        for pos in low..high {
+            let hash = hex::encode(self.hash_at(pos)?);
+            if hash.starts_with(prefix) {
+                hashes.push(hash);
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Returns the offset of the object in the pack if it is in the index
+    pub fn find_offset(&self, hash: &str) -> Result<Option<usize>> {
+        let Ok(hash) = hex::decode(hash) else {
+            return Ok(None);
+        };
+        if hash.len() != 20 {
+            return Ok(None);
+        }
+
+        // objects starting with the same byte as hash are between fanout[byte - 1]
+        // and fanout[byte]
+        let mut low = match hash[0] {
+            0 => 0,
+            first_byte => self.fanout(first_byte - 1)?,
+        };
+        let mut high = self.fanout(hash[0])?;
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.hash_at(mid)?.cmp(&hash[..]) {
+                std::cmp::Ordering::Equal => return Ok(Some(self.offset_at(mid)?)),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,72 @@
+use crate::git_pack::{GitPack, PackWriteOptions};
+use crate::git_repository::Repository;
+use crate::git_rev_walk::{boundary_blobs, list_objects};
+use crate::git_revision::resolve_revision;
+use crate::Result;
+
+#[derive(Debug, Default)]
+pub struct PackObjectsOptions {
+    /// the input lines are revisions (`^` or `--not` excluding the following
+    /// ones) and the pack contains the objects they need (--revs), instead of
+    /// `<hash> [<path>]` lines
+    pub revs: bool,
+    /// deltas can be made against objects of the excluded revisions, which
+    /// are not written in the pack (--thin). Only used with revs
+    pub thin: bool,
+    pub write_options: PackWriteOptions,
+}
+
+/// Creates a pack of the objects listed by the input lines
+pub fn git_pack_objects(
+    repository: &Repository,
+    lines: &[String],
+    options: &PackObjectsOptions,
+) -> Result<GitPack> {
+    let object_database = repository.object_database();
+    let mut git_objects = Vec::new();
+    let mut thin_bases = Vec::new();
+    if options.revs {
+        let mut tips = Vec::new();
+        let mut excluded = Vec::new();
+        let mut is_excluding = false;
+        for line in lines {
+            match line.as_str() {
+                "" => {}
+                "--not" => is_excluding = !is_excluding,
+                _ => match line.strip_prefix('^') {
+                    Some(revision) if !is_excluding => {
+                        excluded.push(resolve_revision(repository, revision)?)
+                    }
+                    Some(revision) => tips.push(resolve_revision(repository, revision)?),
+                    None if is_excluding => excluded.push(resolve_revision(repository, line)?),
+                    None => tips.push(resolve_revision(repository, line)?),
+                },
+            }
+        }
+        let object_list = list_objects(repository, &tips, &excluded)?;
+        for (hash, path) in &object_list.objects {
+            git_objects.push((object_database.read_object(hash)?, path.clone()));
+        }
+        if options.thin {
+            for (hash, path) in boundary_blobs(repository, &object_list)? {
+                thin_bases.push((object_database.read_object(&hash)?, path));
+            }
+        }
+    } else {
+        for line in lines.iter().filter(|line| !line.is_empty()) {
+            let (hash, path) = line.split_once(' ').unwrap_or((line, ""));
+            git_objects.push((object_database.read_object(hash)?, path.to_string()));
+        }
+    }
+    GitPack::from_git_objects(&git_objects, &thin_bases, &options.write_options)
+}
+
+/// Writes the pack and its index as `<base_name>-<checksum>.pack` and
+/// `<base_name>-<checksum>.idx`. Returns the checksum
+pub fn write_pack_files(git_pack: GitPack, base_name: &str) -> Result<String> {
+    let (pack_bytes, index) = git_pack.into_index()?;
+    let path = format!("{}-{}", base_name, index.pack_checksum);
+    std::fs::write(format!("{}.pack", path), &pack_bytes)?;
+    std::fs::write(format!("{}.idx", path), index.to_bytes()?)?;
+    Ok(index.pack_checksum)
+}
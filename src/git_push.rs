@@ -0,0 +1,533 @@
+use crate::git_ident::committer_signature;
+use crate::git_pack::{GitPack, PackWriteOptions, UploadPackDiscovery};
+use crate::git_refs::NULL_HASH;
+use crate::git_remote::{find_ref, ref_name_matches, Refspec, Remote};
+use crate::git_repository::Repository;
+use crate::git_rev_walk::{boundary_blobs, is_ancestor, list_objects};
+use crate::git_revision::resolve_revision;
+use crate::{Error, Result};
+
+#[derive(Debug, Default)]
+pub struct PushOptions {
+    /// updates the remote refs even when it is not a fast-forward (--force)
+    pub force: bool,
+    /// --force-with-lease values, `<ref>[:<expect>]`. An empty value applies to
+    /// every pushed ref
+    pub force_with_lease: Vec<String>,
+}
+
+/// What happened to a pushed ref
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushedRefStatus {
+    /// the remote ref already had the value
+    UpToDate,
+    /// the remote ref was created, updated or deleted
+    Ok,
+    /// the remote ref is not an ancestor of the pushed commit
+    NonFastForward,
+    /// the remote tag exists, tags are only replaced when forced
+    AlreadyExists,
+    /// the remote ref points to an object we do not have
+    FetchFirst,
+    /// the remote ref or the pushed object is not a commit
+    NeedsForce,
+    /// --force-with-lease: the remote ref is not at the expected value
+    Stale,
+    /// the server does not support deleting refs
+    NoDelete,
+    /// the server refused the update, for the given reason
+    RemoteRejected(String),
+}
+
+#[derive(Debug)]
+pub struct PushedRef {
+    /// name of the ref in the remote repository, for ex refs/heads/main
+    pub remote_ref: String,
+    /// what was pushed: a local ref or a revision. None for a deletion
+    pub source: Option<String>,
+    /// value of the remote ref before the push, None if it did not exist
+    pub old_hash: Option<String>,
+    /// None for a deletion
+    pub new_hash: Option<String>,
+    /// the update is not a fast-forward, but the refspec or --force allowed it
+    pub forced: bool,
+    pub status: PushedRefStatus,
+}
+
+#[derive(Debug)]
+pub struct PushOutput {
+    /// url of the remote, as configured
+    pub url: String,
+    /// pushed refs: the existing remote refs in the order the server
+    /// advertised them, then the new ones
+    pub refs: Vec<PushedRef>,
+}
+
+/// Value a remote ref must have to be replaced (--force-with-lease)
+#[derive(Debug)]
+enum Expectation {
+    /// the value of its remote-tracking branch, or no value if there is none
+    Tracking,
+    /// the given value, None if the ref must not exist
+    Hash(Option<String>),
+}
+
+#[derive(Debug)]
+struct Lease {
+    /// None when the lease applies to every ref without a lease of its own
+    ref_name: Option<String>,
+    expectation: Expectation,
+}
+
+/// Sends the local objects the remote lacks and updates the remote refs.
+/// The remote defaults to the remote of the current branch, or origin, and
+/// the refspecs to its remote.<name>.push refspecs, or to the current branch
+/// pushed to the branch of the same name (push.default=simple).
+///
+/// A remote ref is only replaced by a descendant of its value, unless the
+/// update is forced. With --force-with-lease, the update is forced as long as
+/// the remote ref has the expected value: by default the value of its
+/// remote-tracking branch, as the last fetch saw it.
+/// The remote-tracking branches of the updated refs are updated too
+pub fn git_push(
+    repository: &Repository,
+    remote_name: Option<&str>,
+    refspecs: &[String],
+    options: &PushOptions,
+) -> Result<PushOutput> {
+    let remote_name = match remote_name {
+        Some(remote_name) => remote_name.to_string(),
+        None => Remote::default_name(repository)?,
+    };
+    let remote = Remote::load(repository, &remote_name)?;
+    let refspecs = if !refspecs.is_empty() {
+        refspecs
+            .iter()
+            .map(|refspec| Refspec::parse_push(refspec))
+            .collect::<Result<Vec<_>>>()?
+    } else if !remote.push_refspecs.is_empty() {
+        remote.push_refspecs.clone()
+    } else {
+        vec![default_refspec(repository, &remote)?]
+    };
+    let leases = parse_leases(repository, &options.force_with_lease)?;
+
+    let discovery =
+        UploadPackDiscovery::from_repository_url_and_service(&remote.url, "git-receive-pack")?;
+    let remote_refs = discovery
+        .refs
+        .iter()
+        .map(|(hash, name)| (name.clone(), hash.clone()))
+        .collect::<Vec<_>>();
+
+    let mut pushed_refs = Vec::new();
+    for (mut pushed_ref, force) in match_push_refs(repository, &remote_refs, &refspecs)? {
+        let expectation = leases
+            .iter()
+            .find(|lease| {
+                lease
+                    .ref_name
+                    .as_deref()
+                    .is_some_and(|ref_name| ref_name_matches(ref_name, &pushed_ref.remote_ref))
+            })
+            .or_else(|| leases.iter().find(|lease| lease.ref_name.is_none()))
+            .map(|lease| &lease.expectation);
+        let expected_hash = match expectation {
+            None => None,
+            Some(Expectation::Hash(hash)) => Some(hash.clone()),
+            Some(Expectation::Tracking) => {
+                Some(match remote.tracking_ref(&pushed_ref.remote_ref) {
+                    Some(tracking_ref) => repository.refs().resolve(&tracking_ref)?,
+                    None => None,
+                })
+            }
+        };
+        check_update(
+            repository,
+            &mut pushed_ref,
+            options.force || force,
+            expected_hash,
+        )?;
+        pushed_refs.push(pushed_ref);
+    }
+    // the remote refs are listed as the server advertised them
+    pushed_refs.sort_by_key(|pushed_ref| {
+        remote_refs
+            .iter()
+            .position(|(name, _)| *name == pushed_ref.remote_ref)
+            .unwrap_or(remote_refs.len())
+    });
+
+    if !discovery.supports("delete-refs") {
+        for pushed_ref in &mut pushed_refs {
+            if pushed_ref.new_hash.is_none() && pushed_ref.status == PushedRefStatus::Ok {
+                pushed_ref.status = PushedRefStatus::NoDelete;
+            }
+        }
+    }
+    if pushed_refs
+        .iter()
+        .any(|pushed_ref| pushed_ref.status == PushedRefStatus::Ok)
+    {
+        send_pack(repository, &discovery, &remote_refs, &mut pushed_refs)?;
+        update_tracking_refs(repository, &remote, &pushed_refs)?;
+    }
+    Ok(PushOutput {
+        url: remote.url.clone(),
+        refs: pushed_refs,
+    })
+}
+
+/// Refspec pushing the current branch to the branch of the same name. If the
+/// remote is the one of the branch, the branch must have an upstream branch of
+/// the same name (push.default=simple)
+fn default_refspec(repository: &Repository, remote: &Remote) -> Result<Refspec> {
+    let config = repository.config()?;
+    let head_ref = repository
+        .refs()
+        .head_ref()?
+        .filter(|head_ref| head_ref.starts_with("refs/heads/"))
+        .ok_or_else(|| Error::NotABranch("HEAD".to_string()))?;
+    let branch = head_ref.trim_start_matches("refs/heads/");
+    let branch_remote = config
+        .get(&format!("branch.{}.remote", branch))
+        .unwrap_or("origin");
+    if branch_remote == remote.name {
+        let merge = config
+            .get(&format!("branch.{}.merge", branch))
+            .ok_or_else(|| Error::NoUpstream(branch.to_string()))?;
+        if merge != head_ref {
+            return Err(Error::UpstreamNameMismatch {
+                branch: branch.to_string(),
+                upstream: merge.to_string(),
+            });
+        }
+    }
+    Ok(Refspec {
+        force: false,
+        source: head_ref.clone(),
+        destination: Some(head_ref),
+    })
+}
+
+/// Parses the --force-with-lease values: `<ref>:<expect>` expects the given
+/// revision (nothing if empty), `<ref>` the value of its remote-tracking
+/// branch, and an empty value the same for every ref
+fn parse_leases(repository: &Repository, values: &[String]) -> Result<Vec<Lease>> {
+    let mut leases = Vec::new();
+    for value in values {
+        let lease = match value.split_once(':') {
+            _ if value.is_empty() => Lease {
+                ref_name: None,
+                expectation: Expectation::Tracking,
+            },
+            None => Lease {
+                ref_name: Some(value.clone()),
+                expectation: Expectation::Tracking,
+            },
+            Some((ref_name, "")) => Lease {
+                ref_name: Some(ref_name.to_string()),
+                expectation: Expectation::Hash(None),
+            },
+            Some((ref_name, expected)) => Lease {
+                ref_name: Some(ref_name.to_string()),
+                expectation: Expectation::Hash(Some(resolve_revision(repository, expected)?)),
+            },
+        };
+        leases.push(lease);
+    }
+    Ok(leases)
+}
+
+/// The remote refs the refspecs update, and whether the refspec forces the
+/// update. The source is a local ref or any revision, and the destination:
+/// - the source ref itself when omitted
+/// - the remote ref it designates, with the same rules as revisions
+/// - a full ref name (refs/...)
+/// - otherwise a ref of the same kind as the source (a branch or a tag)
+///
+/// Patterns push every local ref they match
+fn match_push_refs(
+    repository: &Repository,
+    remote_refs: &[(String, String)],
+    refspecs: &[Refspec],
+) -> Result<Vec<(PushedRef, bool)>> {
+    let refs = repository.refs();
+    let remote_hash = |remote_ref: &str| {
+        remote_refs
+            .iter()
+            .find(|(name, _)| name == remote_ref)
+            .map(|(_, hash)| hash.clone())
+    };
+
+    let mut updates: Vec<(Option<String>, Option<String>, String, bool)> = Vec::new();
+    for refspec in refspecs {
+        let destination = refspec.destination.as_deref();
+        if refspec.is_deletion() {
+            let destination = destination.ok_or(Error::Unreachable)?;
+            let remote_ref = find_ref(remote_refs, destination)
+                .map(|(name, _)| name.clone())
+                .ok_or_else(|| Error::RemoteRefNotFound(destination.to_string()))?;
+            updates.push((None, None, remote_ref, refspec.force));
+        } else if refspec.is_pattern() {
+            for (name, hash) in refs.list("refs/")? {
+                let remote_ref = match destination {
+                    Some(_) => refspec.map_source(&name),
+                    None => refspec.matches_source(&name).then(|| name.clone()),
+                };
+                if let Some(remote_ref) = remote_ref.filter(|r| Remote::is_valid_destination(r)) {
+                    updates.push((Some(name), Some(hash), remote_ref, refspec.force));
+                }
+            }
+        } else {
+            let source_ref = refs.expand_name(&refspec.source)?;
+            let hash = resolve_revision(repository, &refspec.source)?;
+            // a symbolic ref such as HEAD pushes the branch it points to
+            let target = match &source_ref {
+                Some(source_ref) => Some(refs.follow_symbolic(source_ref)?),
+                None => None,
+            };
+            let remote_ref = match destination {
+                None => target
+                    .filter(|target| {
+                        target.starts_with("refs/heads/")
+                            || (target.starts_with("refs/") && source_ref.as_ref() == Some(target))
+                    })
+                    .ok_or_else(|| Error::NotABranch(refspec.source.clone()))?,
+                Some(destination) => match find_ref(remote_refs, destination) {
+                    Some((name, _)) => name.clone(),
+                    None if destination.starts_with("refs/") => destination.to_string(),
+                    None => ["refs/heads/", "refs/tags/"]
+                        .into_iter()
+                        .find(|prefix| target.as_ref().is_some_and(|t| t.starts_with(prefix)))
+                        .map(|prefix| format!("{}{}", prefix, destination))
+                        .ok_or_else(|| Error::UnqualifiedDestination(destination.to_string()))?,
+                },
+            };
+            if !Remote::is_valid_destination(&remote_ref) {
+                return Err(Error::InvalidRefName(remote_ref));
+            }
+            let source = source_ref.unwrap_or_else(|| refspec.source.clone());
+            updates.push((Some(source), Some(hash), remote_ref, refspec.force));
+        }
+    }
+
+    let mut pushed_refs: Vec<(PushedRef, bool)> = Vec::new();
+    for (source, new_hash, remote_ref, force) in updates {
+        // a remote ref is only updated once, by its first refspec
+        if pushed_refs
+            .iter()
+            .any(|(pushed_ref, _)| pushed_ref.remote_ref == remote_ref)
+        {
+            continue;
+        }
+        let pushed_ref = PushedRef {
+            old_hash: remote_hash(&remote_ref),
+            remote_ref,
+            source,
+            new_hash,
+            forced: false,
+            status: PushedRefStatus::Ok,
+        };
+        pushed_refs.push((pushed_ref, force));
+    }
+    Ok(pushed_refs)
+}
+
+/// Decides if the remote ref can be updated. It can if it does not exist, if it
+/// is deleted, or if the new value is a descendant of the old one and the ref
+/// is not a tag. Forcing allows any update, as does a lease whose expected
+/// value is the one of the remote ref
+fn check_update(
+    repository: &Repository,
+    pushed_ref: &mut PushedRef,
+    force: bool,
+    expected_hash: Option<Option<String>>,
+) -> Result<()> {
+    let object_database = repository.object_database();
+    if pushed_ref.new_hash.is_some() && pushed_ref.old_hash == pushed_ref.new_hash {
+        pushed_ref.status = PushedRefStatus::UpToDate;
+        return Ok(());
+    }
+
+    let mut force = force;
+    let mut reject = None;
+    if let Some(expected_hash) = expected_hash {
+        if pushed_ref.old_hash != expected_hash {
+            reject = Some(PushedRefStatus::Stale);
+        } else {
+            force = true;
+        }
+    }
+    if let (None, Some(old_hash), Some(new_hash)) =
+        (&reject, &pushed_ref.old_hash, &pushed_ref.new_hash)
+    {
+        reject = if pushed_ref.remote_ref.starts_with("refs/tags/") {
+            Some(PushedRefStatus::AlreadyExists)
+        } else if !object_database.contains(old_hash)? {
+            Some(PushedRefStatus::FetchFirst)
+        } else {
+            let old_commit = object_database.peel(old_hash, "commit");
+            let new_commit = object_database.peel(new_hash, "commit");
+            match (old_commit, new_commit) {
+                (Ok(old_commit), Ok(new_commit)) => {
+                    if is_ancestor(repository, &old_commit, &new_commit)? {
+                        None
+                    } else {
+                        Some(PushedRefStatus::NonFastForward)
+                    }
+                }
+                _ => Some(PushedRefStatus::NeedsForce),
+            }
+        };
+    }
+
+    match reject {
+        None => {}
+        Some(_) if force => pushed_ref.forced = true,
+        Some(status) => pushed_ref.status = status,
+    }
+    Ok(())
+}
+
+/// Sends the commands updating the accepted refs, `<old> <new> <ref>` with
+/// zeros for a missing value, followed by a pack of the objects the remote
+/// lacks: the objects reachable from the new values but not from the remote
+/// refs. Then reads the report of the server (report-status): `unpack ok`,
+/// then `ok <ref>` or `ng <ref> <reason>` for each command
+fn send_pack(
+    repository: &Repository,
+    discovery: &UploadPackDiscovery,
+    remote_refs: &[(String, String)],
+    pushed_refs: &mut [PushedRef],
+) -> Result<()> {
+    let object_database = repository.object_database();
+    let mut request = Vec::new();
+    let mut tips = Vec::new();
+    let commands = pushed_refs
+        .iter()
+        .filter(|pushed_ref| pushed_ref.status == PushedRefStatus::Ok);
+    for (i, pushed_ref) in commands.enumerate() {
+        let mut line = format!(
+            "{} {} {}",
+            pushed_ref.old_hash.as_deref().unwrap_or(NULL_HASH),
+            pushed_ref.new_hash.as_deref().unwrap_or(NULL_HASH),
+            pushed_ref.remote_ref
+        );
+        if i == 0 {
+            line.push_str("\0report-status");
+        }
+        request.extend(UploadPackDiscovery::create_line(&line).into_bytes());
+        tips.extend(pushed_ref.new_hash.clone());
+    }
+    request.extend(b"0000");
+
+    // a pack is expected unless every command is a deletion
+    if !tips.is_empty() {
+        let mut excluded = Vec::new();
+        for (_, hash) in remote_refs {
+            if object_database.contains(hash)? {
+                excluded.push(hash.clone());
+            }
+        }
+        // the pack is thin: deltas can be made against the blobs of the
+        // commits the remote has
+        let object_list = list_objects(repository, &tips, &excluded)?;
+        let mut git_objects = Vec::new();
+        for (hash, path) in &object_list.objects {
+            git_objects.push((object_database.read_object(hash)?, path.clone()));
+        }
+        let mut thin_bases = Vec::new();
+        for (hash, path) in boundary_blobs(repository, &object_list)? {
+            thin_bases.push((object_database.read_object(&hash)?, path));
+        }
+        let options = PackWriteOptions {
+            ofs_delta: discovery.supports("ofs-delta"),
+            ..Default::default()
+        };
+        let pack = GitPack::from_git_objects(&git_objects, &thin_bases, &options)?;
+        request.extend(pack.pack_bytes());
+    }
+
+    let mut res = GitPack::send_receive_pack_request(&discovery.repository_url, request)?;
+    let mut reported = Vec::new();
+    loop {
+        let size = UploadPackDiscovery::get_line_size(&mut res)?;
+        if size == 0 {
+            break;
+        }
+        let line = UploadPackDiscovery::get_line_content(&mut res, size)?;
+        // the commands are reported even if the pack could not be unpacked
+        if line.starts_with("unpack ") {
+            continue;
+        }
+        let (remote_ref, rejection) = match line.split_once(' ') {
+            Some(("ok", remote_ref)) => (remote_ref.to_string(), None),
+            Some(("ng", rest)) => {
+                let (remote_ref, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                (remote_ref.to_string(), Some(reason.to_string()))
+            }
+            _ => return Err(Error::InvalidSmartHttpRes),
+        };
+        let pushed_ref = pushed_refs
+            .iter_mut()
+            .find(|pushed_ref| pushed_ref.remote_ref == remote_ref)
+            .ok_or(Error::InvalidSmartHttpRes)?;
+        if let Some(reason) = rejection {
+            pushed_ref.status = PushedRefStatus::RemoteRejected(reason);
+        }
+        reported.push(remote_ref);
+    }
+
+    for pushed_ref in pushed_refs {
+        if pushed_ref.status == PushedRefStatus::Ok && !reported.contains(&pushed_ref.remote_ref) {
+            pushed_ref.status =
+                PushedRefStatus::RemoteRejected("remote failed to report status".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Moves the remote-tracking branches of the pushed refs to their new value,
+/// as a fetch would
+fn update_tracking_refs(
+    repository: &Repository,
+    remote: &Remote,
+    pushed_refs: &[PushedRef],
+) -> Result<()> {
+    let refs = repository.refs();
+    let identity = committer_signature(&repository.config()?)?.to_string();
+    for pushed_ref in pushed_refs {
+        if !matches!(
+            pushed_ref.status,
+            PushedRefStatus::Ok | PushedRefStatus::UpToDate
+        ) {
+            continue;
+        }
+        let Some(tracking_ref) = remote.tracking_ref(&pushed_ref.remote_ref) else {
+            continue;
+        };
+        let old_hash = refs.resolve(&tracking_ref)?;
+        match &pushed_ref.new_hash {
+            None => {
+                if old_hash.is_some() {
+                    refs.delete(&tracking_ref, None)?;
+                }
+            }
+            Some(new_hash) if old_hash.as_ref() != Some(new_hash) => {
+                refs.update(&tracking_ref, new_hash, None)?;
+                if refs.has_reflog(&tracking_ref) {
+                    refs.append_reflog(
+                        &tracking_ref,
+                        old_hash.as_deref(),
+                        new_hash,
+                        &identity,
+                        "update by push",
+                    )?;
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
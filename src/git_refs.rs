@@ -0,0 +1,602 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::git_object::Signature;
+use crate::{Error, Result};
+
+/// Hash used by git to say that a ref does not exist, for ex as the old value
+/// of a ref that is created
+pub const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// Symbolic refs pointing to symbolic refs are followed up to this depth
+const MAX_SYMBOLIC_DEPTH: usize = 5;
+
+/// Header written by git at the top of packed-refs. fully-peeled means that
+/// every annotated tag is followed by the object it points to
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+
+/// What a ref file contains
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefValue {
+    /// the hash of an object
+    Hash(String),
+    /// the name of another ref, for ex `ref: refs/heads/main`
+    Symbolic(String),
+}
+
+/// A line of packed-refs, with the `^<hash>` line that may follow it
+#[derive(Debug, Clone)]
+pub struct PackedRef {
+    pub name: String,
+    pub hash: String,
+    /// for annotated tags, the object the tag points to once all the tags are
+    /// followed
+    pub peeled: Option<String>,
+}
+
+/// A line of a reflog: a change of the value of a ref
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    /// NULL_HASH when the ref was created
+    pub old_hash: String,
+    pub new_hash: String,
+    /// who changed the ref, and when
+    pub committer: Signature,
+    /// for ex `commit: fix typo` or `checkout: moving from main to dev`
+    pub message: String,
+}
+
+/// Handle on the refs of a repository: loose refs (.git/HEAD, .git/refs/...)
+/// and .git/packed-refs. A loose ref takes precedence over the packed ref of the
+/// same name.
+/// Refs are updated through a <ref>.lock file that is renamed once written, so
+/// that concurrent updates fail instead of overwriting each other
+#[derive(Debug)]
+pub struct GitRefDatabase {
+    git_dir: PathBuf,
+}
+
+/// Lock on a file (for ex .git/refs/heads/main.lock or .git/config.lock). The
+/// lock is released, without changing the file, when it is dropped before
+/// being committed
+pub(crate) struct FileLock {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl FileLock {
+    pub(crate) fn acquire(path: &Path) -> Result<Self> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        if let Some(parent) = lock_path.parent() {
+            create_dir_all(parent)?;
+        }
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::RefLocked(lock_path));
+            }
+            file => file?,
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// Writes the new content of the file and replaces the file with it
+    pub(crate) fn commit(mut self, content: &[u8]) -> Result<()> {
+        let mut file = self.file.take().ok_or_else(|| Error::Unreachable)?;
+        file.write_all(content)?;
+        drop(file);
+        std::fs::rename(&self.lock_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = std::fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+impl GitRefDatabase {
+    /// Creates a handle on the refs of the given git dir
+    pub fn from_git_dir<P: AsRef<Path>>(git_dir: P) -> Self {
+        Self {
+            git_dir: git_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Reads a ref without following it. Loose refs are read first, then
+    /// packed-refs. Returns None if the ref does not exist
+    pub fn read(&self, name: &str) -> Result<Option<RefValue>> {
+        Self::check_name(name)?;
+        let location = self.git_dir.join(name);
+        if location.is_file() {
+            let content = std::fs::read_to_string(location)?;
+            let content = content.trim_end();
+            if let Some(target) = content.strip_prefix("ref: ") {
+                return Ok(Some(RefValue::Symbolic(target.to_string())));
+            }
+            // FETCH_HEAD has one line per fetched ref, the first one is used
+            let hash = content.split_whitespace().next().unwrap_or_default();
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(Error::InvalidHash(content.to_string()));
+            }
+            return Ok(Some(RefValue::Hash(hash.to_string())));
+        }
+        Ok(self
+            .packed_refs()?
+            .into_iter()
+            .find(|packed_ref| packed_ref.name == name)
+            .map(|packed_ref| RefValue::Hash(packed_ref.hash)))
+    }
+
+    /// Reads a ref (for ex HEAD or refs/heads/main) and returns the hash it
+    /// points to. Symbolic refs are followed.
+    /// Returns None if the ref does not exist, or if it is a symbolic ref to
+    /// a ref that does not exist yet (HEAD of a new repository)
+    pub fn resolve(&self, name: &str) -> Result<Option<String>> {
+        let final_name = self.follow_symbolic(name)?;
+        match self.read(&final_name)? {
+            Some(RefValue::Hash(hash)) => Ok(Some(hash)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Follows the symbolic refs and returns the name of the ref that holds
+    /// the hash, for ex HEAD -> refs/heads/main. The ref does not need to exist
+    pub fn follow_symbolic(&self, name: &str) -> Result<String> {
+        let mut name = name.to_string();
+        for _ in 0..=MAX_SYMBOLIC_DEPTH {
+            match self.read(&name)? {
+                Some(RefValue::Symbolic(target)) => name = target,
+                _ => return Ok(name),
+            }
+        }
+        Err(Error::SymbolicRefTooDeep(name))
+    }
+
+    /// Returns the ref a symbolic ref points to, without following it further.
+    /// Returns None if the ref is not symbolic (for ex a detached HEAD)
+    pub fn read_symbolic(&self, name: &str) -> Result<Option<String>> {
+        match self.read(name)? {
+            Some(RefValue::Symbolic(target)) => Ok(Some(target)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the ref HEAD points to (for ex refs/heads/main), even if the
+    /// branch has no commit yet. Returns None if HEAD is detached
+    pub fn head_ref(&self) -> Result<Option<String>> {
+        self.read_symbolic("HEAD")
+    }
+
+    /// Makes the ref point to the hash. Symbolic refs are not followed: the ref
+    /// itself is overwritten.
+    /// If an old value is given, the update only happens if the ref still has
+    /// this value (NULL_HASH: the ref must not exist). Returns the previous hash
+    pub fn update(
+        &self,
+        name: &str,
+        new_hash: &str,
+        expected_old_hash: Option<&str>,
+    ) -> Result<Option<String>> {
+        self.check_name_conflicts(name)?;
+        let lock = FileLock::acquire(&self.git_dir.join(name))?;
+        let old_hash = self.check_old_value(name, expected_old_hash)?;
+        lock.commit(format!("{}\n", new_hash).as_bytes())?;
+        Ok(old_hash)
+    }
+
+    /// Makes the ref a symbolic ref to the target, for ex HEAD -> refs/heads/main
+    pub fn write_symbolic(&self, name: &str, target: &str) -> Result<()> {
+        self.check_name_conflicts(name)?;
+        let lock = FileLock::acquire(&self.git_dir.join(name))?;
+        lock.commit(format!("ref: {}\n", target).as_bytes())
+    }
+
+    /// Deletes the ref (loose and packed) and its reflog. Symbolic refs are not
+    /// followed. As for updates, an old value may be checked first.
+    /// Returns the hash the ref pointed to, None for a symbolic ref
+    pub fn delete(&self, name: &str, expected_old_hash: Option<&str>) -> Result<Option<String>> {
+        Self::check_name(name)?;
+        let old_value = self
+            .read(name)?
+            .ok_or_else(|| Error::RefNotFound(name.to_string()))?;
+        let location = self.git_dir.join(name);
+        let lock = FileLock::acquire(&location)?;
+        let old_hash = self.check_old_value(name, expected_old_hash)?;
+
+        let packed_refs = self.packed_refs()?;
+        if packed_refs.iter().any(|packed_ref| packed_ref.name == name) {
+            let packed_refs = packed_refs
+                .into_iter()
+                .filter(|packed_ref| packed_ref.name != name)
+                .collect::<Vec<_>>();
+            self.write_packed_refs(&packed_refs)?;
+        }
+        if location.is_file() {
+            std::fs::remove_file(&location)?;
+        }
+        // the lock file has to be removed for the directories to be empty
+        drop(lock);
+        self.remove_empty_parents(name, &self.git_dir);
+
+        let reflog = self.git_dir.join("logs").join(name);
+        if reflog.is_file() {
+            std::fs::remove_file(reflog)?;
+            self.remove_empty_parents(name, &self.git_dir.join("logs"));
+        }
+        match old_value {
+            RefValue::Hash(_) => Ok(old_hash),
+            RefValue::Symbolic(_) => Ok(None),
+        }
+    }
+
+    /// Renames the ref, replacing the new ref if it exists. The reflog moves
+    /// with the ref, and the rename is logged in it with the message.
+    /// Returns the hash of the ref
+    pub fn rename(
+        &self,
+        name: &str,
+        new_name: &str,
+        identity: &str,
+        message: &str,
+    ) -> Result<String> {
+        let hash = match self.read(name)? {
+            Some(RefValue::Hash(hash)) => hash,
+            Some(RefValue::Symbolic(_)) => return Err(Error::InvalidRefName(name.to_string())),
+            None => return Err(Error::RefNotFound(name.to_string())),
+        };
+        let logs = self.git_dir.join("logs");
+        let reflog = logs.join(name);
+        // the reflog is kept aside while the old ref and its reflog are deleted
+        let temporary_reflog = logs.join("refs").join(".tmp-renamed-log");
+        let has_reflog = reflog.is_file();
+        if has_reflog {
+            create_dir_all(logs.join("refs"))?;
+            std::fs::rename(&reflog, &temporary_reflog)?;
+            self.remove_empty_parents(name, &logs);
+        }
+        self.delete(name, Some(&hash))?;
+        if self.read(new_name)?.is_some() {
+            self.delete(new_name, None)?;
+        }
+
+        let (final_name, result) = match self.update(new_name, &hash, Some(NULL_HASH)) {
+            Ok(_) => (new_name, Ok(())),
+            // restores the old ref, for ex when the new name conflicts with another ref
+            Err(error) => {
+                self.update(name, &hash, Some(NULL_HASH))?;
+                (name, Err(error))
+            }
+        };
+        if has_reflog {
+            let final_reflog = logs.join(final_name);
+            if let Some(parent) = final_reflog.parent() {
+                create_dir_all(parent)?;
+            }
+            std::fs::rename(&temporary_reflog, final_reflog)?;
+        }
+        result?;
+        if self.has_reflog(new_name) {
+            self.append_reflog(new_name, Some(&hash), &hash, identity, message)?;
+        }
+        Ok(hash)
+    }
+
+    /// Returns the current hash of the ref, after checking it is the expected one
+    fn check_old_value(
+        &self,
+        name: &str,
+        expected_old_hash: Option<&str>,
+    ) -> Result<Option<String>> {
+        let old_hash = self.resolve(name)?;
+        if let Some(expected) = expected_old_hash {
+            if old_hash.as_deref().unwrap_or(NULL_HASH) != expected {
+                return Err(Error::UnexpectedRefValue {
+                    name: name.to_string(),
+                    expected: expected.to_string(),
+                    got: old_hash.unwrap_or_else(|| NULL_HASH.to_string()),
+                });
+            }
+        }
+        Ok(old_hash)
+    }
+
+    /// A ref can not be created if its name is a directory of another ref (for
+    /// ex refs/heads/a when refs/heads/a/b exists) or the opposite
+    fn check_name_conflicts(&self, name: &str) -> Result<()> {
+        if !Self::is_valid_name(name) && name != "HEAD" {
+            return Err(Error::InvalidRefName(name.to_string()));
+        }
+        let is_conflict = |other: &str| {
+            other.starts_with(&format!("{}/", name)) || name.starts_with(&format!("{}/", other))
+        };
+        let mut ancestor = Path::new(name).parent();
+        while let Some(dir) = ancestor.filter(|dir| !dir.as_os_str().is_empty()) {
+            if self.git_dir.join(dir).is_file() {
+                return Err(Error::RefNameConflict(name.to_string()));
+            }
+            ancestor = dir.parent();
+        }
+        let location = self.git_dir.join(name);
+        let has_loose_children = location.is_dir() && !self.loose_ref_names(name)?.is_empty();
+        let has_packed_conflict = self
+            .packed_refs()?
+            .iter()
+            .any(|packed_ref| is_conflict(&packed_ref.name));
+        if has_loose_children || has_packed_conflict {
+            return Err(Error::RefNameConflict(name.to_string()));
+        }
+        // an empty directory left by a deleted ref is not a conflict
+        if location.is_dir() {
+            std::fs::remove_dir_all(location)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the directories of the ref that became empty, but never the
+    /// top level ones (refs/heads, refs/tags...)
+    fn remove_empty_parents(&self, name: &str, base_dir: &Path) {
+        let mut components = name.split('/').collect::<Vec<_>>();
+        components.pop();
+        while components.len() > 2 {
+            if std::fs::remove_dir(base_dir.join(components.join("/"))).is_err() {
+                break;
+            }
+            components.pop();
+        }
+    }
+
+    /// Reads .git/packed-refs. Returns an empty list if there is no such file
+    pub fn packed_refs(&self) -> Result<Vec<PackedRef>> {
+        let location = self.git_dir.join("packed-refs");
+        if !location.is_file() {
+            return Ok(Vec::new());
+        }
+        let content = std::fs::read_to_string(location)?;
+        let mut packed_refs: Vec<PackedRef> = Vec::new();
+        for line in content.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            // the peeled value of the tag on the previous line
+            if let Some(peeled) = line.strip_prefix('^') {
+                let packed_ref = packed_refs
+                    .last_mut()
+                    .ok_or_else(|| Error::InvalidPackedRefs)?;
+                packed_ref.peeled = Some(peeled.to_string());
+                continue;
+            }
+            let (hash, name) = line
+                .split_once(' ')
+                .ok_or_else(|| Error::InvalidPackedRefs)?;
+            if hash.len() != 40 {
+                return Err(Error::InvalidPackedRefs);
+            }
+            packed_refs.push(PackedRef {
+                name: name.to_string(),
+                hash: hash.to_string(),
+                peeled: None,
+            });
+        }
+        Ok(packed_refs)
+    }
+
+    /// Replaces .git/packed-refs. The refs are written sorted by name
+    pub fn write_packed_refs(&self, packed_refs: &[PackedRef]) -> Result<()> {
+        let lock = FileLock::acquire(&self.git_dir.join("packed-refs"))?;
+        let mut packed_refs = packed_refs.iter().collect::<Vec<_>>();
+        packed_refs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut content = PACKED_REFS_HEADER.to_string();
+        for packed_ref in packed_refs {
+            content.push_str(&format!("{} {}\n", packed_ref.hash, packed_ref.name));
+            if let Some(peeled) = &packed_ref.peeled {
+                content.push_str(&format!("^{}\n", peeled));
+            }
+        }
+        lock.commit(content.as_bytes())
+    }
+
+    /// Lists the refs whose name starts with the prefix (for ex refs/tags/),
+    /// loose and packed, with the hash they point to, sorted by name.
+    /// Symbolic refs are resolved, and skipped if their target does not exist
+    pub fn list(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut refs = BTreeMap::new();
+        for packed_ref in self.packed_refs()? {
+            if packed_ref.name.starts_with(prefix) {
+                refs.insert(packed_ref.name, packed_ref.hash);
+            }
+        }
+        // the prefix may end in the middle of a name (refs/heads/ma)
+        let dir = match prefix.rsplit_once('/') {
+            Some((dir, _)) => dir,
+            None => "",
+        };
+        for name in self.loose_ref_names(dir)? {
+            // files that are not valid refs (for ex leftover locks) are ignored
+            if !name.starts_with(prefix) || !Self::is_valid_name(&name) {
+                continue;
+            }
+            match self.resolve(&name)? {
+                Some(hash) => refs.insert(name, hash),
+                None => refs.remove(&name),
+            };
+        }
+        Ok(refs.into_iter().collect())
+    }
+
+    /// Names of the loose refs in the directory and its subdirectories
+    fn loose_ref_names(&self, dir: &str) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let location = self.git_dir.join(dir);
+        if !location.is_dir() {
+            return Ok(names);
+        }
+        for entry in read_dir(location)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let name = match dir.is_empty() {
+                true => file_name,
+                false => format!("{}/{}", dir, file_name),
+            };
+            if entry.file_type()?.is_dir() {
+                names.extend(self.loose_ref_names(&name)?);
+            } else {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Finds the full name of a ref given on the command line, trying in order:
+    /// `<name>` (only for HEAD-like names such as ORIG_HEAD, or names starting
+    /// with refs/), `refs/<name>`, `refs/tags/<name>`, `refs/heads/<name>`,
+    /// `refs/remotes/<name>` and `refs/remotes/<name>/HEAD`.
+    /// Returns None if there is no such ref
+    pub fn expand_name(&self, name: &str) -> Result<Option<String>> {
+        let is_pseudo_ref = name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+        let mut candidates = Vec::new();
+        if is_pseudo_ref || name.starts_with("refs/") {
+            candidates.push(name.to_string());
+        }
+        for rule in [
+            "refs/{}",
+            "refs/tags/{}",
+            "refs/heads/{}",
+            "refs/remotes/{}",
+            "refs/remotes/{}/HEAD",
+        ] {
+            candidates.push(rule.replace("{}", name));
+        }
+        for candidate in candidates {
+            if Self::is_valid_name(&candidate) && self.resolve(&candidate)?.is_some() {
+                return Ok(Some(candidate));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the reflog of the ref, oldest entry first. Returns an empty list
+    /// if the ref has no reflog
+    pub fn read_reflog(&self, name: &str) -> Result<Vec<ReflogEntry>> {
+        let location = self.git_dir.join("logs").join(name);
+        if !location.is_file() {
+            return Ok(Vec::new());
+        }
+        let content = std::fs::read(location)?;
+        let mut entries = Vec::new();
+        for line in content
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+        {
+            let (header, message) = match line.iter().position(|&b| b == b'\t') {
+                Some(tab) => (&line[..tab], &line[tab + 1..]),
+                None => (line, &b""[..]),
+            };
+            if header.len() < 83 || header[40] != b' ' || header[81] != b' ' {
+                return Err(Error::InvalidReflog(name.to_string()));
+            }
+            entries.push(ReflogEntry {
+                old_hash: String::from_utf8_lossy(&header[..40]).to_string(),
+                new_hash: String::from_utf8_lossy(&header[41..81]).to_string(),
+                committer: Signature::from_bytes(&header[82..])
+                    .map_err(|_| Error::InvalidReflog(name.to_string()))?,
+                message: String::from_utf8_lossy(message).to_string(),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Appends an entry to the reflog of the ref (.git/logs/<ref>).
+    /// The identity is `name <email> timestamp timezone`, the old hash is all
+    /// zeros when the ref is created
+    pub fn append_reflog(
+        &self,
+        name: &str,
+        old_hash: Option<&str>,
+        new_hash: &str,
+        identity: &str,
+        message: &str,
+    ) -> Result<()> {
+        let location = self.git_dir.join("logs").join(name);
+        if let Some(parent) = location.parent() {
+            create_dir_all(parent)?;
+        }
+        let old_hash = old_hash.unwrap_or(NULL_HASH);
+        let mut reflog = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(location)?;
+        writeln!(
+            reflog,
+            "{} {} {}\t{}",
+            old_hash, new_hash, identity, message
+        )?;
+        Ok(())
+    }
+
+    /// Checks if updates of the ref are logged by default (core.logAllRefUpdates):
+    /// HEAD, branches, remote-tracking branches, notes, and refs that already
+    /// have a reflog
+    pub fn has_reflog(&self, name: &str) -> bool {
+        name == "HEAD"
+            || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+            || self.git_dir.join("logs").join(name).is_file()
+    }
+
+    /// Short name of a ref, as displayed to the user: refs/heads/main -> main,
+    /// refs/tags/v1 -> v1, refs/remotes/origin/main -> origin/main
+    pub fn shorten_name(name: &str) -> &str {
+        ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+            .iter()
+            .find_map(|prefix| name.strip_prefix(prefix))
+            .unwrap_or(name)
+    }
+
+    /// Refuses the names that are neither valid ref names nor pseudo-refs
+    /// (HEAD, FETCH_HEAD...), so that they are never used as paths
+    fn check_name(name: &str) -> Result<()> {
+        let is_pseudo_ref =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+        if !is_pseudo_ref && !Self::is_valid_name(name) {
+            return Err(Error::InvalidRefName(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Checks the rules of git check-ref-format: no component starting with a
+    /// dot or ending with .lock, no .., no @{, no control characters nor
+    /// space ~ ^ : ? * [ \, and no trailing / or .
+    pub fn is_valid_name(name: &str) -> bool {
+        let has_invalid_char = name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c));
+        let has_invalid_component = name.split('/').any(|component| {
+            component.is_empty() || component.starts_with('.') || component.ends_with(".lock")
+        });
+        !name.is_empty()
+            && name != "@"
+            && !has_invalid_char
+            && !has_invalid_component
+            && !name.contains("..")
+            && !name.contains("@{")
+            && !name.ends_with('.')
+    }
+}
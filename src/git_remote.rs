@@ -0,0 +1,206 @@
+use crate::{git_refs::GitRefDatabase, git_repository::Repository, Error, Result};
+
+/// Maps the refs of a remote to local refs, for ex
+/// `+refs/heads/*:refs/remotes/origin/*`. The source and the destination
+/// contain either no `*` or one each, matching any part of the ref name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    /// `+`: the destination is updated even when it is not a fast-forward
+    pub force: bool,
+    pub source: String,
+    /// None when the refspec has no `:dst` part: the ref is only fetched
+    pub destination: Option<String>,
+}
+
+impl Refspec {
+    /// Parses `[+]<src>[:<dst>]`. Patterns must have a `*` on both sides
+    pub fn parse(refspec: &str) -> Result<Self> {
+        let invalid = || Error::InvalidRefspec(refspec.to_string());
+        let (force, rest) = match refspec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, refspec),
+        };
+        let (source, destination) = match rest.split_once(':') {
+            Some((source, "")) => (source, None),
+            Some((source, destination)) => (source, Some(destination)),
+            None => (rest, None),
+        };
+        let nb_stars = |side: &str| side.matches('*').count();
+        if source.is_empty()
+            || nb_stars(source) > 1
+            || destination.is_some_and(|destination| nb_stars(destination) != nb_stars(source))
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            force,
+            source: source.to_string(),
+            destination: destination.map(str::to_string),
+        })
+    }
+
+    /// Parses a push refspec: as parse, but `:<dst>` (no source) deletes the
+    /// remote ref
+    pub fn parse_push(refspec: &str) -> Result<Self> {
+        let force = refspec.starts_with('+');
+        match refspec.trim_start_matches('+').strip_prefix(':') {
+            Some(destination) if !destination.is_empty() && !destination.contains('*') => {
+                Ok(Self {
+                    force,
+                    source: String::new(),
+                    destination: Some(destination.to_string()),
+                })
+            }
+            _ => Self::parse(refspec),
+        }
+    }
+
+    /// Push refspec deleting the remote ref
+    pub fn is_deletion(&self) -> bool {
+        self.source.is_empty()
+    }
+
+    pub fn is_pattern(&self) -> bool {
+        self.source.contains('*')
+    }
+
+    /// Local ref the remote ref is stored in, for ex refs/heads/main ->
+    /// refs/remotes/origin/main. None if the refspec does not match the ref or
+    /// has no destination
+    pub fn map_source(&self, name: &str) -> Option<String> {
+        Self::map(&self.source, self.destination.as_deref()?, name)
+    }
+
+    /// Whether the refspec fetches the remote ref
+    pub fn matches_source(&self, name: &str) -> bool {
+        Self::map(&self.source, &self.source, name).is_some()
+    }
+
+    /// Remote ref that is stored in the local ref, the reverse of map_source
+    pub fn map_destination(&self, name: &str) -> Option<String> {
+        Self::map(self.destination.as_deref()?, &self.source, name)
+    }
+
+    /// Replaces the `*` of the target by what the `*` of the pattern matched
+    /// in the name, or returns the target if the pattern is the name itself
+    fn map(pattern: &str, target: &str, name: &str) -> Option<String> {
+        match pattern.split_once('*') {
+            Some((prefix, suffix)) => {
+                let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                Some(target.replacen('*', matched, 1))
+            }
+            None if pattern == name => Some(target.to_string()),
+            None => None,
+        }
+    }
+}
+
+/// A remote repository, configured in the remote.<name> section
+#[derive(Debug)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    /// remote.<name>.fetch: the remote refs fetched when no refspec is given
+    pub fetch_refspecs: Vec<Refspec>,
+    /// remote.<name>.push: the refs pushed when no refspec is given
+    pub push_refspecs: Vec<Refspec>,
+}
+
+impl Remote {
+    /// Reads remote.<name>.url, .fetch and .push. As with git, a name
+    /// that is not configured but looks like a url is used as the url of a
+    /// remote without refspecs
+    pub fn load(repository: &Repository, name: &str) -> Result<Self> {
+        let config = repository.config()?;
+        let Some(url) = config.get(&format!("remote.{}.url", name)) else {
+            if name.contains("://") {
+                return Ok(Self {
+                    name: name.to_string(),
+                    url: name.to_string(),
+                    fetch_refspecs: Vec::new(),
+                    push_refspecs: Vec::new(),
+                });
+            }
+            return Err(Error::RemoteNotFound(name.to_string()));
+        };
+        let fetch_refspecs = config
+            .get_all(&format!("remote.{}.fetch", name))
+            .into_iter()
+            .map(Refspec::parse)
+            .collect::<Result<Vec<_>>>()?;
+        let push_refspecs = config
+            .get_all(&format!("remote.{}.push", name))
+            .into_iter()
+            .map(Refspec::parse_push)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            name: name.to_string(),
+            url: url.to_string(),
+            fetch_refspecs,
+            push_refspecs,
+        })
+    }
+
+    /// Remote of the current branch (branch.<name>.remote), or origin
+    pub fn default_name(repository: &Repository) -> Result<String> {
+        let config = repository.config()?;
+        let remote = repository
+            .refs()
+            .head_ref()?
+            .as_deref()
+            .and_then(|head_ref| head_ref.strip_prefix("refs/heads/"))
+            .and_then(|branch| config.get(&format!("branch.{}.remote", branch)))
+            .filter(|remote| *remote != ".")
+            .unwrap_or("origin");
+        Ok(remote.to_string())
+    }
+
+    /// Local ref that tracks the remote ref, as mapped by the fetch refspecs,
+    /// for ex refs/remotes/origin/main for refs/heads/main
+    pub fn tracking_ref(&self, remote_ref: &str) -> Option<String> {
+        self.fetch_refspecs
+            .iter()
+            .find_map(|refspec| refspec.map_source(remote_ref))
+    }
+
+    /// Url as displayed by fetch and written in FETCH_HEAD: without trailing
+    /// slashes nor .git suffix
+    pub fn display_url(&self) -> &str {
+        let url = self.url.trim_end_matches('/');
+        url.strip_suffix(".git").unwrap_or(url)
+    }
+
+    /// Whether the ref name can be stored by a refspec
+    pub fn is_valid_destination(name: &str) -> bool {
+        name.starts_with("refs/") && GitRefDatabase::is_valid_name(name)
+    }
+}
+
+/// Rules giving the full names a short ref name may designate, by priority: the
+/// same as for revisions (main is refs/heads/main)
+const REF_NAME_RULES: [&str; 6] = [
+    "{}",
+    "refs/{}",
+    "refs/tags/{}",
+    "refs/heads/{}",
+    "refs/remotes/{}",
+    "refs/remotes/{}/HEAD",
+];
+
+/// Finds the ref a short name designates among refs given as (name, hash)
+pub fn find_ref<'a>(
+    refs: &'a [(String, String)],
+    short_name: &str,
+) -> Option<&'a (String, String)> {
+    REF_NAME_RULES.iter().find_map(|rule| {
+        let candidate = rule.replace("{}", short_name);
+        refs.iter().find(|(name, _)| *name == candidate)
+    })
+}
+
+/// Whether the short name designates the full ref name
+pub fn ref_name_matches(short_name: &str, name: &str) -> bool {
+    REF_NAME_RULES
+        .iter()
+        .any(|rule| rule.replace("{}", short_name) == name)
+}
@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+
+use crate::git_config::GitConfig;
+use crate::git_object::GitObject;
+use crate::git_object_database::GitObjectDatabase;
+use crate::git_refs::GitRefDatabase;
+use crate::git_revision::resolve_revision;
+use crate::{Error, Result};
+
+/// A git repository: the .git folder and the working tree it belongs to.
+/// Gives access to the object database and to the refs.
+#[derive(Debug)]
+pub struct Repository {
+    /// the .git folder, or the repository itself for bare repositories
+    git_dir: PathBuf,
+    /// None for bare repositories
+    work_tree: Option<PathBuf>,
+    object_database: GitObjectDatabase,
+    ref_database: GitRefDatabase,
+}
+
+impl Repository {
+    /// Creates the repository from its git dir and work tree
+    pub fn new<P: AsRef<Path>>(git_dir: P, work_tree: Option<PathBuf>) -> Self {
+        let git_dir = git_dir.as_ref().to_path_buf();
+        let object_database = GitObjectDatabase::from_objects_dir(git_dir.join("objects"));
+        let ref_database = GitRefDatabase::from_git_dir(&git_dir);
+        Self {
+            git_dir,
+            work_tree,
+            object_database,
+            ref_database,
+        }
+    }
+
+    /// Finds the repository the current directory belongs to, like git does:
+    /// - if GIT_DIR is set, it is used as the git dir and the work tree is
+    ///   GIT_WORK_TREE, or the current directory
+    /// - otherwise, walks up from the current directory until a .git folder
+    ///   (or a .git file containing `gitdir: <path>`) is found.
+    ///   GIT_WORK_TREE still overrides the work tree
+    pub fn discover() -> Result<Self> {
+        let current_dir = std::env::current_dir()?;
+        let env_work_tree = std::env::var_os("GIT_WORK_TREE").map(|p| current_dir.join(p));
+
+        if let Some(git_dir) = std::env::var_os("GIT_DIR") {
+            let work_tree = env_work_tree.unwrap_or_else(|| current_dir.clone());
+            return Ok(Self::new(current_dir.join(git_dir), Some(work_tree)));
+        }
+
+        for directory in current_dir.ancestors() {
+            if let Some((git_dir, work_tree)) = Self::find_git_dir(directory)? {
+                return Ok(Self::new(git_dir, env_work_tree.or(work_tree)));
+            }
+        }
+        Err(Error::NotAGitRepository(current_dir))
+    }
+
+    /// Opens the repository at the given directory, without looking at the
+    /// parent directories
+    pub fn open<P: AsRef<Path> + ?Sized>(directory: &P) -> Result<Self> {
+        let directory = directory.as_ref();
+        let (git_dir, work_tree) = Self::find_git_dir(directory)?
+            .ok_or_else(|| Error::NotAGitRepository(directory.to_path_buf()))?;
+        Ok(Self::new(git_dir, work_tree))
+    }
+
+    /// A git dir contains at least the HEAD file and the objects folder
+    fn is_git_dir(path: &Path) -> bool {
+        path.join("HEAD").is_file() && path.join("objects").is_dir()
+    }
+
+    /// Returns the git dir and the work tree if the directory is the root of a
+    /// work tree or a bare repository
+    fn find_git_dir(directory: &Path) -> Result<Option<(PathBuf, Option<PathBuf>)>> {
+        let dot_git = directory.join(".git");
+
+        if dot_git.is_dir() && Self::is_git_dir(&dot_git) {
+            return Ok(Some((dot_git, Some(directory.to_path_buf()))));
+        }
+
+        // worktrees and submodules use a .git file pointing to the real git dir
+        if dot_git.is_file() {
+            let content = std::fs::read_to_string(&dot_git)?;
+            let git_dir = content
+                .trim_end()
+                .strip_prefix("gitdir: ")
+                .ok_or_else(|| Error::InvalidGitFile(dot_git.clone()))?;
+            // relative paths are relative to the directory containing the .git file
+            let git_dir = directory.join(git_dir);
+            if !Self::is_git_dir(&git_dir) {
+                return Err(Error::InvalidGitFile(dot_git));
+            }
+            return Ok(Some((git_dir, Some(directory.to_path_buf()))));
+        }
+
+        if Self::is_git_dir(directory) {
+            return Ok(Some((directory.to_path_buf(), None)));
+        }
+        Ok(None)
+    }
+
+    /// the .git folder
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// The root of the working tree. Fails for bare repositories
+    pub fn work_tree(&self) -> Result<&Path> {
+        self.work_tree
+            .as_deref()
+            .ok_or_else(|| Error::BareRepository(self.git_dir.clone()))
+    }
+
+    /// Converts a path given on the command line (relative to the current
+    /// directory) to a path relative to the root of the work tree, with / as
+    /// separator, as stored in the index. The root itself is an empty string
+    pub fn path_in_work_tree<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let absolute_path = Self::normalize(&std::env::current_dir()?.join(path));
+        let work_tree = Self::normalize(self.work_tree()?);
+        let relative_path = absolute_path
+            .strip_prefix(&work_tree)
+            .map_err(|_| Error::PathOutsideRepository(absolute_path.clone()))?;
+        let components = relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        Ok(components.join("/"))
+    }
+
+    /// Removes the . and .. components without following symbolic links
+    fn normalize(path: &Path) -> PathBuf {
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    normalized.pop();
+                }
+                component => normalized.push(component),
+            }
+        }
+        normalized
+    }
+
+    /// Configuration of the repository, including the system and global config
+    pub fn config(&self) -> Result<GitConfig> {
+        GitConfig::load(Some(self))
+    }
+
+    /// Objects of the repository (.git/objects)
+    pub fn object_database(&self) -> &GitObjectDatabase {
+        &self.object_database
+    }
+
+    /// Refs of the repository (.git/HEAD, .git/refs and .git/packed-refs)
+    pub fn refs(&self) -> &GitRefDatabase {
+        &self.ref_database
+    }
+
+    /// Finds an object from a revision: a full or abbreviated hash, a branch,
+    /// a tag, HEAD, or any expression described in git_revision
+    /// Note: for trees, the children are not loaded. Use TreeChild::load
+    pub fn find_object(&self, revision: &str) -> Result<GitObject> {
+        let hash = resolve_revision(self, revision)?;
+        self.object_database.read_object(&hash)
+    }
+}
@@ -0,0 +1,14 @@
+use crate::git_repository::Repository;
+use crate::git_rev_walk::{rev_walk, RevWalkOptions};
+use crate::Result;
+
+/// Hashes of the commits reachable from the revisions, in the order of the
+/// walk (see rev_walk for the syntax of the revisions and ranges)
+pub fn git_rev_list(
+    repository: &Repository,
+    revisions: &[String],
+    options: &RevWalkOptions,
+) -> Result<Vec<String>> {
+    let commits = rev_walk(repository, revisions, options)?;
+    Ok(commits.into_iter().map(|(hash, _)| hash).collect())
+}
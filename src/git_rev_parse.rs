@@ -0,0 +1,113 @@
+use crate::git_refs::GitRefDatabase;
+use crate::git_repository::Repository;
+use crate::git_rev_walk::merge_bases;
+use crate::git_revision::{resolve_ref_name, resolve_revision};
+use crate::{Error, Result};
+
+/// How rev-parse prints the revisions
+#[derive(Debug, Default)]
+pub struct RevParseOptions {
+    /// exactly one revision is expected, and it must be valid
+    pub verify: bool,
+    /// abbreviates the hashes to at least this length, keeping them unique
+    pub short: Option<usize>,
+    /// prints the full name of the ref (refs/heads/main) instead of the hash
+    pub symbolic_full_name: bool,
+    /// prints the short name of the ref (main) instead of the hash
+    pub abbrev_ref: bool,
+}
+
+/// Resolves each revision and returns one line per revision.
+/// `^rev` is printed as ^<hash>, and a range `a..b` as <hash of b> then
+/// ^<hash of a> (a missing end of the range means HEAD). `a...b` is printed as
+/// <hash of b>, <hash of a> then ^<hash> for each of their merge bases.
+/// With symbolic_full_name or abbrev_ref, revisions that are not refs print
+/// nothing, except HEAD when it is detached, that abbrev_ref prints as HEAD
+pub fn git_rev_parse(
+    repository: &Repository,
+    revisions: &[String],
+    options: &RevParseOptions,
+) -> Result<Vec<String>> {
+    if options.verify && revisions.len() != 1 {
+        return Err(Error::InvalidRevision(revisions.join(" ")));
+    }
+
+    let mut lines = Vec::new();
+    for revision in revisions {
+        let is_path = revision.contains(':');
+        if let Some((start, end)) = revision.split_once("...").filter(|_| !is_path) {
+            lines.extend(format_symmetric_difference(
+                repository, start, end, options,
+            )?);
+            continue;
+        }
+        let (excluded, included) = match revision.split_once("..") {
+            _ if options.verify || is_path => (None, revision.as_str()),
+            Some((start, end)) => {
+                let start = if start.is_empty() { "HEAD" } else { start };
+                let end = if end.is_empty() { "HEAD" } else { end };
+                (Some(start), end)
+            }
+            None => match revision.strip_prefix('^') {
+                Some(excluded) => (Some(excluded), ""),
+                None => (None, revision.as_str()),
+            },
+        };
+
+        if !included.is_empty() {
+            lines.extend(format_revision(repository, included, options)?);
+        }
+        if let Some(excluded) = excluded {
+            let line = format_revision(repository, excluded, options)?;
+            lines.extend(line.map(|line| format!("^{}", line)));
+        }
+    }
+    Ok(lines)
+}
+
+/// `a...b`: both ends, then their merge bases as excluded revisions
+fn format_symmetric_difference(
+    repository: &Repository,
+    start: &str,
+    end: &str,
+    options: &RevParseOptions,
+) -> Result<Vec<String>> {
+    let start = if start.is_empty() { "HEAD" } else { start };
+    let end = if end.is_empty() { "HEAD" } else { end };
+    let object_database = repository.object_database();
+    let start_commit = object_database.peel(&resolve_revision(repository, start)?, "commit")?;
+    let end_commit = object_database.peel(&resolve_revision(repository, end)?, "commit")?;
+
+    let mut lines = Vec::new();
+    lines.extend(format_revision(repository, end, options)?);
+    lines.extend(format_revision(repository, start, options)?);
+    for merge_base in merge_bases(repository, &start_commit, &[end_commit])? {
+        let line = format_revision(repository, &merge_base, options)?;
+        lines.extend(line.map(|line| format!("^{}", line)));
+    }
+    Ok(lines)
+}
+
+fn format_revision(
+    repository: &Repository,
+    revision: &str,
+    options: &RevParseOptions,
+) -> Result<Option<String>> {
+    let hash = resolve_revision(repository, revision)?;
+
+    if options.symbolic_full_name || options.abbrev_ref {
+        let ref_name = resolve_ref_name(repository, revision)?;
+        return Ok(match (ref_name, options.abbrev_ref) {
+            (Some(ref_name), true) => Some(GitRefDatabase::shorten_name(&ref_name).to_string()),
+            (Some(ref_name), false) => Some(ref_name),
+            (None, true) if matches!(revision, "HEAD" | "@") => Some("HEAD".to_string()),
+            (None, _) => None,
+        });
+    }
+    match options.short {
+        Some(min_len) => Ok(Some(
+            repository.object_database().abbreviate(&hash, min_len)?,
+        )),
+        None => Ok(Some(hash)),
+    }
+}
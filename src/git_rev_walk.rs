@@ -0,0 +1,763 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::git_object::{CommitObjects, GitObjectContent};
+use crate::git_object_database::GitObjectDatabase;
+use crate::git_repository::Repository;
+use crate::git_revision::resolve_revision;
+use crate::{Error, Result};
+
+/// Order in which the commits are listed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RevWalkOrder {
+    /// most recent commit date first, as the commits are found. A parent can
+    /// be listed before one of its children if their dates are skewed
+    #[default]
+    Walk,
+    /// most recent commit date first, but never a parent before its children
+    /// (--date-order)
+    Date,
+    /// never a parent before its children, and the commits of a branch are
+    /// kept together instead of being interleaved by date (--topo-order)
+    Topo,
+}
+
+/// Which commits are listed, and in which order
+#[derive(Debug, Default)]
+pub struct RevWalkOptions {
+    /// only follows the first parent of merge commits
+    pub first_parent: bool,
+    /// also starts from HEAD and every ref
+    pub all: bool,
+    /// lists the oldest commits first. Applied after max_count
+    pub reverse: bool,
+    /// lists at most this number of commits
+    pub max_count: Option<usize>,
+    pub order: RevWalkOrder,
+    /// only commits whose author (`Name <email>`) contains one of these
+    pub authors: Vec<String>,
+    /// only commits whose message contains one of these
+    pub greps: Vec<String>,
+}
+
+/// Lists the commits reachable from the revisions, most recent first.
+/// Revisions can be:
+/// - `rev`: the commits reachable from rev
+/// - `^rev`: excludes the commits reachable from rev
+/// - `a..b`: the commits reachable from b but not from a (`^a b`). A missing
+///   end means HEAD
+/// - `a...b`: the commits reachable from either a or b, but not from both
+///   (excludes their merge bases)
+pub fn rev_walk(
+    repository: &Repository,
+    revisions: &[String],
+    options: &RevWalkOptions,
+) -> Result<Vec<(String, CommitObjects)>> {
+    let mut walker = RevWalker::new(repository.object_database());
+    let (mut included, excluded) = resolve_ranges(repository, &mut walker, revisions)?;
+    if options.all {
+        included.extend(all_ref_commits(repository)?);
+    }
+    for hash in &excluded {
+        walker.push(hash, UNINTERESTING)?;
+    }
+    for hash in &included {
+        walker.push(hash, 0)?;
+    }
+
+    // without excluded commits, nothing walked can be removed later: the walk
+    // can stop as soon as enough commits are found
+    let can_stop_early = excluded.is_empty() && options.order == RevWalkOrder::Walk;
+    let mut walked = Vec::new();
+    let mut nb_matching = 0;
+    while walker.has_interesting() {
+        let Some(hash) = walker.pop() else { break };
+        let mut parents = walker.commit(&hash)?.parents_sha.clone();
+        // excluded commits exclude all their ancestors, even with first_parent
+        if walker.flags[&hash] & UNINTERESTING != 0 {
+            for parent in &parents {
+                walker.mark_uninteresting(parent)?;
+            }
+            continue;
+        }
+        if options.first_parent {
+            parents.truncate(1);
+        }
+        for parent in &parents {
+            walker.push(parent, 0)?;
+        }
+        if can_stop_early && matches_filters(walker.commit(&hash)?, options) {
+            nb_matching += 1;
+        }
+        walked.push(hash);
+        if can_stop_early && Some(nb_matching) == options.max_count {
+            break;
+        }
+    }
+    // with skewed dates, excluded commits can still be queued while some of
+    // their ancestors were walked. Those that may reach a walked commit (of a
+    // lower generation) are walked too
+    if !excluded.is_empty() {
+        let mut min_generation = u64::MAX;
+        for hash in &walked {
+            if walker.flags[hash] & UNINTERESTING == 0 {
+                min_generation = min_generation.min(walker.generation(hash)?);
+            }
+        }
+        while let Some(hash) = walker.pop() {
+            if walker.generation(&hash)? < min_generation {
+                continue;
+            }
+            for parent in walker.commit(&hash)?.parents_sha.clone() {
+                walker.mark_uninteresting(&parent)?;
+            }
+        }
+    }
+    // commits can be found to be reachable from an excluded commit after they
+    // were walked
+    walked.retain(|hash| walker.flags[hash] & UNINTERESTING == 0);
+
+    let walked = match options.order {
+        RevWalkOrder::Walk => walked,
+        RevWalkOrder::Date => walker.sort_topologically(walked, true)?,
+        RevWalkOrder::Topo => walker.sort_topologically(walked, false)?,
+    };
+    let mut commits = Vec::new();
+    for hash in walked {
+        let commit = walker.commits.remove(&hash).ok_or(Error::Unreachable)?;
+        if matches_filters(&commit, options) {
+            commits.push((hash, commit));
+        }
+        if Some(commits.len()) == options.max_count {
+            break;
+        }
+    }
+    if options.reverse {
+        commits.reverse();
+    }
+    Ok(commits)
+}
+
+/// The best common ancestors of one and the others: the commits reachable from
+/// one and from one of the others, that are not ancestors of another such
+/// commit. Most recent first. There are several of them after criss-cross
+/// merges, and none if the histories are unrelated
+pub fn merge_bases(repository: &Repository, one: &str, others: &[String]) -> Result<Vec<String>> {
+    let mut walker = RevWalker::new(repository.object_database());
+    walker.merge_bases(one, others)
+}
+
+/// The best common ancestors of all the commits (instead of one and any of
+/// the others), for merges of more than two commits
+pub fn octopus_merge_bases(repository: &Repository, commits: &[String]) -> Result<Vec<String>> {
+    let Some((first, others)) = commits.split_first() else {
+        return Ok(Vec::new());
+    };
+    let mut bases = vec![first.clone()];
+    for other in others {
+        let mut new_bases = Vec::new();
+        for base in &bases {
+            new_bases.extend(merge_bases(repository, other, std::slice::from_ref(base))?);
+        }
+        bases = new_bases;
+    }
+    independent_commits(repository, &bases)
+}
+
+/// Whether the commit is reachable from the other one, or is the same
+pub fn is_ancestor(repository: &Repository, ancestor: &str, descendant: &str) -> Result<bool> {
+    let mut walker = RevWalker::new(repository.object_database());
+    walker.is_reachable(ancestor, &[descendant.to_string()])
+}
+
+/// The commits that are not reachable from another one of them, without
+/// duplicates, in their order
+pub fn independent_commits(repository: &Repository, commits: &[String]) -> Result<Vec<String>> {
+    let mut unique = Vec::new();
+    for commit in commits {
+        if !unique.contains(commit) {
+            unique.push(commit.clone());
+        }
+    }
+    let mut walker = RevWalker::new(repository.object_database());
+    let mut independent = Vec::new();
+    for commit in &unique {
+        let others = unique
+            .iter()
+            .filter(|other| *other != commit)
+            .cloned()
+            .collect::<Vec<_>>();
+        if !walker.is_reachable(commit, &others)? {
+            independent.push(commit.clone());
+        }
+    }
+    Ok(independent)
+}
+
+/// Objects listed by list_objects
+#[derive(Debug, Default)]
+pub struct ObjectList {
+    /// hash of each object and the path it was found at in the trees (empty
+    /// for tags, commits and root trees)
+    pub objects: Vec<(String, String)>,
+    /// excluded commits the walk stopped at: their objects are not listed
+    pub boundary: Vec<String>,
+}
+
+/// Objects reachable from the tips but not from the excluded objects, as
+/// `git rev-list --objects`: the annotated tags and commits first, then the
+/// trees and blobs of the commits. The excluded objects must exist locally
+pub fn list_objects(
+    repository: &Repository,
+    tips: &[String],
+    excluded: &[String],
+) -> Result<ObjectList> {
+    let object_database = repository.object_database();
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+
+    let mut excluded_commits = Vec::new();
+    for hash in excluded {
+        seen.insert(hash.clone());
+        if let Ok(commit) = object_database.peel(hash, "commit") {
+            excluded_commits.push(commit);
+        }
+    }
+    let mut revisions = excluded_commits
+        .iter()
+        .map(|commit| format!("^{}", commit))
+        .collect::<Vec<_>>();
+    // tips that are not commits are peeled, and their objects listed directly
+    let mut root_trees = Vec::new();
+    for tip in tips {
+        let mut hash = tip.clone();
+        loop {
+            match object_database.read_object(&hash)?.content {
+                GitObjectContent::Tag { content } => {
+                    if seen.insert(hash.clone()) {
+                        objects.push((hash, String::new()));
+                    }
+                    hash = content.object_sha;
+                }
+                GitObjectContent::Commit { .. } => {
+                    revisions.push(hash);
+                    break;
+                }
+                GitObjectContent::Tree { .. } => {
+                    root_trees.push(hash);
+                    break;
+                }
+                GitObjectContent::Blob { .. } => {
+                    if seen.insert(hash.clone()) {
+                        objects.push((hash, String::new()));
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    let commits = rev_walk(repository, &revisions, &RevWalkOptions::default())?;
+    let walked = commits
+        .iter()
+        .map(|(hash, _)| hash.as_str())
+        .collect::<HashSet<_>>();
+    // the trees of the excluded commits the walk stopped at are known to the
+    // other side, with everything in them
+    let mut boundary = excluded_commits;
+    for (_, commit) in &commits {
+        for parent in &commit.parents_sha {
+            if !walked.contains(parent.as_str()) && !boundary.contains(parent) {
+                boundary.push(parent.clone());
+            }
+        }
+    }
+    for commit in &boundary {
+        if let GitObjectContent::Commit { content } = object_database.read_object(commit)?.content {
+            mark_tree_seen(object_database, &content.tree_sha, &mut seen)?;
+        }
+    }
+
+    for (hash, _) in &commits {
+        if seen.insert(hash.clone()) {
+            objects.push((hash.clone(), String::new()));
+        }
+    }
+    root_trees.extend(commits.into_iter().map(|(_, commit)| commit.tree_sha));
+    for tree in root_trees {
+        list_tree_objects(object_database, &tree, "", &mut seen, &mut objects)?;
+    }
+    Ok(ObjectList { objects, boundary })
+}
+
+/// Blobs of the boundary commits found at the paths of listed blobs: previous
+/// versions of the listed files, that the other side has. They are good delta
+/// bases for a thin pack
+pub fn boundary_blobs(
+    repository: &Repository,
+    object_list: &ObjectList,
+) -> Result<Vec<(String, String)>> {
+    let object_database = repository.object_database();
+    let listed = object_list
+        .objects
+        .iter()
+        .map(|(hash, _)| hash.as_str())
+        .collect::<HashSet<_>>();
+    let paths = object_list
+        .objects
+        .iter()
+        .map(|(_, path)| path.as_str())
+        .filter(|path| !path.is_empty())
+        .collect::<HashSet<_>>();
+
+    let mut blobs = Vec::new();
+    let mut seen = HashSet::new();
+    for commit in &object_list.boundary {
+        let GitObjectContent::Commit { content } = object_database.read_object(commit)?.content
+        else {
+            continue;
+        };
+        for (path, child) in object_database.read_tree_recursively(&content.tree_sha)? {
+            if child.mode != 160000
+                && paths.contains(path.as_str())
+                && !listed.contains(child.hash.as_str())
+                && seen.insert(child.hash.clone())
+            {
+                blobs.push((child.hash, path));
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+/// Adds the tree and the trees and blobs it contains to the seen objects
+fn mark_tree_seen(
+    object_database: &GitObjectDatabase,
+    tree: &str,
+    seen: &mut HashSet<String>,
+) -> Result<()> {
+    if !seen.insert(tree.to_string()) {
+        return Ok(());
+    }
+    let GitObjectContent::Tree { content } = object_database.read_object(tree)?.content else {
+        return Err(Error::NotATreeGitObject);
+    };
+    for child in content {
+        match child.mode {
+            40000 => mark_tree_seen(object_database, &child.hash, seen)?,
+            // submodule commits are not part of the repository
+            160000 => {}
+            _ => {
+                seen.insert(child.hash);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists the tree and the trees and blobs it contains, unless already seen
+fn list_tree_objects(
+    object_database: &GitObjectDatabase,
+    tree: &str,
+    path: &str,
+    seen: &mut HashSet<String>,
+    objects: &mut Vec<(String, String)>,
+) -> Result<()> {
+    if !seen.insert(tree.to_string()) {
+        return Ok(());
+    }
+    objects.push((tree.to_string(), path.to_string()));
+    let GitObjectContent::Tree { content } = object_database.read_object(tree)?.content else {
+        return Err(Error::NotATreeGitObject);
+    };
+    for child in content {
+        let child_path = match path {
+            "" => child.name.clone(),
+            _ => format!("{}/{}", path, child.name),
+        };
+        match child.mode {
+            40000 => list_tree_objects(object_database, &child.hash, &child_path, seen, objects)?,
+            160000 => {}
+            _ => {
+                if seen.insert(child.hash.clone()) {
+                    objects.push((child.hash, child_path));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Hashes of the commits included and excluded by the revisions
+fn resolve_ranges(
+    repository: &Repository,
+    walker: &mut RevWalker,
+    revisions: &[String],
+) -> Result<(Vec<String>, Vec<String>)> {
+    let object_database = repository.object_database();
+    let resolve = |revision: &str| -> Result<String> {
+        let revision = if revision.is_empty() {
+            "HEAD"
+        } else {
+            revision
+        };
+        let hash = resolve_revision(repository, revision)?;
+        object_database
+            .peel(&hash, "commit")
+            .map_err(|_| Error::InvalidRevision(revision.to_string()))
+    };
+
+    let (mut included, mut excluded) = (Vec::new(), Vec::new());
+    for revision in revisions {
+        if let Some((start, end)) = revision.split_once("...") {
+            let (start, end) = (resolve(start)?, resolve(end)?);
+            excluded.extend(walker.merge_bases(&start, std::slice::from_ref(&end))?);
+            included.extend([start, end]);
+        } else if let Some((start, end)) = revision.split_once("..") {
+            excluded.push(resolve(start)?);
+            included.push(resolve(end)?);
+        } else if let Some(revision) = revision.strip_prefix('^') {
+            excluded.push(resolve(revision)?);
+        } else {
+            included.push(resolve(revision)?);
+        }
+    }
+    Ok((included, excluded))
+}
+
+/// Commits HEAD and the refs point to. Refs to other objects (tags of trees or
+/// blobs) are skipped
+fn all_ref_commits(repository: &Repository) -> Result<Vec<String>> {
+    let refs = repository.refs();
+    let mut hashes = Vec::new();
+    let head = refs.resolve("HEAD")?.into_iter();
+    let ref_hashes = refs.list("refs/")?.into_iter().map(|(_, hash)| hash);
+    for hash in head.chain(ref_hashes) {
+        if let Ok(hash) = repository.object_database().peel(&hash, "commit") {
+            hashes.push(hash);
+        }
+    }
+    Ok(hashes)
+}
+
+/// Whether the commit passes the --author and --grep filters
+fn matches_filters(commit: &CommitObjects, options: &RevWalkOptions) -> bool {
+    let author = format!(
+        "{} <{}>",
+        String::from_utf8_lossy(&commit.author.name),
+        String::from_utf8_lossy(&commit.author.email)
+    );
+    let message = String::from_utf8_lossy(commit.message());
+
+    (options.authors.is_empty() || options.authors.iter().any(|a| author.contains(a.as_str())))
+        && (options.greps.is_empty() || options.greps.iter().any(|g| message.contains(g.as_str())))
+}
+
+/// already pushed in the queue
+const SEEN: u8 = 1;
+/// reachable from an excluded commit
+const UNINTERESTING: u8 = 1 << 1;
+/// already popped from the queue: its parents were pushed
+const WALKED: u8 = 1 << 2;
+/// merge bases: reachable from the first commit
+const PARENT1: u8 = 1 << 3;
+/// merge bases: reachable from one of the other commits
+const PARENT2: u8 = 1 << 4;
+/// merge bases: reachable from a common ancestor, so not a best one
+const STALE: u8 = 1 << 5;
+/// merge bases: already added to the common ancestors
+const RESULT: u8 = 1 << 6;
+
+/// Walks the commits by decreasing commit date, keeping flags on the commits it
+/// has seen. Commits with the same date are popped in the order they were
+/// pushed. The merge bases walk by decreasing generation first, as dates can
+/// be skewed
+struct RevWalker<'a> {
+    object_database: &'a GitObjectDatabase,
+    commits: HashMap<String, CommitObjects>,
+    flags: HashMap<String, u8>,
+    /// generation (0 unless by_generation), commit date, then reversed push
+    /// order
+    queue: BinaryHeap<(u64, i64, Reverse<usize>, String)>,
+    nb_pushed: usize,
+    /// number of queued commits that are not uninteresting
+    nb_interesting: usize,
+    by_generation: bool,
+    generations: HashMap<String, u64>,
+}
+
+impl<'a> RevWalker<'a> {
+    fn new(object_database: &'a GitObjectDatabase) -> Self {
+        Self {
+            object_database,
+            commits: HashMap::new(),
+            flags: HashMap::new(),
+            queue: BinaryHeap::new(),
+            nb_pushed: 0,
+            nb_interesting: 0,
+            by_generation: false,
+            generations: HashMap::new(),
+        }
+    }
+
+    /// Forgets the flags and the queued commits, keeping the commits read
+    fn reset(&mut self) {
+        self.flags.clear();
+        self.queue.clear();
+        self.nb_interesting = 0;
+    }
+
+    /// Generation of the commit: 1 for a root commit, otherwise one more than
+    /// its highest parent. A commit can only reach commits of a lower
+    /// generation, whatever their dates
+    fn generation(&mut self, hash: &str) -> Result<u64> {
+        let mut stack = vec![hash.to_string()];
+        while let Some(current) = stack.last().cloned() {
+            if self.generations.contains_key(&current) {
+                stack.pop();
+                continue;
+            }
+            let parents = self.commit(&current)?.parents_sha.clone();
+            let missing = parents
+                .iter()
+                .filter(|parent| !self.generations.contains_key(*parent))
+                .cloned()
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                let generation = parents
+                    .iter()
+                    .map(|parent| self.generations[parent])
+                    .max()
+                    .unwrap_or(0)
+                    + 1;
+                self.generations.insert(current, generation);
+                stack.pop();
+            } else {
+                stack.extend(missing);
+            }
+        }
+        self.generations
+            .get(hash)
+            .copied()
+            .ok_or(Error::Unreachable)
+    }
+
+    /// Reads the commit, or returns it if it was already read
+    fn commit(&mut self, hash: &str) -> Result<&CommitObjects> {
+        if !self.commits.contains_key(hash) {
+            let commit = match self.object_database.read_object(hash)?.content {
+                GitObjectContent::Commit { content } => *content,
+                _ => return Err(Error::InvalidRevision(hash.to_string())),
+            };
+            self.commits.insert(hash.to_string(), commit);
+        }
+        self.commits.get(hash).ok_or(Error::Unreachable)
+    }
+
+    /// Queues the commit with the flags, unless it was already seen
+    fn push(&mut self, hash: &str, flags: u8) -> Result<()> {
+        let commit_flags = self.flags.entry(hash.to_string()).or_default();
+        if *commit_flags & SEEN != 0 {
+            return Ok(());
+        }
+        *commit_flags |= SEEN | flags;
+        self.enqueue(hash)
+    }
+
+    fn enqueue(&mut self, hash: &str) -> Result<()> {
+        let generation = match self.by_generation {
+            true => self.generation(hash)?,
+            false => 0,
+        };
+        let date = self.commit(hash)?.committer.timestamp;
+        self.nb_pushed += 1;
+        self.queue
+            .push((generation, date, Reverse(self.nb_pushed), hash.to_string()));
+        if self.flags[hash] & UNINTERESTING == 0 {
+            self.nb_interesting += 1;
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<String> {
+        let (_, _, _, hash) = self.queue.pop()?;
+        let flags = self.flags.entry(hash.clone()).or_default();
+        *flags |= WALKED;
+        if *flags & UNINTERESTING == 0 {
+            self.nb_interesting -= 1;
+        }
+        Some(hash)
+    }
+
+    /// Whether there is still a queued commit that is not uninteresting
+    fn has_interesting(&self) -> bool {
+        self.nb_interesting > 0
+    }
+
+    /// Marks the commit as uninteresting. If it was already walked, its
+    /// ancestors that were walked too are marked as well
+    fn mark_uninteresting(&mut self, hash: &str) -> Result<()> {
+        let mut stack = vec![hash.to_string()];
+        while let Some(hash) = stack.pop() {
+            let flags = self.flags.get(&hash).copied().unwrap_or_default();
+            if flags & SEEN == 0 {
+                self.push(&hash, UNINTERESTING)?;
+                continue;
+            }
+            if flags & UNINTERESTING != 0 {
+                continue;
+            }
+            self.flags.insert(hash.clone(), flags | UNINTERESTING);
+            if flags & WALKED != 0 {
+                stack.extend(self.commit(&hash)?.parents_sha.clone());
+            } else {
+                // still queued
+                self.nb_interesting -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Orders the commits so that children are listed before their parents.
+    /// The commits available next are taken by date, or depth first from the
+    /// last parent of the last listed commit
+    fn sort_topologically(&mut self, hashes: Vec<String>, by_date: bool) -> Result<Vec<String>> {
+        // number of children of the commit among the commits to sort
+        let mut nb_children: HashMap<String, usize> =
+            hashes.iter().map(|hash| (hash.clone(), 0)).collect();
+        for hash in &hashes {
+            for parent in &self.commit(hash)?.parents_sha {
+                if let Some(count) = nb_children.get_mut(parent) {
+                    *count += 1;
+                }
+            }
+        }
+
+        let mut ready = BinaryHeap::new();
+        let mut ready_stack = Vec::new();
+        for (index, hash) in hashes.iter().enumerate() {
+            if nb_children[hash] == 0 {
+                let date = self.commit(hash)?.committer.timestamp;
+                ready.push((date, Reverse(index), hash.clone()));
+                ready_stack.insert(0, hash.clone());
+            }
+        }
+        let mut sorted = Vec::with_capacity(hashes.len());
+        let mut nb_pushed = hashes.len();
+        loop {
+            let hash = match by_date {
+                true => ready.pop().map(|(_, _, hash)| hash),
+                false => ready_stack.pop(),
+            };
+            let Some(hash) = hash else { break };
+            for parent in self.commit(&hash)?.parents_sha.clone() {
+                let Some(count) = nb_children.get_mut(&parent) else {
+                    continue;
+                };
+                *count -= 1;
+                if *count == 0 {
+                    nb_pushed += 1;
+                    let date = self.commit(&parent)?.committer.timestamp;
+                    ready.push((date, Reverse(nb_pushed), parent.clone()));
+                    ready_stack.push(parent);
+                }
+            }
+            sorted.push(hash);
+        }
+        Ok(sorted)
+    }
+
+    /// Paints the commits reachable from one and from the others, stopping
+    /// below the common ancestors, then removes the common ancestors that are
+    /// reachable from another one. The commits are walked by generation so
+    /// that a commit is only walked after all its descendants: a common
+    /// ancestor is then always marked stale before being walked if it is
+    /// reachable from another one
+    fn merge_bases(&mut self, one: &str, others: &[String]) -> Result<Vec<String>> {
+        if others.iter().any(|other| other == one) {
+            return Ok(vec![one.to_string()]);
+        }
+        self.reset();
+        self.by_generation = true;
+        self.paint(one, PARENT1)?;
+        for other in others {
+            self.paint(other, PARENT2)?;
+        }
+
+        let mut candidates = Vec::new();
+        while self
+            .queue
+            .iter()
+            .any(|(_, _, _, hash)| self.flags[hash] & STALE == 0)
+        {
+            let Some((_, _, _, hash)) = self.queue.pop() else {
+                break;
+            };
+            let mut flags = self.flags[&hash] & (PARENT1 | PARENT2 | STALE);
+            if flags == PARENT1 | PARENT2 {
+                if self.flags[&hash] & RESULT == 0 {
+                    *self.flags.entry(hash.clone()).or_default() |= RESULT;
+                    candidates.push(hash.clone());
+                }
+                flags |= STALE;
+            }
+            for parent in self.commit(&hash)?.parents_sha.clone() {
+                self.paint(&parent, flags)?;
+            }
+        }
+        candidates.retain(|hash| self.flags[hash] & STALE == 0);
+        // the walker can be used again for another walk
+        self.reset();
+        self.by_generation = false;
+        self.remove_redundant(candidates)
+    }
+
+    /// Adds the flags to the commit, and queues it if it did not have them
+    fn paint(&mut self, hash: &str, flags: u8) -> Result<()> {
+        let commit_flags = self.flags.entry(hash.to_string()).or_default();
+        if *commit_flags & flags == flags {
+            return Ok(());
+        }
+        *commit_flags |= flags;
+        self.enqueue(hash)
+    }
+
+    /// Removes the commits that are ancestors of another one
+    fn remove_redundant(&mut self, candidates: Vec<String>) -> Result<Vec<String>> {
+        let mut kept = Vec::new();
+        for candidate in &candidates {
+            let others = candidates
+                .iter()
+                .filter(|other| *other != candidate)
+                .cloned()
+                .collect::<Vec<_>>();
+            if !self.is_reachable(candidate, &others)? {
+                kept.push(candidate.clone());
+            }
+        }
+        let mut dated = Vec::new();
+        for hash in kept {
+            dated.push((Reverse(self.commit(&hash)?.committer.timestamp), hash));
+        }
+        dated.sort();
+        Ok(dated.into_iter().map(|(_, hash)| hash).collect())
+    }
+
+    /// Whether the commit is one of the others or is reachable from one of
+    /// them. The walk does not go below the generation of the commit
+    fn is_reachable(&mut self, hash: &str, from: &[String]) -> Result<bool> {
+        let generation = self.generation(hash)?;
+        let mut stack = from.to_vec();
+        let mut visited = HashSet::new();
+        while let Some(current) = stack.pop() {
+            if current == hash {
+                return Ok(true);
+            }
+            if !visited.insert(current.clone()) || self.generation(&current)? <= generation {
+                continue;
+            }
+            stack.extend(self.commit(&current)?.parents_sha.clone());
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,531 @@
+use crate::git_ident::parse_date;
+use crate::git_index::GitIndex;
+use crate::git_object::GitObjectContent;
+use crate::git_refs::NULL_HASH;
+use crate::git_remote::Refspec;
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// Resolves a revision (see https://git-scm.com/docs/gitrevisions) to the hash
+/// of an object. Supported forms:
+/// - a full or abbreviated hash (at least 4 characters, it must be unique)
+/// - a ref name (`main`, `v1.0`, `origin/main`, `refs/heads/main`, `HEAD`...),
+///   looked up as described in GitRefDatabase::expand_name. `@` is HEAD
+/// - reflog selectors: `main@{2}`, `@{1}`, `HEAD@{2024-04-28 13:55:10}`, and
+///   `@{-1}` for the branch checked out before the current one
+/// - `@{upstream}` / `@{u}` and `@{push}` (`main@{u}`)
+/// - ancestry: `rev~n` (n-th first parent), `rev^n` (n-th parent), `rev^0`
+/// - peeling: `rev^{}`, `rev^{commit}`, `rev^{tree}`, `rev^{blob}`,
+///   `rev^{tag}`, `rev^{object}`
+/// - paths: `rev:path/in/tree` (`./` and `../` are relative to the current
+///   directory), `:path` and `:n:path` for the entries of the index
+pub fn resolve_revision(repository: &Repository, revision: &str) -> Result<String> {
+    if let Some(index_path) = revision.strip_prefix(':') {
+        return resolve_index_path(repository, index_path, revision);
+    }
+    if let Some((tree_revision, path)) = split_outside_braces(revision, ':') {
+        return resolve_tree_path(repository, tree_revision, path, revision);
+    }
+
+    let (base, operators) = split_base(revision);
+    let mut hash = resolve_base(repository, base, revision)?;
+    let object_database = repository.object_database();
+    let invalid = || Error::InvalidRevision(revision.to_string());
+
+    let mut operators = operators;
+    while let Some(operator) = operators.chars().next() {
+        operators = &operators[operator.len_utf8()..];
+        if operator == '^' && operators.starts_with('{') {
+            let end = operators.find('}').ok_or_else(invalid)?;
+            let target_type = &operators[1..end];
+            operators = &operators[end + 1..];
+            hash = peel_to_type(repository, &hash, target_type).map_err(|_| invalid())?;
+            continue;
+        }
+
+        let nb_digits = operators
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(operators.len());
+        let number = match nb_digits {
+            0 => 1,
+            _ => operators[..nb_digits]
+                .parse::<usize>()
+                .map_err(|_| invalid())?,
+        };
+        operators = &operators[nb_digits..];
+
+        hash = object_database
+            .peel(&hash, "commit")
+            .map_err(|_| invalid())?;
+        match operator {
+            // n-th first parent
+            '~' => {
+                for _ in 0..number {
+                    hash = commit_parents(repository, &hash)?
+                        .into_iter()
+                        .next()
+                        .ok_or_else(invalid)?;
+                }
+            }
+            // ^0 is the commit itself
+            '^' if number > 0 => {
+                hash = commit_parents(repository, &hash)?
+                    .into_iter()
+                    .nth(number - 1)
+                    .ok_or_else(invalid)?;
+            }
+            '^' => {}
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(hash)
+}
+
+/// Full name of the ref a revision designates, for ex refs/heads/main for
+/// `main`, `HEAD` (when main is checked out) or `@{-1}`, and the upstream
+/// branch for `main@{u}`.
+/// Returns None if the revision is not a ref (a hash, HEAD when detached, an
+/// expression with operators...)
+pub fn resolve_ref_name(repository: &Repository, revision: &str) -> Result<Option<String>> {
+    let refs = repository.refs();
+    let revision = match revision {
+        "@" => "HEAD",
+        revision => revision,
+    };
+    if let Some((name, selector)) = split_selector(revision) {
+        let selector_lowercase = selector.to_ascii_lowercase();
+        if matches!(selector_lowercase.as_str(), "u" | "upstream" | "push") {
+            let branch = branch_name(repository, name)?;
+            return Ok(Some(upstream_ref(repository, &branch)?));
+        }
+        if let Some(Ok(nth)) = selector.strip_prefix('-').map(str::parse::<usize>) {
+            if name.is_empty() {
+                let previous = previous_branch(repository, nth)?;
+                return resolve_ref_name(repository, &previous);
+            }
+        }
+        return Ok(None);
+    }
+
+    let Some(name) = refs.expand_name(revision)? else {
+        return Ok(None);
+    };
+    match refs.read_symbolic(&name)? {
+        Some(_) => Ok(Some(refs.follow_symbolic(&name)?)),
+        // a detached HEAD is not a ref
+        None if name == "HEAD" => Ok(None),
+        None => Ok(Some(name)),
+    }
+}
+
+/// Splits the revision at the first separator that is not inside braces (the
+/// text of `^{...}` and `@{...}` may contain anything)
+fn split_outside_braces(revision: &str, separator: char) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (pos, c) in revision.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                return Some((&revision[..pos], &revision[pos + 1..]));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits the revision between its base (a hash or a ref, with an optional
+/// @{...} selector) and the ~ and ^ operators that follow
+fn split_base(revision: &str) -> (&str, &str) {
+    let mut depth = 0;
+    for (pos, c) in revision.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '~' | '^' if depth == 0 => return (&revision[..pos], &revision[pos..]),
+            _ => {}
+        }
+    }
+    (revision, "")
+}
+
+/// Splits `name@{selector}` in the name (possibly empty) and the selector
+fn split_selector(base: &str) -> Option<(&str, &str)> {
+    let start = base.rfind("@{")?;
+    let selector = base[start + 2..].strip_suffix('}')?;
+    Some((&base[..start], selector))
+}
+
+fn resolve_base(repository: &Repository, base: &str, revision: &str) -> Result<String> {
+    let refs = repository.refs();
+    let object_database = repository.object_database();
+    let invalid = || Error::InvalidRevision(revision.to_string());
+
+    if let Some((name, selector)) = split_selector(base) {
+        if let Some(nth) = selector.strip_prefix('-').filter(|_| name.is_empty()) {
+            let nth = nth.parse::<usize>().map_err(|_| invalid())?;
+            return resolve_base(repository, &previous_branch(repository, nth)?, revision);
+        }
+        let selector_lowercase = selector.to_ascii_lowercase();
+        if matches!(selector_lowercase.as_str(), "u" | "upstream" | "push") {
+            let upstream = upstream_ref(repository, &branch_name(repository, name)?)?;
+            return refs.resolve(&upstream)?.ok_or_else(invalid);
+        }
+        return resolve_reflog_selector(repository, name, selector, revision);
+    }
+
+    let base = match base {
+        "@" => "HEAD",
+        base => base,
+    };
+    if base.len() == 40 && base.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(base.to_ascii_lowercase());
+    }
+    // refs take precedence over abbreviated hashes
+    if let Some(name) = refs.expand_name(base)? {
+        return refs.resolve(&name)?.ok_or_else(invalid);
+    }
+    if base.len() >= 4 {
+        let hashes = object_database.find_hashes_with_prefix(base)?;
+        match hashes.as_slice() {
+            [hash] => return Ok(hash.clone()),
+            [] => {}
+            _ => return Err(Error::AmbiguousRevision(base.to_string())),
+        }
+    }
+    Err(invalid())
+}
+
+/// `ref@{n}`: n-th previous value of the ref, `ref@{date}`: value of the ref at
+/// the given date. Without ref, the current branch is used
+fn resolve_reflog_selector(
+    repository: &Repository,
+    name: &str,
+    selector: &str,
+    revision: &str,
+) -> Result<String> {
+    let refs = repository.refs();
+    let ref_name = match name {
+        "" => refs.follow_symbolic("HEAD")?,
+        name => refs
+            .expand_name(name)?
+            .ok_or_else(|| Error::InvalidRevision(revision.to_string()))?,
+    };
+    let reflog = refs.read_reflog(&ref_name)?;
+    let not_found = || Error::ReflogEntryNotFound(revision.to_string());
+
+    if selector.starts_with('-') {
+        return Err(Error::InvalidRevision(revision.to_string()));
+    }
+    let hash = match selector.parse::<usize>() {
+        Ok(nth) => reflog
+            .iter()
+            .rev()
+            .nth(nth)
+            .map(|entry| entry.new_hash.clone())
+            .ok_or_else(not_found)?,
+        Err(_) => {
+            let (timestamp, _) = parse_date(selector)?;
+            match reflog
+                .iter()
+                .rev()
+                .find(|entry| entry.committer.timestamp <= timestamp)
+            {
+                Some(entry) => entry.new_hash.clone(),
+                // older than the reflog: the value before the first entry
+                None => reflog
+                    .first()
+                    .map(|entry| entry.old_hash.clone())
+                    .ok_or_else(not_found)?,
+            }
+        }
+    };
+    if hash == NULL_HASH {
+        return Err(not_found());
+    }
+    Ok(hash)
+}
+
+/// Name of the branch (without refs/heads/) of a revision, the current branch
+/// if it is empty
+fn branch_name(repository: &Repository, name: &str) -> Result<String> {
+    let ref_name = match name {
+        "" | "@" | "HEAD" => repository.refs().head_ref()?,
+        name => repository.refs().expand_name(name)?,
+    };
+    ref_name
+        .as_deref()
+        .and_then(|ref_name| ref_name.strip_prefix("refs/heads/"))
+        .map(str::to_string)
+        .ok_or_else(|| Error::NoUpstream(name.to_string()))
+}
+
+/// Remote-tracking branch a branch is configured to follow
+/// (branch.<name>.remote and branch.<name>.merge), for ex refs/remotes/origin/main.
+/// The merge ref is mapped with the fetch refspecs of the remote, or used as
+/// is if the remote is the repository itself (.)
+pub fn upstream_ref(repository: &Repository, branch: &str) -> Result<String> {
+    let config = repository.config()?;
+    let no_upstream = || Error::NoUpstream(branch.to_string());
+    let remote = config
+        .get(&format!("branch.{}.remote", branch))
+        .ok_or_else(no_upstream)?;
+    let merge = config
+        .get(&format!("branch.{}.merge", branch))
+        .ok_or_else(no_upstream)?;
+    if remote == "." {
+        return Ok(merge.to_string());
+    }
+    for refspec in config.get_all(&format!("remote.{}.fetch", remote)) {
+        let Ok(refspec) = Refspec::parse(refspec) else {
+            continue;
+        };
+        if let Some(destination) = refspec.map_source(merge) {
+            return Ok(destination);
+        }
+    }
+    Err(no_upstream())
+}
+
+/// Branch (or commit, if HEAD was detached) that was checked out before the
+/// nth last checkout, from the reflog of HEAD (`checkout: moving from a to b`)
+fn previous_branch(repository: &Repository, nth: usize) -> Result<String> {
+    repository
+        .refs()
+        .read_reflog("HEAD")?
+        .iter()
+        .rev()
+        .filter_map(|entry| {
+            let moves = entry.message.strip_prefix("checkout: moving from ")?;
+            moves.split_once(" to ").map(|(from, _)| from.to_string())
+        })
+        .nth(nth.max(1) - 1)
+        .ok_or_else(|| Error::ReflogEntryNotFound(format!("@{{-{}}}", nth)))
+}
+
+/// Follows tags (and commits to their tree) until an object of the type is
+/// found. An empty type peels tags only, object accepts any type
+fn peel_to_type(repository: &Repository, hash: &str, target_type: &str) -> Result<String> {
+    let object_database = repository.object_database();
+    match target_type {
+        "" => object_database.peel_tags(hash),
+        "object" => Ok(object_database.read_object(hash)?.hash),
+        "commit" | "tree" => object_database.peel(hash, target_type),
+        "blob" | "tag" => {
+            let hash = match target_type {
+                "blob" => object_database.peel_tags(hash)?,
+                _ => hash.to_string(),
+            };
+            if object_database.read_object(&hash)?.content_type() != target_type {
+                return Err(Error::CannotPeel {
+                    hash,
+                    target_type: target_type.to_string(),
+                });
+            }
+            Ok(hash)
+        }
+        _ => Err(Error::InvalidRevision(target_type.to_string())),
+    }
+}
+
+fn commit_parents(repository: &Repository, hash: &str) -> Result<Vec<String>> {
+    match repository.object_database().read_object(hash)?.content {
+        GitObjectContent::Commit { content } => Ok(content.parents_sha),
+        _ => Err(Error::InvalidGitObject),
+    }
+}
+
+/// `rev:path`: the object at the path in the tree of the revision
+fn resolve_tree_path(
+    repository: &Repository,
+    tree_revision: &str,
+    path: &str,
+    revision: &str,
+) -> Result<String> {
+    let object_database = repository.object_database();
+    let tree_hash = resolve_revision(repository, tree_revision)?;
+    let mut hash = object_database.peel(&tree_hash, "tree")?;
+
+    let path = match path.starts_with("./") || path.starts_with("../") {
+        true => repository.path_in_work_tree(path)?,
+        false => path.trim_end_matches('/').to_string(),
+    };
+    let not_found = || Error::PathNotInRevision {
+        path: path.clone(),
+        revision: revision.to_string(),
+    };
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        let GitObjectContent::Tree { content } = object_database.read_object(&hash)?.content else {
+            return Err(not_found());
+        };
+        hash = content
+            .into_iter()
+            .find(|tree_child| tree_child.name == component)
+            .map(|tree_child| tree_child.hash)
+            .ok_or_else(not_found)?;
+    }
+    Ok(hash)
+}
+
+/// `:path` or `:n:path`: the blob of the index entry at the path, for the
+/// given stage (0 by default)
+fn resolve_index_path(repository: &Repository, index_path: &str, revision: &str) -> Result<String> {
+    let (stage, path) = match index_path.split_once(':') {
+        Some((stage, path)) if stage.len() == 1 => (
+            stage
+                .parse::<u16>()
+                .map_err(|_| Error::InvalidRevision(revision.to_string()))?,
+            path,
+        ),
+        _ => (0, index_path),
+    };
+    let path = match path.starts_with("./") || path.starts_with("../") {
+        true => repository.path_in_work_tree(path)?,
+        false => path.to_string(),
+    };
+    GitIndex::read(repository)?
+        .entries
+        .into_iter()
+        .find(|entry| entry.path == path && entry.stage() == stage)
+        .map(|entry| entry.hash)
+        .ok_or_else(|| Error::PathNotInRevision {
+            path,
+            revision: revision.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_init::git_init;
+    use crate::git_object::{CommitObjects, GitObject, Signature};
+
+    #[test]
+    fn split_revisions() {
+        assert_eq!(split_base("main~2^2"), ("main", "~2^2"));
+        assert_eq!(split_base("main@{1}^{tree}"), ("main@{1}", "^{tree}"));
+        assert_eq!(
+            split_base("HEAD@{1 day ~ago}~1"),
+            ("HEAD@{1 day ~ago}", "~1")
+        );
+        assert_eq!(split_base("v1.0"), ("v1.0", ""));
+
+        assert_eq!(split_selector("main@{1}"), Some(("main", "1")));
+        assert_eq!(split_selector("@{-1}"), Some(("", "-1")));
+        assert_eq!(split_selector("main@{u"), None);
+        assert_eq!(split_selector("main"), None);
+
+        assert_eq!(
+            split_outside_braces("main:dir/file", ':'),
+            Some(("main", "dir/file"))
+        );
+        assert_eq!(
+            split_outside_braces("HEAD@{12:00}:file", ':'),
+            Some(("HEAD@{12:00}", "file"))
+        );
+        assert_eq!(split_outside_braces("HEAD@{12:00}", ':'), None);
+    }
+
+    /// Writes the object in the repository and returns its hash
+    fn write(repository: &Repository, git_object: GitObject) -> String {
+        git_object.write(repository.object_database()).unwrap();
+        git_object.hash
+    }
+
+    fn tree_with(repository: &Repository, mode: &str, name: &str, hash: &str) -> String {
+        let mut content = format!("{} {}\0", mode, name).into_bytes();
+        content.extend(hex::decode(hash).unwrap());
+        write(
+            repository,
+            GitObject::from_tree_content_bytes(content).unwrap(),
+        )
+    }
+
+    fn commit(repository: &Repository, tree: &str, parents: &[&str], message: &str) -> String {
+        let signature = Signature {
+            name: b"A U Thor".to_vec(),
+            email: b"author@example.com".to_vec(),
+            timestamp: 1_700_000_000,
+            timezone: "+0000".to_string(),
+            raw: None,
+        };
+        let commit_objects = CommitObjects {
+            tree_sha: tree.to_string(),
+            parents_sha: parents.iter().map(|parent| parent.to_string()).collect(),
+            author: signature.clone(),
+            committer: signature,
+            extra_headers: Vec::new(),
+            commit_msg: Some(format!("{}\n", message).into_bytes()),
+            headers_end_with_newline: true,
+        };
+        write(
+            repository,
+            GitObject::from_commit_objects(commit_objects).unwrap(),
+        )
+    }
+
+    #[test]
+    fn resolve_revisions() {
+        let directory = std::env::temp_dir().join(format!("git-revision-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        git_init(&directory).unwrap();
+        let repository = Repository::open(&directory).unwrap();
+
+        let blob = write(
+            &repository,
+            GitObject::from_blob_content_bytes(b"content\n".to_vec()).unwrap(),
+        );
+        let dir_tree = tree_with(&repository, "100644", "file", &blob);
+        let tree = tree_with(&repository, "40000", "dir", &dir_tree);
+        let root = commit(&repository, &tree, &[], "root");
+        let side = commit(&repository, &tree, &[&root], "side");
+        let second = commit(&repository, &tree, &[&root], "second");
+        let merge = commit(&repository, &tree, &[&second, &side], "merge");
+        repository
+            .refs()
+            .update("refs/heads/main", &merge, None)
+            .unwrap();
+        repository
+            .refs()
+            .update("refs/tags/v1", &root, None)
+            .unwrap();
+
+        let resolve = |revision| resolve_revision(&repository, revision);
+        for (revision, expected) in [
+            ("HEAD", &merge),
+            ("@", &merge),
+            ("main", &merge),
+            ("refs/heads/main", &merge),
+            (&merge[..7], &merge),
+            ("main^", &second),
+            ("main^1", &second),
+            ("main^2", &side),
+            ("main~1", &second),
+            ("main^2~1", &root),
+            ("main~2", &root),
+            ("HEAD^0", &merge),
+            ("v1", &root),
+            ("main^{commit}", &merge),
+            ("main^{tree}", &tree),
+            ("main:dir", &dir_tree),
+            ("main:dir/file", &blob),
+            ("main~2:dir/file", &blob),
+        ] {
+            assert_eq!(&resolve(revision).unwrap(), expected, "{}", revision);
+        }
+        for revision in ["main~3", "main^3", "unknown", "main^{blob}", "main:missing"] {
+            assert!(resolve(revision).is_err(), "{}", revision);
+        }
+        for revision in ["HEAD^é", "main~é", "main^2é"] {
+            let result = resolve(revision);
+            assert!(
+                matches!(result, Err(Error::InvalidRevision(_))),
+                "{}",
+                revision
+            );
+        }
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
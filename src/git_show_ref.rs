@@ -0,0 +1,90 @@
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// Which refs show-ref lists
+#[derive(Debug, Default)]
+pub struct ShowRefOptions {
+    /// only refs/heads
+    pub heads: bool,
+    /// only refs/tags
+    pub tags: bool,
+    /// also shows HEAD, first
+    pub include_head: bool,
+    /// adds the object annotated tags point to, as <tag>^{}
+    pub dereference: bool,
+    /// patterns are full ref names that must all exist
+    pub verify: bool,
+}
+
+/// Lists the refs with the hash they point to, sorted by name.
+/// A ref matches a pattern if the pattern is its name or the last components of
+/// its name (main matches refs/heads/main and refs/remotes/origin/main).
+/// Without pattern, every ref is listed
+pub fn git_show_ref(
+    repository: &Repository,
+    patterns: &[String],
+    options: &ShowRefOptions,
+) -> Result<Vec<(String, String)>> {
+    let refs = repository.refs();
+    let mut found = Vec::new();
+
+    if options.verify {
+        for name in patterns {
+            let is_ref = name == "HEAD" || name.starts_with("refs/");
+            let hash = match is_ref {
+                true => refs.resolve(name)?,
+                false => None,
+            };
+            found.push((
+                name.clone(),
+                hash.ok_or_else(|| Error::RefNotFound(name.clone()))?,
+            ));
+        }
+    } else {
+        if options.include_head {
+            found.extend(refs.resolve("HEAD")?.map(|hash| ("HEAD".to_string(), hash)));
+        }
+        let matches = |name: &str| {
+            patterns.is_empty()
+                || patterns.iter().any(|pattern| {
+                    name == pattern
+                        || name.ends_with(&format!("/{}", pattern.trim_start_matches('/')))
+                })
+        };
+        for (name, hash) in refs.list("refs/")? {
+            let is_selected = match (options.heads, options.tags) {
+                (false, false) => true,
+                (heads, tags) => {
+                    (heads && name.starts_with("refs/heads/"))
+                        || (tags && name.starts_with("refs/tags/"))
+                }
+            };
+            if is_selected && matches(&name) {
+                found.push((name, hash));
+            }
+        }
+    }
+
+    if !options.dereference {
+        return Ok(found);
+    }
+    // packed-refs already contains the peeled value of packed tags
+    let packed_refs = refs.packed_refs()?;
+    let mut dereferenced = Vec::new();
+    for (name, hash) in found {
+        let packed_peeled = packed_refs
+            .iter()
+            .find(|packed_ref| packed_ref.name == name && packed_ref.hash == hash)
+            .and_then(|packed_ref| packed_ref.peeled.clone());
+        let peeled = match packed_peeled {
+            Some(peeled) => peeled,
+            None => repository.object_database().peel_tags(&hash)?,
+        };
+        let is_tag = peeled != hash;
+        dereferenced.push((name.clone(), hash));
+        if is_tag {
+            dereferenced.push((format!("{}^{{}}", name), peeled));
+        }
+    }
+    Ok(dereferenced)
+}
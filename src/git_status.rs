@@ -0,0 +1,393 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_dir;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::git_ignore::IgnoreRules;
+use crate::git_index::{GitIndex, IndexEntry};
+use crate::git_object::{GitObject, GitObjectContent};
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// Status of a file, either between HEAD and the index (staged changes) or
+/// between the index and the working tree (unstaged changes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Unmodified,
+    Modified,
+    /// the file became a symbolic link or the opposite
+    TypeChanged,
+    Added,
+    Deleted,
+}
+
+impl FileStatus {
+    /// Letter used in the short format
+    pub fn code(&self) -> char {
+        match self {
+            FileStatus::Unmodified => ' ',
+            FileStatus::Modified => 'M',
+            FileStatus::TypeChanged => 'T',
+            FileStatus::Added => 'A',
+            FileStatus::Deleted => 'D',
+        }
+    }
+
+    /// Label used in the long format
+    pub fn label(&self) -> &'static str {
+        match self {
+            FileStatus::Unmodified => "unmodified",
+            FileStatus::Modified => "modified",
+            FileStatus::TypeChanged => "typechange",
+            FileStatus::Added => "new file",
+            FileStatus::Deleted => "deleted",
+        }
+    }
+}
+
+/// A tracked file that changed in the index or in the working tree
+#[derive(Debug)]
+pub struct StatusEntry {
+    /// path relative to the root of the repository
+    pub path: String,
+    /// HEAD compared to the index
+    pub staged: FileStatus,
+    /// index compared to the working tree
+    pub unstaged: FileStatus,
+}
+
+/// A file in conflict after a merge, with the stages present in the index
+/// (1: common ancestor, 2: ours, 3: theirs)
+#[derive(Debug)]
+pub struct UnmergedEntry {
+    pub path: String,
+    pub stages: BTreeSet<u16>,
+}
+
+impl UnmergedEntry {
+    /// Two letters used in the short format, for ex UU when both sides modified
+    /// the file
+    pub fn code(&self) -> &'static str {
+        let has = |stage| self.stages.contains(&stage);
+        match (has(1), has(2), has(3)) {
+            (true, false, false) => "DD",
+            (false, true, false) => "AU",
+            (true, false, true) => "DU",
+            (false, false, true) => "UA",
+            (true, true, false) => "UD",
+            (false, true, true) => "AA",
+            _ => "UU",
+        }
+    }
+
+    /// Label used in the long format
+    pub fn label(&self) -> &'static str {
+        match self.code() {
+            "DD" => "both deleted",
+            "AU" => "added by us",
+            "DU" => "deleted by us",
+            "UA" => "added by them",
+            "UD" => "deleted by them",
+            "AA" => "both added",
+            _ => "both modified",
+        }
+    }
+}
+
+/// Differences between the HEAD commit, the index and the working tree
+#[derive(Debug)]
+pub struct Status {
+    /// ref HEAD points to (for ex refs/heads/main), None if HEAD is detached
+    pub head_ref: Option<String>,
+    /// commit HEAD points to, None if there is no commit yet
+    pub head_hash: Option<String>,
+    /// sorted by path
+    pub entries: Vec<StatusEntry>,
+    /// sorted by path
+    pub unmerged: Vec<UnmergedEntry>,
+    /// files that are not in the index. Directories without any tracked file
+    /// are reported as a whole, with a trailing /
+    pub untracked: Vec<String>,
+}
+
+/// Compares HEAD, the index and the working tree.
+/// Files whose stat data matches the index are considered unchanged without
+/// being hashed. The stat data of files that were hashed and found unchanged is
+/// refreshed in the index, so that they are not hashed again next time
+pub fn git_status(repository: &Repository) -> Result<Status> {
+    let object_database = repository.object_database();
+    let work_tree = repository.work_tree()?;
+    let mut index = GitIndex::read(repository)?;
+
+    let head_hash = repository.refs().resolve("HEAD")?;
+    let head_tree = match &head_hash {
+        None => BTreeMap::new(),
+        Some(head_hash) => {
+            let GitObjectContent::Commit { content } =
+                object_database.read_object(head_hash)?.content
+            else {
+                return Err(Error::InvalidGitObject);
+            };
+            object_database.read_tree_recursively(&content.tree_sha)?
+        }
+    };
+
+    // files modified in the same second as the index was written may change
+    // again without their stat data changing: they are always hashed
+    let index_mtime = std::fs::metadata(repository.git_dir().join("index"))
+        .map(|metadata| (metadata.mtime() as u32, metadata.mtime_nsec() as u32))
+        .ok();
+
+    let mut entries = Vec::new();
+    let mut unmerged: BTreeMap<String, BTreeSet<u16>> = BTreeMap::new();
+    let mut refreshed_entries = Vec::new();
+
+    for entry in &index.entries {
+        if entry.stage() != 0 {
+            unmerged
+                .entry(entry.path.clone())
+                .or_default()
+                .insert(entry.stage());
+            continue;
+        }
+
+        let staged = match head_tree.get(&entry.path) {
+            None => FileStatus::Added,
+            Some(tree_child) if file_kind(tree_child.mode) != file_kind(entry.mode) => {
+                FileStatus::TypeChanged
+            }
+            Some(tree_child) if tree_child.hash != entry.hash || tree_child.mode != entry.mode => {
+                FileStatus::Modified
+            }
+            Some(_) => FileStatus::Unmodified,
+        };
+
+        let unstaged = match std::fs::symlink_metadata(work_tree.join(&entry.path)) {
+            Err(_) => FileStatus::Deleted,
+            // submodules are not inspected
+            Ok(_) if entry.mode == 160000 => FileStatus::Unmodified,
+            Ok(metadata) if metadata.is_dir() => FileStatus::Deleted,
+            Ok(metadata) => {
+                let mode = IndexEntry::mode_from_metadata(&metadata);
+                let is_racy = index_mtime
+                    .is_some_and(|mtime| (entry.mtime_seconds, entry.mtime_nanoseconds) >= mtime);
+                if file_kind(mode) != file_kind(entry.mode) {
+                    FileStatus::TypeChanged
+                } else if entry.matches_metadata(&metadata) && !is_racy {
+                    FileStatus::Unmodified
+                } else if mode != entry.mode {
+                    FileStatus::Modified
+                } else {
+                    let git_object = GitObject::from_work_tree_file(work_tree.join(&entry.path))?;
+                    if git_object.hash == entry.hash {
+                        refreshed_entries.push(IndexEntry {
+                            flags: entry.flags,
+                            extended_flags: entry.extended_flags,
+                            ..IndexEntry::from_metadata(&entry.path, &entry.hash, &metadata)
+                        });
+                        FileStatus::Unmodified
+                    } else {
+                        FileStatus::Modified
+                    }
+                }
+            }
+        };
+
+        if staged != FileStatus::Unmodified || unstaged != FileStatus::Unmodified {
+            entries.push(StatusEntry {
+                path: entry.path.clone(),
+                staged,
+                unstaged,
+            });
+        }
+    }
+
+    let tracked_paths = index
+        .entries
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect::<BTreeSet<_>>();
+
+    // files of HEAD that are no longer in the index
+    for path in head_tree.keys() {
+        if !tracked_paths.contains(path.as_str()) {
+            entries.push(StatusEntry {
+                path: path.clone(),
+                staged: FileStatus::Deleted,
+                unstaged: FileStatus::Unmodified,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut tracked_dirs = BTreeSet::new();
+    for path in &tracked_paths {
+        let mut dir = *path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            tracked_dirs.insert(parent);
+            dir = parent;
+        }
+    }
+    let mut untracked = Vec::new();
+    let mut ignore_rules = IgnoreRules::load(repository)?;
+    let tracked = (&tracked_paths, &tracked_dirs);
+    find_untracked(work_tree, "", tracked, &mut ignore_rules, &mut untracked)?;
+    untracked.sort();
+
+    if !refreshed_entries.is_empty() {
+        for entry in refreshed_entries {
+            if let Some(index_entry) = index
+                .entries
+                .iter_mut()
+                .find(|e| e.path == entry.path && e.stage() == 0)
+            {
+                *index_entry = entry;
+            }
+        }
+        index.write(repository)?;
+    }
+
+    Ok(Status {
+        head_ref: repository.refs().head_ref()?,
+        head_hash,
+        entries,
+        unmerged: unmerged
+            .into_iter()
+            .map(|(path, stages)| UnmergedEntry { path, stages })
+            .collect(),
+        untracked,
+    })
+}
+
+/// Files and executables are the same kind of file, symbolic links and
+/// submodules are not
+fn file_kind(mode: u32) -> u32 {
+    match mode {
+        100755 => 100644,
+        mode => mode,
+    }
+}
+
+/// Walks the directory (relative to the root of the work tree) looking for
+/// files that are not in the index (the tracked paths and their directories)
+/// and not ignored
+fn find_untracked(
+    work_tree: &Path,
+    dir: &str,
+    tracked: (&BTreeSet<&str>, &BTreeSet<&str>),
+    ignore_rules: &mut IgnoreRules,
+    untracked: &mut Vec<String>,
+) -> Result<()> {
+    let (tracked_paths, tracked_dirs) = tracked;
+    let mut children = read_dir(work_tree.join(dir))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    children.sort();
+
+    for child in children {
+        if child == ".git" {
+            continue;
+        }
+        let child = child.to_string_lossy();
+        let path = match dir.is_empty() {
+            true => child.to_string(),
+            false => format!("{}/{}", dir, child),
+        };
+        let metadata = std::fs::symlink_metadata(work_tree.join(&path))?;
+
+        if !metadata.is_dir() {
+            if !tracked_paths.contains(path.as_str()) && !ignore_rules.is_ignored(&path, false)? {
+                untracked.push(path);
+            }
+        } else if tracked_dirs.contains(path.as_str()) {
+            find_untracked(work_tree, &path, tracked, ignore_rules, untracked)?;
+        } else if contains_unignored_file(work_tree, &path, ignore_rules)? {
+            // empty directories are not shown, as git does not track directories,
+            // nor directories of ignored files
+            untracked.push(format!("{}/", path));
+        }
+    }
+    Ok(())
+}
+
+/// Checks if there is at least one file that is not ignored in the directory
+/// or its subdirectories
+fn contains_unignored_file(
+    work_tree: &Path,
+    dir: &str,
+    ignore_rules: &mut IgnoreRules,
+) -> Result<bool> {
+    if ignore_rules.is_ignored(dir, true)? {
+        return Ok(false);
+    }
+    for entry in read_dir(work_tree.join(dir))? {
+        let entry = entry?;
+        let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+        if entry.file_name() == ".git" {
+            return Ok(true);
+        }
+        let is_found = match entry.file_type()?.is_dir() {
+            true => contains_unignored_file(work_tree, &path, ignore_rules)?,
+            false => !ignore_rules.is_ignored(&path, false)?,
+        };
+        if is_found {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Makes a path relative to the root of the repository relative to another
+/// directory of the repository (for ex the current directory), as paths are
+/// displayed by status
+pub fn relative_path(path: &str, base_dir: &str) -> String {
+    if base_dir.is_empty() {
+        return path.to_string();
+    }
+    let path_components = path.split('/').collect::<Vec<_>>();
+    let base_components = base_dir.split('/').collect::<Vec<_>>();
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut components = vec![".."; base_components.len() - common];
+    components.extend(&path_components[common..]);
+    let relative_path = components.join("/");
+    // keeps the trailing / of untracked directories
+    match relative_path.is_empty() {
+        true => "./".to_string(),
+        false => relative_path,
+    }
+}
+
+/// Quotes the path the way git displays it when it has special characters:
+/// between double quotes, with C escapes (\t, \", \\...) and the other
+/// control characters in octal. Bytes above 0x7f are written in octal as well
+/// when quote_non_ascii is set (core.quotePath, on by default)
+pub fn quote_path(path: &str, quote_non_ascii: bool) -> String {
+    let is_special = |b: u8| {
+        b < 0x20 || b == b'"' || b == b'\\' || b == 0x7f || (quote_non_ascii && b > 0x7f)
+    };
+    if !path.bytes().any(is_special) {
+        return path.to_string();
+    }
+    let mut quoted = vec![b'"'];
+    for b in path.bytes() {
+        match b {
+            0x07 => quoted.extend(b"\\a"),
+            0x08 => quoted.extend(b"\\b"),
+            b'\t' => quoted.extend(b"\\t"),
+            b'\n' => quoted.extend(b"\\n"),
+            0x0b => quoted.extend(b"\\v"),
+            0x0c => quoted.extend(b"\\f"),
+            b'\r' => quoted.extend(b"\\r"),
+            b'"' | b'\\' => quoted.extend([b'\\', b]),
+            b if is_special(b) => quoted.extend(format!("\\{:03o}", b).as_bytes()),
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(b'"');
+    String::from_utf8_lossy(&quoted).to_string()
+}
@@ -0,0 +1,53 @@
+use crate::git_ident::committer_signature;
+use crate::git_refs::GitRefDatabase;
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// Returns the ref the symbolic ref points to (for ex refs/heads/main for
+/// HEAD), shortened to main if short is set. Fails if the ref is not symbolic
+pub fn git_symbolic_ref_read(repository: &Repository, name: &str, short: bool) -> Result<String> {
+    let target = repository
+        .refs()
+        .read_symbolic(name)?
+        .ok_or_else(|| Error::NotASymbolicRef(name.to_string()))?;
+    match short {
+        true => Ok(GitRefDatabase::shorten_name(&target).to_string()),
+        false => Ok(target),
+    }
+}
+
+/// Makes the ref a symbolic ref to the target. HEAD can only point to refs
+/// under refs/.
+/// With a message, the change of the hash HEAD resolves to is recorded in its
+/// reflog
+pub fn git_symbolic_ref_write(
+    repository: &Repository,
+    name: &str,
+    target: &str,
+    message: Option<&str>,
+) -> Result<()> {
+    if !GitRefDatabase::is_valid_name(target) || (name == "HEAD" && !target.starts_with("refs/")) {
+        return Err(Error::InvalidRefName(target.to_string()));
+    }
+    let refs = repository.refs();
+    let old_hash = refs.resolve(name)?;
+    refs.write_symbolic(name, target)?;
+
+    if let (Some(message), Some(new_hash)) = (message, refs.resolve(target)?) {
+        if refs.has_reflog(name) {
+            let identity = committer_signature(&repository.config()?)?.to_string();
+            refs.append_reflog(name, old_hash.as_deref(), &new_hash, &identity, message)?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the symbolic ref itself, not the ref it points to
+pub fn git_symbolic_ref_delete(repository: &Repository, name: &str) -> Result<()> {
+    let refs = repository.refs();
+    if refs.read_symbolic(name)?.is_none() {
+        return Err(Error::NotASymbolicRef(name.to_string()));
+    }
+    refs.delete(name, None)?;
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use crate::git_ident::committer_signature;
+use crate::git_object::{GitObject, TagObjects};
+use crate::git_refs::{GitRefDatabase, RefValue, NULL_HASH};
+use crate::git_repository::Repository;
+use crate::{Error, Result};
+
+/// Creates the tag refs/tags/<name> pointing to the target (a hash, a branch,
+/// a tag or HEAD).
+/// Without message, the tag is lightweight: the ref points directly to the
+/// target. With a message, an annotated tag object is written, with the
+/// committer as tagger, and the ref points to it.
+/// An existing tag is only replaced with force. Returns the hash the tag
+/// pointed to before, if any
+pub fn git_tag_create(
+    repository: &Repository,
+    name: &str,
+    target: &str,
+    message: Option<&[u8]>,
+    force: bool,
+) -> Result<Option<String>> {
+    let ref_name = format!("refs/tags/{}", name);
+    if name.starts_with('-') || !GitRefDatabase::is_valid_name(&ref_name) {
+        return Err(Error::InvalidRefName(name.to_string()));
+    }
+    let old_hash = repository.refs().resolve(&ref_name)?;
+    if old_hash.is_some() && !force {
+        return Err(Error::TagAlreadyExists(name.to_string()));
+    }
+
+    let target = repository.find_object(target)?;
+    let hash = match message {
+        None => target.hash,
+        Some(message) => {
+            let tag_objects = TagObjects {
+                object_sha: target.hash.clone(),
+                object_type: target.content_type(),
+                tag_name: name.to_string(),
+                tagger: Some(committer_signature(&repository.config()?)?),
+                extra_headers: Vec::new(),
+                tag_msg: Some(strip_message(message)),
+            };
+            let git_obj = GitObject::from_tag_objects(tag_objects)?;
+            git_obj.write(repository.object_database())?;
+            git_obj.hash
+        }
+    };
+
+    // fails if the tag was changed in the meantime
+    let expected_old_hash = old_hash.as_deref().unwrap_or(NULL_HASH);
+    repository
+        .refs()
+        .update(&ref_name, &hash, Some(expected_old_hash))?;
+    Ok(old_hash)
+}
+
+/// Names of the tags, sorted. When patterns are given, only the tags
+/// matching at least one of them are listed
+pub fn git_tag_list(repository: &Repository, patterns: &[String]) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for (ref_name, _) in repository.refs().list("refs/tags/")? {
+        let name = ref_name
+            .strip_prefix("refs/tags/")
+            .ok_or_else(|| Error::Unreachable)?;
+        if patterns.is_empty() || patterns.iter().any(|p| matches_pattern(p, name)) {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Deletes the tag and returns the hash it pointed to. A symbolic ref is not
+/// a tag and is left as it is
+pub fn git_tag_delete(repository: &Repository, name: &str) -> Result<String> {
+    let refs = repository.refs();
+    let ref_name = format!("refs/tags/{}", name);
+    let not_found = || Error::TagNotFound(name.to_string());
+    if !GitRefDatabase::is_valid_name(&ref_name) {
+        return Err(not_found());
+    }
+    let hash = match refs.read(&ref_name)? {
+        Some(RefValue::Hash(hash)) => hash,
+        Some(RefValue::Symbolic(_)) => return Err(Error::InvalidRefName(ref_name)),
+        None => return Err(not_found()),
+    };
+    refs.delete(&ref_name, Some(&hash))?;
+    Ok(hash)
+}
+
+/// Cleans up a message the way git does by default: trailing whitespace and
+/// comment lines are removed, consecutive empty lines are collapsed, and
+/// leading and trailing empty lines are removed. Each line ends with a newline
+fn strip_message(message: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::new();
+    let mut pending_empty_line = false;
+    for line in message.split(|&b| b == b'\n') {
+        if line.starts_with(b"#") {
+            continue;
+        }
+        let end = line
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(0, |pos| pos + 1);
+        let line = &line[..end];
+        if line.is_empty() {
+            pending_empty_line = !stripped.is_empty();
+            continue;
+        }
+        if pending_empty_line {
+            stripped.push(b'\n');
+            pending_empty_line = false;
+        }
+        stripped.extend(line);
+        stripped.push(b'\n');
+    }
+    stripped
+}
+
+/// Matches a name against a shell glob pattern: * matches any sequence of
+/// characters (including /), ? any character, [...] a set of characters
+/// ([!...] or [^...] to negate it, with ranges like a-z) and \ escapes the
+/// next character
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    matches_chars(&pattern, &name)
+}
+
+fn matches_chars(pattern: &[char], name: &[char]) -> bool {
+    let Some((&first, rest)) = pattern.split_first() else {
+        return name.is_empty();
+    };
+    match first {
+        '*' => (0..=name.len()).any(|skipped| matches_chars(rest, &name[skipped..])),
+        '?' => !name.is_empty() && matches_chars(rest, &name[1..]),
+        '[' => match (name.first(), match_bracket(rest, name.first().copied())) {
+            (Some(_), Some((true, rest))) => matches_chars(rest, &name[1..]),
+            (_, Some((false, _))) | (None, Some(_)) => false,
+            // an unclosed bracket is a literal [
+            (_, None) => name.first() == Some(&'[') && matches_chars(rest, &name[1..]),
+        },
+        '\\' if !rest.is_empty() => {
+            name.first() == Some(&rest[0]) && matches_chars(&rest[1..], &name[1..])
+        }
+        c => name.first() == Some(&c) && matches_chars(rest, &name[1..]),
+    }
+}
+
+/// Matches a character against the set following a [.
+/// Returns whether it matched and the rest of the pattern after the ], or None
+/// if the bracket is not closed
+fn match_bracket(pattern: &[char], c: Option<char>) -> Option<(bool, &[char])> {
+    let (negated, mut pattern) = match pattern.first() {
+        Some('!' | '^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let (&start, rest) = pattern.split_first()?;
+        // a ] right after the [ is part of the set
+        if start == ']' && !first {
+            return Some((matched != negated, rest));
+        }
+        first = false;
+        let (start, rest) = match (start, rest.split_first()) {
+            ('\\', Some((&escaped, rest))) => (escaped, rest),
+            _ => (start, rest),
+        };
+        match rest {
+            ['-', end, rest @ ..] if *end != ']' => {
+                matched |= c.is_some_and(|c| (start..=*end).contains(&c));
+                pattern = rest;
+            }
+            _ => {
+                matched |= c == Some(start);
+                pattern = rest;
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::git_ident::committer_signature;
+use crate::git_refs::NULL_HASH;
+use crate::git_repository::Repository;
+use crate::Result;
+
+/// Makes the ref point to the new value (a hash, a branch, a tag or HEAD).
+/// Symbolic refs are followed unless no_deref is set: update-ref HEAD moves the
+/// current branch.
+/// If an old value is given, the ref is only updated if it still has this value
+/// (NULL_HASH or an empty string: the ref must not exist).
+/// The update is recorded in the reflogs of the ref and, when going through it,
+/// of HEAD
+pub fn git_update_ref(
+    repository: &Repository,
+    name: &str,
+    new_value: &str,
+    old_value: Option<&str>,
+    no_deref: bool,
+    message: &str,
+) -> Result<()> {
+    let new_hash = resolve_value(repository, new_value)?;
+    if new_hash == NULL_HASH {
+        return git_delete_ref(repository, name, old_value, no_deref);
+    }
+    let expected_old_hash = old_value
+        .map(|old_value| resolve_value(repository, old_value))
+        .transpose()?;
+
+    let refs = repository.refs();
+    let target = match no_deref {
+        true => name.to_string(),
+        false => refs.follow_symbolic(name)?,
+    };
+    let old_hash = refs.update(&target, &new_hash, expected_old_hash.as_deref())?;
+
+    let mut logged_refs = vec![target.as_str()];
+    if target != name {
+        logged_refs.push(name);
+    }
+    let logged_refs = logged_refs
+        .into_iter()
+        .filter(|logged_ref| refs.has_reflog(logged_ref))
+        .collect::<Vec<_>>();
+    if !logged_refs.is_empty() {
+        let identity = committer_signature(&repository.config()?)?.to_string();
+        for logged_ref in logged_refs {
+            refs.append_reflog(
+                logged_ref,
+                old_hash.as_deref(),
+                &new_hash,
+                &identity,
+                message,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the ref, or the ref it points to if it is a symbolic ref and
+/// no_deref is not set. The old value is checked as for updates
+pub fn git_delete_ref(
+    repository: &Repository,
+    name: &str,
+    old_value: Option<&str>,
+    no_deref: bool,
+) -> Result<()> {
+    let expected_old_hash = old_value
+        .map(|old_value| resolve_value(repository, old_value))
+        .transpose()?;
+    let refs = repository.refs();
+    let target = match no_deref {
+        true => name.to_string(),
+        false => refs.follow_symbolic(name)?,
+    };
+    refs.delete(&target, expected_old_hash.as_deref())?;
+    Ok(())
+}
+
+/// Hash of a value given on the command line. The null hash and an empty
+/// value stand for a ref that does not exist
+fn resolve_value(repository: &Repository, value: &str) -> Result<String> {
+    if value.is_empty() || value == NULL_HASH {
+        return Ok(NULL_HASH.to_string());
+    }
+    Ok(repository.find_object(value)?.hash)
+}
@@ -1,13 +1,14 @@
-use crate::git_object::GitObject;
+use crate::git_index::GitIndex;
+use crate::git_repository::Repository;
 use crate::Result;
 
-/// Writes the current directory to the .git folder of the current directory
-/// All trees and blobs are written
-pub fn git_write_tree() -> Result<()> {
-    let git_obj = GitObject::from_dir(".")?;
-
-    git_obj.write(".", true)?;
-    println!("{}", git_obj.hash);
-
-    Ok(())
+/// Writes the trees corresponding to the staging area (.git/index)
+/// Blobs are already written when files are added, only the trees are written
+/// The index is saved back so that its cache tree is up to date
+/// Returns the hash of the root tree
+pub fn git_write_tree(repository: &Repository) -> Result<String> {
+    let mut index = GitIndex::read(repository)?;
+    let hash = index.write_tree(repository.object_database())?;
+    index.write(repository)?;
+    Ok(hash)
 }
@@ -0,0 +1,97 @@
+//! A small git implementation.
+//!
+//! The main entry points are:
+//! - [`Repository`]: finds the .git folder of a repository, resolves refs and reads
+//!   objects
+//! - [`git_object_database::GitObjectDatabase`]: reads and writes objects, either
+//!   loose or stored in packs
+//! - [`git_object::GitObject`]: parsed git objects (blobs, trees, commits, tags)
+//! - [`git_pack`]: smart http discovery ([`git_pack::UploadPackDiscovery`]) and
+//!   packfile parsing and writing ([`git_pack::GitPack`])
+//! - one module per command (`git_clone`, `git_cat_file`, ...), returning data that
+//!   the command line interface prints
+
+pub mod error;
+/// `add`: adds files to the staging area
+pub mod git_add;
+/// `branch`: lists, creates, deletes and renames branches
+pub mod git_branch;
+/// `cat-file`: content, type or size of an object
+pub mod git_cat_file;
+/// `checkout` and `switch`: updates the working tree and moves HEAD
+pub mod git_checkout;
+/// `clone`: clones a repository over smart http
+pub mod git_clone;
+/// `commit`: records the content of the index in a new commit
+pub mod git_commit;
+/// `commit-tree`: creates a commit object
+pub mod git_commit_tree;
+/// Configuration files (.git/config, ~/.gitconfig...)
+pub mod git_config;
+/// `diff`: changes between trees, the index and the working tree
+pub mod git_diff;
+/// `fetch`: downloads objects and refs from a remote repository
+pub mod git_fetch;
+/// `hash-object`: creates a blob object from a file
+pub mod git_hash_object;
+/// Author and committer identities
+pub mod git_ident;
+/// Ignore rules: .gitignore, .git/info/exclude and core.excludesFile
+pub mod git_ignore;
+/// Staging area (.git/index)
+pub mod git_index;
+/// `index-pack`: checks a pack and writes its index
+pub mod git_index_pack;
+/// `init`: creates an empty repository
+pub mod git_init;
+/// Line diffs (Myers, patience, histogram) and unified hunks
+pub mod git_line_diff;
+/// `log`: shows the commit history
+pub mod git_log;
+/// `ls-tree`: lists the content of a tree
+pub mod git_ls_tree;
+/// `merge`: joins the history of another commit into the current branch
+pub mod git_merge;
+/// `merge-base`: best common ancestors of commits
+pub mod git_merge_base;
+/// Git objects and their parsing
+pub mod git_object;
+/// Storage of the objects, loose or in packs
+pub mod git_object_database;
+/// Smart http protocol and packfiles
+pub mod git_pack;
+/// Pack indexes (.idx)
+pub mod git_pack_index;
+/// `pack-objects`: writes a pack of objects
+pub mod git_pack_objects;
+/// `push`: updates remote refs along with the objects they need
+pub mod git_push;
+/// References: HEAD, branches and tags, loose or packed
+pub mod git_refs;
+/// Remotes and the refspecs mapping their refs to local refs
+pub mod git_remote;
+/// Discovery of the repository and refs
+pub mod git_repository;
+/// `rev-list`: lists the commits of a history walk
+pub mod git_rev_list;
+/// `rev-parse`: resolves revisions to hashes or ref names
+pub mod git_rev_parse;
+/// Walks of the commit history (log, rev-list, merge bases)
+pub mod git_rev_walk;
+/// Revision expressions (HEAD~2, main^{tree}, v1.0:src/main.rs...)
+pub mod git_revision;
+/// `show-ref`: lists the refs
+pub mod git_show_ref;
+/// `status`: differences between HEAD, the index and the working tree
+pub mod git_status;
+/// `symbolic-ref`: reads, writes and deletes symbolic refs
+pub mod git_symbolic_ref;
+/// `tag`: creates, lists and deletes tags
+pub mod git_tag;
+/// `update-ref`: updates or deletes a ref safely
+pub mod git_update_ref;
+/// `write-tree`: creates a tree object from the staging area
+pub mod git_write_tree;
+
+pub use error::{Error, Result};
+pub use git_repository::Repository;
@@ -1,26 +1,55 @@
-mod error;
-mod git_cat_file;
-mod git_clone;
-mod git_commit_tree;
-mod git_hash_object;
-mod git_init;
-mod git_ls_tree;
-mod git_object;
-mod git_pack;
-mod git_write_tree;
-
+use std::io::{Read, Write};
 use std::path::PathBuf;
 
-pub use error::{Error, Result};
-use git_cat_file::git_cat_file;
-use git_clone::git_clone;
-use git_commit_tree::git_commit_tree;
-use git_hash_object::git_hash_object;
-use git_init::git_init;
-use git_ls_tree::git_ls_tree;
-use git_write_tree::git_write_tree;
+use git_starter_rust::git_add::git_add;
+use git_starter_rust::git_branch::{
+    branch_tracking, detached_head_description, git_branch_create, git_branch_delete,
+    git_branch_list, git_branch_rename, git_branch_set_upstream, git_branch_unset_upstream,
+    BranchCreateOptions, BranchKind,
+};
+use git_starter_rust::git_cat_file::{git_cat_file, CatFileMode, CatFileOutput};
+use git_starter_rust::git_checkout::{
+    branch_ref, git_checkout_paths, git_switch, SwitchOptions, SwitchOutput,
+};
+use git_starter_rust::git_clone::git_clone;
+use git_starter_rust::git_commit::git_commit;
+use git_starter_rust::git_commit_tree::git_commit_tree;
+use git_starter_rust::git_diff::{git_diff, DiffOptions, DiffOutput};
+use git_starter_rust::git_fetch::{
+    describe_remote_ref, git_fetch, FetchOptions, FetchOutput, FetchedRef, FetchedRefStatus,
+};
+use git_starter_rust::git_hash_object::git_hash_object;
+use git_starter_rust::git_ident::DateFormat;
+use git_starter_rust::git_index_pack::{git_index_pack, git_index_pack_stdin};
+use git_starter_rust::git_init::git_init;
+use git_starter_rust::git_line_diff::DiffAlgorithm;
+use git_starter_rust::git_log::{git_log, LogFormat, LogOptions};
+use git_starter_rust::git_ls_tree::git_ls_tree;
+use git_starter_rust::git_merge::{
+    git_merge, merge_in_progress_message, ConflictStyle, MergeOptions, MergeStatus,
+};
+use git_starter_rust::git_merge_base::{git_merge_base, git_merge_base_is_ancestor};
+use git_starter_rust::git_pack::PackWriteOptions;
+use git_starter_rust::git_pack_objects::{git_pack_objects, write_pack_files, PackObjectsOptions};
+use git_starter_rust::git_push::{git_push, PushOptions, PushOutput, PushedRefStatus};
+use git_starter_rust::git_refs::{GitRefDatabase, RefValue, NULL_HASH};
+use git_starter_rust::git_rev_list::git_rev_list;
+use git_starter_rust::git_rev_parse::{git_rev_parse, RevParseOptions};
+use git_starter_rust::git_rev_walk::{RevWalkOptions, RevWalkOrder};
+use git_starter_rust::git_revision::resolve_revision;
+use git_starter_rust::git_show_ref::{git_show_ref, ShowRefOptions};
+use git_starter_rust::git_status::{
+    git_status, quote_path, relative_path, FileStatus, Status,
+};
+use git_starter_rust::git_symbolic_ref::{
+    git_symbolic_ref_delete, git_symbolic_ref_read, git_symbolic_ref_write,
+};
+use git_starter_rust::git_tag::{git_tag_create, git_tag_delete, git_tag_list};
+use git_starter_rust::git_update_ref::{git_delete_ref, git_update_ref};
+use git_starter_rust::git_write_tree::git_write_tree;
+use git_starter_rust::{Error, Repository, Result};
 
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Args, CommandFactory, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(version, about="Custom git", long_about=None )]
@@ -33,6 +62,16 @@ struct Cli {
 enum Commands {
     /// Create an empty Git repository.
     Init,
+    /// Add file contents to the index
+    Add {
+        #[arg(short, long, help = "Add the files even if they are ignored")]
+        force: bool,
+        #[arg(
+            required = true,
+            help = "Files to add. Directories are added recursively"
+        )]
+        paths: Vec<PathBuf>,
+    },
     ///  Provide content or type and size information for repository objects
     CatFile {
         #[arg(
@@ -88,16 +127,545 @@ enum Commands {
         #[arg(help = "hash corresponding to a given git <object>")]
         hash: String,
     },
-    /// Writes the full directory (not just what is in the staging area like in regular git)
+    /// Show the working tree status
+    Status {
+        #[arg(short, long, help = "Give the output in the short-format")]
+        short: bool,
+        #[arg(
+            long,
+            value_name = "VERSION",
+            num_args = 0..=1,
+            default_missing_value = "v1",
+            value_parser = ["v1"],
+            help = "Give the output in an easy-to-parse format for scripts"
+        )]
+        porcelain: Option<String>,
+    },
+    /// Create a tree object from the current index
     WriteTree,
-    /// Writes the commit object based on a tree and a parent commit
+    /// Record changes to the repository
+    Commit {
+        #[arg(
+            short,
+            long,
+            help = "Use the given message as the commit message. Required unless concluding a merge"
+        )]
+        message: Option<String>,
+    },
+    /// Writes the commit object based on a tree and its parent commits
+    /// The message is read from the standard input if neither -m nor -F is given
     CommitTree {
         #[arg(help = "sha1 of the tree")]
         tree_sha: String,
-        #[arg(short, help = "sha of parent commit")]
-        parent_commit_sha: String,
-        #[arg(short, help = "commit message")]
+        #[arg(short, help = "sha of a parent commit. Can be repeated")]
+        parent_commit_sha: Vec<String>,
+        #[arg(
+            short,
+            help = "commit message. Each -m is a separate paragraph",
+            conflicts_with = "file"
+        )]
+        message: Vec<String>,
+        #[arg(
+            short = 'F',
+            help = "read the commit message from a file (- for stdin)"
+        )]
+        file: Option<PathBuf>,
+    },
+    /// Create, list or delete tags
+    /// Without message, the tag is lightweight: it points directly to the commit
+    #[command(group(ArgGroup::new("tag_message").args(["message", "file"])))]
+    Tag {
+        #[arg(
+            short,
+            long,
+            help = "List tags. With patterns, only the matching tags are listed"
+        )]
+        list: bool,
+        #[arg(
+            short,
+            long,
+            conflicts_with_all = ["list", "tag_message", "force"],
+            help = "Delete existing tags with the given names"
+        )]
+        delete: bool,
+        #[arg(short, requires = "tag_message", help = "Make an annotated tag object")]
+        annotate: bool,
+        #[arg(
+            short,
+            conflicts_with = "file",
+            help = "tag message. Each -m is a separate paragraph. Implies -a"
+        )]
+        message: Vec<String>,
+        #[arg(
+            short = 'F',
+            help = "read the tag message from a file (- for stdin). Implies -a"
+        )]
+        file: Option<PathBuf>,
+        #[arg(short, long, help = "Replace an existing tag")]
+        force: bool,
+        #[arg(help = "<tagname> [<commit>], the patterns to list or the tags to delete")]
+        names: Vec<String>,
+    },
+    /// Update the object name stored in a ref safely
+    UpdateRef {
+        #[arg(short, help = "Delete the ref, after checking <OLDVALUE> if given")]
+        delete: bool,
+        #[arg(
+            long,
+            help = "Update the symbolic ref itself instead of the ref it points to"
+        )]
+        no_deref: bool,
+        #[arg(
+            short,
+            default_value = "",
+            help = "Reason of the update, for the reflog"
+        )]
         message: String,
+        #[arg(help = "ref to update, for ex refs/heads/main")]
+        name: String,
+        #[arg(help = "<NEWVALUE> [<OLDVALUE>], or [<OLDVALUE>] with -d", num_args = 0..=2)]
+        values: Vec<String>,
+    },
+    /// Read, modify and delete symbolic refs
+    SymbolicRef {
+        #[arg(
+            short,
+            long,
+            conflicts_with = "target",
+            help = "Delete the symbolic ref"
+        )]
+        delete: bool,
+        #[arg(long, help = "Shorten the ref name (refs/heads/main -> main)")]
+        short: bool,
+        #[arg(short, help = "Reason of the update, for the reflog")]
+        message: Option<String>,
+        #[arg(help = "symbolic ref, for ex HEAD")]
+        name: String,
+        #[arg(help = "ref the symbolic ref should point to")]
+        target: Option<String>,
+    },
+    /// List references in a local repository
+    ShowRef {
+        #[arg(long, help = "Only show branches")]
+        heads: bool,
+        #[arg(long, help = "Only show tags")]
+        tags: bool,
+        #[arg(
+            long,
+            help = "Show the HEAD reference, even if it would be filtered out"
+        )]
+        head: bool,
+        #[arg(
+            short,
+            long,
+            help = "Dereference tags into object IDs, shown as <tag>^{}"
+        )]
+        dereference: bool,
+        #[arg(
+            short = 's',
+            long,
+            help = "Only show the object ID, not the reference name"
+        )]
+        hash: bool,
+        #[arg(long, help = "Only show refs that exactly match the given full names")]
+        verify: bool,
+        #[arg(short, long, help = "Do not print any results to stdout")]
+        quiet: bool,
+        #[arg(help = "only show refs whose name ends with one of the patterns")]
+        patterns: Vec<String>,
+    },
+    /// Pick out and massage parameters: resolves revisions to hashes
+    RevParse {
+        #[arg(
+            long,
+            help = "Check that exactly one parameter is given and that it is a valid object"
+        )]
+        verify: bool,
+        #[arg(
+            short,
+            long,
+            help = "With --verify, exit with a non-zero status without message if the object is invalid"
+        )]
+        quiet: bool,
+        #[arg(
+            long,
+            value_name = "LENGTH",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "7",
+            help = "Shorten the object names to a unique prefix of at least LENGTH characters"
+        )]
+        short: Option<usize>,
+        #[arg(long, help = "Print the full name of the refs (refs/heads/main)")]
+        symbolic_full_name: bool,
+        #[arg(long, help = "Print the short name of the refs (main)")]
+        abbrev_ref: bool,
+        #[arg(long, help = "Show the path of the .git directory")]
+        git_dir: bool,
+        #[arg(
+            long,
+            help = "Show the absolute path of the top-level directory of the working tree"
+        )]
+        show_toplevel: bool,
+        #[arg(
+            long,
+            help = "Print true if the current directory is inside the working tree"
+        )]
+        is_inside_work_tree: bool,
+        #[arg(help = "revisions, for ex HEAD~2, main^{tree}, v1.0:src/main.rs or a..b")]
+        revisions: Vec<String>,
+    },
+    /// Show commit logs
+    Log {
+        #[arg(long, help = "Shorthand for --pretty=oneline --abbrev-commit")]
+        oneline: bool,
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            conflicts_with = "oneline",
+            help = "oneline, short, medium, full, fuller, raw, format:<template> or tformat:<template>"
+        )]
+        pretty: Option<String>,
+        #[arg(
+            long,
+            value_name = "TEMPLATE",
+            conflicts_with_all = ["oneline", "pretty"],
+            help = "Template with placeholders (%H, %h, %an, %ad, %s, %b...), each commit on its own line"
+        )]
+        format: Option<String>,
+        #[arg(long, help = "Show abbreviated commit hashes")]
+        abbrev_commit: bool,
+        #[arg(
+            long,
+            default_value = "default",
+            help = "Date format: default, iso, iso-strict, rfc, short, raw, unix or relative"
+        )]
+        date: String,
+        #[command(flatten)]
+        walk: RevWalkArgs,
+        #[arg(help = "revisions or ranges (a..b, ^a, a...b). Defaults to HEAD")]
+        revisions: Vec<String>,
+    },
+    /// Lists commit objects in reverse chronological order
+    RevList {
+        #[arg(long, help = "Print the number of commits instead of their hashes")]
+        count: bool,
+        #[command(flatten)]
+        walk: RevWalkArgs,
+        #[arg(
+            required_unless_present = "all",
+            help = "revisions or ranges (a..b, ^a, a...b)"
+        )]
+        revisions: Vec<String>,
+    },
+    /// Show changes between commits, the index and the working tree
+    #[command(group(ArgGroup::new("output").args(["stat", "name_status", "name_only"])))]
+    #[command(group(
+        ArgGroup::new("algorithm").args(["diff_algorithm", "patience", "histogram", "minimal"])
+    ))]
+    Diff {
+        #[arg(
+            long,
+            visible_alias = "staged",
+            help = "Compare the index to HEAD (or to the given commit)"
+        )]
+        cached: bool,
+        #[arg(
+            short = 'U',
+            long,
+            value_name = "N",
+            default_value_t = 3,
+            help = "Number of context lines around the changes"
+        )]
+        unified: usize,
+        #[arg(long, help = "Show the number of changed lines of each file")]
+        stat: bool,
+        #[arg(long, help = "Show only the status and the names of changed files")]
+        name_status: bool,
+        #[arg(long, help = "Show only the names of changed files")]
+        name_only: bool,
+        #[arg(
+            long,
+            value_name = "ALGORITHM",
+            help = "myers (default), minimal, patience or histogram"
+        )]
+        diff_algorithm: Option<String>,
+        #[arg(long, help = "Use the patience diff algorithm")]
+        patience: bool,
+        #[arg(long, help = "Use the histogram diff algorithm")]
+        histogram: bool,
+        #[arg(
+            long,
+            help = "Spend extra time to make sure the smallest diff is produced"
+        )]
+        minimal: bool,
+        #[arg(help = "commits or ranges (a..b, a...b) to compare")]
+        revisions: Vec<String>,
+        #[arg(last = true, help = "limit the diff to these paths")]
+        paths: Vec<PathBuf>,
+    },
+    /// Switch branches or restore working tree files
+    Checkout {
+        #[arg(
+            short = 'b',
+            value_name = "NEW_BRANCH",
+            help = "Create a new branch at the target and switch to it"
+        )]
+        new_branch: Option<String>,
+        #[arg(short, long, help = "Throw away the local changes")]
+        force: bool,
+        #[arg(
+            long,
+            help = "Detach HEAD at the commit, even if the target is a branch"
+        )]
+        detach: bool,
+        #[arg(help = "branch or commit to switch to, or where the paths are restored from")]
+        target: Option<String>,
+        #[arg(last = true, help = "restore these paths instead of switching")]
+        paths: Vec<PathBuf>,
+    },
+    /// Switch branches
+    Switch {
+        #[arg(
+            short = 'c',
+            long = "create",
+            value_name = "NEW_BRANCH",
+            help = "Create a new branch at the target (HEAD by default) and switch to it"
+        )]
+        new_branch: Option<String>,
+        #[arg(short, long, help = "Detach HEAD at the commit")]
+        detach: bool,
+        #[arg(
+            short,
+            long,
+            visible_alias = "discard-changes",
+            help = "Throw away the local changes"
+        )]
+        force: bool,
+        #[arg(
+            required_unless_present = "new_branch",
+            help = "branch to switch to (- for the previous one)"
+        )]
+        target: Option<String>,
+    },
+    /// List, create, rename or delete branches
+    #[command(group(ArgGroup::new("action").args([
+        "delete", "force_delete", "move_branch", "force_move", "set_upstream_to", "unset_upstream"
+    ])))]
+    Branch {
+        #[arg(
+            short,
+            long,
+            help = "Delete branches, if they are merged in their upstream or HEAD"
+        )]
+        delete: bool,
+        #[arg(short = 'D', help = "Delete branches, even if they are not merged")]
+        force_delete: bool,
+        #[arg(
+            short = 'm',
+            long = "move",
+            help = "Rename a branch (the current one by default) with its reflog"
+        )]
+        move_branch: bool,
+        #[arg(short = 'M', help = "Rename a branch, even if the new name exists")]
+        force_move: bool,
+        #[arg(
+            short,
+            long,
+            help = "Reset the branch to the start point if it exists. With -d, delete even if not merged"
+        )]
+        force: bool,
+        #[arg(short, long, help = "List or delete remote-tracking branches")]
+        remotes: bool,
+        #[arg(short, long, help = "List both local and remote-tracking branches")]
+        all: bool,
+        #[arg(
+            short,
+            long,
+            action = clap::ArgAction::Count,
+            help = "Show the hash and subject of the tips, and how they compare to their upstream. Twice to also show the upstream"
+        )]
+        verbose: u8,
+        #[arg(
+            short,
+            long,
+            conflicts_with = "no_track",
+            help = "Set the start point as upstream of the new branch"
+        )]
+        track: bool,
+        #[arg(
+            long,
+            help = "Do not set the start point as upstream of the new branch"
+        )]
+        no_track: bool,
+        #[arg(
+            short = 'u',
+            long,
+            value_name = "UPSTREAM",
+            help = "Set the upstream of a branch (the current one by default)"
+        )]
+        set_upstream_to: Option<String>,
+        #[arg(
+            long,
+            help = "Remove the upstream of a branch (the current one by default)"
+        )]
+        unset_upstream: bool,
+        #[arg(
+            help = "<branchname> [<start-point>] to create, the branches to delete, [<oldbranch>] <newbranch> to rename"
+        )]
+        names: Vec<String>,
+    },
+    /// Join the history of another commit into the current branch
+    Merge {
+        #[arg(
+            long,
+            help = "Create a merge commit even when the branch can be fast-forwarded"
+        )]
+        no_ff: bool,
+        #[arg(long, help = "Merge histories that have no common ancestor")]
+        allow_unrelated_histories: bool,
+        #[arg(
+            long,
+            value_name = "STYLE",
+            value_parser = ["merge", "diff3", "zdiff3"],
+            help = "How conflicts are written in the files, overriding merge.conflictStyle"
+        )]
+        conflict: Option<String>,
+        #[arg(help = "commit to merge (- for the previous branch)")]
+        revision: String,
+    },
+    /// Find the best common ancestors of commits
+    MergeBase {
+        #[arg(
+            short,
+            long,
+            help = "Print all the best common ancestors, not only one"
+        )]
+        all: bool,
+        #[arg(
+            long,
+            conflicts_with = "is_ancestor",
+            help = "Find the best common ancestors of all the commits, for an n-way merge"
+        )]
+        octopus: bool,
+        #[arg(
+            long,
+            conflicts_with = "all",
+            help = "Exit with status 0 if the first commit is an ancestor of the second one, 1 otherwise"
+        )]
+        is_ancestor: bool,
+        #[arg(
+            required = true,
+            help = "the commit whose common ancestors with the others are searched, then the others"
+        )]
+        commits: Vec<String>,
+    },
+    /// Download objects and refs from another repository
+    Fetch {
+        #[arg(
+            short,
+            long,
+            help = "Update the local refs even when they are not fast-forwards"
+        )]
+        force: bool,
+        #[arg(help = "remote name or url (default: the remote of the current branch, or origin)")]
+        remote: Option<String>,
+        #[arg(help = "refs to fetch and where to store them, [+]<src>[:<dst>]")]
+        refspecs: Vec<String>,
+    },
+    /// Update remote refs along with associated objects
+    Push {
+        #[arg(
+            short,
+            long,
+            help = "Update the remote refs even when they are not fast-forwards"
+        )]
+        force: bool,
+        #[arg(
+            long,
+            value_name = "REF[:EXPECT]",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "",
+            help = "Force the update only if the remote ref has the expected value (by default, the one of its remote-tracking branch)"
+        )]
+        force_with_lease: Vec<String>,
+        #[arg(help = "remote name or url (default: the remote of the current branch, or origin)")]
+        remote: Option<String>,
+        #[arg(help = "what to push and which remote ref to update, [+]<src>[:<dst>]")]
+        refspecs: Vec<String>,
+    },
+    /// Write a pack of the objects (or of the objects needed by the revisions)
+    /// read from the standard input
+    PackObjects {
+        #[arg(
+            long,
+            help = "Read revisions instead of objects, ^<rev> excluding the objects reachable from <rev>"
+        )]
+        revs: bool,
+        #[arg(
+            long,
+            requires = "stdout",
+            conflicts_with = "base_name",
+            help = "Make deltas against objects of the excluded revisions, without writing them"
+        )]
+        thin: bool,
+        #[arg(long, help = "Write the pack to the standard output")]
+        stdout: bool,
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 10,
+            help = "Number of objects tried as the delta base of each object"
+        )]
+        window: usize,
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 50,
+            help = "Maximum length of the delta chains"
+        )]
+        depth: usize,
+        #[arg(
+            long,
+            value_name = "LEVEL",
+            default_value_t = 6,
+            value_parser = clap::value_parser!(u32).range(0..=9),
+            help = "Zlib compression level, from 0 (none) to 9"
+        )]
+        compression: u32,
+        #[arg(long, help = "Give the offset of delta bases instead of their hash")]
+        delta_base_offset: bool,
+        #[arg(
+            required_unless_present = "stdout",
+            conflicts_with = "stdout",
+            help = "write the pack to <base-name>-<checksum>.pack, and its index"
+        )]
+        base_name: Option<String>,
+    },
+    /// Check a pack and write its index
+    IndexPack {
+        #[arg(
+            short,
+            value_name = "INDEX_FILE",
+            help = "Write the index to this file"
+        )]
+        output: Option<PathBuf>,
+        #[arg(
+            long,
+            conflicts_with_all = ["output", "pack_file"],
+            help = "Read the pack from the standard input and store it in the repository"
+        )]
+        stdin: bool,
+        #[arg(
+            long,
+            requires = "stdin",
+            conflicts_with = "pack_file",
+            help = "Add the missing delta bases of a thin pack from the repository"
+        )]
+        fix_thin: bool,
+        #[arg(required_unless_present = "stdin", help = "Path to the .pack file")]
+        pack_file: Option<PathBuf>,
     },
     /// Clones a repository url in a target directory
     Clone {
@@ -108,13 +676,74 @@ enum Commands {
     },
 }
 
+/// Options of the history walk shared by log and rev-list
+#[derive(Args)]
+struct RevWalkArgs {
+    #[arg(
+        short = 'n',
+        long,
+        value_name = "NUMBER",
+        help = "Limit the number of commits"
+    )]
+    max_count: Option<usize>,
+    #[arg(long, help = "Only follow the first parent of merge commits")]
+    first_parent: bool,
+    #[arg(long, help = "Also start from HEAD and every ref")]
+    all: bool,
+    #[arg(long, help = "Show the oldest commits first")]
+    reverse: bool,
+    #[arg(
+        long,
+        conflicts_with = "date_order",
+        help = "Show no parents before all of their children, without interleaving branches"
+    )]
+    topo_order: bool,
+    #[arg(
+        long,
+        help = "Show no parents before all of their children, by commit date"
+    )]
+    date_order: bool,
+    #[arg(
+        long,
+        help = "Only commits whose author contains the text. Can be repeated"
+    )]
+    author: Vec<String>,
+    #[arg(
+        long,
+        help = "Only commits whose message contains the text. Can be repeated"
+    )]
+    grep: Vec<String>,
+}
+
+impl RevWalkArgs {
+    fn options(&self) -> RevWalkOptions {
+        let order = match (self.topo_order, self.date_order) {
+            (true, _) => RevWalkOrder::Topo,
+            (_, true) => RevWalkOrder::Date,
+            _ => RevWalkOrder::Walk,
+        };
+        RevWalkOptions {
+            first_parent: self.first_parent,
+            all: self.all,
+            reverse: self.reverse,
+            max_count: self.max_count,
+            order,
+            authors: self.author.clone(),
+            greps: self.grep.clone(),
+        }
+    }
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     // You can check for the existence of subcommands, and if found use their
     // matches just as you would the top level cmd
     match &cli.command {
-        Commands::Init => git_init(".")?,
+        Commands::Init => {
+            git_init(".")?;
+            println!("Initialized git directory");
+        }
         // TODO: There must be a better way. Can we have incompatible args as an enum?
         Commands::CatFile {
             pretty_print,
@@ -123,41 +752,1244 @@ fn main() -> Result<()> {
             size,
             hash,
         } => {
-            git_cat_file(
-                *pretty_print,
-                *exit_with_zero_status_if_exists,
-                *type_obj,
-                *size,
-                hash,
-            )?;
+            let mode = if *exit_with_zero_status_if_exists {
+                CatFileMode::Exists
+            } else if *pretty_print {
+                CatFileMode::PrettyPrint
+            } else if *size {
+                CatFileMode::Size
+            } else if *type_obj {
+                CatFileMode::Type
+            } else {
+                return Ok(());
+            };
+            match git_cat_file(&Repository::discover()?, mode, hash)? {
+                CatFileOutput::Exists => println!("Valid object"),
+                CatFileOutput::Content(content) => std::io::stdout().write_all(&content)?,
+                CatFileOutput::Type(content_type) => println!("{}", content_type),
+                CatFileOutput::Size(size) => println!("{}", size),
+            }
+        }
+        Commands::Add { force, paths } => {
+            git_add(&Repository::discover()?, paths.as_slice(), *force)?
+        }
+        Commands::HashObject { write_obj, file } => {
+            // the repository is only needed to write the object
+            let repository = match write_obj {
+                true => Some(Repository::discover()?),
+                false => None,
+            };
+            println!("{}", git_hash_object(repository.as_ref(), file)?);
         }
-        Commands::HashObject { write_obj, file } => git_hash_object(*write_obj, file)?,
         Commands::LsTree {
             name_only,
             recursive,
             long,
             hash,
-        } => git_ls_tree(*name_only, *recursive, *long, hash)?,
-        Commands::WriteTree => git_write_tree()?,
+        } => {
+            let entries = git_ls_tree(&Repository::discover()?, hash, *recursive, *long)?;
+            for entry in entries {
+                if *name_only {
+                    println!("{}", entry.path);
+                } else if *long {
+                    let size = entry
+                        .size
+                        .map(|size| size.to_string())
+                        .unwrap_or_else(|| "-".to_string());
+                    println!(
+                        "{:0>6} {} {} {:>7}\t{}",
+                        entry.mode, entry.content_type, entry.hash, size, entry.path
+                    );
+                } else {
+                    println!(
+                        "{:0>6} {} {}\t{}",
+                        entry.mode, entry.content_type, entry.hash, entry.path
+                    );
+                }
+            }
+        }
+        Commands::Status { short, porcelain } => {
+            let repository = Repository::discover()?;
+            let status = git_status(&repository)?;
+            let quote_non_ascii = repository
+                .config()?
+                .get_bool("core.quotePath")?
+                .unwrap_or(true);
+            if porcelain.is_some() {
+                // paths are always relative to the root of the repository
+                print_short_status(&status, "", quote_non_ascii);
+            } else if *short {
+                let current_dir = repository.path_in_work_tree(".")?;
+                print_short_status(&status, &current_dir, quote_non_ascii);
+            } else {
+                let current_dir = repository.path_in_work_tree(".")?;
+                print_long_status(&status, &current_dir, quote_non_ascii);
+            }
+        }
+        Commands::WriteTree => println!("{}", git_write_tree(&Repository::discover()?)?),
+        Commands::Commit { message } => {
+            let repository = Repository::discover()?;
+            let message = match message {
+                Some(message) => message.clone(),
+                None => merge_in_progress_message(&repository)?
+                    .filter(|message| !message.is_empty())
+                    .ok_or(Error::EmptyCommitMessage)?,
+            };
+            let output = git_commit(&repository, &message)?;
+            let branch = match &output.head_ref {
+                Some(head_ref) => head_ref.strip_prefix("refs/heads/").unwrap_or(head_ref),
+                None => "detached HEAD",
+            };
+            let root = match output.is_root {
+                true => " (root-commit)",
+                false => "",
+            };
+            let subject = message.lines().next().unwrap_or_default();
+            println!("[{}{} {}] {}", branch, root, &output.hash[..7], subject);
+        }
         Commands::CommitTree {
             tree_sha,
             parent_commit_sha,
             message,
-        } => git_commit_tree(tree_sha, parent_commit_sha, message)?,
+            file,
+        } => {
+            let message = match file {
+                _ if !message.is_empty() => message
+                    .iter()
+                    .map(|paragraph| format!("{}\n", paragraph))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .into_bytes(),
+                Some(file) if file.as_os_str() != "-" => std::fs::read(file)?,
+                _ => {
+                    let mut message = Vec::new();
+                    std::io::stdin().read_to_end(&mut message)?;
+                    message
+                }
+            };
+            let repository = Repository::discover()?;
+            let object_database = repository.object_database();
+            let tree_sha =
+                object_database.peel(&resolve_revision(&repository, tree_sha)?, "tree")?;
+            let parents_sha = parent_commit_sha
+                .iter()
+                .map(|parent| {
+                    object_database.peel(&resolve_revision(&repository, parent)?, "commit")
+                })
+                .collect::<Result<Vec<_>>>()?;
+            println!(
+                "{}",
+                git_commit_tree(&repository, &tree_sha, &parents_sha, &message)?
+            )
+        }
+        Commands::Tag {
+            list,
+            delete,
+            annotate: _,
+            message,
+            file,
+            force,
+            names,
+        } => {
+            let repository = Repository::discover()?;
+            if *delete {
+                for name in names {
+                    let hash = git_tag_delete(&repository, name)?;
+                    println!("Deleted tag '{}' (was {})", name, &hash[..7]);
+                }
+            } else if *list || names.is_empty() {
+                for name in git_tag_list(&repository, names)? {
+                    println!("{}", name);
+                }
+            } else {
+                let [name, target @ ..] = names.as_slice() else {
+                    return Err(Error::Unreachable);
+                };
+                if target.len() > 1 {
+                    let mut command = Cli::command();
+                    let tag_command = command
+                        .find_subcommand_mut("tag")
+                        .ok_or_else(|| Error::Unreachable)?;
+                    tag_command
+                        .error(clap::error::ErrorKind::TooManyValues, "too many arguments")
+                        .exit();
+                }
+                let target = target.first().map_or("HEAD", String::as_str);
+                let message = match file {
+                    _ if !message.is_empty() => Some(message.join("\n\n").into_bytes()),
+                    Some(file) if file.as_os_str() != "-" => Some(std::fs::read(file)?),
+                    Some(_) => {
+                        let mut message = Vec::new();
+                        std::io::stdin().read_to_end(&mut message)?;
+                        Some(message)
+                    }
+                    None => None,
+                };
+                let old_hash =
+                    git_tag_create(&repository, name, target, message.as_deref(), *force)?;
+                let new_hash = repository
+                    .refs()
+                    .resolve(&format!("refs/tags/{}", name))?
+                    .ok_or_else(|| Error::Unreachable)?;
+                if let Some(old_hash) = old_hash.filter(|old_hash| *old_hash != new_hash) {
+                    println!("Updated tag '{}' (was {})", name, &old_hash[..7]);
+                }
+            }
+        }
+        Commands::UpdateRef {
+            delete,
+            no_deref,
+            message,
+            name,
+            values,
+        } => {
+            let repository = Repository::discover()?;
+            match (delete, values.as_slice()) {
+                (true, old_value) if old_value.len() <= 1 => git_delete_ref(
+                    &repository,
+                    name,
+                    old_value.first().map(String::as_str),
+                    *no_deref,
+                )?,
+                (false, [new_value, old_value @ ..]) => git_update_ref(
+                    &repository,
+                    name,
+                    new_value,
+                    old_value.first().map(String::as_str),
+                    *no_deref,
+                    message,
+                )?,
+                _ => {
+                    let mut command = Cli::command();
+                    let update_ref_command = command
+                        .find_subcommand_mut("update-ref")
+                        .ok_or_else(|| Error::Unreachable)?;
+                    update_ref_command
+                        .error(
+                            clap::error::ErrorKind::WrongNumberOfValues,
+                            "expected <NEWVALUE> [<OLDVALUE>], or -d [<OLDVALUE>]",
+                        )
+                        .exit();
+                }
+            }
+        }
+        Commands::SymbolicRef {
+            delete,
+            short,
+            message,
+            name,
+            target,
+        } => {
+            let repository = Repository::discover()?;
+            match target {
+                _ if *delete => git_symbolic_ref_delete(&repository, name)?,
+                Some(target) => {
+                    git_symbolic_ref_write(&repository, name, target, message.as_deref())?
+                }
+                None => println!("{}", git_symbolic_ref_read(&repository, name, *short)?),
+            }
+        }
+        Commands::ShowRef {
+            heads,
+            tags,
+            head,
+            dereference,
+            hash,
+            verify,
+            quiet,
+            patterns,
+        } => {
+            let options = ShowRefOptions {
+                heads: *heads,
+                tags: *tags,
+                include_head: *head,
+                dereference: *dereference,
+                verify: *verify,
+            };
+            let refs = git_show_ref(&Repository::discover()?, patterns, &options)?;
+            if refs.is_empty() {
+                // like git, nothing is printed but the exit code tells there is no match
+                std::process::exit(1);
+            }
+            for (name, ref_hash) in refs {
+                match (quiet, hash) {
+                    (true, _) => {}
+                    (false, true) => println!("{}", ref_hash),
+                    (false, false) => println!("{} {}", ref_hash, name),
+                }
+            }
+        }
+        Commands::RevParse {
+            verify,
+            quiet,
+            short,
+            symbolic_full_name,
+            abbrev_ref,
+            git_dir,
+            show_toplevel,
+            is_inside_work_tree,
+            revisions,
+        } => {
+            let repository = Repository::discover()?;
+            if *git_dir {
+                // relative when run from the top-level directory, as git does
+                let current_dir = std::env::current_dir()?;
+                match repository.git_dir().strip_prefix(&current_dir) {
+                    Ok(relative) if relative == std::path::Path::new(".git") => println!(".git"),
+                    _ => println!("{}", repository.git_dir().display()),
+                }
+            }
+            if *show_toplevel {
+                println!("{}", repository.work_tree()?.display());
+            }
+            if *is_inside_work_tree {
+                println!("{}", repository.work_tree().is_ok());
+            }
+            let options = RevParseOptions {
+                verify: *verify,
+                short: *short,
+                symbolic_full_name: *symbolic_full_name,
+                abbrev_ref: *abbrev_ref,
+            };
+            match git_rev_parse(&repository, revisions, &options) {
+                Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+                Err(_) if *quiet && *verify => std::process::exit(1),
+                Err(error) => return Err(error),
+            }
+        }
+        Commands::Log {
+            oneline,
+            pretty,
+            format,
+            abbrev_commit,
+            date,
+            walk,
+            revisions,
+        } => {
+            let repository = Repository::discover()?;
+            let format = match (oneline, pretty, format) {
+                (true, _, _) => LogFormat::Oneline,
+                (_, Some(pretty), _) => LogFormat::from_name(pretty)?,
+                (_, _, Some(template)) => LogFormat::Format {
+                    template: template.clone(),
+                    terminator: true,
+                },
+                _ => LogFormat::Medium,
+            };
+            let options = LogOptions {
+                walk: walk.options(),
+                format,
+                date_format: DateFormat::from_name(date)?,
+                abbrev_commit: *abbrev_commit || *oneline,
+            };
+            std::io::stdout().write_all(&git_log(&repository, revisions, &options)?)?;
+        }
+        Commands::RevList {
+            count,
+            walk,
+            revisions,
+        } => {
+            let repository = Repository::discover()?;
+            let hashes = git_rev_list(&repository, revisions, &walk.options())?;
+            match count {
+                true => println!("{}", hashes.len()),
+                false => hashes.iter().for_each(|hash| println!("{}", hash)),
+            }
+        }
+        Commands::Diff {
+            cached,
+            unified,
+            stat,
+            name_status,
+            name_only,
+            diff_algorithm,
+            patience,
+            histogram,
+            minimal,
+            revisions,
+            paths,
+        } => {
+            let repository = Repository::discover()?;
+            let output = match (stat, name_status, name_only) {
+                (true, _, _) => DiffOutput::Stat,
+                (_, true, _) => DiffOutput::NameStatus,
+                (_, _, true) => DiffOutput::NameOnly,
+                _ => DiffOutput::Patch,
+            };
+            let algorithm = match (diff_algorithm, patience, histogram, minimal) {
+                (Some(name), _, _, _) => DiffAlgorithm::from_name(name)?,
+                (_, true, _, _) => DiffAlgorithm::Patience,
+                (_, _, true, _) => DiffAlgorithm::Histogram,
+                (_, _, _, true) => DiffAlgorithm::Minimal,
+                _ => DiffAlgorithm::Myers,
+            };
+            let options = DiffOptions {
+                output,
+                context: *unified,
+                algorithm,
+                paths: paths
+                    .iter()
+                    .map(|path| repository.path_in_work_tree(path))
+                    .collect::<Result<_>>()?,
+                summary: false,
+                quote_non_ascii: repository
+                    .config()?
+                    .get_bool("core.quotePath")?
+                    .unwrap_or(true),
+            };
+            let output = git_diff(&repository, revisions, *cached, &options)?;
+            std::io::stdout().write_all(&output)?;
+        }
+        Commands::Checkout {
+            new_branch,
+            force,
+            detach,
+            target,
+            paths,
+        } => {
+            let repository = Repository::discover()?;
+            let paths = paths
+                .iter()
+                .map(|path| repository.path_in_work_tree(path))
+                .collect::<Result<Vec<_>>>()?;
+            match target {
+                _ if !paths.is_empty() => {
+                    git_checkout_paths(&repository, target.as_deref(), &paths)?
+                }
+                // a target that is not a revision is a path
+                Some(target)
+                    if new_branch.is_none()
+                        && target != "-"
+                        && resolve_revision(&repository, target).is_err() =>
+                {
+                    let path = repository.path_in_work_tree(target)?;
+                    git_checkout_paths(&repository, None, &[path])?
+                }
+                None if new_branch.is_none() => print_local_changes(&repository)?,
+                target => {
+                    let target = target.as_deref().unwrap_or("HEAD");
+                    let options = SwitchOptions {
+                        new_branch: new_branch.clone(),
+                        detach: *detach
+                            || (new_branch.is_none() && branch_ref(&repository, target)?.is_none()),
+                        force: *force,
+                    };
+                    let output = git_switch(&repository, target, &options)?;
+                    print_switch(&repository, &output, *force)?;
+                }
+            }
+        }
+        Commands::Switch {
+            new_branch,
+            detach,
+            force,
+            target,
+        } => {
+            let repository = Repository::discover()?;
+            let options = SwitchOptions {
+                new_branch: new_branch.clone(),
+                detach: *detach,
+                force: *force,
+            };
+            let target = target.as_deref().unwrap_or("HEAD");
+            let output = git_switch(&repository, target, &options)?;
+            print_switch(&repository, &output, *force)?;
+        }
+        Commands::Branch {
+            delete,
+            force_delete,
+            move_branch,
+            force_move,
+            force,
+            remotes,
+            all,
+            verbose,
+            track,
+            no_track,
+            set_upstream_to,
+            unset_upstream,
+            names,
+        } => {
+            let repository = Repository::discover()?;
+            let check_arguments = |min: usize, max: usize| {
+                if names.len() < min || names.len() > max {
+                    let mut command = Cli::command();
+                    if let Some(branch_command) = command.find_subcommand_mut("branch") {
+                        let (kind, message) = match names.len() < min {
+                            true => (clap::error::ErrorKind::TooFewValues, "branch name required"),
+                            false => (clap::error::ErrorKind::TooManyValues, "too many arguments"),
+                        };
+                        branch_command.error(kind, message).exit();
+                    }
+                }
+            };
+            let current_branch = || -> Result<String> {
+                let head_ref = repository.refs().head_ref()?;
+                Ok(head_ref
+                    .as_deref()
+                    .map_or("HEAD", GitRefDatabase::shorten_name)
+                    .to_string())
+            };
+
+            if *delete || *force_delete {
+                check_arguments(1, usize::MAX);
+                for name in names {
+                    let force = *force_delete || *force;
+                    let was = match git_branch_delete(&repository, name, *remotes, force)? {
+                        RefValue::Hash(hash) => {
+                            repository.object_database().abbreviate(&hash, 7)?
+                        }
+                        RefValue::Symbolic(target) => target,
+                    };
+                    match remotes {
+                        true => println!("Deleted remote-tracking branch {} (was {}).", name, was),
+                        false => println!("Deleted branch {} (was {}).", name, was),
+                    }
+                }
+            } else if *move_branch || *force_move {
+                check_arguments(1, 2);
+                let (name, new_name) = match names.as_slice() {
+                    [new_name] => (None, new_name),
+                    [name, new_name] => (Some(name.as_str()), new_name),
+                    _ => return Err(Error::Unreachable),
+                };
+                git_branch_rename(&repository, name, new_name, *force_move || *force)?;
+            } else if let Some(upstream) = set_upstream_to {
+                check_arguments(0, 1);
+                let name = names.first().map(String::as_str);
+                let upstream = git_branch_set_upstream(&repository, name, upstream)?;
+                println!(
+                    "branch '{}' set up to track '{}'.",
+                    name.map_or_else(current_branch, |name| Ok(name.to_string()))?,
+                    GitRefDatabase::shorten_name(&upstream)
+                );
+            } else if *unset_upstream {
+                check_arguments(0, 1);
+                git_branch_unset_upstream(&repository, names.first().map(String::as_str))?;
+            } else if names.is_empty() {
+                let kind = match (all, remotes) {
+                    (true, _) => BranchKind::All,
+                    (false, true) => BranchKind::Remote,
+                    (false, false) => BranchKind::Local,
+                };
+                print_branches(&repository, kind, *verbose)?;
+            } else {
+                check_arguments(1, 2);
+                let options = BranchCreateOptions {
+                    force: *force,
+                    track: match (track, no_track) {
+                        (true, _) => Some(true),
+                        (false, true) => Some(false),
+                        (false, false) => None,
+                    },
+                };
+                let start = names.get(1).map_or("HEAD", String::as_str);
+                if let Some(upstream) = git_branch_create(&repository, &names[0], start, &options)?
+                {
+                    println!(
+                        "branch '{}' set up to track '{}'.",
+                        names[0],
+                        GitRefDatabase::shorten_name(&upstream)
+                    );
+                }
+            }
+        }
+        Commands::Merge {
+            no_ff,
+            allow_unrelated_histories,
+            conflict,
+            revision,
+        } => {
+            let repository = Repository::discover()?;
+            let options = MergeOptions {
+                no_ff: *no_ff,
+                allow_unrelated_histories: *allow_unrelated_histories,
+                conflict_style: conflict.as_deref().and_then(ConflictStyle::from_name),
+            };
+            let output = git_merge(&repository, revision, &options)?;
+            for warning in &output.warnings {
+                eprintln!("warning: {}", warning);
+            }
+            let Some(previous_hash) = &output.previous_hash else {
+                return Ok(());
+            };
+            if output.status == MergeStatus::FastForward {
+                let object_database = repository.object_database();
+                println!(
+                    "Updating {}..{}",
+                    object_database.abbreviate(previous_hash, 7)?,
+                    object_database.abbreviate(&output.hash, 7)?
+                );
+                println!("Fast-forward");
+            }
+            for message in &output.messages {
+                println!("{}", message);
+            }
+            match output.status {
+                MergeStatus::UpToDate => println!("Already up to date."),
+                MergeStatus::Conflicts => {
+                    println!("Automatic merge failed; fix conflicts and then commit the result.");
+                    std::process::exit(1);
+                }
+                MergeStatus::Merged => println!("Merge made by the 'recursive' strategy."),
+                MergeStatus::FastForward => {}
+            }
+            if output.status != MergeStatus::UpToDate {
+                let options = DiffOptions {
+                    output: DiffOutput::Stat,
+                    summary: true,
+                    ..Default::default()
+                };
+                let revisions = [previous_hash.clone(), output.hash.clone()];
+                let diff = git_diff(&repository, &revisions, false, &options)?;
+                std::io::stdout().write_all(&diff)?;
+            }
+        }
+        Commands::MergeBase {
+            all,
+            octopus,
+            is_ancestor,
+            commits,
+        } => {
+            let repository = Repository::discover()?;
+            let usage_error = if *is_ancestor && commits.len() != 2 {
+                Some("--is-ancestor takes exactly two commits")
+            } else if !*octopus && commits.len() < 2 {
+                Some("at least two commits are required")
+            } else {
+                None
+            };
+            if let Some(message) = usage_error {
+                let mut command = Cli::command();
+                if let Some(merge_base_command) = command.find_subcommand_mut("merge-base") {
+                    merge_base_command
+                        .error(clap::error::ErrorKind::WrongNumberOfValues, message)
+                        .exit();
+                }
+            }
+            if *is_ancestor {
+                // like git, nothing is printed but the exit code tells the answer
+                if !git_merge_base_is_ancestor(&repository, &commits[0], &commits[1])? {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            let merge_bases = git_merge_base(&repository, commits, *octopus)?;
+            if merge_bases.is_empty() {
+                std::process::exit(1);
+            }
+            let nb_shown = if *all { merge_bases.len() } else { 1 };
+            for merge_base in &merge_bases[..nb_shown] {
+                println!("{}", merge_base);
+            }
+        }
+        Commands::Fetch {
+            force,
+            remote,
+            refspecs,
+        } => {
+            let repository = Repository::discover()?;
+            // as git, the reflog tells which command line updated the refs
+            let reflog_action = std::iter::once("fetch".to_string())
+                .chain(std::env::args().skip_while(|arg| arg != "fetch").skip(1))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let options = FetchOptions {
+                force: *force,
+                reflog_action,
+            };
+            let output = git_fetch(&repository, remote.as_deref(), refspecs, &options)?;
+            print_fetch(&repository, &output)?;
+            let is_rejected = output
+                .refs
+                .iter()
+                .any(|fetched_ref| fetched_ref.status == FetchedRefStatus::Rejected);
+            if is_rejected {
+                std::process::exit(1);
+            }
+        }
+        Commands::Push {
+            force,
+            force_with_lease,
+            remote,
+            refspecs,
+        } => {
+            let repository = Repository::discover()?;
+            let options = PushOptions {
+                force: *force,
+                force_with_lease: force_with_lease.clone(),
+            };
+            let output = git_push(&repository, remote.as_deref(), refspecs, &options)?;
+            if !print_push(&repository, &output)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::PackObjects {
+            revs,
+            thin,
+            stdout,
+            window,
+            depth,
+            compression,
+            delta_base_offset,
+            base_name,
+        } => {
+            let repository = Repository::discover()?;
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            let lines = input.lines().map(str::to_string).collect::<Vec<_>>();
+            let options = PackObjectsOptions {
+                revs: *revs,
+                thin: *thin,
+                write_options: PackWriteOptions {
+                    window: *window,
+                    depth: *depth,
+                    compression: *compression,
+                    ofs_delta: *delta_base_offset,
+                },
+            };
+            let git_pack = git_pack_objects(&repository, &lines, &options)?;
+            match base_name {
+                Some(base_name) if !*stdout => {
+                    println!("{}", write_pack_files(git_pack, base_name)?)
+                }
+                _ => std::io::stdout().write_all(git_pack.pack_bytes())?,
+            }
+        }
+        Commands::IndexPack {
+            output,
+            stdin,
+            fix_thin,
+            pack_file,
+        } => match pack_file {
+            Some(pack_file) if !*stdin => {
+                println!("{}", git_index_pack(pack_file, output.as_deref())?)
+            }
+            _ => {
+                let repository = Repository::discover()?;
+                let mut pack_bytes = Vec::new();
+                std::io::stdin().read_to_end(&mut pack_bytes)?;
+                let checksum = git_index_pack_stdin(&repository, pack_bytes, *fix_thin)?;
+                println!("pack\t{}", checksum)
+            }
+        },
         Commands::Clone {
             repository_url,
             directory,
         } => {
             let directory = match directory {
                 None => {
-                    let dirname = repository_url.split('/').last().ok_or(Error::Unreachable)?;
+                    let dirname = repository_url
+                        .split('/')
+                        .next_back()
+                        .ok_or(Error::Unreachable)?;
                     std::path::PathBuf::from(dirname)
                 }
                 Some(directory) => directory.clone(),
             };
 
-            git_clone(repository_url, &directory)?
+            git_clone(repository_url, &directory)?;
+            println!("Initialized git directory");
         }
     };
     Ok(())
 }
+
+/// Abbreviated hash and subject of the commit
+fn describe_commit(repository: &Repository, hash: &str) -> Result<String> {
+    let options = LogOptions {
+        walk: RevWalkOptions {
+            max_count: Some(1),
+            ..Default::default()
+        },
+        format: LogFormat::Format {
+            template: "%h %s".to_string(),
+            terminator: false,
+        },
+        date_format: DateFormat::Default,
+        abbrev_commit: false,
+    };
+    let output = git_log(repository, &[hash.to_string()], &options)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Lists the branches, the current one marked with `*`. Verbose shows the tip
+/// of each branch and how it compares to its upstream, named if very verbose
+/// Prints the updated refs on stderr as git fetch does, after the url:
+/// ` <flag> <summary> <remote ref> -> <local ref>  (<reason>)`.
+/// Up to date refs are not shown
+fn print_fetch(repository: &Repository, output: &FetchOutput) -> Result<()> {
+    let object_database = repository.object_database();
+    let prettify = |name: &str| prettify_ref_name(name).to_string();
+    let remote_name = |fetched_ref: &FetchedRef| prettify(&fetched_ref.remote_ref);
+    let fetched_refs = output
+        .refs
+        .iter()
+        .filter(|fetched_ref| fetched_ref.status != FetchedRefStatus::UpToDate)
+        .collect::<Vec<_>>();
+    if fetched_refs.is_empty() {
+        return Ok(());
+    }
+
+    // the remote refs are aligned, unless a line would not fit in 80 columns
+    let mut width = 10;
+    for fetched_ref in &fetched_refs {
+        if let Some(local_ref) = &fetched_ref.local_ref {
+            let remote_len = remote_name(fetched_ref).chars().count();
+            let local_len = prettify(local_ref).chars().count();
+            if 21 + remote_len + 4 + local_len < 80 {
+                width = width.max(remote_len);
+            }
+        }
+    }
+
+    eprintln!("From {}", output.url);
+    for fetched_ref in fetched_refs {
+        let abbreviated = |hash: &Option<String>| -> Result<String> {
+            let hash = hash.as_deref().ok_or(Error::Unreachable)?;
+            object_database.abbreviate(hash, 7)
+        };
+        let new_hash = Some(fetched_ref.new_hash.clone());
+        let (flag, summary) = match fetched_ref.status {
+            FetchedRefStatus::FastForward => (
+                ' ',
+                format!(
+                    "{}..{}",
+                    abbreviated(&fetched_ref.old_hash)?,
+                    abbreviated(&new_hash)?
+                ),
+            ),
+            FetchedRefStatus::Forced => (
+                '+',
+                format!(
+                    "{}...{}",
+                    abbreviated(&fetched_ref.old_hash)?,
+                    abbreviated(&new_hash)?
+                ),
+            ),
+            FetchedRefStatus::New if fetched_ref.remote_ref.starts_with("refs/tags/") => {
+                ('*', "[new tag]".to_string())
+            }
+            FetchedRefStatus::New if fetched_ref.remote_ref.starts_with("refs/heads/") => {
+                ('*', "[new branch]".to_string())
+            }
+            FetchedRefStatus::New => ('*', "[new ref]".to_string()),
+            FetchedRefStatus::TagUpdate => ('t', "[tag update]".to_string()),
+            FetchedRefStatus::Rejected => ('!', "[rejected]".to_string()),
+            FetchedRefStatus::UpToDate => ('=', "[up to date]".to_string()),
+            FetchedRefStatus::FetchHeadOnly => {
+                let kind = match describe_remote_ref(&fetched_ref.remote_ref).0 {
+                    "" => "branch",
+                    kind => kind,
+                };
+                ('*', kind.to_string())
+            }
+        };
+        let local_name = match &fetched_ref.local_ref {
+            Some(local_ref) => prettify(local_ref),
+            None => "FETCH_HEAD".to_string(),
+        };
+        let reason = match fetched_ref.reason {
+            Some(reason) => format!("  ({})", reason),
+            None => String::new(),
+        };
+        eprintln!(
+            " {} {:<17} {:<width$} -> {}{}",
+            flag,
+            summary,
+            remote_name(fetched_ref),
+            local_name,
+            reason,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// Ref name as displayed by fetch and push: refs/heads/, refs/tags/ and
+/// refs/remotes/ are removed
+fn prettify_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Prints the pushed refs on stderr as git push does, after the url:
+/// ` <flag> <summary> <source> -> <remote ref> (<reason>)`, the updates first
+/// and then the rejections, followed by advice on how to push the rejected
+/// refs. Up to date refs are not shown. Returns false if a ref was rejected
+fn print_push(repository: &Repository, output: &PushOutput) -> Result<bool> {
+    let object_database = repository.object_database();
+    let abbreviated =
+        |hash: &Option<String>| object_database.abbreviate(hash.as_deref().unwrap_or(NULL_HASH), 7);
+    let mut width = 7;
+    for pushed_ref in &output.refs {
+        width = width
+            .max(abbreviated(&pushed_ref.old_hash)?.len())
+            .max(abbreviated(&pushed_ref.new_hash)?.len());
+    }
+    let width = 2 * width + 3;
+
+    let is_ok = |status: &PushedRefStatus| *status == PushedRefStatus::Ok;
+    let is_printed = |status: &PushedRefStatus| *status != PushedRefStatus::UpToDate;
+    let updated = output.refs.iter().filter(|r| is_ok(&r.status));
+    let rejected = output
+        .refs
+        .iter()
+        .filter(|r| !is_ok(&r.status) && is_printed(&r.status));
+    for (i, pushed_ref) in updated.chain(rejected).enumerate() {
+        if i == 0 {
+            eprintln!("To {}", output.url);
+        }
+        let (flag, summary, reason) = match &pushed_ref.status {
+            PushedRefStatus::Ok if pushed_ref.new_hash.is_none() => {
+                ('-', "[deleted]".to_string(), None)
+            }
+            PushedRefStatus::Ok if pushed_ref.old_hash.is_none() => {
+                let summary = if pushed_ref.remote_ref.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else if pushed_ref.remote_ref.starts_with("refs/heads/") {
+                    "[new branch]"
+                } else {
+                    "[new reference]"
+                };
+                ('*', summary.to_string(), None)
+            }
+            PushedRefStatus::Ok => {
+                let old = abbreviated(&pushed_ref.old_hash)?;
+                let new = abbreviated(&pushed_ref.new_hash)?;
+                match pushed_ref.forced {
+                    true => ('+', format!("{}...{}", old, new), Some("forced update")),
+                    false => (' ', format!("{}..{}", old, new), None),
+                }
+            }
+            PushedRefStatus::UpToDate => ('=', "[up to date]".to_string(), None),
+            PushedRefStatus::NonFastForward => {
+                ('!', "[rejected]".to_string(), Some("non-fast-forward"))
+            }
+            PushedRefStatus::AlreadyExists => {
+                ('!', "[rejected]".to_string(), Some("already exists"))
+            }
+            PushedRefStatus::FetchFirst => ('!', "[rejected]".to_string(), Some("fetch first")),
+            PushedRefStatus::NeedsForce => ('!', "[rejected]".to_string(), Some("needs force")),
+            PushedRefStatus::Stale => ('!', "[rejected]".to_string(), Some("stale info")),
+            PushedRefStatus::NoDelete => (
+                '!',
+                "[rejected]".to_string(),
+                Some("remote does not support deleting refs"),
+            ),
+            PushedRefStatus::RemoteRejected(reason) => {
+                ('!', "[remote rejected]".to_string(), Some(reason.as_str()))
+            }
+        };
+        let names = match (&pushed_ref.source, &pushed_ref.new_hash) {
+            (Some(source), Some(_)) => format!(
+                "{} -> {}",
+                prettify_ref_name(source),
+                prettify_ref_name(&pushed_ref.remote_ref)
+            ),
+            _ => prettify_ref_name(&pushed_ref.remote_ref).to_string(),
+        };
+        let reason = match reason {
+            Some(reason) => format!(" ({})", reason),
+            None => String::new(),
+        };
+        eprintln!(
+            " {} {:<width$} {}{}",
+            flag,
+            summary,
+            names,
+            reason,
+            width = width
+        );
+    }
+
+    let statuses = output.refs.iter().map(|r| &r.status).collect::<Vec<_>>();
+    if statuses.iter().all(|status| !is_printed(status)) {
+        eprintln!("Everything up-to-date");
+    }
+    if statuses
+        .iter()
+        .all(|status| is_ok(status) || !is_printed(status))
+    {
+        return Ok(true);
+    }
+    eprintln!("error: failed to push some refs to '{}'", output.url);
+
+    // the advice is given for the first of these reasons that applies
+    let head_ref = repository.refs().head_ref()?;
+    let non_fast_forwards = output
+        .refs
+        .iter()
+        .filter(|r| r.status == PushedRefStatus::NonFastForward)
+        .map(|r| Some(&r.remote_ref))
+        .collect::<Vec<_>>();
+    let advice = if non_fast_forwards.contains(&head_ref.as_ref()) {
+        "Updates were rejected because the tip of your current branch is behind\n\
+         its remote counterpart. Integrate the remote changes (e.g.\n\
+         'git pull ...') before pushing again.\n\
+         See the 'Note about fast-forwards' in 'git push --help' for details."
+    } else if !non_fast_forwards.is_empty() {
+        "Updates were rejected because a pushed branch tip is behind its remote\n\
+         counterpart. Check out this branch and integrate the remote changes\n\
+         (e.g. 'git pull ...') before pushing again.\n\
+         See the 'Note about fast-forwards' in 'git push --help' for details."
+    } else if statuses.contains(&&PushedRefStatus::AlreadyExists) {
+        "Updates were rejected because the tag already exists in the remote."
+    } else if statuses.contains(&&PushedRefStatus::FetchFirst) {
+        "Updates were rejected because the remote contains work that you do\n\
+         not have locally. This is usually caused by another repository pushing\n\
+         to the same ref. You may want to first integrate the remote changes\n\
+         (e.g., 'git pull ...') before pushing again.\n\
+         See the 'Note about fast-forwards' in 'git push --help' for details."
+    } else if statuses.contains(&&PushedRefStatus::NeedsForce) {
+        "You cannot update a remote ref that points at a non-commit object,\n\
+         or update a remote ref to make it point at a non-commit object,\n\
+         without using the '--force' option."
+    } else {
+        ""
+    };
+    for line in advice.lines() {
+        eprintln!("hint: {}", line);
+    }
+    Ok(false)
+}
+
+fn print_branches(repository: &Repository, kind: BranchKind, verbose: u8) -> Result<()> {
+    let branches = git_branch_list(repository, kind)?;
+    let mut names = Vec::new();
+    for branch in &branches {
+        let name = match &branch.ref_name {
+            None => format!("({})", detached_head_description(repository)?),
+            Some(ref_name) if kind == BranchKind::All => ref_name
+                .strip_prefix("refs/")
+                .filter(|name| name.starts_with("remotes/"))
+                .unwrap_or_else(|| GitRefDatabase::shorten_name(ref_name))
+                .to_string(),
+            Some(ref_name) => GitRefDatabase::shorten_name(ref_name).to_string(),
+        };
+        names.push(name);
+    }
+    let width = names.iter().map(String::len).max().unwrap_or_default();
+
+    for (branch, name) in branches.iter().zip(names) {
+        let marker = if branch.is_head { '*' } else { ' ' };
+        if let Some(target) = &branch.target {
+            let width = if verbose > 0 { width } else { 0 };
+            let target = GitRefDatabase::shorten_name(target);
+            println!("{} {:width$} -> {}", marker, name, target, width = width);
+            continue;
+        }
+        if verbose == 0 {
+            println!("{} {}", marker, name);
+            continue;
+        }
+        let tracking = match &branch.ref_name {
+            Some(ref_name) => branch_tracking(repository, ref_name)?,
+            None => None,
+        };
+        let tracking = match tracking {
+            None => String::new(),
+            Some(tracking) => {
+                let mut counts = Vec::new();
+                match tracking.ahead_behind {
+                    None => counts.push("gone".to_string()),
+                    Some((ahead, behind)) => {
+                        if ahead > 0 {
+                            counts.push(format!("ahead {}", ahead));
+                        }
+                        if behind > 0 {
+                            counts.push(format!("behind {}", behind));
+                        }
+                    }
+                }
+                let upstream = GitRefDatabase::shorten_name(&tracking.upstream);
+                match (verbose, counts.is_empty()) {
+                    (1, true) => String::new(),
+                    (1, false) => format!("[{}] ", counts.join(", ")),
+                    (_, true) => format!("[{}] ", upstream),
+                    (_, false) => format!("[{}: {}] ", upstream, counts.join(", ")),
+                }
+            }
+        };
+        let description = describe_commit(repository, &branch.hash)?;
+        let (hash, subject) = description.split_once(' ').unwrap_or((&description, ""));
+        println!(
+            "{} {:width$} {} {}{}",
+            marker,
+            name,
+            hash,
+            tracking,
+            subject,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// Describes a switch as git does, followed by the files with local changes
+fn print_switch(repository: &Repository, output: &SwitchOutput, force: bool) -> Result<()> {
+    let describe = |hash: &str| describe_commit(repository, hash);
+    if let (None, Some(previous_hash)) = (&output.previous_branch, &output.previous_hash) {
+        if Some(previous_hash) != output.hash.as_ref() {
+            eprintln!("Previous HEAD position was {}", describe(previous_hash)?);
+        }
+    }
+    match &output.branch {
+        Some(branch) => {
+            let name = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+            if output.created {
+                eprintln!("Switched to a new branch '{}'", name);
+            } else if output.previous_branch.as_ref() == Some(branch) {
+                eprintln!("Already on '{}'", name);
+            } else {
+                eprintln!("Switched to branch '{}'", name);
+            }
+        }
+        None => {
+            let hash = output.hash.as_deref().ok_or(Error::Unreachable)?;
+            eprintln!("HEAD is now at {}", describe(hash)?)
+        }
+    }
+    if !force {
+        print_local_changes(repository)?;
+    }
+    Ok(())
+}
+
+/// Status letter and path of the files that differ from HEAD in the working tree
+fn print_local_changes(repository: &Repository) -> Result<()> {
+    if repository.refs().resolve("HEAD")?.is_none() {
+        return Ok(());
+    }
+    let options = DiffOptions {
+        output: DiffOutput::NameStatus,
+        quote_non_ascii: repository
+            .config()?
+            .get_bool("core.quotePath")?
+            .unwrap_or(true),
+        ..Default::default()
+    };
+    let output = git_diff(repository, &["HEAD".to_string()], false, &options)?;
+    std::io::stdout().write_all(&output)?;
+    Ok(())
+}
+
+/// XY PATH where X is the status of the index and Y the status of the working tree
+fn print_short_status(status: &Status, current_dir: &str, quote_non_ascii: bool) {
+    let display_path = |path: &str| quote_path(&relative_path(path, current_dir), quote_non_ascii);
+    let mut lines = Vec::new();
+    for entry in &status.entries {
+        let code = format!("{}{}", entry.staged.code(), entry.unstaged.code());
+        lines.push((entry.path.as_str(), code));
+    }
+    for entry in &status.unmerged {
+        lines.push((entry.path.as_str(), entry.code().to_string()));
+    }
+    lines.sort();
+    for (path, code) in lines {
+        println!("{} {}", code, display_path(path));
+    }
+    for path in &status.untracked {
+        println!("?? {}", display_path(path));
+    }
+}
+
+/// Same output as git status, with the files grouped by kind of change
+fn print_long_status(status: &Status, current_dir: &str, quote_non_ascii: bool) {
+    let display_path = |path: &str| quote_path(&relative_path(path, current_dir), quote_non_ascii);
+    match (&status.head_ref, &status.head_hash) {
+        (Some(head_ref), _) => println!(
+            "On branch {}",
+            head_ref.strip_prefix("refs/heads/").unwrap_or(head_ref)
+        ),
+        (None, Some(head_hash)) => println!("HEAD detached at {}", &head_hash[..7]),
+        (None, None) => {}
+    }
+    if status.head_hash.is_none() {
+        println!("\nNo commits yet\n");
+    }
+
+    let staged = status
+        .entries
+        .iter()
+        .filter(|entry| entry.staged != FileStatus::Unmodified)
+        .collect::<Vec<_>>();
+    let unstaged = status
+        .entries
+        .iter()
+        .filter(|entry| entry.unstaged != FileStatus::Unmodified)
+        .collect::<Vec<_>>();
+
+    if !staged.is_empty() {
+        println!("Changes to be committed:");
+        match status.head_hash {
+            Some(_) => println!("  (use \"git restore --staged <file>...\" to unstage)"),
+            None => println!("  (use \"git rm --cached <file>...\" to unstage)"),
+        }
+        for entry in &staged {
+            let label = format!("{}:", entry.staged.label());
+            println!("\t{:<12}{}", label, display_path(&entry.path));
+        }
+        println!();
+    }
+
+    if !status.unmerged.is_empty() {
+        println!("Unmerged paths:");
+        if status
+            .unmerged
+            .iter()
+            .any(|entry| entry.code().contains('D'))
+        {
+            println!("  (use \"git add/rm <file>...\" as appropriate to mark resolution)");
+        } else {
+            println!("  (use \"git add <file>...\" to mark resolution)");
+        }
+        for entry in &status.unmerged {
+            let label = format!("{}:", entry.label());
+            println!("\t{:<17}{}", label, display_path(&entry.path));
+        }
+        println!();
+    }
+
+    if !unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        if unstaged
+            .iter()
+            .any(|entry| entry.unstaged == FileStatus::Deleted)
+        {
+            println!("  (use \"git add/rm <file>...\" to update what will be committed)");
+        } else {
+            println!("  (use \"git add <file>...\" to update what will be committed)");
+        }
+        println!("  (use \"git restore <file>...\" to discard changes in working directory)");
+        for entry in &unstaged {
+            let label = format!("{}:", entry.unstaged.label());
+            println!("\t{:<12}{}", label, display_path(&entry.path));
+        }
+        println!();
+    }
+
+    if !status.untracked.is_empty() {
+        println!("Untracked files:");
+        println!("  (use \"git add <file>...\" to include in what will be committed)");
+        for path in &status.untracked {
+            println!("\t{}", display_path(path));
+        }
+        println!();
+    }
+
+    if !staged.is_empty() {
+        return;
+    }
+    if !unstaged.is_empty() || !status.unmerged.is_empty() {
+        println!("no changes added to commit (use \"git add\" and/or \"git commit -a\")");
+    } else if !status.untracked.is_empty() {
+        println!("nothing added to commit but untracked files present (use \"git add\" to track)");
+    } else if status.head_hash.is_none() {
+        println!("nothing to commit (create/copy files and use \"git add\" to track)");
+    } else {
+        println!("nothing to commit, working tree clean");
+    }
+}